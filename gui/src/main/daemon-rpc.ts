@@ -924,6 +924,7 @@ function convertFromObfuscationEndpoint(
 ): IObfuscationEndpoint {
   const obfuscationTypes: Record<grpcTypes.ObfuscationType, ObfuscationType> = {
     [grpcTypes.ObfuscationType.UDP2TCP]: 'udp2tcp',
+    [grpcTypes.ObfuscationType.SHADOWSOCKS]: 'shadowsocks',
   };
 
   return {
@@ -252,6 +252,8 @@ struct Wireguard {
     port_ranges: Vec<(u16, u16)>,
     ipv4_gateway: Ipv4Addr,
     ipv6_gateway: Ipv6Addr,
+    #[serde(default)]
+    shadowsocks: Vec<relay_list::ShadowsocksEndpointData>,
     relays: Vec<WireGuardRelay>,
 }
 
@@ -262,6 +264,7 @@ impl From<&Wireguard> for relay_list::WireguardEndpointData {
             ipv4_gateway: wg.ipv4_gateway,
             ipv6_gateway: wg.ipv6_gateway,
             udp2tcp_ports: vec![],
+            shadowsocks: wg.shadowsocks.clone(),
         }
     }
 }
@@ -0,0 +1,229 @@
+#[cfg(target_os = "linux")]
+use crate::{new_rpc_client, Error};
+use crate::{Command, Result};
+#[cfg(target_os = "linux")]
+use mullvad_management_interface::types::{leak_probe, leak_probe_result, LeakProbeResult};
+#[cfg(target_os = "linux")]
+use std::convert::TryFrom;
+#[cfg(target_os = "linux")]
+use talpid_types::net::{
+    dns::{DnsConfigDrift, EncryptedDnsFallback},
+    drop_log::DroppedPackets,
+    NetworkChange,
+};
+
+pub struct Debug;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Debug {
+    fn name(&self) -> &'static str {
+        "debug"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static> {
+        #[allow(unused_mut)]
+        let mut subcommand = clap::App::new(self.name())
+            .about("Debug and diagnostic tools")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp);
+        #[cfg(target_os = "linux")]
+        {
+            subcommand = subcommand
+                .subcommand(clap::App::new("leak-test").about(
+                    "Send traffic outside the tunnel and check that the firewall blocks it",
+                ))
+                .subcommand(clap::App::new("dropped").about(
+                    "Print packets rejected by the firewall until interrupted. Requires logging of \
+                     rejected packets to be enabled with `log-drops set on`",
+                ))
+                .subcommand(
+                    clap::App::new("log-drops")
+                        .about("Control whether the firewall logs rejected packets")
+                        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                        .subcommand(
+                            clap::App::new("set").arg(
+                                clap::Arg::new("policy")
+                                    .required(true)
+                                    .possible_values(&["on", "off"]),
+                            ),
+                        )
+                        .subcommand(clap::App::new("get")),
+                )
+                .subcommand(clap::App::new("dns-drift").about(
+                    "Print a message every time DNS config that was changed by another program \
+                     is re-applied, until interrupted",
+                ))
+                .subcommand(clap::App::new("dns-fallback").about(
+                    "Print a message every time encrypted DNS to the relay falls back to plain \
+                     DNS, or recovers, until interrupted",
+                ))
+                .subcommand(clap::App::new("network-changes").about(
+                    "Print a message every time the tunnel is kept up across a change of network, \
+                     until interrupted",
+                ));
+        }
+        subcommand
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        match matches.subcommand() {
+            #[cfg(target_os = "linux")]
+            Some(("leak-test", _)) => self.leak_test().await,
+            #[cfg(target_os = "linux")]
+            Some(("dropped", _)) => self.dropped().await,
+            #[cfg(target_os = "linux")]
+            Some(("log-drops", matches)) => self.log_drops(matches).await,
+            #[cfg(target_os = "linux")]
+            Some(("dns-drift", _)) => self.dns_drift().await,
+            #[cfg(target_os = "linux")]
+            Some(("dns-fallback", _)) => self.dns_fallback().await,
+            #[cfg(target_os = "linux")]
+            Some(("network-changes", _)) => self.network_changes().await,
+            _ => unreachable!("No debug command given"),
+        }
+    }
+}
+
+impl Debug {
+    #[cfg(target_os = "linux")]
+    async fn leak_test(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let results = rpc.run_leak_test(()).await?.into_inner().results;
+
+        let mut leaked = false;
+        for result in &results {
+            leaked |= Self::print_probe_result(result);
+        }
+
+        if leaked {
+            Err(Error::CommandFailed("Traffic escaped the tunnel"))
+        } else {
+            println!("No leaks detected");
+            Ok(())
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn dropped(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut events = rpc
+            .firewall_drop_events(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to subscribe to dropped packets", error))?
+            .into_inner();
+
+        while let Some(event) = events.message().await? {
+            for drops in event.drops {
+                match DroppedPackets::try_from(drops) {
+                    Ok(drops) => println!("{}", drops),
+                    Err(error) => eprintln!("Invalid dropped packets: {}", error),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn log_drops(&self, matches: &clap::ArgMatches) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        match matches.subcommand() {
+            Some(("get", _)) => {
+                let settings = rpc.get_settings(()).await?.into_inner();
+                let enabled_str = if settings.log_firewall_drops {
+                    "on"
+                } else {
+                    "off"
+                };
+                println!("Log rejected packets: {}", enabled_str);
+            }
+            Some(("set", matches)) => {
+                let enabled_str = matches.value_of("policy").expect("missing policy");
+                rpc.set_log_firewall_drops(enabled_str == "on").await?;
+                println!("Log rejected packets: {}", enabled_str);
+            }
+            _ => unreachable!("unhandled command"),
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn dns_drift(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut events = rpc
+            .dns_config_drift_events(())
+            .await
+            .map_err(|error| {
+                Error::RpcFailedExt("Failed to subscribe to DNS config changes", error)
+            })?
+            .into_inner();
+
+        while let Some(drift) = events.message().await? {
+            println!("{}", DnsConfigDrift::from(drift));
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn dns_fallback(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut events = rpc
+            .encrypted_dns_fallback_events(())
+            .await
+            .map_err(|error| {
+                Error::RpcFailedExt("Failed to subscribe to encrypted DNS fallback", error)
+            })?
+            .into_inner();
+
+        while let Some(event) = events.message().await? {
+            println!("{}", EncryptedDnsFallback::from(event));
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn network_changes(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut events = rpc
+            .network_change_events(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to subscribe to network changes", error))?
+            .into_inner();
+
+        while let Some(change) = events.message().await? {
+            println!("{}", NetworkChange::from(change));
+        }
+        Ok(())
+    }
+
+    /// Prints a probe result. Returns whether the probe leaked unexpectedly.
+    #[cfg(target_os = "linux")]
+    fn print_probe_result(result: &LeakProbeResult) -> bool {
+        let (probe_type, address) = match &result.probe {
+            Some(probe) => {
+                let probe_type = match leak_probe::ProbeType::from_i32(probe.probe_type) {
+                    Some(leak_probe::ProbeType::Tcp) => "TCP",
+                    Some(leak_probe::ProbeType::Udp) => "UDP",
+                    Some(leak_probe::ProbeType::Icmp) => "ICMP",
+                    Some(leak_probe::ProbeType::Dns) => "DNS",
+                    None => "unknown",
+                };
+                (probe_type, probe.address.as_str())
+            }
+            None => ("unknown", ""),
+        };
+
+        let outcome = leak_probe_result::Outcome::from_i32(result.outcome);
+        let outcome_str = match outcome {
+            Some(leak_probe_result::Outcome::Blocked) => "blocked".to_owned(),
+            Some(leak_probe_result::Outcome::Leaked) if result.expected_allowed => {
+                "allowed".to_owned()
+            }
+            Some(leak_probe_result::Outcome::Leaked) => "LEAKED".to_owned(),
+            Some(leak_probe_result::Outcome::Unreachable) => "unreachable".to_owned(),
+            Some(leak_probe_result::Outcome::Failed) => format!("failed: {}", result.error),
+            None => "unknown".to_owned(),
+        };
+        println!("{:<5} {:<45} {}", probe_type, address, outcome_str);
+
+        outcome == Some(leak_probe_result::Outcome::Leaked) && !result.expected_allowed
+    }
+}
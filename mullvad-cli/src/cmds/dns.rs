@@ -1,7 +1,14 @@
+#[cfg(target_os = "linux")]
+use crate::Error;
 use crate::{new_rpc_client, Command, Result};
 use mullvad_management_interface::types;
 use mullvad_types::settings::{DnsOptions, DnsState};
 use std::{convert::TryInto, net::IpAddr};
+#[cfg(target_os = "linux")]
+use talpid_types::net::dns::{
+    DnsOverride, DnsQueryLogEntry, DnsServerTestResult, EncryptedDnsProtocol, EncryptedDnsServer,
+    LocalDnsRules,
+};
 
 pub struct Dns;
 
@@ -12,71 +19,47 @@ impl Command for Dns {
     }
 
     fn clap_subcommand(&self) -> clap::App<'static> {
-        clap::App::new(self.name())
+        #[allow(unused_mut)]
+        let mut subcommand = clap::App::new(self.name())
             .about("Configure DNS servers to use when connected")
             .setting(clap::AppSettings::SubcommandRequiredElseHelp)
             .subcommand(clap::App::new("get").about("Display the current DNS settings"))
-            .subcommand(
-                clap::App::new("set")
-                    .about("Set DNS servers to use")
-                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
-                    .subcommand(
-                        clap::App::new("default")
-                            .about("Use default DNS servers")
-                            .arg(
-                                clap::Arg::new("block ads")
-                                    .long("block-ads")
-                                    .takes_value(false)
-                                    .help("Block domain names used for ads"),
-                            )
-                            .arg(
-                                clap::Arg::new("block trackers")
-                                    .long("block-trackers")
-                                    .takes_value(false)
-                                    .help("Block domain names used for tracking"),
-                            )
-                            .arg(
-                                clap::Arg::new("block malware")
-                                    .long("block-malware")
-                                    .takes_value(false)
-                                    .help("Block domains known to be used by malware"),
-                            )
-                            .arg(
-                                clap::Arg::new("block adult content")
-                                    .long("block-adult-content")
-                                    .takes_value(false)
-                                    .help("Block domains known to be used for adult content"),
-                            )
-                            .arg(
-                                clap::Arg::new("block gambling")
-                                    .long("block-gambling")
-                                    .takes_value(false)
-                                    .help("Block domains known to be used for gambling"),
-                            ),
-                    )
-                    .subcommand(
-                        clap::App::new("custom")
-                            .about("Set a list of custom DNS servers")
-                            .arg(
-                                clap::Arg::new("servers")
-                                    .multiple_occurrences(true)
-                                    .help("One or more IP addresses pointing to DNS resolvers.")
-                                    .required(true),
-                            ),
-                    ),
-            )
+            .subcommand(create_set_subcommand());
+        #[cfg(target_os = "linux")]
+        {
+            subcommand = subcommand
+                .subcommand(create_split_subcommand())
+                .subcommand(create_blocklist_subcommand())
+                .subcommand(create_override_subcommand())
+                .subcommand(create_log_subcommand())
+                .subcommand(create_blocked_state_subcommand())
+                .subcommand(clap::App::new("test").about(
+                    "Check that the custom DNS servers can be reached, and send them a test \
+                         query if connected",
+                ));
+        }
+        subcommand
     }
 
     async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
         match matches.subcommand() {
             Some(("set", matches)) => match matches.subcommand() {
                 Some(("default", matches)) => {
+                    #[cfg(target_os = "linux")]
+                    let encryption = match matches.value_of("encrypt") {
+                        Some("tls") => types::default_dns_options::Encryption::Tls,
+                        Some("https") => types::default_dns_options::Encryption::Https,
+                        _ => types::default_dns_options::Encryption::None,
+                    };
+                    #[cfg(not(target_os = "linux"))]
+                    let encryption = types::default_dns_options::Encryption::None;
                     self.set_default(
                         matches.is_present("block ads"),
                         matches.is_present("block trackers"),
                         matches.is_present("block malware"),
                         matches.is_present("block adult content"),
                         matches.is_present("block gambling"),
+                        encryption,
                     )
                     .await
                 }
@@ -90,14 +73,273 @@ impl Command for Dns {
                     };
                     self.set_custom(servers).await
                 }
+                #[cfg(target_os = "linux")]
+                Some(("encrypted", matches)) => {
+                    let bootstrap_addresses = matches
+                        .values_of_t::<IpAddr>("bootstrap")
+                        .unwrap_or_else(|e| e.exit());
+                    let server = EncryptedDnsServer::from_url(
+                        matches.value_of("url").unwrap(),
+                        bootstrap_addresses,
+                        matches.value_of("spki pin").map(str::to_owned),
+                    )
+                    .unwrap_or_else(|error| {
+                        clap::Error::raw(clap::ErrorKind::ValueValidation, error.to_string()).exit()
+                    });
+                    self.set_encrypted(server).await
+                }
                 _ => unreachable!("No custom-dns server command given"),
             },
             Some(("get", _)) => self.get().await,
+            #[cfg(target_os = "linux")]
+            Some(("split", matches)) => self.handle_split_cmd(matches).await,
+            #[cfg(target_os = "linux")]
+            Some(("blocklist", matches)) => self.handle_blocklist_cmd(matches).await,
+            #[cfg(target_os = "linux")]
+            Some(("override", matches)) => self.handle_override_cmd(matches).await,
+            #[cfg(target_os = "linux")]
+            Some(("blocked-state", matches)) => match matches.subcommand() {
+                Some(("set", matches)) => {
+                    let servers = matches
+                        .values_of_t::<IpAddr>("servers")
+                        .unwrap_or_else(|e| e.exit());
+                    self.set_blocked_state_servers(servers).await
+                }
+                Some(("clear", _)) => self.set_blocked_state_servers(vec![]).await,
+                _ => unreachable!("No blocked-state command given"),
+            },
+            #[cfg(target_os = "linux")]
+            Some(("test", _)) => self.test_servers().await,
+            #[cfg(target_os = "linux")]
+            Some(("log", matches)) => match matches.subcommand() {
+                Some(("enable", _)) => self.set_query_log(true).await,
+                Some(("disable", _)) => self.set_query_log(false).await,
+                Some(("show", matches)) => self.show_query_log(matches.is_present("follow")).await,
+                _ => unreachable!("No DNS log command given"),
+            },
             _ => unreachable!("No custom-dns command given"),
         }
     }
 }
 
+fn create_set_subcommand() -> clap::App<'static> {
+    #[allow(unused_mut)]
+    let mut default_subcommand = clap::App::new("default")
+        .about("Use default DNS servers")
+        .arg(
+            clap::Arg::new("block ads")
+                .long("block-ads")
+                .takes_value(false)
+                .help("Block domain names used for ads"),
+        )
+        .arg(
+            clap::Arg::new("block trackers")
+                .long("block-trackers")
+                .takes_value(false)
+                .help("Block domain names used for tracking"),
+        )
+        .arg(
+            clap::Arg::new("block malware")
+                .long("block-malware")
+                .takes_value(false)
+                .help("Block domains known to be used by malware"),
+        )
+        .arg(
+            clap::Arg::new("block adult content")
+                .long("block-adult-content")
+                .takes_value(false)
+                .help("Block domains known to be used for adult content"),
+        )
+        .arg(
+            clap::Arg::new("block gambling")
+                .long("block-gambling")
+                .takes_value(false)
+                .help("Block domains known to be used for gambling"),
+        );
+    #[cfg(target_os = "linux")]
+    {
+        default_subcommand = default_subcommand.arg(
+            clap::Arg::new("encrypt")
+                .long("encrypt")
+                .takes_value(true)
+                .possible_values(&["tls", "https"])
+                .help(
+                    "Reach the resolver on the relay over DNS-over-TLS or DNS-over-HTTPS. Plain \
+                     DNS is used while this fails",
+                ),
+        );
+    }
+
+    #[allow(unused_mut)]
+    let mut subcommand = clap::App::new("set")
+        .about("Set DNS servers to use")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(default_subcommand)
+        .subcommand(
+            clap::App::new("custom")
+                .about("Set a list of custom DNS servers")
+                .arg(
+                    clap::Arg::new("servers")
+                        .multiple_occurrences(true)
+                        .help("One or more IP addresses pointing to DNS resolvers.")
+                        .required(true),
+                ),
+        );
+    #[cfg(target_os = "linux")]
+    {
+        subcommand = subcommand.subcommand(create_set_encrypted_subcommand());
+    }
+    subcommand
+}
+
+#[cfg(target_os = "linux")]
+fn create_set_encrypted_subcommand() -> clap::App<'static> {
+    clap::App::new("encrypted")
+        .about("Use a DNS-over-TLS or DNS-over-HTTPS resolver")
+        .arg(
+            clap::Arg::new("url")
+                .help("Resolver URL, such as tls://dns.example or https://dns.example/dns-query")
+                .required(true),
+        )
+        .arg(
+            clap::Arg::new("bootstrap")
+                .multiple_occurrences(true)
+                .help("One or more IP addresses of the resolver")
+                .required(true),
+        )
+        .arg(
+            clap::Arg::new("spki pin")
+                .long("spki-pin")
+                .takes_value(true)
+                .help("Base64 encoded SHA-256 digest of the public key the resolver must use"),
+        )
+}
+
+#[cfg(target_os = "linux")]
+fn create_split_subcommand() -> clap::App<'static> {
+    clap::App::new("split")
+        .about("Send queries for some domains to resolvers of their own when connected")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::App::new("list").about("List split DNS rules"))
+        .subcommand(
+            clap::App::new("add")
+                .about("Send queries for a domain and its subdomains to the given resolvers")
+                .arg(
+                    clap::Arg::new("domain")
+                        .help("The domain, such as corp.example")
+                        .required(true),
+                )
+                .arg(
+                    clap::Arg::new("servers")
+                        .multiple_occurrences(true)
+                        .help("One or more IP addresses pointing to DNS resolvers")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::App::new("remove")
+                .about("Remove the split DNS rule for a domain")
+                .arg(clap::Arg::new("domain").required(true)),
+        )
+        .subcommand(clap::App::new("clear").about("Remove all split DNS rules"))
+}
+
+#[cfg(target_os = "linux")]
+fn create_blocklist_subcommand() -> clap::App<'static> {
+    clap::App::new("blocklist")
+        .about("Manage domains that are blocked locally, in addition to any server-side blocking")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::App::new("list").about("List blocked domains"))
+        .subcommand(
+            clap::App::new("add").about("Block domains").arg(
+                clap::Arg::new("domains")
+                    .multiple_occurrences(true)
+                    .help("Domains to block. *.example blocks example and all of its subdomains")
+                    .required(true),
+            ),
+        )
+        .subcommand(
+            clap::App::new("remove").about("Stop blocking domains").arg(
+                clap::Arg::new("domains")
+                    .multiple_occurrences(true)
+                    .required(true),
+            ),
+        )
+        .subcommand(
+            clap::App::new("import")
+                .about("Add the entries of a file in hosts format")
+                .long_about(
+                    "Add the entries of a file in hosts format. Names mapped to 0.0.0.0 or a \
+                     loopback address are blocked, and other names are added as overrides.",
+                )
+                .arg(clap::Arg::new("file").required(true)),
+        )
+        .subcommand(clap::App::new("clear").about("Remove all blocked domains"))
+}
+
+#[cfg(target_os = "linux")]
+fn create_override_subcommand() -> clap::App<'static> {
+    clap::App::new("override")
+        .about("Manage names that resolve to fixed addresses")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::App::new("list").about("List overridden names"))
+        .subcommand(
+            clap::App::new("add")
+                .about("Resolve a name to a fixed address")
+                .arg(clap::Arg::new("name").required(true))
+                .arg(clap::Arg::new("address").required(true)),
+        )
+        .subcommand(
+            clap::App::new("remove")
+                .about("Remove all overrides for a name")
+                .arg(clap::Arg::new("name").required(true)),
+        )
+        .subcommand(clap::App::new("clear").about("Remove all overrides"))
+}
+
+#[cfg(target_os = "linux")]
+fn create_blocked_state_subcommand() -> clap::App<'static> {
+    clap::App::new("blocked-state")
+        .about("Configure resolvers that may be reached while traffic is blocked")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::App::new("set")
+                .about(
+                    "Allow DNS to resolvers on the local network, such as the router, while not \
+                     connected. The system must already be configured to use them",
+                )
+                .arg(
+                    clap::Arg::new("servers")
+                        .multiple_occurrences(true)
+                        .help("One or more IP addresses on the local network")
+                        .required(true),
+                ),
+        )
+        .subcommand(clap::App::new("clear").about("Block all DNS while not connected"))
+}
+
+#[cfg(target_os = "linux")]
+fn create_log_subcommand() -> clap::App<'static> {
+    clap::App::new("log")
+        .about("Inspect DNS queries made while connected")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::App::new("enable")
+                .about("Keep a log of recent queries in memory. It is never written to disk"),
+        )
+        .subcommand(clap::App::new("disable").about("Stop logging queries and clear the log"))
+        .subcommand(
+            clap::App::new("show")
+                .about("Display recently logged queries")
+                .arg(
+                    clap::Arg::new("follow")
+                        .long("follow")
+                        .short('f')
+                        .help("Keep printing new queries as they are made"),
+                ),
+        )
+}
+
 impl Dns {
     async fn set_default(
         &self,
@@ -106,6 +348,7 @@ impl Dns {
         block_malware: bool,
         block_adult_content: bool,
         block_gambling: bool,
+        encryption: types::default_dns_options::Encryption,
     ) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
         let settings = rpc.get_settings(()).await?.into_inner();
@@ -117,6 +360,7 @@ impl Dns {
                 block_malware,
                 block_adult_content,
                 block_gambling,
+                encryption: encryption as i32,
             }),
             ..settings.tunnel_options.unwrap().dns_options.unwrap()
         })
@@ -136,6 +380,24 @@ impl Dns {
                     .into_iter()
                     .map(|a| a.to_string())
                     .collect(),
+                encrypted_servers: vec![],
+            }),
+            ..settings.tunnel_options.unwrap().dns_options.unwrap()
+        })
+        .await?;
+        println!("Updated DNS settings");
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn set_encrypted(&self, server: EncryptedDnsServer) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.get_settings(()).await?.into_inner();
+        rpc.set_dns_options(types::DnsOptions {
+            state: types::dns_options::DnsState::Custom as i32,
+            custom_options: Some(types::CustomDnsOptions {
+                addresses: vec![],
+                encrypted_servers: vec![types::EncryptedDnsServer::from(&server)],
             }),
             ..settings.tunnel_options.unwrap().dns_options.unwrap()
         })
@@ -144,6 +406,241 @@ impl Dns {
         Ok(())
     }
 
+    #[cfg(target_os = "linux")]
+    async fn handle_split_cmd(&self, matches: &clap::ArgMatches) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut options = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .tunnel_options
+            .unwrap()
+            .dns_options
+            .unwrap();
+
+        match matches.subcommand() {
+            Some(("list", _)) => {
+                for rule in &options.split_dns {
+                    println!("{} -> {}", rule.domain, rule.servers.join(", "));
+                }
+                return Ok(());
+            }
+            Some(("add", matches)) => {
+                let domain = matches.value_of("domain").unwrap().to_owned();
+                let servers = matches
+                    .values_of_t::<IpAddr>("servers")
+                    .unwrap_or_else(|e| e.exit());
+                options.split_dns.retain(|rule| rule.domain != domain);
+                options.split_dns.push(types::SplitDnsRule {
+                    domain,
+                    servers: servers.iter().map(|server| server.to_string()).collect(),
+                });
+            }
+            Some(("remove", matches)) => {
+                let domain = matches.value_of("domain").unwrap();
+                let rule_count = options.split_dns.len();
+                options.split_dns.retain(|rule| rule.domain != domain);
+                if options.split_dns.len() == rule_count {
+                    return Err(Error::InvalidCommand("No split DNS rule for the domain"));
+                }
+            }
+            Some(("clear", _)) => options.split_dns.clear(),
+            _ => unreachable!("No split DNS command given"),
+        }
+
+        rpc.set_dns_options(options).await?;
+        println!("Updated DNS settings");
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn handle_blocklist_cmd(&self, matches: &clap::ArgMatches) -> Result<()> {
+        self.update_local_rules(|rules| {
+            match matches.subcommand() {
+                Some(("list", _)) => {
+                    for domain in &rules.blocked_domains {
+                        println!("{}", domain);
+                    }
+                    return Ok(false);
+                }
+                Some(("add", matches)) => {
+                    for domain in matches.values_of("domains").unwrap() {
+                        if !rules
+                            .blocked_domains
+                            .iter()
+                            .any(|blocked| blocked == domain)
+                        {
+                            rules.blocked_domains.push(domain.to_owned());
+                        }
+                    }
+                }
+                Some(("remove", matches)) => {
+                    let domains: Vec<_> = matches.values_of("domains").unwrap().collect();
+                    rules
+                        .blocked_domains
+                        .retain(|blocked| !domains.contains(&blocked.as_str()));
+                }
+                Some(("import", matches)) => {
+                    let path = matches.value_of("file").unwrap();
+                    let contents = std::fs::read_to_string(path).map_err(|error| {
+                        eprintln!("Failed to read {}: {}", path, error);
+                        Error::CommandFailed("Failed to read the hosts file")
+                    })?;
+                    let added = rules.import_hosts_file(&contents);
+                    println!("Imported {} entries", added);
+                }
+                Some(("clear", _)) => rules.blocked_domains.clear(),
+                _ => unreachable!("No blocklist command given"),
+            }
+            Ok(true)
+        })
+        .await
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn handle_override_cmd(&self, matches: &clap::ArgMatches) -> Result<()> {
+        self.update_local_rules(|rules| {
+            match matches.subcommand() {
+                Some(("list", _)) => {
+                    for entry in &rules.overrides {
+                        println!("{}", entry);
+                    }
+                    return Ok(false);
+                }
+                Some(("add", matches)) => {
+                    let entry = DnsOverride {
+                        name: matches.value_of("name").unwrap().to_owned(),
+                        address: matches.value_of_t("address").unwrap_or_else(|e| e.exit()),
+                    };
+                    if !rules.overrides.contains(&entry) {
+                        rules.overrides.push(entry);
+                    }
+                }
+                Some(("remove", matches)) => {
+                    let name = matches.value_of("name").unwrap();
+                    rules.overrides.retain(|entry| entry.name != name);
+                }
+                Some(("clear", _)) => rules.overrides.clear(),
+                _ => unreachable!("No override command given"),
+            }
+            Ok(true)
+        })
+        .await
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn set_blocked_state_servers(&self, servers: Vec<IpAddr>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut options = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .tunnel_options
+            .unwrap()
+            .dns_options
+            .unwrap();
+        options.blocked_state_servers = servers.iter().map(|server| server.to_string()).collect();
+        rpc.set_dns_options(options).await?;
+        println!("Updated DNS settings");
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn set_query_log(&self, enabled: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut options = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .tunnel_options
+            .unwrap()
+            .dns_options
+            .unwrap();
+        options.query_log = enabled;
+        rpc.set_dns_options(options).await?;
+        println!("Updated DNS settings");
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn show_query_log(&self, follow: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut entries = rpc
+            .dns_query_log(types::DnsQueryLogRequest { follow })
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to read the DNS query log", error))?
+            .into_inner();
+
+        while let Some(entry) = entries.message().await? {
+            match DnsQueryLogEntry::try_from(entry) {
+                Ok(entry) => {
+                    let time = chrono::DateTime::<chrono::Local>::from(entry.time);
+                    println!("{} {}", time.format("%H:%M:%S%.3f"), entry);
+                }
+                Err(error) => eprintln!("Invalid DNS query log entry: {}", error),
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn test_servers(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let results = rpc.test_dns_servers(()).await?.into_inner().results;
+        if results.is_empty() {
+            println!("No custom DNS servers are set");
+            return Ok(());
+        }
+
+        let mut failed = false;
+        for result in results {
+            match DnsServerTestResult::try_from(result) {
+                Ok(result) => {
+                    failed |= result.outcome.is_failure();
+                    println!("{}", result);
+                }
+                Err(error) => eprintln!("Invalid DNS server test result: {}", error),
+            }
+        }
+
+        if failed {
+            Err(Error::CommandFailed("Some DNS servers can't be used"))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Applies `update` to the local DNS rules, and saves them if it returns `true`.
+    #[cfg(target_os = "linux")]
+    async fn update_local_rules(
+        &self,
+        update: impl FnOnce(&mut LocalDnsRules) -> Result<bool>,
+    ) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut options = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .tunnel_options
+            .unwrap()
+            .dns_options
+            .unwrap();
+        let mut rules = options
+            .local_rules
+            .take()
+            .map(|rules| LocalDnsRules::try_from(rules).unwrap())
+            .unwrap_or_default();
+
+        if !update(&mut rules)? {
+            return Ok(());
+        }
+
+        options.local_rules = Some(types::LocalDnsRules::from(&rules));
+        rpc.set_dns_options(options).await?;
+        println!("Updated DNS settings");
+        Ok(())
+    }
+
     async fn get(&self) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
         let options: DnsOptions = rpc
@@ -168,12 +665,50 @@ impl Dns {
                     options.default_options.block_adult_content
                 );
                 println!("Block gambling: {}", options.default_options.block_gambling);
+                #[cfg(target_os = "linux")]
+                match options.default_options.encryption {
+                    Some(EncryptedDnsProtocol::Tls) => println!("Encryption: DNS-over-TLS"),
+                    Some(EncryptedDnsProtocol::Https) => println!("Encryption: DNS-over-HTTPS"),
+                    None => println!("Encryption: off"),
+                }
             }
             DnsState::Custom => {
                 println!("Custom DNS: yes\nServers:");
                 for server in &options.custom_options.addresses {
                     println!("{}", server);
                 }
+                #[cfg(target_os = "linux")]
+                for server in &options.custom_options.encrypted_servers {
+                    println!("{}", server);
+                }
+            }
+        }
+
+        #[cfg(target_os = "linux")]
+        if !options.split_dns.is_empty() {
+            println!("Split DNS:");
+            for rule in &options.split_dns {
+                println!("{}", rule);
+            }
+        }
+        #[cfg(target_os = "linux")]
+        if !options.local_rules.is_empty() {
+            println!(
+                "Local rules: {} blocked domain(s), {} override(s)",
+                options.local_rules.blocked_domains.len(),
+                options.local_rules.overrides.len()
+            );
+        }
+        #[cfg(target_os = "linux")]
+        println!(
+            "Query log: {}",
+            if options.query_log { "on" } else { "off" }
+        );
+        #[cfg(target_os = "linux")]
+        if !options.blocked_state_servers.is_empty() {
+            println!("Servers allowed while blocked:");
+            for server in &options.blocked_state_servers {
+                println!("{}", server);
             }
         }
 
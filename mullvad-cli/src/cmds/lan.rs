@@ -1,4 +1,6 @@
 use crate::{new_rpc_client, Command, Result};
+#[cfg(target_os = "linux")]
+use mullvad_management_interface::types::{self, forwarded_interface, ForwardedInterface};
 
 pub struct Lan;
 
@@ -9,7 +11,7 @@ impl Command for Lan {
     }
 
     fn clap_subcommand(&self) -> clap::App<'static> {
-        clap::App::new(self.name())
+        let app = clap::App::new(self.name())
             .about("Control the allow local network sharing setting")
             .setting(clap::AppSettings::SubcommandRequiredElseHelp)
             .subcommand(
@@ -21,7 +23,10 @@ impl Command for Lan {
             )
             .subcommand(
                 clap::App::new("get").about("Display the current local network sharing setting"),
-            )
+            );
+        #[cfg(target_os = "linux")]
+        let app = app.subcommand(create_forward_subcommand());
+        app
     }
 
     async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
@@ -30,6 +35,8 @@ impl Command for Lan {
             self.set(allow_lan == "allow").await
         } else if let Some(_matches) = matches.subcommand_matches("get") {
             self.get().await
+        } else if let Some(forward_matches) = matches.subcommand_matches("forward") {
+            self.handle_forward_cmd(forward_matches).await
         } else {
             unreachable!("No lan command given");
         }
@@ -53,4 +60,80 @@ impl Lan {
         );
         Ok(())
     }
+
+    #[cfg(target_os = "linux")]
+    async fn handle_forward_cmd(&self, matches: &clap::ArgMatches) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut interfaces = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .forwarded_interfaces;
+
+        match matches.subcommand() {
+            Some(("list", _)) => {
+                for forwarded in &interfaces {
+                    let mode = match forwarded_interface::Mode::from_i32(forwarded.mode) {
+                        Some(forwarded_interface::Mode::Tunnel) => "tunnel",
+                        Some(forwarded_interface::Mode::Local) => "local",
+                        None => "unknown",
+                    };
+                    println!("{} (via {})", forwarded.interface, mode);
+                }
+                return Ok(());
+            }
+            Some(("add", add_matches)) => {
+                let interface = add_matches.value_of("interface").unwrap().to_owned();
+                let mode = match add_matches.value_of("via").unwrap() {
+                    "local" => forwarded_interface::Mode::Local,
+                    _ => forwarded_interface::Mode::Tunnel,
+                };
+                interfaces.retain(|forwarded| forwarded.interface != interface);
+                interfaces.push(ForwardedInterface {
+                    interface,
+                    mode: i32::from(mode),
+                });
+            }
+            Some(("remove", remove_matches)) => {
+                let interface = remove_matches.value_of("interface").unwrap();
+                interfaces.retain(|forwarded| forwarded.interface != interface);
+            }
+            _ => unreachable!("unhandled command"),
+        }
+
+        rpc.set_forwarded_interfaces(types::ForwardedInterfaces { interfaces })
+            .await?;
+        println!("Updated forwarded interfaces");
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn handle_forward_cmd(&self, _matches: &clap::ArgMatches) -> Result<()> {
+        unreachable!("forwarding is only supported on Linux");
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn create_forward_subcommand() -> clap::App<'static> {
+    clap::App::new("forward")
+        .about("Allow traffic forwarded from bridge interfaces, such as docker0 or virbr0")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::App::new("list").about("List forwarded bridge interfaces"))
+        .subcommand(
+            clap::App::new("add")
+                .about("Allow traffic forwarded from a bridge interface")
+                .arg(clap::Arg::new("interface").required(true))
+                .arg(
+                    clap::Arg::new("via")
+                        .long("via")
+                        .help("Send forwarded traffic through the tunnel or only to local networks")
+                        .default_value("tunnel")
+                        .possible_values(&["tunnel", "local"]),
+                ),
+        )
+        .subcommand(
+            clap::App::new("remove")
+                .about("Stop allowing traffic forwarded from a bridge interface")
+                .arg(clap::Arg::new("interface").required(true)),
+        )
 }
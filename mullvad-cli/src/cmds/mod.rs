@@ -19,6 +19,9 @@ pub use self::bridge::Bridge;
 mod connect;
 pub use self::connect::Connect;
 
+mod debug;
+pub use self::debug::Debug;
+
 mod disconnect;
 pub use self::disconnect::Disconnect;
 
@@ -63,6 +66,7 @@ pub fn get_commands() -> HashMap<&'static str, Box<dyn Command>> {
         Box::new(BlockWhenDisconnected),
         Box::new(Bridge),
         Box::new(Connect),
+        Box::new(Debug),
         Box::new(Disconnect),
         Box::new(Dns),
         Box::new(Reconnect),
@@ -46,6 +46,7 @@ impl Obfuscation {
                     "auto" => SelectedObfuscation::Auto,
                     "off" => SelectedObfuscation::Off,
                     "udp2tcp" => SelectedObfuscation::Udp2Tcp,
+                    "shadowsocks" => SelectedObfuscation::Shadowsocks,
                     _ => unreachable!("Unhandled obfuscator mode"),
                 };
                 Self::set_obfuscation_settings(&mut rpc, &settings).await?;
@@ -63,6 +64,19 @@ impl Obfuscation {
                 };
                 Self::set_obfuscation_settings(&mut rpc, &settings).await?;
             }
+            Some(("shadowsocks", settings_matches)) => {
+                let port: String = settings_matches.value_of_t_or_exit("port");
+                let mut rpc = new_rpc_client().await?;
+                let mut settings = Self::get_obfuscation_settings(&mut rpc).await?;
+                settings.shadowsocks.port = if port == "any" {
+                    mullvad_types::relay_constraints::Constraint::Any
+                } else {
+                    mullvad_types::relay_constraints::Constraint::Only(
+                        port.parse::<u16>().expect("Invalid port number"),
+                    )
+                };
+                Self::set_obfuscation_settings(&mut rpc, &settings).await?;
+            }
             _ => unreachable!("unhandled command"),
         }
         Ok(())
@@ -76,6 +90,7 @@ impl Obfuscation {
             obfuscation_settings.selected_obfuscation
         );
         println!("udp2tcp settings: {}", obfuscation_settings.udp2tcp);
+        println!("shadowsocks settings: {}", obfuscation_settings.shadowsocks);
         Ok(())
     }
 
@@ -116,7 +131,7 @@ fn create_obfuscation_set_subcommand() -> clap::App<'static> {
                     )
                     .required(true)
                     .index(1)
-                    .possible_values(&["auto", "off", "udp2tcp"]),
+                    .possible_values(&["auto", "off", "udp2tcp", "shadowsocks"]),
             ),
         )
         .subcommand(
@@ -130,6 +145,17 @@ fn create_obfuscation_set_subcommand() -> clap::App<'static> {
                         .takes_value(true),
                 ),
         )
+        .subcommand(
+            clap::App::new("shadowsocks")
+                .about("Specifies the config for the Shadowsocks obfuscator")
+                .setting(clap::AppSettings::ArgRequiredElseHelp)
+                .arg(
+                    clap::Arg::new("port")
+                        .help("UDP port of remote endpoint. Either 'any' or a specific port")
+                        .long("port")
+                        .takes_value(true),
+                ),
+        )
 }
 
 fn create_obfuscation_get_subcommand() -> clap::App<'static> {
@@ -37,27 +37,27 @@ impl Command for Relay {
                                 .arg(
                                     clap::Arg::new("host")
                                         .help("Hostname or IP")
-                                        .required(true),
+                                        .required_unless_present("config"),
                                 )
                                 .arg(
                                     clap::Arg::new("port")
                                         .help("Remote network port")
-                                        .required(true),
+                                        .required_unless_present("config"),
                                 )
                                 .arg(
                                     clap::Arg::new("peer-pubkey")
                                         .help("Base64 encoded peer public key")
-                                        .required(true),
+                                        .required_unless_present("config"),
                                 )
                                 .arg(
                                     clap::Arg::new("v4-gateway")
                                         .help("IPv4 gateway address")
-                                        .required(true),
+                                        .required_unless_present("config"),
                                 )
                                 .arg(
                                     clap::Arg::new("addr")
                                         .help("Local address of wireguard tunnel")
-                                        .required(true)
+                                        .required_unless_present("config")
                                         .multiple_values(true),
                                 )
                                 .arg(
@@ -66,6 +66,22 @@ impl Command for Relay {
                                         .long("v6-gateway")
                                         .takes_value(true),
                                 )
+                                .arg(
+                                    clap::Arg::new("config")
+                                        .help("Read the relay from a wg-quick configuration file. \
+                                            The first IPv4 DNS server is used as the gateway, \
+                                            and MTU is ignored")
+                                        .long("config")
+                                        .takes_value(true)
+                                        .conflicts_with_all(&[
+                                            "host",
+                                            "port",
+                                            "peer-pubkey",
+                                            "v4-gateway",
+                                            "addr",
+                                            "v6-gateway",
+                                        ]),
+                                )
                             )
                             .subcommand(clap::App::new("openvpn")
                                 .arg(
@@ -255,6 +271,12 @@ impl Relay {
     }
 
     async fn set_custom(&self, matches: &clap::ArgMatches) -> Result<()> {
+        if let Some(("wireguard", wg_matches)) = matches.subcommand() {
+            if let Some(path) = wg_matches.value_of("config") {
+                return Self::import_wireguard_config(path).await;
+            }
+        }
+
         let custom_endpoint = match matches.subcommand() {
             Some(("openvpn", openvpn_matches)) => Self::read_custom_openvpn_relay(openvpn_matches),
             Some(("wireguard", wg_matches)) => Self::read_custom_wireguard_relay(wg_matches),
@@ -267,6 +289,20 @@ impl Relay {
         .await
     }
 
+    async fn import_wireguard_config(path: &str) -> Result<()> {
+        let config = std::fs::read_to_string(path).map_err(|error| {
+            eprintln!("Failed to read {}: {}", path, error);
+            Error::CommandFailed("Failed to read the WireGuard config")
+        })?;
+
+        let mut rpc = new_rpc_client().await?;
+        rpc.import_wireguard_config(config)
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to import WireGuard config", error))?;
+        println!("Relay constraints updated");
+        Ok(())
+    }
+
     fn read_custom_openvpn_relay(matches: &clap::ArgMatches) -> types::CustomRelaySettings {
         let host = matches.value_of_t_or_exit("host");
         let port = matches.value_of_t_or_exit("port");
@@ -336,7 +372,9 @@ impl Relay {
                                 .collect(),
                             endpoint: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port)
                                 .to_string(),
+                            psk: vec![],
                         }),
+                        additional_peers: vec![],
                         ipv4_gateway: ipv4_gateway.to_string(),
                         ipv6_gateway: ipv6_gateway
                             .as_ref()
@@ -1,5 +1,7 @@
 use crate::{new_rpc_client, Command, Error, Result};
 use mullvad_management_interface::types::{self, Timestamp, TunnelOptions};
+#[cfg(target_os = "linux")]
+use mullvad_management_interface::types::{ipv6_firewall_mode, wireguard_backend};
 use mullvad_types::wireguard::DEFAULT_ROTATION_INTERVAL;
 use std::{convert::TryFrom, time::Duration};
 
@@ -33,20 +35,28 @@ impl Command for Tunnel {
 }
 
 fn create_wireguard_subcommand() -> clap::App<'static> {
-    let subcmd = clap::App::new("wireguard")
+    #[allow(unused_mut)]
+    let mut subcmd = clap::App::new("wireguard")
         .about("Manage options for Wireguard tunnels")
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(create_wireguard_mtu_subcommand())
+        .subcommand(create_wireguard_keepalive_subcommand())
+        .subcommand(create_wireguard_connectivity_subcommand())
+        .subcommand(create_wireguard_probe_address_subcommand())
         .subcommand(create_wireguard_quantum_resistant_tunnel_subcommand())
-        .subcommand(create_wireguard_keys_subcommand());
-    #[cfg(windows)]
+        .subcommand(create_wireguard_keys_subcommand())
+        .subcommand(create_wireguard_export_subcommand());
+    #[cfg(target_os = "linux")]
     {
-        subcmd.subcommand(create_wireguard_use_wg_nt_subcommand())
+        subcmd = subcmd
+            .subcommand(create_wireguard_mtu_discovery_subcommand())
+            .subcommand(create_wireguard_backend_subcommand());
     }
-    #[cfg(not(windows))]
+    #[cfg(windows)]
     {
-        subcmd
+        subcmd = subcmd.subcommand(create_wireguard_use_wg_nt_subcommand());
     }
+    subcmd
 }
 
 fn create_wireguard_mtu_subcommand() -> clap::App<'static> {
@@ -58,6 +68,112 @@ fn create_wireguard_mtu_subcommand() -> clap::App<'static> {
         .subcommand(clap::App::new("set").arg(clap::Arg::new("mtu").required(true)))
 }
 
+#[cfg(target_os = "linux")]
+fn create_wireguard_mtu_discovery_subcommand() -> clap::App<'static> {
+    clap::App::new("mtu-discovery")
+        .about(
+            "Probe the largest packet size that passes through the tunnel after connecting, and \
+             lower the MTU to it",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::App::new("get"))
+        .subcommand(
+            clap::App::new("set").arg(
+                clap::Arg::new("policy")
+                    .required(true)
+                    .takes_value(true)
+                    .possible_values(&["on", "off"]),
+            ),
+        )
+}
+
+#[cfg(target_os = "linux")]
+fn create_wireguard_backend_subcommand() -> clap::App<'static> {
+    clap::App::new("backend")
+        .about(
+            "Select the WireGuard implementation. 'auto' uses NetworkManager if it manages DNS, \
+             and the kernel module otherwise. The userspace implementation is used if the \
+             selected backend fails",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::App::new("get"))
+        .subcommand(
+            clap::App::new("set").arg(
+                clap::Arg::new("backend")
+                    .required(true)
+                    .takes_value(true)
+                    .possible_values(&["auto", "kernel", "network-manager", "userspace"]),
+            ),
+        )
+}
+
+fn create_wireguard_keepalive_subcommand() -> clap::App<'static> {
+    clap::App::new("keepalive")
+        .about("Configure persistent keepalive, which sends packets to the relay at an interval")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::App::new("get"))
+        .subcommand(clap::App::new("unset"))
+        .subcommand(
+            clap::App::new("set").arg(
+                clap::Arg::new("interval")
+                    .help("Interval in seconds")
+                    .required(true),
+            ),
+        )
+}
+
+fn create_wireguard_connectivity_subcommand() -> clap::App<'static> {
+    let timeout_arg = |name: &'static str, help: &'static str| {
+        clap::Arg::new(name)
+            .long(name)
+            .help(help)
+            .takes_value(true)
+            .required_if_eq("profile", "custom")
+    };
+    clap::App::new("connectivity")
+        .about(
+            "Configure how long the tunnel may go without receiving traffic before it is \
+             considered broken and reconnected. 'tolerant' suits links with high latency or long \
+             dropouts, such as satellite links",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::App::new("get"))
+        .subcommand(
+            clap::App::new("set")
+                .arg(
+                    clap::Arg::new("profile")
+                        .required(true)
+                        .takes_value(true)
+                        .possible_values(&["aggressive", "default", "tolerant", "custom"]),
+                )
+                .arg(timeout_arg(
+                    "rx-timeout",
+                    "Seconds to wait for a response to outgoing traffic before pinging",
+                ))
+                .arg(timeout_arg(
+                    "ping-timeout",
+                    "Seconds to keep pinging without a response before reconnecting",
+                ))
+                .arg(timeout_arg(
+                    "establish-timeout",
+                    "Seconds to wait for traffic when connecting. Doubled after each failed \
+                     attempt, up to the ping timeout",
+                )),
+        )
+}
+
+fn create_wireguard_probe_address_subcommand() -> clap::App<'static> {
+    clap::App::new("probe-address")
+        .about(
+            "Configure the IPv4 address inside the tunnel that is pinged to check connectivity. \
+             The tunnel gateway is used if unset",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::App::new("get"))
+        .subcommand(clap::App::new("unset"))
+        .subcommand(clap::App::new("set").arg(clap::Arg::new("address").required(true)))
+}
+
 fn create_wireguard_quantum_resistant_tunnel_subcommand() -> clap::App<'static> {
     clap::App::new("quantum-resistant-tunnel")
         .about("EXPERIMENTAL: Enables quantum-resistant PSK exchange in the tunnel")
@@ -75,6 +191,19 @@ fn create_wireguard_keys_subcommand() -> clap::App<'static> {
         .subcommand(create_wireguard_keys_rotation_interval_subcommand())
 }
 
+fn create_wireguard_export_subcommand() -> clap::App<'static> {
+    clap::App::new("export")
+        .about(
+            "Print the active WireGuard connection as a wg-quick config. Multihop connections are \
+             printed as an entry config followed by an exit config",
+        )
+        .arg(
+            clap::Arg::new("include-private-key")
+                .long("include-private-key")
+                .help("Include the private key of this device, which makes the config usable"),
+        )
+}
+
 #[cfg(windows)]
 fn create_wireguard_use_wg_nt_subcommand() -> clap::App<'static> {
     clap::App::new("use-wireguard-nt")
@@ -117,7 +246,7 @@ fn create_openvpn_mssfix_subcommand() -> clap::App<'static> {
 }
 
 fn create_ipv6_subcommand() -> clap::App<'static> {
-    clap::App::new("ipv6")
+    let subcmd = clap::App::new("ipv6")
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(clap::App::new("get"))
         .subcommand(
@@ -127,6 +256,35 @@ fn create_ipv6_subcommand() -> clap::App<'static> {
                     .takes_value(true)
                     .possible_values(&["on", "off"]),
             ),
+        );
+    #[cfg(target_os = "linux")]
+    {
+        subcmd.subcommand(create_ipv6_firewall_subcommand())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        subcmd
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn create_ipv6_firewall_subcommand() -> clap::App<'static> {
+    clap::App::new("firewall")
+        .about("Configure which IPv6 traffic is allowed outside the tunnel")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::App::new("get"))
+        .subcommand(
+            clap::App::new("set").arg(
+                clap::Arg::new("mode")
+                    .help(
+                        "default: allow NDP, DHCPv6 and, if LAN sharing is on, IPv6 LAN. \
+                         block-outside-tunnel: block all IPv6 except NDP outside the tunnel. \
+                         tunnel-only: allow NDP and DHCPv6 but never IPv6 LAN",
+                    )
+                    .required(true)
+                    .takes_value(true)
+                    .possible_values(&["default", "block-outside-tunnel", "tunnel-only"]),
+            ),
         )
 }
 
@@ -158,6 +316,40 @@ impl Tunnel {
                 _ => unreachable!("unhandled command"),
             },
 
+            #[cfg(target_os = "linux")]
+            Some(("mtu-discovery", matches)) => match matches.subcommand() {
+                Some(("get", _)) => Self::process_wireguard_mtu_discovery_get().await,
+                Some(("set", matches)) => Self::process_wireguard_mtu_discovery_set(matches).await,
+                _ => unreachable!("unhandled command"),
+            },
+
+            #[cfg(target_os = "linux")]
+            Some(("backend", matches)) => match matches.subcommand() {
+                Some(("get", _)) => Self::process_wireguard_backend_get().await,
+                Some(("set", matches)) => Self::process_wireguard_backend_set(matches).await,
+                _ => unreachable!("unhandled command"),
+            },
+
+            Some(("keepalive", matches)) => match matches.subcommand() {
+                Some(("get", _)) => Self::process_wireguard_keepalive_get().await,
+                Some(("set", matches)) => Self::process_wireguard_keepalive_set(matches).await,
+                Some(("unset", _)) => Self::process_wireguard_keepalive_unset().await,
+                _ => unreachable!("unhandled command"),
+            },
+
+            Some(("connectivity", matches)) => match matches.subcommand() {
+                Some(("get", _)) => Self::process_wireguard_connectivity_get().await,
+                Some(("set", matches)) => Self::process_wireguard_connectivity_set(matches).await,
+                _ => unreachable!("unhandled command"),
+            },
+
+            Some(("probe-address", matches)) => match matches.subcommand() {
+                Some(("get", _)) => Self::process_wireguard_probe_address_get().await,
+                Some(("set", matches)) => Self::process_wireguard_probe_address_set(matches).await,
+                Some(("unset", _)) => Self::process_wireguard_probe_address_unset().await,
+                _ => unreachable!("unhandled command"),
+            },
+
             Some(("key", matches)) => match matches.subcommand() {
                 Some(("check", _)) => Self::process_wireguard_key_check().await,
                 Some(("regenerate", _)) => Self::process_wireguard_key_generate().await,
@@ -172,6 +364,8 @@ impl Tunnel {
                 _ => unreachable!("unhandled command"),
             },
 
+            Some(("export", matches)) => Self::process_wireguard_export(matches).await,
+
             Some(("quantum-resistant-tunnel", matches)) => match matches.subcommand() {
                 Some(("get", _)) => Self::process_wireguard_quantum_resistant_tunnel_get().await,
                 Some(("set", matches)) => {
@@ -220,6 +414,193 @@ impl Tunnel {
         Ok(())
     }
 
+    #[cfg(target_os = "linux")]
+    async fn process_wireguard_mtu_discovery_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options().await?;
+        if tunnel_options.wireguard.unwrap().mtu_discovery {
+            println!("enabled");
+        } else {
+            println!("disabled");
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn process_wireguard_mtu_discovery_set(matches: &clap::ArgMatches) -> Result<()> {
+        let enabled = matches.value_of("policy").unwrap() == "on";
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_mtu_discovery(enabled).await?;
+        println!("Updated MTU discovery setting");
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn process_wireguard_backend_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options().await?;
+        println!(
+            "{}",
+            crate::format::wireguard_backend_name(tunnel_options.wireguard.unwrap().backend)
+        );
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn process_wireguard_backend_set(matches: &clap::ArgMatches) -> Result<()> {
+        let backend = match matches.value_of("backend").unwrap() {
+            "auto" => wireguard_backend::Backend::Auto,
+            "kernel" => wireguard_backend::Backend::Kernel,
+            "network-manager" => wireguard_backend::Backend::NetworkManager,
+            "userspace" => wireguard_backend::Backend::Userspace,
+            _ => unreachable!("unhandled backend"),
+        };
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_backend(types::WireguardBackend {
+            backend: i32::from(backend),
+        })
+        .await?;
+        println!("Updated WireGuard backend setting");
+        Ok(())
+    }
+
+    async fn process_wireguard_keepalive_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options().await?;
+        let interval = tunnel_options.wireguard.unwrap().persistent_keepalive;
+        println!(
+            "persistent keepalive: {}",
+            if interval != 0 {
+                format!("{} seconds", interval)
+            } else {
+                "unset".to_string()
+            },
+        );
+        Ok(())
+    }
+
+    async fn process_wireguard_keepalive_set(matches: &clap::ArgMatches) -> Result<()> {
+        let interval = matches.value_of_t_or_exit::<u16>("interval");
+        if interval == 0 {
+            return Err(Error::InvalidCommand(
+                "The interval must be at least 1 second. Use unset to disable keepalive",
+            ));
+        }
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_persistent_keepalive(u32::from(interval))
+            .await?;
+        println!("Wireguard persistent keepalive has been updated");
+        Ok(())
+    }
+
+    async fn process_wireguard_keepalive_unset() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_persistent_keepalive(0).await?;
+        println!("Wireguard persistent keepalive has been unset");
+        Ok(())
+    }
+
+    async fn process_wireguard_connectivity_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options().await?;
+        let profile = tunnel_options
+            .wireguard
+            .unwrap()
+            .connectivity_profile
+            .unwrap_or_default();
+        let preset = match types::connectivity_profile::Preset::from_i32(profile.preset) {
+            Some(types::connectivity_profile::Preset::Default) => "default",
+            Some(types::connectivity_profile::Preset::Aggressive) => "aggressive",
+            Some(types::connectivity_profile::Preset::Tolerant) => "tolerant",
+            Some(types::connectivity_profile::Preset::Custom) => "custom",
+            None => "unknown",
+        };
+        let seconds = |timeout: Option<types::Duration>| {
+            timeout
+                .and_then(|timeout| Duration::try_from(timeout).ok())
+                .unwrap_or_default()
+                .as_secs()
+        };
+        println!("Connectivity profile: {}", preset);
+        println!("Rx timeout: {} seconds", seconds(profile.rx_timeout));
+        println!("Ping timeout: {} seconds", seconds(profile.ping_timeout));
+        println!(
+            "Establish timeout: {} seconds",
+            seconds(profile.establish_timeout)
+        );
+        Ok(())
+    }
+
+    async fn process_wireguard_connectivity_set(matches: &clap::ArgMatches) -> Result<()> {
+        let preset = match matches.value_of("profile").unwrap() {
+            "aggressive" => types::connectivity_profile::Preset::Aggressive,
+            "default" => types::connectivity_profile::Preset::Default,
+            "tolerant" => types::connectivity_profile::Preset::Tolerant,
+            "custom" => types::connectivity_profile::Preset::Custom,
+            _ => unreachable!("unhandled profile"),
+        };
+        let mut profile = types::ConnectivityProfile {
+            preset: i32::from(preset),
+            ..Default::default()
+        };
+        if preset == types::connectivity_profile::Preset::Custom {
+            let timeout = |name: &str| -> Result<Option<types::Duration>> {
+                let seconds = matches.value_of_t_or_exit::<u64>(name);
+                if seconds == 0 {
+                    return Err(Error::InvalidCommand(
+                        "Connectivity timeouts must be at least 1 second",
+                    ));
+                }
+                Ok(Some(types::Duration::from(Duration::from_secs(seconds))))
+            };
+            profile.rx_timeout = timeout("rx-timeout")?;
+            profile.ping_timeout = timeout("ping-timeout")?;
+            profile.establish_timeout = timeout("establish-timeout")?;
+        }
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_connectivity_profile(profile).await?;
+        println!("Updated WireGuard connectivity profile");
+        Ok(())
+    }
+
+    async fn process_wireguard_probe_address_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options().await?;
+        let address = tunnel_options.wireguard.unwrap().probe_address;
+        println!(
+            "Probe address: {}",
+            if !address.is_empty() {
+                address
+            } else {
+                "unset (tunnel gateway)".to_string()
+            },
+        );
+        Ok(())
+    }
+
+    async fn process_wireguard_probe_address_set(matches: &clap::ArgMatches) -> Result<()> {
+        let address = matches.value_of_t_or_exit::<std::net::Ipv4Addr>("address");
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_probe_address(address.to_string()).await?;
+        println!("Wireguard probe address has been updated");
+        Ok(())
+    }
+
+    async fn process_wireguard_probe_address_unset() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_probe_address(String::new()).await?;
+        println!("Wireguard probe address has been unset");
+        Ok(())
+    }
+
+    async fn process_wireguard_export(matches: &clap::ArgMatches) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let export = rpc
+            .export_wireguard_config(types::WireguardConfigExportRequest {
+                include_private_key: matches.is_present("include-private-key"),
+            })
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to export WireGuard config", error))?
+            .into_inner();
+        print!("{}", export.configs.join("\n"));
+        Ok(())
+    }
+
     async fn process_wireguard_quantum_resistant_tunnel_get() -> Result<()> {
         let tunnel_options = Self::get_tunnel_options().await?;
         if tunnel_options.wireguard.unwrap().use_pq_safe_psk {
@@ -351,12 +732,12 @@ impl Tunnel {
     }
 
     async fn handle_ipv6_cmd(matches: &clap::ArgMatches) -> Result<()> {
-        if matches.subcommand_matches("get").is_some() {
-            Self::process_ipv6_get().await
-        } else if let Some(m) = matches.subcommand_matches("set") {
-            Self::process_ipv6_set(m).await
-        } else {
-            unreachable!("unhandled command");
+        match matches.subcommand() {
+            Some(("get", _)) => Self::process_ipv6_get().await,
+            Some(("set", m)) => Self::process_ipv6_set(m).await,
+            #[cfg(target_os = "linux")]
+            Some(("firewall", m)) => Self::handle_ipv6_firewall_cmd(m).await,
+            _ => unreachable!("unhandled command"),
         }
     }
 
@@ -425,6 +806,54 @@ impl Tunnel {
         Ok(())
     }
 
+    #[cfg(target_os = "linux")]
+    async fn handle_ipv6_firewall_cmd(matches: &clap::ArgMatches) -> Result<()> {
+        if matches.subcommand_matches("get").is_some() {
+            Self::process_ipv6_firewall_get().await
+        } else if let Some(m) = matches.subcommand_matches("set") {
+            Self::process_ipv6_firewall_set(m).await
+        } else {
+            unreachable!("unhandled command");
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn process_ipv6_firewall_get() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mode = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .ipv6_firewall_mode
+            .map(|mode| mode.mode)
+            .unwrap_or_default();
+        let mode = match ipv6_firewall_mode::Mode::from_i32(mode) {
+            Some(ipv6_firewall_mode::Mode::Default) => "default",
+            Some(ipv6_firewall_mode::Mode::BlockOutsideTunnel) => "block-outside-tunnel",
+            Some(ipv6_firewall_mode::Mode::TunnelOnly) => "tunnel-only",
+            None => "unknown",
+        };
+        println!("IPv6 firewall mode: {}", mode);
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn process_ipv6_firewall_set(matches: &clap::ArgMatches) -> Result<()> {
+        let mode = match matches.value_of("mode").unwrap() {
+            "default" => ipv6_firewall_mode::Mode::Default,
+            "block-outside-tunnel" => ipv6_firewall_mode::Mode::BlockOutsideTunnel,
+            "tunnel-only" => ipv6_firewall_mode::Mode::TunnelOnly,
+            _ => unreachable!("invalid IPv6 firewall mode"),
+        };
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_ipv6_firewall_mode(types::Ipv6FirewallMode {
+            mode: i32::from(mode),
+        })
+        .await?;
+        println!("Updated IPv6 firewall mode");
+        Ok(())
+    }
+
     fn format_key_timestamp(timestamp: &Timestamp) -> String {
         let ndt = chrono::NaiveDateTime::from_timestamp(timestamp.seconds, timestamp.nanos as u32);
         let utc = chrono::DateTime::<chrono::Utc>::from_utc(ndt, chrono::Utc);
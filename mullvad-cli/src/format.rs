@@ -5,8 +5,8 @@ use mullvad_management_interface::types::{
     },
     tunnel_state,
     tunnel_state::State::*,
-    ErrorState, ObfuscationType, ProxyType, TransportProtocol, TunnelState, TunnelStateRelayInfo,
-    TunnelType,
+    wireguard_backend, wireguard_backend_info, ErrorState, ObfuscationType, ProxyType,
+    TransportProtocol, TunnelState, TunnelStateRelayInfo, TunnelType, WireguardBackendInfo,
 };
 use mullvad_types::auth_failed::AuthFailed;
 
@@ -109,6 +109,22 @@ fn format_relay_connection(relay_info: &TunnelStateRelayInfo, verbose: bool) ->
     } else {
         String::new()
     };
+    let mtu = if verbose && endpoint.mtu != 0 {
+        format!("\nDiscovered MTU: {}", endpoint.mtu)
+    } else {
+        String::new()
+    };
+    let wireguard_backend = match endpoint.wireguard_backend.as_ref() {
+        Some(info) if verbose => {
+            format!("\nWireGuard backend: {}", format_wireguard_backend(info))
+        }
+        _ => String::new(),
+    };
+    let nat64 = if verbose && endpoint.nat64 {
+        "\nReached through NAT64: yes"
+    } else {
+        ""
+    };
     let quantum_resistant = if !verbose {
         ""
     } else if endpoint.quantum_resistant {
@@ -134,16 +150,41 @@ fn format_relay_connection(relay_info: &TunnelStateRelayInfo, verbose: bool) ->
     }
 
     format!(
-        "{exit_endpoint}{first_hop}{bridge}{obfuscator}{tunnel_type}{quantum_resistant}{bridge_type}{obfuscator_type}",
+        "{exit_endpoint}{first_hop}{bridge}{obfuscator}{tunnel_type}{wireguard_backend}{mtu}{nat64}{quantum_resistant}{bridge_type}{obfuscator_type}",
         first_hop = first_hop.unwrap_or_default(),
         bridge = bridge.unwrap_or_default(),
         obfuscator = obfuscator.unwrap_or_default(),
     )
 }
 
+fn format_wireguard_backend(info: &WireguardBackendInfo) -> String {
+    let backend = wireguard_backend_name(info.backend);
+    match wireguard_backend_info::Reason::from_i32(info.reason).expect("invalid backend reason") {
+        wireguard_backend_info::Reason::Selected => format!("{backend} (selected in settings)"),
+        wireguard_backend_info::Reason::Forced => {
+            format!("{backend} (forced by environment variable)")
+        }
+        wireguard_backend_info::Reason::Automatic => format!("{backend} (selected automatically)"),
+        wireguard_backend_info::Reason::Fallback => format!(
+            "{backend} (fallback because {} failed)",
+            wireguard_backend_name(info.failed_backend)
+        ),
+    }
+}
+
+pub fn wireguard_backend_name(backend: i32) -> &'static str {
+    match wireguard_backend::Backend::from_i32(backend).expect("invalid WireGuard backend") {
+        wireguard_backend::Backend::Auto => "auto",
+        wireguard_backend::Backend::Kernel => "kernel",
+        wireguard_backend::Backend::NetworkManager => "network-manager",
+        wireguard_backend::Backend::Userspace => "userspace",
+    }
+}
+
 fn convert_obfuscator_type(obfuscator: i32) -> &'static str {
     match ObfuscationType::from_i32(obfuscator).expect("invalid obfuscator type") {
         ObfuscationType::Udp2tcp => "Udp2Tcp",
+        ObfuscationType::Shadowsocks => "Shadowsocks",
     }
 }
 
@@ -1,5 +1,14 @@
 use mullvad_types::settings::{DnsOptions, DnsState};
+#[cfg(target_os = "linux")]
+use std::net::Ipv6Addr;
 use std::net::{IpAddr, Ipv4Addr};
+#[cfg(target_os = "linux")]
+use talpid_core::{firewall, resolver};
+#[cfg(target_os = "linux")]
+use talpid_types::{
+    net::dns::{DnsServerTestOutcome, DnsServerTestResult, EncryptedDnsServer},
+    ErrorExt,
+};
 
 /// When we want to block certain contents with the help of DNS server side,
 /// we compute the resolver IP to use based on these constants. The last
@@ -11,6 +20,15 @@ const DNS_MALWARE_BLOCKING_IP_BIT: u8 = 1 << 2; // 0b00000100
 const DNS_ADULT_BLOCKING_IP_BIT: u8 = 1 << 3; // 0b00001000
 const DNS_GAMBLING_BLOCKING_IP_BIT: u8 = 1 << 4; // 0b00010000
 
+/// Name that the certificate of the resolver on the relay is valid for.
+#[cfg(target_os = "linux")]
+const RELAY_DNS_HOSTNAME: &str = "dns.mullvad.net";
+/// Addresses of the resolver on the relay. These are private, but reached through the tunnel.
+#[cfg(target_os = "linux")]
+const RELAY_GATEWAY_IPV4: Ipv4Addr = Ipv4Addr::new(10, 64, 0, 1);
+#[cfg(target_os = "linux")]
+const RELAY_GATEWAY_IPV6: Ipv6Addr = Ipv6Addr::new(0xfc00, 0xbbbb, 0xbbbb, 0xbb01, 0, 0, 0, 1);
+
 /// Return the resolvers as a vector of `IpAddr`s. Returns `None` when no special resolvers
 /// are requested and the tunnel default gateway should be used.
 pub fn addresses_from_options(options: &DnsOptions) -> Option<Vec<IpAddr>> {
@@ -53,3 +71,157 @@ pub fn addresses_from_options(options: &DnsOptions) -> Option<Vec<IpAddr>> {
         }
     }
 }
+
+/// Return the encrypted resolvers to use instead of the regular ones. With default DNS, this is
+/// the resolver on the relay if encryption is enabled for it.
+#[cfg(target_os = "linux")]
+pub fn encrypted_servers_from_options(options: &DnsOptions) -> Vec<EncryptedDnsServer> {
+    match options.state {
+        DnsState::Default => match options.default_options.encryption {
+            Some(protocol) => vec![EncryptedDnsServer {
+                protocol,
+                hostname: RELAY_DNS_HOSTNAME.to_owned(),
+                port: protocol.default_port(),
+                bootstrap_addresses: addresses_from_options(options)
+                    .unwrap_or_else(|| vec![RELAY_GATEWAY_IPV4.into(), RELAY_GATEWAY_IPV6.into()]),
+                spki_pin: None,
+            }],
+            None => vec![],
+        },
+        DnsState::Custom => options.custom_options.encrypted_servers.clone(),
+    }
+}
+
+/// Return whether plain DNS may be used while the encrypted resolvers can't be reached. This is
+/// only allowed for the resolver on the relay, since the plain path to it also goes through the
+/// tunnel.
+#[cfg(target_os = "linux")]
+pub fn encrypted_fallback_from_options(options: &DnsOptions) -> bool {
+    options.state == DnsState::Default && options.default_options.encryption.is_some()
+}
+
+/// Reasons that traffic to a custom DNS server can't be routed.
+#[cfg(target_os = "linux")]
+#[derive(err_derive::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnroutableServer {
+    #[error(display = "The unspecified address can't be used")]
+    Unspecified,
+
+    #[error(display = "Multicast and broadcast addresses can't be used")]
+    Multicast,
+
+    #[error(display = "The server is on the local network, but local network sharing is disabled")]
+    LocalNetwork,
+}
+
+/// Checks that traffic to a custom DNS server can be routed. Servers on the local network are
+/// only reachable when `allow_lan` is set, while loopback servers and the resolver on the relay
+/// always are.
+#[cfg(target_os = "linux")]
+pub fn check_routable(server: &IpAddr, allow_lan: bool) -> Result<(), UnroutableServer> {
+    if server.is_unspecified() {
+        return Err(UnroutableServer::Unspecified);
+    }
+    let is_broadcast = match server {
+        IpAddr::V4(address) => address.is_broadcast(),
+        IpAddr::V6(_) => false,
+    };
+    if server.is_multicast() || is_broadcast {
+        return Err(UnroutableServer::Multicast);
+    }
+    let is_relay_gateway =
+        *server == IpAddr::V4(RELAY_GATEWAY_IPV4) || *server == IpAddr::V6(RELAY_GATEWAY_IPV6);
+    if !allow_lan
+        && !server.is_loopback()
+        && !is_relay_gateway
+        && firewall::is_local_address(server)
+    {
+        return Err(UnroutableServer::LocalNetwork);
+    }
+    Ok(())
+}
+
+/// Checks each of `servers`, and sends a test query to the routable ones if `probe` is set.
+#[cfg(target_os = "linux")]
+pub async fn test_servers(
+    servers: Vec<IpAddr>,
+    allow_lan: bool,
+    probe: bool,
+) -> Vec<DnsServerTestResult> {
+    let tests = servers.into_iter().map(|address| async move {
+        let outcome = match check_routable(&address, allow_lan) {
+            Err(error) => DnsServerTestOutcome::Unroutable(error.to_string()),
+            Ok(()) if !probe => DnsServerTestOutcome::Routable,
+            Ok(()) => match resolver::probe_server(address).await {
+                Ok(latency) => DnsServerTestOutcome::Responded(latency),
+                Err(error) => DnsServerTestOutcome::NoResponse(error.display_chain()),
+            },
+        };
+        DnsServerTestResult { address, outcome }
+    });
+    futures::future::join_all(tests).await
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+    use talpid_types::net::dns::EncryptedDnsProtocol;
+
+    #[test]
+    fn test_relay_encrypted_servers() {
+        let mut options = DnsOptions::default();
+        assert!(encrypted_servers_from_options(&options).is_empty());
+        assert!(!encrypted_fallback_from_options(&options));
+
+        options.default_options.encryption = Some(EncryptedDnsProtocol::Tls);
+        let servers = encrypted_servers_from_options(&options);
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].port, 853);
+        assert_eq!(
+            servers[0].bootstrap_addresses,
+            vec![
+                IpAddr::from(RELAY_GATEWAY_IPV4),
+                IpAddr::from(RELAY_GATEWAY_IPV6)
+            ]
+        );
+        assert!(encrypted_fallback_from_options(&options));
+
+        options.default_options.block_ads = true;
+        assert_eq!(
+            encrypted_servers_from_options(&options)[0].bootstrap_addresses,
+            vec![IpAddr::from([100, 64, 0, 1])]
+        );
+
+        options.state = DnsState::Custom;
+        assert!(encrypted_servers_from_options(&options).is_empty());
+        assert!(!encrypted_fallback_from_options(&options));
+    }
+
+    #[test]
+    fn test_check_routable() {
+        let public = IpAddr::from([1, 1, 1, 1]);
+        let lan = IpAddr::from([192, 168, 1, 1]);
+        let loopback = IpAddr::from(Ipv4Addr::LOCALHOST);
+
+        assert_eq!(check_routable(&public, false), Ok(()));
+        assert_eq!(check_routable(&loopback, false), Ok(()));
+        assert_eq!(check_routable(&RELAY_GATEWAY_IPV4.into(), false), Ok(()));
+        assert_eq!(check_routable(&lan, true), Ok(()));
+        assert_eq!(
+            check_routable(&lan, false),
+            Err(UnroutableServer::LocalNetwork)
+        );
+        assert_eq!(
+            check_routable(&Ipv6Addr::UNSPECIFIED.into(), true),
+            Err(UnroutableServer::Unspecified)
+        );
+        assert_eq!(
+            check_routable(&Ipv4Addr::BROADCAST.into(), true),
+            Err(UnroutableServer::Multicast)
+        );
+        assert_eq!(
+            check_routable(&IpAddr::from([224, 0, 0, 251]), true),
+            Err(UnroutableServer::Multicast)
+        );
+    }
+}
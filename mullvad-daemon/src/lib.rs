@@ -42,7 +42,9 @@ use mullvad_types::{
     account::{AccountData, AccountToken, VoucherSubmission},
     device::{Device, DeviceEvent, DeviceEventCause, DeviceId, DeviceState, RemoveDeviceEvent},
     location::GeoIpLocation,
-    relay_constraints::{BridgeSettings, BridgeState, ObfuscationSettings, RelaySettingsUpdate},
+    relay_constraints::{
+        BridgeSettings, BridgeState, ObfuscationSettings, RelaySettings, RelaySettingsUpdate,
+    },
     relay_list::RelayList,
     settings::{DnsOptions, Settings},
     states::{TargetState, TunnelState},
@@ -57,21 +59,40 @@ use std::{collections::HashSet, ffi::OsString};
 use std::{
     marker::PhantomData,
     mem,
+    net::{IpAddr, Ipv4Addr},
     path::PathBuf,
     pin::Pin,
     sync::{Arc, Weak},
     time::Duration,
 };
+#[cfg(target_os = "linux")]
+use talpid_core::leak_test;
 #[cfg(any(target_os = "linux", windows))]
 use talpid_core::split_tunnel;
+#[cfg(target_os = "linux")]
+use talpid_core::tunnel_state_machine::NetworkChangeListeners;
+#[cfg(target_os = "linux")]
+use talpid_core::{
+    dns::DriftListeners,
+    firewall::drop_log,
+    resolver::{FallbackListeners, QueryLog},
+};
 use talpid_core::{
     mpsc::Sender,
     tunnel_state_machine::{self, TunnelCommand, TunnelStateMachineHandle},
 };
 #[cfg(target_os = "android")]
 use talpid_types::android::AndroidContext;
+#[cfg(target_os = "linux")]
+use talpid_types::net::leak_test::LeakProbeResult;
+#[cfg(target_os = "linux")]
+use talpid_types::net::{
+    dns::{DnsConfigDrift, DnsQueryLogEntry, DnsServerTestResult, EncryptedDnsFallback},
+    drop_log::DroppedPackets,
+    ForwardedInterface, Ipv6FirewallMode, NetworkChange,
+};
 use talpid_types::{
-    net::{TunnelEndpoint, TunnelType},
+    net::{wireguard::PrivateKey, TunnelEndpoint, TunnelType},
     tunnel::{ErrorStateCause, TunnelStateTransition},
     ErrorExt,
 };
@@ -79,8 +100,9 @@ use talpid_types::{
 use tokio::fs;
 use tokio::io;
 
-/// Delay between generating a new WireGuard key and reconnecting
-const WG_RECONNECT_DELAY: Duration = Duration::from_secs(4 * 60);
+/// Delay between generating a new WireGuard key and using it, so that it can propagate to the
+/// relays
+const WG_KEY_PROPAGATION_DELAY: Duration = Duration::from_secs(4 * 60);
 
 pub type ResponseTx<T, E> = oneshot::Sender<Result<T, E>>;
 
@@ -149,6 +171,10 @@ pub enum Error {
     #[error(display = "Account history error")]
     AccountHistory(#[error(source)] account_history::Error),
 
+    #[cfg(target_os = "linux")]
+    #[error(display = "Custom DNS server {} can't be used", _0)]
+    UnroutableDnsServer(IpAddr, #[error(source)] dns::UnroutableServer),
+
     #[cfg(not(target_os = "android"))]
     #[error(display = "Factory reset partially failed: {}", _0)]
     FactoryResetError(&'static str),
@@ -206,6 +232,15 @@ pub enum DaemonCommand {
     UpdateRelaySettings(ResponseTx<(), settings::Error>, RelaySettingsUpdate),
     /// Set the allow LAN setting.
     SetAllowLan(ResponseTx<(), settings::Error>, bool),
+    /// Set bridge interfaces whose forwarded traffic should be allowed by the firewall
+    #[cfg(target_os = "linux")]
+    SetForwardedInterfaces(ResponseTx<(), settings::Error>, Vec<ForwardedInterface>),
+    /// Set which IPv6 traffic the firewall should allow outside the tunnel
+    #[cfg(target_os = "linux")]
+    SetIpv6FirewallMode(ResponseTx<(), settings::Error>, Ipv6FirewallMode),
+    /// Set whether the firewall should log rejected packets
+    #[cfg(target_os = "linux")]
+    SetLogFirewallDrops(ResponseTx<(), settings::Error>, bool),
     /// Set the beta program setting.
     SetShowBetaReleases(ResponseTx<(), settings::Error>, bool),
     /// Set the block_when_disconnected setting.
@@ -223,10 +258,31 @@ pub enum DaemonCommand {
     /// Set whether to enable PQ PSK exchange in the tunnel
     SetQuantumResistantTunnel(ResponseTx<(), settings::Error>, bool),
     /// Set DNS options or servers to use
-    SetDnsOptions(ResponseTx<(), settings::Error>, DnsOptions),
+    SetDnsOptions(ResponseTx<(), Error>, DnsOptions),
+    /// Check that the custom DNS servers can be reached, and query them if connected
+    #[cfg(target_os = "linux")]
+    TestDnsServers(oneshot::Sender<Vec<DnsServerTestResult>>),
     /// Toggle macOS network check leak
     /// Set MTU for wireguard tunnels
     SetWireguardMtu(ResponseTx<(), settings::Error>, Option<u16>),
+    /// Set the persistent keepalive interval for wireguard tunnels
+    SetWireguardPersistentKeepalive(ResponseTx<(), settings::Error>, Option<u16>),
+    /// Enable or disable path MTU discovery for wireguard tunnels
+    #[cfg(target_os = "linux")]
+    SetWireguardMtuDiscovery(ResponseTx<(), settings::Error>, bool),
+    /// Set the WireGuard implementation to use. `None` picks one automatically
+    #[cfg(target_os = "linux")]
+    SetWireguardBackend(
+        ResponseTx<(), settings::Error>,
+        Option<talpid_types::net::wireguard::Backend>,
+    ),
+    /// Set how quickly wireguard tunnels are considered broken when no traffic is received
+    SetWireguardConnectivityProfile(
+        ResponseTx<(), settings::Error>,
+        talpid_types::net::wireguard::ConnectivityProfile,
+    ),
+    /// Set the in-tunnel address pinged to check connectivity. `None` uses the tunnel gateway
+    SetWireguardProbeAddress(ResponseTx<(), settings::Error>, Option<Ipv4Addr>),
     /// Set automatic key rotation interval for wireguard tunnels
     SetWireguardRotationInterval(ResponseTx<(), settings::Error>, Option<RotationInterval>),
     /// Get the daemon settings
@@ -235,6 +291,9 @@ pub enum DaemonCommand {
     RotateWireguardKey(ResponseTx<(), Error>),
     /// Return a public key of the currently set wireguard private key, if there is one
     GetWireguardKey(ResponseTx<Option<PublicKey>, Error>),
+    /// Return the active WireGuard connection as wg-quick configs, optionally including the
+    /// private key. Returns `None` unless connected over WireGuard.
+    ExportWireguardConfig(oneshot::Sender<Option<Vec<String>>>, bool),
     /// Get information about the currently running and latest app versions
     GetVersionInfo(oneshot::Sender<Option<AppVersionInfo>>),
     /// Return whether the daemon is performing post-upgrade tasks
@@ -279,6 +338,34 @@ pub enum DaemonCommand {
     CheckVolumes(ResponseTx<(), Error>),
     /// Register settings for WireGuard obfuscator
     SetObfuscationSettings(ResponseTx<(), settings::Error>, ObfuscationSettings),
+    /// Send probes outside the tunnel and report which of them were blocked by the firewall
+    #[cfg(target_os = "linux")]
+    RunLeakTest(oneshot::Sender<Vec<LeakProbeResult>>),
+    /// Subscribe to reports of packets rejected by the firewall. Responds with `None` if drop
+    /// logging is disabled.
+    #[cfg(target_os = "linux")]
+    SubscribeFirewallDrops(oneshot::Sender<Option<mpsc::UnboundedReceiver<Vec<DroppedPackets>>>>),
+    /// Get the recorded DNS queries and subscribe to new ones. Responds with `None` if the query
+    /// log is disabled.
+    #[cfg(target_os = "linux")]
+    SubscribeDnsQueryLog(
+        oneshot::Sender<
+            Option<(
+                Vec<DnsQueryLogEntry>,
+                mpsc::UnboundedReceiver<DnsQueryLogEntry>,
+            )>,
+        >,
+    ),
+    /// Subscribe to notifications about DNS config that was changed by other programs and
+    /// re-applied.
+    #[cfg(target_os = "linux")]
+    SubscribeDnsDrift(oneshot::Sender<mpsc::UnboundedReceiver<DnsConfigDrift>>),
+    /// Subscribe to notifications about encrypted DNS falling back to plain DNS.
+    #[cfg(target_os = "linux")]
+    SubscribeEncryptedDnsFallback(oneshot::Sender<mpsc::UnboundedReceiver<EncryptedDnsFallback>>),
+    /// Subscribe to notifications about the tunnel being kept up across a change of network.
+    #[cfg(target_os = "linux")]
+    SubscribeNetworkChanges(oneshot::Sender<mpsc::UnboundedReceiver<NetworkChange>>),
     /// Makes the daemon exit the main loop and quit.
     Shutdown,
     /// Saves the target tunnel state and enters a blocking state. The state is restored
@@ -302,6 +389,8 @@ pub(crate) enum InternalDaemonEvent {
     DeviceEvent(PrivateDeviceEvent),
     /// Handles updates from versions without devices.
     DeviceMigrationEvent(Result<PrivateAccountAndDevice, device::Error>),
+    /// A rotated WireGuard key has had time to propagate to the relays.
+    WireguardKeyPropagated(PrivateKey),
     /// The split tunnel paths or state were updated.
     #[cfg(target_os = "windows")]
     ExcludedPathsEvent(ExcludedPathsUpdate, oneshot::Sender<Result<(), Error>>),
@@ -517,6 +606,16 @@ pub struct Daemon<L: EventListener> {
     state: DaemonExecutionState,
     #[cfg(target_os = "linux")]
     exclude_pids: split_tunnel::PidManager,
+    #[cfg(target_os = "linux")]
+    drop_monitor: Option<drop_log::DropMonitor>,
+    #[cfg(target_os = "linux")]
+    dns_query_log: QueryLog,
+    #[cfg(target_os = "linux")]
+    dns_drift_listeners: DriftListeners,
+    #[cfg(target_os = "linux")]
+    dns_fallback_listeners: FallbackListeners,
+    #[cfg(target_os = "linux")]
+    network_change_listeners: NetworkChangeListeners,
     rx: mpsc::UnboundedReceiver<InternalDaemonEvent>,
     tx: DaemonEventSender,
     reconnection_job: Option<AbortHandle>,
@@ -653,6 +752,16 @@ where
             settings.tunnel_options.clone(),
         );
         let (offline_state_tx, offline_state_rx) = mpsc::unbounded();
+        #[cfg(target_os = "linux")]
+        let dns_query_log = QueryLog::new();
+        #[cfg(target_os = "linux")]
+        dns_query_log.set_enabled(settings.tunnel_options.dns_options.query_log);
+        #[cfg(target_os = "linux")]
+        let dns_drift_listeners = DriftListeners::new();
+        #[cfg(target_os = "linux")]
+        let dns_fallback_listeners = FallbackListeners::new();
+        #[cfg(target_os = "linux")]
+        let network_change_listeners = NetworkChangeListeners::new();
         #[cfg(target_os = "windows")]
         let (volume_update_tx, volume_update_rx) = mpsc::unbounded();
         let tunnel_state_machine_handle = tunnel_state_machine::spawn(
@@ -662,6 +771,38 @@ where
                 dns_servers: dns::addresses_from_options(&settings.tunnel_options.dns_options),
                 allowed_endpoint: initial_api_endpoint,
                 reset_firewall: *target_state != TargetState::Secured,
+                #[cfg(target_os = "linux")]
+                blocked_state_dns_servers: settings
+                    .tunnel_options
+                    .dns_options
+                    .blocked_state_servers
+                    .clone(),
+                #[cfg(target_os = "linux")]
+                forwarded_interfaces: settings.forwarded_interfaces.clone(),
+                #[cfg(target_os = "linux")]
+                ipv6_firewall_mode: settings.ipv6_firewall_mode,
+                #[cfg(target_os = "linux")]
+                log_firewall_drops: settings.log_firewall_drops,
+                #[cfg(target_os = "linux")]
+                split_dns_rules: settings.tunnel_options.dns_options.split_dns.clone(),
+                #[cfg(target_os = "linux")]
+                encrypted_dns_servers: dns::encrypted_servers_from_options(
+                    &settings.tunnel_options.dns_options,
+                ),
+                #[cfg(target_os = "linux")]
+                encrypted_dns_fallback: dns::encrypted_fallback_from_options(
+                    &settings.tunnel_options.dns_options,
+                ),
+                #[cfg(target_os = "linux")]
+                local_dns_rules: settings.tunnel_options.dns_options.local_rules.clone(),
+                #[cfg(target_os = "linux")]
+                dns_query_log: dns_query_log.clone(),
+                #[cfg(target_os = "linux")]
+                dns_drift_listeners: dns_drift_listeners.clone(),
+                #[cfg(target_os = "linux")]
+                dns_fallback_listeners: dns_fallback_listeners.clone(),
+                #[cfg(target_os = "linux")]
+                network_change_listeners: network_change_listeners.clone(),
                 #[cfg(windows)]
                 exclude_paths,
             },
@@ -711,12 +852,29 @@ where
         // Attempt to download a fresh relay list
         relay_list_updater.update().await;
 
+        #[cfg(target_os = "linux")]
+        let drop_monitor = if settings.log_firewall_drops || drop_log::is_forced() {
+            Self::start_drop_monitor()
+        } else {
+            None
+        };
+
         let daemon = Daemon {
             tunnel_state: TunnelState::Disconnected,
             target_state,
             state: DaemonExecutionState::Running,
             #[cfg(target_os = "linux")]
             exclude_pids: split_tunnel::PidManager::new().map_err(Error::InitSplitTunneling)?,
+            #[cfg(target_os = "linux")]
+            drop_monitor,
+            #[cfg(target_os = "linux")]
+            dns_query_log,
+            #[cfg(target_os = "linux")]
+            dns_drift_listeners,
+            #[cfg(target_os = "linux")]
+            dns_fallback_listeners,
+            #[cfg(target_os = "linux")]
+            network_change_listeners,
             rx: internal_event_rx,
             tx: internal_event_tx,
             reconnection_job: None,
@@ -826,6 +984,9 @@ where
             }
             DeviceEvent(event) => self.handle_device_event(event).await,
             DeviceMigrationEvent(event) => self.handle_device_migration_event(event).await,
+            WireguardKeyPropagated(private_key) => {
+                self.handle_wireguard_key_propagated(private_key).await
+            }
             #[cfg(windows)]
             ExcludedPathsEvent(update, tx) => self.handle_new_excluded_paths(update, tx).await,
         }
@@ -930,6 +1091,21 @@ where
         self.reconnection_job = Some(abort_handle);
     }
 
+    /// Applies `private_key` to the tunnel after `delay`, without reconnecting. A scheduled
+    /// reconnect replaces the update, since new tunnels use the current key anyway.
+    fn schedule_wireguard_key_update(&mut self, delay: Duration, private_key: PrivateKey) {
+        self.unschedule_reconnect();
+
+        let daemon_tx = self.tx.clone();
+        let (future, abort_handle) = abortable(Box::pin(async move {
+            tokio::time::sleep(delay).await;
+            let _ = daemon_tx.send(InternalDaemonEvent::WireguardKeyPropagated(private_key));
+        }));
+
+        tokio::spawn(future);
+        self.reconnection_job = Some(abort_handle);
+    }
+
     fn unschedule_reconnect(&mut self) {
         if let Some(job) = self.reconnection_job.take() {
             job.abort();
@@ -970,6 +1146,14 @@ where
             ClearAccountHistory(tx) => self.on_clear_account_history(tx).await,
             UpdateRelaySettings(tx, update) => self.on_update_relay_settings(tx, update).await,
             SetAllowLan(tx, allow_lan) => self.on_set_allow_lan(tx, allow_lan).await,
+            #[cfg(target_os = "linux")]
+            SetForwardedInterfaces(tx, interfaces) => {
+                self.on_set_forwarded_interfaces(tx, interfaces).await
+            }
+            #[cfg(target_os = "linux")]
+            SetIpv6FirewallMode(tx, mode) => self.on_set_ipv6_firewall_mode(tx, mode).await,
+            #[cfg(target_os = "linux")]
+            SetLogFirewallDrops(tx, enabled) => self.on_set_log_firewall_drops(tx, enabled).await,
             SetShowBetaReleases(tx, enabled) => self.on_set_show_beta_releases(tx, enabled).await,
             SetBlockWhenDisconnected(tx, block_when_disconnected) => {
                 self.on_set_block_when_disconnected(tx, block_when_disconnected)
@@ -986,13 +1170,36 @@ where
                 self.on_set_quantum_resistant_tunnel(tx, enable_pq).await
             }
             SetDnsOptions(tx, dns_servers) => self.on_set_dns_options(tx, dns_servers).await,
+            #[cfg(target_os = "linux")]
+            TestDnsServers(tx) => self.on_test_dns_servers(tx),
             SetWireguardMtu(tx, mtu) => self.on_set_wireguard_mtu(tx, mtu).await,
+            SetWireguardPersistentKeepalive(tx, interval) => {
+                self.on_set_wireguard_persistent_keepalive(tx, interval)
+                    .await
+            }
+            #[cfg(target_os = "linux")]
+            SetWireguardMtuDiscovery(tx, enabled) => {
+                self.on_set_wireguard_mtu_discovery(tx, enabled).await
+            }
+            #[cfg(target_os = "linux")]
+            SetWireguardBackend(tx, backend) => self.on_set_wireguard_backend(tx, backend).await,
+            SetWireguardConnectivityProfile(tx, profile) => {
+                self.on_set_wireguard_connectivity_profile(tx, profile)
+                    .await
+            }
+            SetWireguardProbeAddress(tx, address) => {
+                self.on_set_wireguard_probe_address(tx, address).await
+            }
             SetWireguardRotationInterval(tx, interval) => {
                 self.on_set_wireguard_rotation_interval(tx, interval).await
             }
             GetSettings(tx) => self.on_get_settings(tx),
             RotateWireguardKey(tx) => self.on_rotate_wireguard_key(tx).await,
             GetWireguardKey(tx) => self.on_get_wireguard_key(tx).await,
+            ExportWireguardConfig(tx, include_private_key) => {
+                self.on_export_wireguard_config(tx, include_private_key)
+                    .await
+            }
             GetVersionInfo(tx) => self.on_get_version_info(tx).await,
             IsPerformingPostUpgrade(tx) => self.on_is_performing_post_upgrade(tx).await,
             GetCurrentVersion(tx) => self.on_get_current_version(tx),
@@ -1023,6 +1230,27 @@ where
             SetObfuscationSettings(tx, settings) => {
                 self.on_set_obfuscation_settings(tx, settings).await
             }
+            #[cfg(target_os = "linux")]
+            RunLeakTest(tx) => self.on_run_leak_test(tx).await,
+            #[cfg(target_os = "linux")]
+            SubscribeFirewallDrops(tx) => self.on_subscribe_firewall_drops(tx),
+            #[cfg(target_os = "linux")]
+            SubscribeDnsQueryLog(tx) => self.on_subscribe_dns_query_log(tx),
+            #[cfg(target_os = "linux")]
+            SubscribeDnsDrift(tx) => {
+                let receiver = self.dns_drift_listeners.subscribe();
+                Self::oneshot_send(tx, receiver, "subscribe_dns_drift response");
+            }
+            #[cfg(target_os = "linux")]
+            SubscribeEncryptedDnsFallback(tx) => {
+                let receiver = self.dns_fallback_listeners.subscribe();
+                Self::oneshot_send(tx, receiver, "subscribe_encrypted_dns_fallback response");
+            }
+            #[cfg(target_os = "linux")]
+            SubscribeNetworkChanges(tx) => {
+                let receiver = self.network_change_listeners.subscribe();
+                Self::oneshot_send(tx, receiver, "subscribe_network_changes response");
+            }
             Shutdown => self.trigger_shutdown_event(),
             PrepareRestart => self.on_prepare_restart(),
             #[cfg(target_os = "android")]
@@ -1060,9 +1288,12 @@ where
                     self.connect_tunnel();
                 }
             }
-            PrivateDeviceEvent::RotatedKey(_) => {
+            PrivateDeviceEvent::RotatedKey(device) => {
                 if self.get_target_tunnel_type() == Some(TunnelType::Wireguard) {
-                    self.schedule_reconnect(WG_RECONNECT_DELAY);
+                    self.schedule_wireguard_key_update(
+                        WG_KEY_PROPAGATION_DELAY,
+                        device.device.wg_data.private_key.clone(),
+                    );
                 }
             }
             _ => (),
@@ -1071,6 +1302,21 @@ where
             .notify_device_event(DeviceEvent::from(event));
     }
 
+    async fn handle_wireguard_key_propagated(&mut self, private_key: PrivateKey) {
+        self.reconnection_job = None;
+        if !matches!(self.settings.get_relay_settings(), RelaySettings::Normal(_)) {
+            // Custom tunnels do not use the device key.
+            return;
+        }
+        if self.get_target_tunnel_type() == Some(TunnelType::Wireguard) {
+            log::debug!("Applying rotated WireGuard key to the tunnel");
+            self.parameters_generator
+                .set_last_wireguard_private_key(private_key.clone())
+                .await;
+            self.send_tunnel_command(TunnelCommand::RotateWireguardKey(private_key));
+        }
+    }
+
     async fn handle_device_migration_event(
         &mut self,
         result: Result<PrivateAccountAndDevice, device::Error>,
@@ -1471,6 +1717,45 @@ where
         );
     }
 
+    #[cfg(target_os = "linux")]
+    async fn on_run_leak_test(&mut self, tx: oneshot::Sender<Vec<LeakProbeResult>>) {
+        let allowed_endpoint =
+            api::get_allowed_endpoint(self.api_runtime.address_cache.get_address().await);
+        tokio::spawn(async move {
+            let results = leak_test::run(leak_test::default_probes(), Some(allowed_endpoint)).await;
+            for result in results.iter().filter(|result| result.is_unexpected_leak()) {
+                log::warn!("Leak test: traffic escaped the tunnel: {}", result.probe);
+            }
+            Self::oneshot_send(tx, results, "run_leak_test response");
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_subscribe_firewall_drops(
+        &mut self,
+        tx: oneshot::Sender<Option<mpsc::UnboundedReceiver<Vec<DroppedPackets>>>>,
+    ) {
+        let receiver = self
+            .drop_monitor
+            .as_ref()
+            .map(|monitor| monitor.subscribe());
+        Self::oneshot_send(tx, receiver, "subscribe_firewall_drops response");
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_subscribe_dns_query_log(
+        &mut self,
+        tx: oneshot::Sender<
+            Option<(
+                Vec<DnsQueryLogEntry>,
+                mpsc::UnboundedReceiver<DnsQueryLogEntry>,
+            )>,
+        >,
+    ) {
+        let subscription = self.dns_query_log.subscribe();
+        Self::oneshot_send(tx, subscription, "subscribe_dns_query_log response");
+    }
+
     #[cfg(not(target_os = "android"))]
     async fn on_factory_reset(&mut self, tx: ResponseTx<(), Error>) {
         let mut last_error = Ok(());
@@ -1772,6 +2057,97 @@ where
         }
     }
 
+    #[cfg(target_os = "linux")]
+    async fn on_set_forwarded_interfaces(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        interfaces: Vec<ForwardedInterface>,
+    ) {
+        let save_result = self
+            .settings
+            .set_forwarded_interfaces(interfaces.clone())
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_forwarded_interfaces response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::ForwardedInterfaces(interfaces));
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_forwarded_interfaces response");
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn on_set_ipv6_firewall_mode(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        mode: Ipv6FirewallMode,
+    ) {
+        let save_result = self.settings.set_ipv6_firewall_mode(mode).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_ipv6_firewall_mode response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::Ipv6FirewallMode(mode));
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_ipv6_firewall_mode response");
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn on_set_log_firewall_drops(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        enabled: bool,
+    ) {
+        let save_result = self.settings.set_log_firewall_drops(enabled).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_log_firewall_drops response");
+                if settings_changed {
+                    if enabled {
+                        if self.drop_monitor.is_none() {
+                            self.drop_monitor = Self::start_drop_monitor();
+                        }
+                    } else if !drop_log::is_forced() {
+                        self.drop_monitor = None;
+                    }
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::LogFirewallDrops(enabled));
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_log_firewall_drops response");
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn start_drop_monitor() -> Option<drop_log::DropMonitor> {
+        drop_log::DropMonitor::start()
+            .map_err(|error| {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to start firewall drop monitor")
+                );
+            })
+            .ok()
+    }
+
     async fn on_set_show_beta_releases(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
@@ -2010,11 +2386,23 @@ where
         }
     }
 
-    async fn on_set_dns_options(
-        &mut self,
-        tx: ResponseTx<(), settings::Error>,
-        dns_options: DnsOptions,
-    ) {
+    async fn on_set_dns_options(&mut self, tx: ResponseTx<(), Error>, dns_options: DnsOptions) {
+        #[cfg(target_os = "linux")]
+        {
+            // Servers on the local network are only a problem while they are in use
+            let allow_lan = self.settings.allow_lan
+                || dns_options.state != mullvad_types::settings::DnsState::Custom;
+            for server in &dns_options.custom_options.addresses {
+                if let Err(error) = dns::check_routable(server, allow_lan) {
+                    Self::oneshot_send(
+                        tx,
+                        Err(Error::UnroutableDnsServer(*server, error)),
+                        "set_dns_options response",
+                    );
+                    return;
+                }
+            }
+        }
         let save_result = self.settings.set_dns_options(dns_options.clone()).await;
         match save_result {
             Ok(settings_changed) => {
@@ -2026,17 +2414,63 @@ where
                     self.parameters_generator
                         .set_tunnel_options(&settings.tunnel_options)
                         .await;
+                    #[cfg(target_os = "linux")]
+                    let split_dns_rules = settings.tunnel_options.dns_options.split_dns.clone();
+                    #[cfg(target_os = "linux")]
+                    let encrypted_dns_servers =
+                        dns::encrypted_servers_from_options(&settings.tunnel_options.dns_options);
+                    #[cfg(target_os = "linux")]
+                    let encrypted_dns_fallback =
+                        dns::encrypted_fallback_from_options(&settings.tunnel_options.dns_options);
+                    #[cfg(target_os = "linux")]
+                    let local_dns_rules = settings.tunnel_options.dns_options.local_rules.clone();
+                    #[cfg(target_os = "linux")]
+                    let query_log = settings.tunnel_options.dns_options.query_log;
+                    #[cfg(target_os = "linux")]
+                    let blocked_state_servers = settings
+                        .tunnel_options
+                        .dns_options
+                        .blocked_state_servers
+                        .clone();
                     self.event_listener.notify_settings(settings);
                     self.send_tunnel_command(TunnelCommand::Dns(resolvers));
+                    #[cfg(target_os = "linux")]
+                    self.send_tunnel_command(TunnelCommand::SplitDns(split_dns_rules));
+                    #[cfg(target_os = "linux")]
+                    self.send_tunnel_command(TunnelCommand::EncryptedDnsFallback(
+                        encrypted_dns_fallback,
+                    ));
+                    #[cfg(target_os = "linux")]
+                    self.send_tunnel_command(TunnelCommand::EncryptedDns(encrypted_dns_servers));
+                    #[cfg(target_os = "linux")]
+                    self.send_tunnel_command(TunnelCommand::LocalDnsRules(local_dns_rules));
+                    #[cfg(target_os = "linux")]
+                    self.send_tunnel_command(TunnelCommand::DnsQueryLog(query_log));
+                    #[cfg(target_os = "linux")]
+                    self.send_tunnel_command(TunnelCommand::BlockedStateDns(blocked_state_servers));
                 }
             }
             Err(e) => {
                 log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "set_dns_options response");
+                Self::oneshot_send(tx, Err(Error::SettingsError(e)), "set_dns_options response");
             }
         }
     }
 
+    #[cfg(target_os = "linux")]
+    fn on_test_dns_servers(&mut self, tx: oneshot::Sender<Vec<DnsServerTestResult>>) {
+        let dns_options = &self.settings.tunnel_options.dns_options;
+        let servers = dns_options.custom_options.addresses.clone();
+        let allow_lan = self.settings.allow_lan;
+        // Queries to the custom servers are only let through while they are in use
+        let probe = dns_options.state == mullvad_types::settings::DnsState::Custom
+            && matches!(self.tunnel_state, TunnelState::Connected { .. });
+        tokio::spawn(async move {
+            let results = dns::test_servers(servers, allow_lan, probe).await;
+            Self::oneshot_send(tx, results, "test_dns_servers response");
+        });
+    }
+
     async fn on_set_wireguard_mtu(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
@@ -2067,6 +2501,165 @@ where
         }
     }
 
+    async fn on_set_wireguard_persistent_keepalive(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        interval: Option<u16>,
+    ) {
+        let save_result = self
+            .settings
+            .set_wireguard_persistent_keepalive(interval)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_persistent_keepalive response");
+                if settings_changed {
+                    self.parameters_generator
+                        .set_tunnel_options(&self.settings.tunnel_options)
+                        .await;
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_target_tunnel_type() {
+                        self.send_tunnel_command(TunnelCommand::SetWireguardPersistentKeepalive(
+                            interval,
+                        ));
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_wireguard_persistent_keepalive response");
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn on_set_wireguard_mtu_discovery(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        enabled: bool,
+    ) {
+        let save_result = self.settings.set_wireguard_mtu_discovery(enabled).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_mtu_discovery response");
+                if settings_changed {
+                    self.parameters_generator
+                        .set_tunnel_options(&self.settings.tunnel_options)
+                        .await;
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        log::info!(
+                            "Initiating tunnel restart because the WireGuard MTU discovery \
+                             setting changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_wireguard_mtu_discovery response");
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn on_set_wireguard_backend(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        backend: Option<talpid_types::net::wireguard::Backend>,
+    ) {
+        let save_result = self.settings.set_wireguard_backend(backend).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_backend response");
+                if settings_changed {
+                    self.parameters_generator
+                        .set_tunnel_options(&self.settings.tunnel_options)
+                        .await;
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        log::info!(
+                            "Initiating tunnel restart because the WireGuard backend changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_wireguard_backend response");
+            }
+        }
+    }
+
+    async fn on_set_wireguard_connectivity_profile(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        profile: talpid_types::net::wireguard::ConnectivityProfile,
+    ) {
+        let save_result = self
+            .settings
+            .set_wireguard_connectivity_profile(profile)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_connectivity_profile response");
+                if settings_changed {
+                    self.parameters_generator
+                        .set_tunnel_options(&self.settings.tunnel_options)
+                        .await;
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        log::info!(
+                            "Initiating tunnel restart because the WireGuard connectivity \
+                             profile changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_wireguard_connectivity_profile response");
+            }
+        }
+    }
+
+    async fn on_set_wireguard_probe_address(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        address: Option<Ipv4Addr>,
+    ) {
+        let save_result = self.settings.set_wireguard_probe_address(address).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_probe_address response");
+                if settings_changed {
+                    self.parameters_generator
+                        .set_tunnel_options(&self.settings.tunnel_options)
+                        .await;
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        log::info!(
+                            "Initiating tunnel restart because the WireGuard probe address changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_wireguard_probe_address response");
+            }
+        }
+    }
+
     async fn on_set_wireguard_rotation_interval(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
@@ -2126,6 +2719,41 @@ where
         Self::oneshot_send(tx, result, "get_wireguard_key response");
     }
 
+    async fn on_export_wireguard_config(
+        &self,
+        tx: oneshot::Sender<Option<Vec<String>>>,
+        include_private_key: bool,
+    ) {
+        let parameters = match self.get_connected_tunnel_type() {
+            Some(TunnelType::Wireguard) => {
+                self.parameters_generator
+                    .get_last_wireguard_parameters()
+                    .await
+            }
+            _ => None,
+        };
+        let configs = parameters.and_then(|parameters| {
+            let config =
+                talpid_core::tunnel::wireguard::config::Config::from_parameters(&parameters)
+                    .map_err(|error| {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg("Failed to create WireGuard config")
+                        );
+                    })
+                    .ok()?;
+            let dns_servers =
+                dns::addresses_from_options(&self.settings.tunnel_options.dns_options)
+                    .unwrap_or_else(|| {
+                        std::iter::once(IpAddr::V4(config.ipv4_gateway))
+                            .chain(config.ipv6_gateway.map(IpAddr::V6))
+                            .collect()
+                    });
+            Some(config.to_wg_quick_format(&dns_servers, include_private_key))
+        });
+        Self::oneshot_send(tx, configs, "export_wireguard_config response");
+    }
+
     fn on_get_settings(&self, tx: oneshot::Sender<Settings>) {
         Self::oneshot_send(tx, self.settings.to_settings(), "get_settings response");
     }
@@ -11,6 +11,8 @@ use mullvad_management_interface::{
 use mullvad_paths;
 #[cfg(not(target_os = "android"))]
 use mullvad_types::settings::DnsOptions;
+#[cfg(not(target_os = "linux"))]
+use mullvad_types::ConnectionConfig;
 use mullvad_types::{
     account::AccountToken,
     relay_constraints::{BridgeSettings, BridgeState, ObfuscationSettings, RelaySettingsUpdate},
@@ -18,6 +20,7 @@ use mullvad_types::{
     settings::Settings,
     states::{TargetState, TunnelState},
     version,
+    wg_quick::WgQuickConfig,
     wireguard::{RotationInterval, RotationIntervalError},
 };
 use parking_lot::RwLock;
@@ -25,9 +28,13 @@ use parking_lot::RwLock;
 use std::path::PathBuf;
 use std::{
     convert::{TryFrom, TryInto},
+    str::FromStr,
     sync::Arc,
     time::Duration,
 };
+use talpid_types::net::wireguard;
+#[cfg(target_os = "linux")]
+use talpid_types::net::{ForwardedInterface, Ipv6FirewallMode};
 use talpid_types::ErrorExt;
 use tokio_stream::wrappers::UnboundedReceiverStream;
 
@@ -55,6 +62,14 @@ const USED_VOUCHER_MESSAGE: &str = "This voucher code has already been used";
 impl ManagementService for ManagementServiceImpl {
     type GetSplitTunnelProcessesStream = UnboundedReceiverStream<Result<i32, Status>>;
     type EventsListenStream = EventsListenerReceiver;
+    type FirewallDropEventsStream =
+        UnboundedReceiverStream<Result<types::FirewallDropEvent, Status>>;
+    type DnsQueryLogStream = UnboundedReceiverStream<Result<types::DnsQueryLogEntry, Status>>;
+    type DnsConfigDriftEventsStream =
+        UnboundedReceiverStream<Result<types::DnsConfigDrift, Status>>;
+    type EncryptedDnsFallbackEventsStream =
+        UnboundedReceiverStream<Result<types::EncryptedDnsFallback, Status>>;
+    type NetworkChangeEventsStream = UnboundedReceiverStream<Result<types::NetworkChange, Status>>;
 
     // Control and get the tunnel state
     //
@@ -178,6 +193,8 @@ impl ManagementService for ManagementServiceImpl {
         let (tx, rx) = oneshot::channel();
         let constraints_update =
             RelaySettingsUpdate::try_from(request.into_inner()).map_err(map_protobuf_type_err)?;
+        #[cfg(not(target_os = "linux"))]
+        check_additional_peers(&constraints_update)?;
 
         let message = DaemonCommand::UpdateRelaySettings(tx, constraints_update);
         self.send_command_to_daemon(message)?;
@@ -187,6 +204,37 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn import_wireguard_config(&self, request: Request<String>) -> ServiceResult<()> {
+        log::debug!("import_wireguard_config");
+        let config = WgQuickConfig::from_str(&request.into_inner())
+            .map_err(|error| Status::invalid_argument(error.to_string()))?;
+
+        for server in config.ignored_dns_servers() {
+            log::warn!(
+                "Ignoring DNS server {} in WireGuard config. Only the first IPv4 and IPv6 \
+                 servers are used",
+                server
+            );
+        }
+        if let Some(mtu) = config.mtu {
+            log::warn!(
+                "Ignoring MTU {} in WireGuard config. The WireGuard MTU setting is left unchanged",
+                mtu
+            );
+        }
+
+        let (tx, rx) = oneshot::channel();
+        let update = RelaySettingsUpdate::CustomTunnelEndpoint(config.endpoint);
+        #[cfg(not(target_os = "linux"))]
+        check_additional_peers(&update)?;
+        self.send_command_to_daemon(DaemonCommand::UpdateRelaySettings(tx, update))?;
+        self.wait_for_result(rx)
+            .await?
+            .map_err(map_settings_error)?;
+
+        Ok(Response::new(()))
+    }
+
     async fn get_relay_locations(&self, _: Request<()>) -> ServiceResult<types::RelayList> {
         log::debug!("get_relay_locations");
 
@@ -276,6 +324,34 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    #[cfg(target_os = "linux")]
+    async fn set_forwarded_interfaces(
+        &self,
+        request: Request<types::ForwardedInterfaces>,
+    ) -> ServiceResult<()> {
+        let interfaces = request
+            .into_inner()
+            .interfaces
+            .into_iter()
+            .map(ForwardedInterface::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(map_protobuf_type_err)?;
+        log::debug!("set_forwarded_interfaces({:?})", interfaces);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetForwardedInterfaces(tx, interfaces))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+    #[cfg(not(target_os = "linux"))]
+    async fn set_forwarded_interfaces(
+        &self,
+        _: Request<types::ForwardedInterfaces>,
+    ) -> ServiceResult<()> {
+        Ok(Response::new(()))
+    }
+
     async fn set_show_beta_releases(&self, request: Request<bool>) -> ServiceResult<()> {
         let enabled = request.into_inner();
         log::debug!("set_show_beta_releases({})", enabled);
@@ -340,6 +416,96 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn set_wireguard_persistent_keepalive(&self, request: Request<u32>) -> ServiceResult<()> {
+        let interval = request.into_inner();
+        let interval = if interval != 0 {
+            Some(u16::try_from(interval).map_err(|_| {
+                Status::invalid_argument("persistent keepalive interval is too large")
+            })?)
+        } else {
+            None
+        };
+        log::debug!("set_wireguard_persistent_keepalive({:?})", interval);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetWireguardPersistentKeepalive(tx, interval))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn set_wireguard_mtu_discovery(&self, request: Request<bool>) -> ServiceResult<()> {
+        let enabled = request.into_inner();
+        log::debug!("set_wireguard_mtu_discovery({})", enabled);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetWireguardMtuDiscovery(tx, enabled))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn set_wireguard_mtu_discovery(&self, _: Request<bool>) -> ServiceResult<()> {
+        Err(Status::unimplemented(
+            "path MTU discovery is only supported on Linux",
+        ))
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn set_wireguard_backend(
+        &self,
+        request: Request<types::WireguardBackend>,
+    ) -> ServiceResult<()> {
+        let backend = Option::<wireguard::Backend>::try_from(request.into_inner())
+            .map_err(map_protobuf_type_err)?;
+        log::debug!("set_wireguard_backend({:?})", backend);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetWireguardBackend(tx, backend))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn set_wireguard_backend(
+        &self,
+        _: Request<types::WireguardBackend>,
+    ) -> ServiceResult<()> {
+        Err(Status::unimplemented(
+            "selecting the WireGuard backend is only supported on Linux",
+        ))
+    }
+
+    async fn set_wireguard_connectivity_profile(
+        &self,
+        request: Request<types::ConnectivityProfile>,
+    ) -> ServiceResult<()> {
+        let profile = wireguard::ConnectivityProfile::try_from(request.into_inner())
+            .map_err(map_protobuf_type_err)?;
+        log::debug!("set_wireguard_connectivity_profile({:?})", profile);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetWireguardConnectivityProfile(tx, profile))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_wireguard_probe_address(&self, request: Request<String>) -> ServiceResult<()> {
+        let address = types::try_probe_address_from_str(&request.into_inner())
+            .map_err(map_protobuf_type_err)?;
+        log::debug!("set_wireguard_probe_address({:?})", address);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetWireguardProbeAddress(tx, address))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     async fn set_enable_ipv6(&self, request: Request<bool>) -> ServiceResult<()> {
         let enable_ipv6 = request.into_inner();
         log::debug!("set_enable_ipv6({})", enable_ipv6);
@@ -351,6 +517,29 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    #[cfg(target_os = "linux")]
+    async fn set_ipv6_firewall_mode(
+        &self,
+        request: Request<types::Ipv6FirewallMode>,
+    ) -> ServiceResult<()> {
+        let mode =
+            Ipv6FirewallMode::try_from(request.into_inner()).map_err(map_protobuf_type_err)?;
+        log::debug!("set_ipv6_firewall_mode({})", mode);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetIpv6FirewallMode(tx, mode))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+    #[cfg(not(target_os = "linux"))]
+    async fn set_ipv6_firewall_mode(
+        &self,
+        _: Request<types::Ipv6FirewallMode>,
+    ) -> ServiceResult<()> {
+        Ok(Response::new(()))
+    }
+
     async fn set_quantum_resistant_tunnel(&self, request: Request<bool>) -> ServiceResult<()> {
         let enable = request.into_inner();
         log::debug!("set_quantum_resistant_tunnel({})", enable);
@@ -367,12 +556,24 @@ impl ManagementService for ManagementServiceImpl {
         let options = DnsOptions::try_from(request.into_inner()).map_err(map_protobuf_type_err)?;
         log::debug!("set_dns_options({:?})", options);
 
+        #[cfg(target_os = "linux")]
+        if let Some(server) = options
+            .blocked_state_servers
+            .iter()
+            .find(|server| !talpid_core::firewall::is_local_address(server))
+        {
+            return Err(Status::invalid_argument(format!(
+                "{} is not on the local network",
+                server
+            )));
+        }
+
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::SetDnsOptions(tx, options))?;
         self.wait_for_result(rx)
             .await?
             .map(Response::new)
-            .map_err(map_settings_error)
+            .map_err(map_daemon_error)
     }
 
     #[cfg(target_os = "android")]
@@ -380,6 +581,27 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(()))
     }
 
+    #[cfg(target_os = "linux")]
+    async fn test_dns_servers(&self, _: Request<()>) -> ServiceResult<types::DnsServerTestResults> {
+        log::debug!("test_dns_servers");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::TestDnsServers(tx))?;
+        let results = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::DnsServerTestResults {
+            results: results
+                .into_iter()
+                .map(types::DnsServerTestResult::from)
+                .collect(),
+        }))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn test_dns_servers(&self, _: Request<()>) -> ServiceResult<types::DnsServerTestResults> {
+        Err(Status::unimplemented(
+            "testing DNS servers is only supported on Linux",
+        ))
+    }
+
     // Account management
     //
 
@@ -600,6 +822,23 @@ impl ManagementService for ManagementServiceImpl {
         }
     }
 
+    async fn export_wireguard_config(
+        &self,
+        request: Request<types::WireguardConfigExportRequest>,
+    ) -> ServiceResult<types::WireguardConfigExport> {
+        log::debug!("export_wireguard_config");
+        let include_private_key = request.into_inner().include_private_key;
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ExportWireguardConfig(
+            tx,
+            include_private_key,
+        ))?;
+        match self.wait_for_result(rx).await? {
+            Some(configs) => Ok(Response::new(types::WireguardConfigExport { configs })),
+            None => Err(Status::failed_precondition("not connected over WireGuard")),
+        }
+    }
+
     // Split tunneling
     //
 
@@ -812,6 +1051,223 @@ impl ManagementService for ManagementServiceImpl {
     async fn check_volumes(&self, _: Request<()>) -> ServiceResult<()> {
         Ok(Response::new(()))
     }
+
+    // Debugging
+    //
+
+    #[cfg(target_os = "linux")]
+    async fn run_leak_test(&self, _: Request<()>) -> ServiceResult<types::LeakTestResult> {
+        log::debug!("run_leak_test");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::RunLeakTest(tx))?;
+        let results = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::LeakTestResult {
+            results: results
+                .into_iter()
+                .map(types::LeakProbeResult::from)
+                .collect(),
+        }))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn run_leak_test(&self, _: Request<()>) -> ServiceResult<types::LeakTestResult> {
+        Err(Status::unimplemented(
+            "leak test is only supported on Linux",
+        ))
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn set_log_firewall_drops(&self, request: Request<bool>) -> ServiceResult<()> {
+        let enabled = request.into_inner();
+        log::debug!("set_log_firewall_drops({})", enabled);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetLogFirewallDrops(tx, enabled))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn set_log_firewall_drops(&self, _: Request<bool>) -> ServiceResult<()> {
+        Err(Status::unimplemented(
+            "logging of dropped packets is only supported on Linux",
+        ))
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn firewall_drop_events(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<Self::FirewallDropEventsStream> {
+        log::debug!("firewall_drop_events");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SubscribeFirewallDrops(tx))?;
+        let mut drops_rx = self.wait_for_result(rx).await?.ok_or_else(|| {
+            Status::failed_precondition("logging of dropped packets is not enabled")
+        })?;
+
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(drops) = drops_rx.next().await {
+                if tx.send(Ok(types::FirewallDropEvent::from(drops))).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(UnboundedReceiverStream::new(rx)))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn firewall_drop_events(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<Self::FirewallDropEventsStream> {
+        Err(Status::unimplemented(
+            "logging of dropped packets is only supported on Linux",
+        ))
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn dns_query_log(
+        &self,
+        request: Request<types::DnsQueryLogRequest>,
+    ) -> ServiceResult<Self::DnsQueryLogStream> {
+        log::debug!("dns_query_log");
+        let follow = request.into_inner().follow;
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SubscribeDnsQueryLog(tx))?;
+        let (entries, mut entries_rx) = self
+            .wait_for_result(rx)
+            .await?
+            .ok_or_else(|| Status::failed_precondition("the DNS query log is not enabled"))?;
+
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        for entry in entries {
+            let _ = tx.send(Ok(types::DnsQueryLogEntry::from(entry)));
+        }
+        if follow {
+            tokio::spawn(async move {
+                while let Some(entry) = entries_rx.next().await {
+                    if tx.send(Ok(types::DnsQueryLogEntry::from(entry))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        Ok(Response::new(UnboundedReceiverStream::new(rx)))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn dns_query_log(
+        &self,
+        _: Request<types::DnsQueryLogRequest>,
+    ) -> ServiceResult<Self::DnsQueryLogStream> {
+        Err(Status::unimplemented(
+            "the DNS query log is only supported on Linux",
+        ))
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn dns_config_drift_events(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<Self::DnsConfigDriftEventsStream> {
+        log::debug!("dns_config_drift_events");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SubscribeDnsDrift(tx))?;
+        let mut drift_rx = self.wait_for_result(rx).await?;
+
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(drift) = drift_rx.next().await {
+                if tx.send(Ok(types::DnsConfigDrift::from(drift))).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(UnboundedReceiverStream::new(rx)))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn dns_config_drift_events(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<Self::DnsConfigDriftEventsStream> {
+        Err(Status::unimplemented(
+            "monitoring of DNS config is only supported on Linux",
+        ))
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn encrypted_dns_fallback_events(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<Self::EncryptedDnsFallbackEventsStream> {
+        log::debug!("encrypted_dns_fallback_events");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SubscribeEncryptedDnsFallback(tx))?;
+        let mut fallback_rx = self.wait_for_result(rx).await?;
+
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(event) = fallback_rx.next().await {
+                if tx
+                    .send(Ok(types::EncryptedDnsFallback::from(event)))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(UnboundedReceiverStream::new(rx)))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn encrypted_dns_fallback_events(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<Self::EncryptedDnsFallbackEventsStream> {
+        Err(Status::unimplemented(
+            "encrypted DNS is only supported on Linux",
+        ))
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn network_change_events(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<Self::NetworkChangeEventsStream> {
+        log::debug!("network_change_events");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SubscribeNetworkChanges(tx))?;
+        let mut change_rx = self.wait_for_result(rx).await?;
+
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(change) = change_rx.next().await {
+                if tx.send(Ok(types::NetworkChange::from(change))).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(UnboundedReceiverStream::new(rx)))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn network_change_events(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<Self::NetworkChangeEventsStream> {
+        Err(Status::unimplemented(
+            "keeping the tunnel up across network changes is only supported on Linux",
+        ))
+    }
 }
 
 impl ManagementServiceImpl {
@@ -940,6 +1396,22 @@ impl ManagementInterfaceEventBroadcaster {
     }
 }
 
+/// Rejects custom WireGuard tunnels with more than one peer, since the firewall only allows
+/// additional peers on Linux.
+#[cfg(not(target_os = "linux"))]
+fn check_additional_peers(update: &RelaySettingsUpdate) -> Result<(), Status> {
+    if let RelaySettingsUpdate::CustomTunnelEndpoint(endpoint) = update {
+        if let ConnectionConfig::Wireguard(config) = &endpoint.config {
+            if !config.additional_peers.is_empty() {
+                return Err(Status::unimplemented(
+                    "Custom WireGuard tunnels with multiple peers are only supported on Linux",
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
 /// Converts [`mullvad_daemon::Error`] into a tonic status.
 fn map_daemon_error(error: crate::Error) -> Status {
     use crate::Error as DaemonError;
@@ -957,6 +1429,10 @@ fn map_daemon_error(error: crate::Error) -> Status {
         #[cfg(windows)]
         DaemonError::SplitTunnelError(error) => map_split_tunnel_error(error),
         DaemonError::AccountHistory(error) => map_account_history_error(error),
+        #[cfg(target_os = "linux")]
+        DaemonError::UnroutableDnsServer(server, reason) => {
+            Status::invalid_argument(format!("{}: {}", server, reason))
+        }
         DaemonError::NoAccountToken | DaemonError::NoAccountTokenHistory => {
             Status::unauthenticated(error.to_string())
         }
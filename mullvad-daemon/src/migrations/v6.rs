@@ -0,0 +1,182 @@
+use super::Result;
+use mullvad_types::settings::SettingsVersion;
+
+// ======================================================
+// Section for vendoring types and values that
+// this settings version depend on. See `mod.rs`.
+
+// ======================================================
+
+/// # Changes to the format
+///
+/// Custom DNS options gained a list of encrypted resolvers, `encrypted_servers`, which are reached
+/// over DNS-over-TLS or DNS-over-HTTPS. Existing custom DNS options get an empty list, so that
+/// only the plain `addresses` remain in use.
+pub fn migrate(settings: &mut serde_json::Value) -> Result<()> {
+    if !version_matches(settings) {
+        return Ok(());
+    }
+
+    log::info!("Migrating settings format to V7");
+
+    if let Some(custom_options) = settings
+        .get_mut("tunnel_options")
+        .and_then(|options| options.get_mut("dns_options"))
+        .and_then(|options| options.get_mut("custom_options"))
+        .and_then(|options| options.as_object_mut())
+    {
+        custom_options
+            .entry("encrypted_servers")
+            .or_insert_with(|| serde_json::json!([]));
+    }
+
+    settings["settings_version"] = serde_json::json!(SettingsVersion::V7);
+
+    Ok(())
+}
+
+fn version_matches(settings: &mut serde_json::Value) -> bool {
+    settings
+        .get("settings_version")
+        .map(|version| version == SettingsVersion::V6 as u64)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{migrate, version_matches};
+
+    pub const V6_SETTINGS: &str = r#"
+{
+  "relay_settings": {
+    "normal": {
+      "location": "any",
+      "tunnel_protocol": "any",
+      "wireguard_constraints": {
+        "port": "any",
+        "ip_version": "any",
+        "use_multihop": false,
+        "entry_location": "any"
+      },
+      "openvpn_constraints": {
+        "port": "any"
+      }
+    }
+  },
+  "bridge_settings": {
+    "normal": {
+      "location": "any"
+    }
+  },
+  "obfuscation_settings": {
+    "selected_obfuscation": "off",
+    "udp2tcp": {
+      "port": "any"
+    }
+  },
+  "bridge_state": "auto",
+  "allow_lan": true,
+  "block_when_disconnected": false,
+  "auto_connect": false,
+  "tunnel_options": {
+    "openvpn": {
+      "mssfix": null
+    },
+    "wireguard": {
+      "mtu": null,
+      "rotation_interval": null
+    },
+    "generic": {
+      "enable_ipv6": false
+    },
+    "dns_options": {
+      "state": "custom",
+      "default_options": {
+        "block_ads": false,
+        "block_trackers": false
+      },
+      "custom_options": {
+        "addresses": [
+          "1.1.1.1",
+          "1.2.3.4"
+        ]
+      }
+    }
+  },
+  "settings_version": 6
+}
+"#;
+
+    pub const V7_SETTINGS: &str = r#"
+{
+  "relay_settings": {
+    "normal": {
+      "location": "any",
+      "tunnel_protocol": "any",
+      "wireguard_constraints": {
+        "port": "any",
+        "ip_version": "any",
+        "use_multihop": false,
+        "entry_location": "any"
+      },
+      "openvpn_constraints": {
+        "port": "any"
+      }
+    }
+  },
+  "bridge_settings": {
+    "normal": {
+      "location": "any"
+    }
+  },
+  "obfuscation_settings": {
+    "selected_obfuscation": "off",
+    "udp2tcp": {
+      "port": "any"
+    }
+  },
+  "bridge_state": "auto",
+  "allow_lan": true,
+  "block_when_disconnected": false,
+  "auto_connect": false,
+  "tunnel_options": {
+    "openvpn": {
+      "mssfix": null
+    },
+    "wireguard": {
+      "mtu": null,
+      "rotation_interval": null
+    },
+    "generic": {
+      "enable_ipv6": false
+    },
+    "dns_options": {
+      "state": "custom",
+      "default_options": {
+        "block_ads": false,
+        "block_trackers": false
+      },
+      "custom_options": {
+        "addresses": [
+          "1.1.1.1",
+          "1.2.3.4"
+        ],
+        "encrypted_servers": []
+      }
+    }
+  },
+  "settings_version": 7
+}
+"#;
+
+    #[test]
+    fn test_v6_to_v7_migration() {
+        let mut old_settings = serde_json::from_str(V6_SETTINGS).unwrap();
+
+        assert!(version_matches(&mut old_settings));
+        migrate(&mut old_settings).unwrap();
+        let new_settings: serde_json::Value = serde_json::from_str(V7_SETTINGS).unwrap();
+
+        assert_eq!(&old_settings, &new_settings);
+    }
+}
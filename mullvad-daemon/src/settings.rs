@@ -8,9 +8,13 @@ use mullvad_types::{
 #[cfg(target_os = "windows")]
 use std::collections::HashSet;
 use std::{
+    net::Ipv4Addr,
     ops::Deref,
     path::{Path, PathBuf},
 };
+use talpid_types::net::wireguard;
+#[cfg(target_os = "linux")]
+use talpid_types::net::{ForwardedInterface, Ipv6FirewallMode};
 use talpid_types::ErrorExt;
 use tokio::{
     fs,
@@ -264,6 +268,70 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    pub async fn set_wireguard_persistent_keepalive(
+        &mut self,
+        interval: Option<u16>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self
+                .settings
+                .tunnel_options
+                .wireguard
+                .options
+                .persistent_keepalive,
+            interval,
+        );
+        self.update(should_save).await
+    }
+
+    #[cfg(target_os = "linux")]
+    pub async fn set_wireguard_mtu_discovery(&mut self, enabled: bool) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_options.wireguard.options.mtu_discovery,
+            enabled,
+        );
+        self.update(should_save).await
+    }
+
+    #[cfg(target_os = "linux")]
+    pub async fn set_wireguard_backend(
+        &mut self,
+        backend: Option<wireguard::Backend>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_options.wireguard.options.backend,
+            backend,
+        );
+        self.update(should_save).await
+    }
+
+    pub async fn set_wireguard_connectivity_profile(
+        &mut self,
+        profile: wireguard::ConnectivityProfile,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self
+                .settings
+                .tunnel_options
+                .wireguard
+                .options
+                .connectivity_profile,
+            profile,
+        );
+        self.update(should_save).await
+    }
+
+    pub async fn set_wireguard_probe_address(
+        &mut self,
+        address: Option<Ipv4Addr>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_options.wireguard.options.probe_address,
+            address,
+        );
+        self.update(should_save).await
+    }
+
     pub async fn set_wireguard_rotation_interval(
         &mut self,
         interval: Option<RotationInterval>,
@@ -327,6 +395,31 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    #[cfg(target_os = "linux")]
+    pub async fn set_forwarded_interfaces(
+        &mut self,
+        interfaces: Vec<ForwardedInterface>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.forwarded_interfaces, interfaces);
+        self.update(should_save).await
+    }
+
+    #[cfg(target_os = "linux")]
+    pub async fn set_ipv6_firewall_mode(&mut self, mode: Ipv6FirewallMode) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.ipv6_firewall_mode, mode);
+        self.update(should_save).await
+    }
+
+    #[cfg(target_os = "linux")]
+    pub async fn set_log_firewall_drops(
+        &mut self,
+        log_firewall_drops: bool,
+    ) -> Result<bool, Error> {
+        let should_save =
+            Self::update_field(&mut self.settings.log_firewall_drops, log_firewall_drops);
+        self.update(should_save).await
+    }
+
     fn update_field<T: Eq>(field: &mut T, new_value: T) -> bool {
         if *field != new_value {
             *field = new_value;
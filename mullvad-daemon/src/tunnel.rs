@@ -8,7 +8,10 @@ use mullvad_types::{
 };
 use talpid_core::tunnel_state_machine::TunnelParametersGenerator;
 use talpid_types::{
-    net::{wireguard, TunnelParameters},
+    net::{
+        nat64, obfuscation::ObfuscatorConfig, wireguard, IpAvailability, NetworkState,
+        TunnelParameters,
+    },
     tunnel::ParameterGenerationError,
     ErrorExt,
 };
@@ -43,6 +46,7 @@ struct InnerParametersGenerator {
 
     // TODO: Move this to `RelaySelector`?
     last_generated_relays: Option<LastSelectedRelays>,
+    last_wireguard_parameters: Option<wireguard::TunnelParameters>,
 }
 
 impl ParametersGenerator {
@@ -59,6 +63,7 @@ impl ParametersGenerator {
             account_manager,
 
             last_generated_relays: None,
+            last_wireguard_parameters: None,
         })))
     }
 
@@ -67,6 +72,19 @@ impl ParametersGenerator {
         self.0.lock().await.tunnel_options = tunnel_options.clone();
     }
 
+    /// Gets the last generated tunnel parameters, if they are for a WireGuard tunnel.
+    pub async fn get_last_wireguard_parameters(&self) -> Option<wireguard::TunnelParameters> {
+        self.0.lock().await.last_wireguard_parameters.clone()
+    }
+
+    /// Replaces the private key in the last generated WireGuard parameters, after it has been
+    /// applied to the running tunnel.
+    pub async fn set_last_wireguard_private_key(&self, private_key: wireguard::PrivateKey) {
+        if let Some(parameters) = self.0.lock().await.last_wireguard_parameters.as_mut() {
+            parameters.connection.tunnel.private_key = private_key;
+        }
+    }
+
     /// Gets the location associated with the last generated tunnel parameters.
     pub async fn get_last_location(&self) -> Option<GeoIpLocation> {
         let inner = self.0.lock().await;
@@ -120,9 +138,31 @@ impl ParametersGenerator {
 }
 
 impl InnerParametersGenerator {
-    async fn generate(&mut self, retry_attempt: u32) -> Result<TunnelParameters, Error> {
+    async fn generate(
+        &mut self,
+        retry_attempt: u32,
+        network: Option<NetworkState>,
+    ) -> Result<TunnelParameters, Error> {
+        let ip_availability = network.map(|network| network.ip_availability);
+        let parameters = self.generate_inner(retry_attempt, ip_availability).await?;
+        match network {
+            Some(network) if network.ip_availability == IpAvailability::Ipv6 => {
+                Ok(use_nat64(parameters, network.nat64_prefix))
+            }
+            _ => Ok(parameters),
+        }
+    }
+
+    async fn generate_inner(
+        &mut self,
+        retry_attempt: u32,
+        ip_availability: Option<IpAvailability>,
+    ) -> Result<TunnelParameters, Error> {
         let _data = self.device().await?;
-        match self.relay_selector.get_relay(retry_attempt) {
+        match self
+            .relay_selector
+            .get_relay(retry_attempt, ip_availability)
+        {
             Ok((SelectedRelay::Custom(custom_relay), _bridge, _obfsucator)) => {
                 custom_relay
                     // TODO: generate proxy settings for custom tunnels
@@ -182,6 +222,7 @@ impl InnerParametersGenerator {
                     options: self.tunnel_options.openvpn.clone(),
                     generic_options: self.tunnel_options.generic.clone(),
                     proxy: bridge_settings,
+                    nat64: false,
                 }
                 .into())
             }
@@ -214,12 +255,14 @@ impl InnerParametersGenerator {
                         tunnel,
                         peer: endpoint.peer,
                         exit_peer: endpoint.exit_peer,
+                        additional_peers: vec![],
                         ipv4_gateway: endpoint.ipv4_gateway,
                         ipv6_gateway: Some(endpoint.ipv6_gateway),
                     },
                     options: self.tunnel_options.wireguard.options.clone(),
                     generic_options: self.tunnel_options.generic.clone(),
                     obfuscation: obfuscator_config,
+                    nat64: false,
                 }
                 .into())
             }
@@ -237,30 +280,77 @@ impl InnerParametersGenerator {
     }
 }
 
+/// Replaces the IPv4 address of the first hop with one synthesized for the NAT64 gateway of the
+/// current network, since IPv4 hosts can't be reached directly from an IPv6-only network. The
+/// prefix is discovered by the offline monitor whenever the default routes change.
+fn use_nat64(mut parameters: TunnelParameters, prefix: Option<nat64::Prefix>) -> TunnelParameters {
+    if !parameters.get_next_hop_endpoint().address.is_ipv4() {
+        return parameters;
+    }
+    match prefix {
+        Some(prefix) => synthesize_nat64_endpoints(&mut parameters, prefix),
+        None => log::warn!("Only IPv6 is available, but no NAT64 gateway was found"),
+    }
+    parameters
+}
+
+/// Replaces the IPv4 address of the first hop with one synthesized from the NAT64 `prefix`.
+fn synthesize_nat64_endpoints(parameters: &mut TunnelParameters, prefix: nat64::Prefix) {
+    match parameters {
+        TunnelParameters::Wireguard(params) => {
+            match &mut params.obfuscation {
+                Some(ObfuscatorConfig::Udp2Tcp { endpoint })
+                | Some(ObfuscatorConfig::Shadowsocks { endpoint, .. }) => {
+                    // The Shadowsocks server forwards the traffic from the relay, so its
+                    // `wireguard_endpoint` is left as it is.
+                    *endpoint = prefix.synthesize_socket_addr(*endpoint);
+                }
+                None => {
+                    let peer = &mut params.connection.peer;
+                    peer.endpoint = prefix.synthesize_socket_addr(peer.endpoint);
+                }
+            }
+            params.nat64 = true;
+        }
+        TunnelParameters::OpenVpn(params) => {
+            if params.proxy.is_some() {
+                log::warn!("Bridges are not reached through NAT64");
+                return;
+            }
+            let endpoint = &mut params.config.endpoint;
+            endpoint.address = prefix.synthesize_socket_addr(endpoint.address);
+            params.nat64 = true;
+        }
+    }
+}
+
 impl TunnelParametersGenerator for ParametersGenerator {
     fn generate(
         &mut self,
         retry_attempt: u32,
+        network: Option<NetworkState>,
     ) -> Pin<Box<dyn Future<Output = Result<TunnelParameters, ParameterGenerationError>>>> {
         let generator = self.0.clone();
         Box::pin(async move {
             let mut inner = generator.lock().await;
-            inner
-                .generate(retry_attempt)
-                .await
-                .map_err(|error| match error {
-                    Error::NoBridgeAvailable => ParameterGenerationError::NoMatchingBridgeRelay,
-                    Error::ResolveCustomHostname => {
-                        ParameterGenerationError::CustomTunnelHostResultionError
-                    }
-                    error => {
-                        log::error!(
-                            "{}",
-                            error.display_chain_with_msg("Failed to generate tunnel parameters")
-                        );
-                        ParameterGenerationError::NoMatchingRelay
-                    }
-                })
+            let parameters = inner.generate(retry_attempt, network).await;
+            inner.last_wireguard_parameters = match &parameters {
+                Ok(TunnelParameters::Wireguard(parameters)) => Some(parameters.clone()),
+                _ => None,
+            };
+            parameters.map_err(|error| match error {
+                Error::NoBridgeAvailable => ParameterGenerationError::NoMatchingBridgeRelay,
+                Error::ResolveCustomHostname => {
+                    ParameterGenerationError::CustomTunnelHostResultionError
+                }
+                error => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to generate tunnel parameters")
+                    );
+                    ParameterGenerationError::NoMatchingRelay
+                }
+            })
         })
     }
 }
@@ -283,3 +373,85 @@ enum LastSelectedRelays {
     #[cfg(not(target_os = "android"))]
     OpenVpn { relay: Relay, bridge: Option<Relay> },
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use talpid_types::net::GenericTunnelOptions;
+
+    fn wireguard_parameters(obfuscation: Option<ObfuscatorConfig>) -> TunnelParameters {
+        wireguard::TunnelParameters {
+            connection: wireguard::ConnectionConfig {
+                tunnel: wireguard::TunnelConfig {
+                    private_key: wireguard::PrivateKey::from([1; 32]),
+                    addresses: vec![],
+                },
+                peer: wireguard::PeerConfig {
+                    public_key: wireguard::PublicKey::from([2; 32]),
+                    allowed_ips: vec![],
+                    endpoint: "192.0.2.1:51820".parse().unwrap(),
+                    psk: None,
+                },
+                exit_peer: None,
+                additional_peers: vec![],
+                ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+                ipv6_gateway: None,
+            },
+            options: wireguard::TunnelOptions::default(),
+            generic_options: GenericTunnelOptions { enable_ipv6: true },
+            obfuscation,
+            nat64: false,
+        }
+        .into()
+    }
+
+    fn wireguard(parameters: &TunnelParameters) -> &wireguard::TunnelParameters {
+        match parameters {
+            TunnelParameters::Wireguard(params) => params,
+            TunnelParameters::OpenVpn(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_synthesize_nat64_endpoints() {
+        let prefix = nat64::Prefix::from_synthesized("64:ff9b::c000:aa".parse().unwrap()).unwrap();
+        let relay: SocketAddr = "192.0.2.1:51820".parse().unwrap();
+        let synthesized: SocketAddr = "[64:ff9b::c000:201]:51820".parse().unwrap();
+
+        let mut parameters = wireguard_parameters(None);
+        synthesize_nat64_endpoints(&mut parameters, prefix);
+        assert_eq!(wireguard(&parameters).connection.peer.endpoint, synthesized);
+        assert!(wireguard(&parameters).nat64);
+
+        let obfuscators = [
+            ObfuscatorConfig::Udp2Tcp { endpoint: relay },
+            ObfuscatorConfig::Shadowsocks {
+                endpoint: relay,
+                wireguard_endpoint: relay,
+                cipher: "aes-256-gcm".to_string(),
+                password: "mullvad".to_string(),
+            },
+        ];
+        for obfuscator in obfuscators {
+            let mut parameters = wireguard_parameters(Some(obfuscator));
+            synthesize_nat64_endpoints(&mut parameters, prefix);
+            let params = wireguard(&parameters);
+            match params.obfuscation.as_ref().unwrap() {
+                ObfuscatorConfig::Udp2Tcp { endpoint }
+                | ObfuscatorConfig::Shadowsocks { endpoint, .. } => {
+                    assert_eq!(*endpoint, synthesized)
+                }
+            }
+            if let Some(ObfuscatorConfig::Shadowsocks {
+                wireguard_endpoint, ..
+            }) = params.obfuscation
+            {
+                assert_eq!(wireguard_endpoint, relay);
+            }
+            // The relay is reached through the obfuscator
+            assert_eq!(params.connection.peer.endpoint, relay);
+            assert!(params.nat64);
+        }
+    }
+}
@@ -54,12 +54,18 @@ impl From<talpid_types::net::TunnelEndpoint> for TunnelEndpoint {
                     )),
                     obfuscation_type: match obfuscation_endpoint.obfuscation_type {
                         net::ObfuscationType::Udp2Tcp => i32::from(ObfuscationType::Udp2tcp),
+                        net::ObfuscationType::Shadowsocks => {
+                            i32::from(ObfuscationType::Shadowsocks)
+                        }
                     },
                 }),
             entry_endpoint: endpoint.entry_endpoint.map(|entry| Endpoint {
                 address: entry.address.to_string(),
                 protocol: i32::from(TransportProtocol::from(entry.protocol)),
             }),
+            mtu: u32::from(endpoint.mtu.unwrap_or_default()),
+            nat64: endpoint.nat64,
+            wireguard_backend: endpoint.wireguard_backend.map(WireguardBackendInfo::from),
         }
     }
 }
@@ -350,16 +356,14 @@ impl From<mullvad_types::ConnectionConfig> for ConnectionConfig {
                                 .map(|address| address.to_string())
                                 .collect(),
                         }),
-                        peer: Some(connection_config::wireguard_config::PeerConfig {
-                            public_key: config.peer.public_key.as_bytes().to_vec(),
-                            allowed_ips: config
-                                .peer
-                                .allowed_ips
-                                .iter()
-                                .map(|address| address.to_string())
-                                .collect(),
-                            endpoint: config.peer.endpoint.to_string(),
-                        }),
+                        peer: Some(connection_config::wireguard_config::PeerConfig::from(
+                            &config.peer,
+                        )),
+                        additional_peers: config
+                            .additional_peers
+                            .iter()
+                            .map(connection_config::wireguard_config::PeerConfig::from)
+                            .collect(),
                         ipv4_gateway: config.ipv4_gateway.to_string(),
                         ipv6_gateway: config
                             .ipv6_gateway
@@ -382,6 +386,276 @@ impl From<talpid_types::net::TransportProtocol> for TransportProtocol {
     }
 }
 
+impl From<talpid_types::net::leak_test::LeakProbe> for LeakProbe {
+    fn from(probe: talpid_types::net::leak_test::LeakProbe) -> Self {
+        use talpid_types::net::{leak_test::LeakProbe as TalpidLeakProbe, TransportProtocol};
+
+        let (probe_type, address) = match probe {
+            TalpidLeakProbe::Transport(endpoint) => {
+                let probe_type = match endpoint.protocol {
+                    TransportProtocol::Tcp => leak_probe::ProbeType::Tcp,
+                    TransportProtocol::Udp => leak_probe::ProbeType::Udp,
+                };
+                (probe_type, endpoint.address.to_string())
+            }
+            TalpidLeakProbe::Icmp(address) => (leak_probe::ProbeType::Icmp, address.to_string()),
+            TalpidLeakProbe::Dns(endpoint) => {
+                (leak_probe::ProbeType::Dns, endpoint.address.to_string())
+            }
+        };
+        LeakProbe {
+            probe_type: i32::from(probe_type),
+            address,
+        }
+    }
+}
+
+impl From<talpid_types::net::leak_test::LeakProbeResult> for LeakProbeResult {
+    fn from(result: talpid_types::net::leak_test::LeakProbeResult) -> Self {
+        use talpid_types::net::leak_test::LeakProbeOutcome;
+
+        let (outcome, error) = match result.outcome {
+            LeakProbeOutcome::Blocked => (leak_probe_result::Outcome::Blocked, String::new()),
+            LeakProbeOutcome::Leaked => (leak_probe_result::Outcome::Leaked, String::new()),
+            LeakProbeOutcome::Unreachable => {
+                (leak_probe_result::Outcome::Unreachable, String::new())
+            }
+            LeakProbeOutcome::Failed(error) => (leak_probe_result::Outcome::Failed, error),
+        };
+        LeakProbeResult {
+            probe: Some(LeakProbe::from(result.probe)),
+            outcome: i32::from(outcome),
+            error,
+            expected_allowed: result.expected_allowed,
+        }
+    }
+}
+
+impl From<talpid_types::net::drop_log::DroppedPackets> for DroppedPackets {
+    fn from(drops: talpid_types::net::drop_log::DroppedPackets) -> Self {
+        DroppedPackets {
+            destination: drops.destination.to_string(),
+            protocol: u32::from(drops.protocol),
+            port: drops.port.map(u32::from),
+            uid: drops.uid,
+            count: drops.count,
+        }
+    }
+}
+
+impl TryFrom<DroppedPackets> for talpid_types::net::drop_log::DroppedPackets {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(drops: DroppedPackets) -> Result<Self, Self::Error> {
+        let invalid = || FromProtobufTypeError::InvalidArgument("invalid dropped packets");
+        Ok(talpid_types::net::drop_log::DroppedPackets {
+            destination: drops.destination.parse().map_err(|_| invalid())?,
+            protocol: u8::try_from(drops.protocol).map_err(|_| invalid())?,
+            port: drops
+                .port
+                .map(u16::try_from)
+                .transpose()
+                .map_err(|_| invalid())?,
+            uid: drops.uid,
+            count: drops.count,
+        })
+    }
+}
+
+impl From<Vec<talpid_types::net::drop_log::DroppedPackets>> for FirewallDropEvent {
+    fn from(drops: Vec<talpid_types::net::drop_log::DroppedPackets>) -> Self {
+        FirewallDropEvent {
+            drops: drops.into_iter().map(DroppedPackets::from).collect(),
+        }
+    }
+}
+
+impl From<talpid_types::net::dns::DnsQueryLogEntry> for DnsQueryLogEntry {
+    fn from(entry: talpid_types::net::dns::DnsQueryLogEntry) -> Self {
+        let since_epoch = entry
+            .time
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        DnsQueryLogEntry {
+            time: Some(Timestamp {
+                seconds: since_epoch.as_secs() as i64,
+                nanos: since_epoch.subsec_nanos() as i32,
+            }),
+            name: entry.name,
+            query_type: entry.query_type,
+            upstream: entry.upstream.unwrap_or_default(),
+            blocked_by: entry.blocked_by.unwrap_or_default(),
+            response_code: entry.response_code,
+            latency: Some(Duration {
+                seconds: entry.latency.as_secs() as i64,
+                nanos: entry.latency.subsec_nanos() as i32,
+            }),
+        }
+    }
+}
+
+impl TryFrom<DnsQueryLogEntry> for talpid_types::net::dns::DnsQueryLogEntry {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(entry: DnsQueryLogEntry) -> Result<Self, Self::Error> {
+        let to_std_duration = |seconds: i64, nanos: i32| {
+            if seconds < 0 || nanos < 0 {
+                return Err(FromProtobufTypeError::InvalidArgument(
+                    "invalid DNS query log entry",
+                ));
+            }
+            Ok(std::time::Duration::new(seconds as u64, nanos as u32))
+        };
+        let time = entry.time.ok_or(FromProtobufTypeError::InvalidArgument(
+            "missing DNS query time",
+        ))?;
+        let latency = entry.latency.ok_or(FromProtobufTypeError::InvalidArgument(
+            "missing DNS query latency",
+        ))?;
+
+        Ok(talpid_types::net::dns::DnsQueryLogEntry {
+            time: std::time::UNIX_EPOCH + to_std_duration(time.seconds, time.nanos)?,
+            name: entry.name,
+            query_type: entry.query_type,
+            upstream: Some(entry.upstream).filter(|upstream| !upstream.is_empty()),
+            blocked_by: Some(entry.blocked_by).filter(|blocked_by| !blocked_by.is_empty()),
+            response_code: entry.response_code,
+            latency: to_std_duration(latency.seconds, latency.nanos)?,
+        })
+    }
+}
+
+impl From<talpid_types::net::dns::DnsConfigDrift> for DnsConfigDrift {
+    fn from(drift: talpid_types::net::dns::DnsConfigDrift) -> Self {
+        DnsConfigDrift {
+            manager: drift.manager,
+            interface: drift.interface,
+            reapply_count: drift.reapply_count,
+        }
+    }
+}
+
+impl From<DnsConfigDrift> for talpid_types::net::dns::DnsConfigDrift {
+    fn from(drift: DnsConfigDrift) -> Self {
+        talpid_types::net::dns::DnsConfigDrift {
+            manager: drift.manager,
+            interface: drift.interface,
+            reapply_count: drift.reapply_count,
+        }
+    }
+}
+
+impl From<talpid_types::net::dns::EncryptedDnsFallback> for EncryptedDnsFallback {
+    fn from(event: talpid_types::net::dns::EncryptedDnsFallback) -> Self {
+        match event {
+            talpid_types::net::dns::EncryptedDnsFallback::Started(error) => EncryptedDnsFallback {
+                active: true,
+                error,
+            },
+            talpid_types::net::dns::EncryptedDnsFallback::Stopped => EncryptedDnsFallback {
+                active: false,
+                error: String::new(),
+            },
+        }
+    }
+}
+
+impl From<EncryptedDnsFallback> for talpid_types::net::dns::EncryptedDnsFallback {
+    fn from(event: EncryptedDnsFallback) -> Self {
+        if event.active {
+            talpid_types::net::dns::EncryptedDnsFallback::Started(event.error)
+        } else {
+            talpid_types::net::dns::EncryptedDnsFallback::Stopped
+        }
+    }
+}
+
+impl From<talpid_types::net::NetworkChange> for NetworkChange {
+    fn from(change: talpid_types::net::NetworkChange) -> Self {
+        NetworkChange {
+            interface: change.interface.unwrap_or_default(),
+            was_offline: change.was_offline,
+        }
+    }
+}
+
+impl From<NetworkChange> for talpid_types::net::NetworkChange {
+    fn from(change: NetworkChange) -> Self {
+        talpid_types::net::NetworkChange {
+            interface: Some(change.interface).filter(|interface| !interface.is_empty()),
+            was_offline: change.was_offline,
+        }
+    }
+}
+
+impl From<talpid_types::net::dns::DnsServerTestResult> for DnsServerTestResult {
+    fn from(result: talpid_types::net::dns::DnsServerTestResult) -> Self {
+        use talpid_types::net::dns::DnsServerTestOutcome;
+
+        let (outcome, error, latency) = match result.outcome {
+            DnsServerTestOutcome::Responded(latency) => (
+                dns_server_test_result::Outcome::Responded,
+                String::new(),
+                Some(Duration {
+                    seconds: latency.as_secs() as i64,
+                    nanos: latency.subsec_nanos() as i32,
+                }),
+            ),
+            DnsServerTestOutcome::Routable => (
+                dns_server_test_result::Outcome::Routable,
+                String::new(),
+                None,
+            ),
+            DnsServerTestOutcome::Unroutable(error) => {
+                (dns_server_test_result::Outcome::Unroutable, error, None)
+            }
+            DnsServerTestOutcome::NoResponse(error) => {
+                (dns_server_test_result::Outcome::NoResponse, error, None)
+            }
+        };
+        DnsServerTestResult {
+            address: result.address.to_string(),
+            outcome: i32::from(outcome),
+            error,
+            latency,
+        }
+    }
+}
+
+impl TryFrom<DnsServerTestResult> for talpid_types::net::dns::DnsServerTestResult {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(result: DnsServerTestResult) -> Result<Self, Self::Error> {
+        use talpid_types::net::dns::DnsServerTestOutcome;
+
+        let invalid = || FromProtobufTypeError::InvalidArgument("invalid DNS server test result");
+        let outcome = match dns_server_test_result::Outcome::from_i32(result.outcome) {
+            Some(dns_server_test_result::Outcome::Responded) => {
+                let latency = result.latency.ok_or_else(invalid)?;
+                if latency.seconds < 0 || latency.nanos < 0 {
+                    return Err(invalid());
+                }
+                DnsServerTestOutcome::Responded(std::time::Duration::new(
+                    latency.seconds as u64,
+                    latency.nanos as u32,
+                ))
+            }
+            Some(dns_server_test_result::Outcome::Routable) => DnsServerTestOutcome::Routable,
+            Some(dns_server_test_result::Outcome::Unroutable) => {
+                DnsServerTestOutcome::Unroutable(result.error)
+            }
+            Some(dns_server_test_result::Outcome::NoResponse) => {
+                DnsServerTestOutcome::NoResponse(result.error)
+            }
+            None => return Err(invalid()),
+        };
+        Ok(talpid_types::net::dns::DnsServerTestResult {
+            address: result.address.parse().map_err(|_| invalid())?,
+            outcome,
+        })
+    }
+}
+
 impl From<talpid_types::net::IpVersion> for IpVersion {
     fn from(version: talpid_types::net::IpVersion) -> Self {
         match version {
@@ -483,6 +757,104 @@ impl From<&mullvad_types::settings::Settings> for Settings {
             show_beta_releases: settings.show_beta_releases,
             obfuscation_settings: Some(ObfuscationSettings::from(&settings.obfuscation_settings)),
             split_tunnel,
+            #[cfg(target_os = "linux")]
+            forwarded_interfaces: settings
+                .forwarded_interfaces
+                .iter()
+                .cloned()
+                .map(ForwardedInterface::from)
+                .collect(),
+            #[cfg(not(target_os = "linux"))]
+            forwarded_interfaces: vec![],
+            #[cfg(target_os = "linux")]
+            ipv6_firewall_mode: Some(Ipv6FirewallMode::from(settings.ipv6_firewall_mode)),
+            #[cfg(not(target_os = "linux"))]
+            ipv6_firewall_mode: None,
+            #[cfg(target_os = "linux")]
+            log_firewall_drops: settings.log_firewall_drops,
+            #[cfg(not(target_os = "linux"))]
+            log_firewall_drops: false,
+        }
+    }
+}
+
+impl From<talpid_types::net::ForwardedInterface> for ForwardedInterface {
+    fn from(forwarded: talpid_types::net::ForwardedInterface) -> Self {
+        use talpid_types::net::ForwardingMode;
+
+        ForwardedInterface {
+            interface: forwarded.interface,
+            mode: i32::from(match forwarded.mode {
+                ForwardingMode::Tunnel => forwarded_interface::Mode::Tunnel,
+                ForwardingMode::Local => forwarded_interface::Mode::Local,
+            }),
+        }
+    }
+}
+
+impl From<Option<talpid_types::net::wireguard::Backend>> for wireguard_backend::Backend {
+    fn from(backend: Option<talpid_types::net::wireguard::Backend>) -> Self {
+        use talpid_types::net::wireguard::Backend;
+
+        match backend {
+            None => wireguard_backend::Backend::Auto,
+            Some(Backend::Kernel) => wireguard_backend::Backend::Kernel,
+            Some(Backend::NetworkManager) => wireguard_backend::Backend::NetworkManager,
+            Some(Backend::Userspace) => wireguard_backend::Backend::Userspace,
+        }
+    }
+}
+
+impl From<talpid_types::net::wireguard::BackendInfo> for WireguardBackendInfo {
+    fn from(info: talpid_types::net::wireguard::BackendInfo) -> Self {
+        use talpid_types::net::wireguard::BackendReason;
+
+        let (reason, failed_backend) = match info.reason {
+            BackendReason::Selected => (wireguard_backend_info::Reason::Selected, None),
+            BackendReason::Forced => (wireguard_backend_info::Reason::Forced, None),
+            BackendReason::Automatic => (wireguard_backend_info::Reason::Automatic, None),
+            BackendReason::Fallback(backend) => {
+                (wireguard_backend_info::Reason::Fallback, Some(backend))
+            }
+        };
+        WireguardBackendInfo {
+            backend: i32::from(wireguard_backend::Backend::from(Some(info.backend))),
+            reason: i32::from(reason),
+            failed_backend: i32::from(wireguard_backend::Backend::from(failed_backend)),
+        }
+    }
+}
+
+impl From<talpid_types::net::wireguard::ConnectivityProfile> for ConnectivityProfile {
+    fn from(profile: talpid_types::net::wireguard::ConnectivityProfile) -> Self {
+        use talpid_types::net::wireguard::ConnectivityProfile as Profile;
+
+        let preset = match profile {
+            Profile::Aggressive => connectivity_profile::Preset::Aggressive,
+            Profile::Default => connectivity_profile::Preset::Default,
+            Profile::Tolerant => connectivity_profile::Preset::Tolerant,
+            Profile::Custom(_) => connectivity_profile::Preset::Custom,
+        };
+        let timeouts = profile.timeouts();
+        ConnectivityProfile {
+            preset: i32::from(preset),
+            rx_timeout: Some(Duration::from(timeouts.rx_timeout)),
+            ping_timeout: Some(Duration::from(timeouts.ping_timeout)),
+            establish_timeout: Some(Duration::from(timeouts.establish_timeout)),
+        }
+    }
+}
+
+impl From<talpid_types::net::Ipv6FirewallMode> for Ipv6FirewallMode {
+    fn from(mode: talpid_types::net::Ipv6FirewallMode) -> Self {
+        use talpid_types::net::Ipv6FirewallMode as Mode;
+
+        Ipv6FirewallMode {
+            mode: i32::from(match mode {
+                Mode::Default => ipv6_firewall_mode::Mode::Default,
+                Mode::BlockOutsideTunnel => ipv6_firewall_mode::Mode::BlockOutsideTunnel,
+                Mode::TunnelOnly => ipv6_firewall_mode::Mode::TunnelOnly,
+            }),
         }
     }
 }
@@ -507,10 +879,24 @@ impl From<&mullvad_types::relay_constraints::ObfuscationSettings> for Obfuscatio
             SelectedObfuscation::Auto => obfuscation_settings::SelectedObfuscation::Auto,
             SelectedObfuscation::Off => obfuscation_settings::SelectedObfuscation::Off,
             SelectedObfuscation::Udp2Tcp => obfuscation_settings::SelectedObfuscation::Udp2tcp,
+            SelectedObfuscation::Shadowsocks => {
+                obfuscation_settings::SelectedObfuscation::Shadowsocks
+            }
         });
         Self {
             selected_obfuscation,
             udp2tcp: Some(Udp2TcpObfuscationSettings::from(&settings.udp2tcp)),
+            shadowsocks: Some(ShadowsocksObfuscationSettings::from(&settings.shadowsocks)),
+        }
+    }
+}
+
+impl From<&mullvad_types::relay_constraints::ShadowsocksObfuscationSettings>
+    for ShadowsocksObfuscationSettings
+{
+    fn from(settings: &mullvad_types::relay_constraints::ShadowsocksObfuscationSettings) -> Self {
+        Self {
+            port: u32::from(settings.port.unwrap_or(0)),
         }
     }
 }
@@ -652,6 +1038,18 @@ impl From<&mullvad_types::settings::DnsOptions> for DnsOptions {
                 block_malware: options.default_options.block_malware,
                 block_adult_content: options.default_options.block_adult_content,
                 block_gambling: options.default_options.block_gambling,
+                #[cfg(target_os = "linux")]
+                encryption: match options.default_options.encryption {
+                    None => default_dns_options::Encryption::None as i32,
+                    Some(talpid_types::net::dns::EncryptedDnsProtocol::Tls) => {
+                        default_dns_options::Encryption::Tls as i32
+                    }
+                    Some(talpid_types::net::dns::EncryptedDnsProtocol::Https) => {
+                        default_dns_options::Encryption::Https as i32
+                    }
+                },
+                #[cfg(not(target_os = "linux"))]
+                encryption: default_dns_options::Encryption::None as i32,
             }),
             custom_options: Some(CustomDnsOptions {
                 addresses: options
@@ -660,7 +1058,86 @@ impl From<&mullvad_types::settings::DnsOptions> for DnsOptions {
                     .iter()
                     .map(|addr| addr.to_string())
                     .collect(),
+                #[cfg(target_os = "linux")]
+                encrypted_servers: options
+                    .custom_options
+                    .encrypted_servers
+                    .iter()
+                    .map(EncryptedDnsServer::from)
+                    .collect(),
+                #[cfg(not(target_os = "linux"))]
+                encrypted_servers: vec![],
             }),
+            #[cfg(target_os = "linux")]
+            split_dns: options.split_dns.iter().map(SplitDnsRule::from).collect(),
+            #[cfg(not(target_os = "linux"))]
+            split_dns: vec![],
+            #[cfg(target_os = "linux")]
+            local_rules: Some(LocalDnsRules::from(&options.local_rules)),
+            #[cfg(not(target_os = "linux"))]
+            local_rules: None,
+            #[cfg(target_os = "linux")]
+            query_log: options.query_log,
+            #[cfg(not(target_os = "linux"))]
+            query_log: false,
+            #[cfg(target_os = "linux")]
+            blocked_state_servers: options
+                .blocked_state_servers
+                .iter()
+                .map(|addr| addr.to_string())
+                .collect(),
+            #[cfg(not(target_os = "linux"))]
+            blocked_state_servers: vec![],
+        }
+    }
+}
+
+impl From<&talpid_types::net::dns::SplitDnsRule> for SplitDnsRule {
+    fn from(rule: &talpid_types::net::dns::SplitDnsRule) -> Self {
+        SplitDnsRule {
+            domain: rule.domain.clone(),
+            servers: rule
+                .servers
+                .iter()
+                .map(|server| server.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl From<&talpid_types::net::dns::LocalDnsRules> for LocalDnsRules {
+    fn from(rules: &talpid_types::net::dns::LocalDnsRules) -> Self {
+        LocalDnsRules {
+            blocked_domains: rules.blocked_domains.clone(),
+            overrides: rules
+                .overrides
+                .iter()
+                .map(|entry| DnsOverride {
+                    name: entry.name.clone(),
+                    address: entry.address.to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&talpid_types::net::dns::EncryptedDnsServer> for EncryptedDnsServer {
+    fn from(server: &talpid_types::net::dns::EncryptedDnsServer) -> Self {
+        use talpid_types::net::dns::EncryptedDnsProtocol;
+
+        EncryptedDnsServer {
+            protocol: match server.protocol {
+                EncryptedDnsProtocol::Tls => encrypted_dns_server::Protocol::Tls as i32,
+                EncryptedDnsProtocol::Https => encrypted_dns_server::Protocol::Https as i32,
+            },
+            hostname: server.hostname.clone(),
+            port: u32::from(server.port),
+            bootstrap_addresses: server
+                .bootstrap_addresses
+                .iter()
+                .map(|address| address.to_string())
+                .collect(),
+            spki_pin: server.spki_pin.clone().unwrap_or_default(),
         }
     }
 }
@@ -682,6 +1159,32 @@ impl From<&mullvad_types::settings::TunnelOptions> for TunnelOptions {
                 #[cfg(not(windows))]
                 use_wireguard_nt: false,
                 use_pq_safe_psk: options.wireguard.options.use_pq_safe_psk,
+                persistent_keepalive: u32::from(
+                    options
+                        .wireguard
+                        .options
+                        .persistent_keepalive
+                        .unwrap_or_default(),
+                ),
+                #[cfg(target_os = "linux")]
+                mtu_discovery: options.wireguard.options.mtu_discovery,
+                #[cfg(not(target_os = "linux"))]
+                mtu_discovery: false,
+                #[cfg(target_os = "linux")]
+                backend: i32::from(wireguard_backend::Backend::from(
+                    options.wireguard.options.backend,
+                )),
+                #[cfg(not(target_os = "linux"))]
+                backend: i32::from(wireguard_backend::Backend::Auto),
+                connectivity_profile: Some(ConnectivityProfile::from(
+                    options.wireguard.options.connectivity_profile,
+                )),
+                probe_address: options
+                    .wireguard
+                    .options
+                    .probe_address
+                    .map(|address| address.to_string())
+                    .unwrap_or_default(),
             }),
             generic: Some(tunnel_options::GenericOptions {
                 enable_ipv6: options.generic.enable_ipv6,
@@ -732,17 +1235,23 @@ impl From<mullvad_types::relay_list::BridgeEndpointData> for BridgeEndpointData
             shadowsocks: bridge
                 .shadowsocks
                 .into_iter()
-                .map(|endpoint| ShadowsocksEndpointData {
-                    port: u32::from(endpoint.port),
-                    cipher: endpoint.cipher,
-                    password: endpoint.password,
-                    protocol: TransportProtocol::from(endpoint.protocol) as i32,
-                })
+                .map(ShadowsocksEndpointData::from)
                 .collect(),
         }
     }
 }
 
+impl From<mullvad_types::relay_list::ShadowsocksEndpointData> for ShadowsocksEndpointData {
+    fn from(endpoint: mullvad_types::relay_list::ShadowsocksEndpointData) -> Self {
+        ShadowsocksEndpointData {
+            port: u32::from(endpoint.port),
+            cipher: endpoint.cipher,
+            password: endpoint.password,
+            protocol: TransportProtocol::from(endpoint.protocol) as i32,
+        }
+    }
+}
+
 impl From<mullvad_types::relay_list::WireguardEndpointData> for WireguardEndpointData {
     fn from(wireguard: mullvad_types::relay_list::WireguardEndpointData) -> Self {
         WireguardEndpointData {
@@ -757,6 +1266,11 @@ impl From<mullvad_types::relay_list::WireguardEndpointData> for WireguardEndpoin
             ipv4_gateway: wireguard.ipv4_gateway.to_string(),
             ipv6_gateway: wireguard.ipv6_gateway.to_string(),
             udp2tcp_ports: wireguard.udp2tcp_ports.into_iter().map(u32::from).collect(),
+            shadowsocks: wireguard
+                .shadowsocks
+                .into_iter()
+                .map(ShadowsocksEndpointData::from)
+                .collect(),
         }
     }
 }
@@ -1235,8 +1749,12 @@ impl TryFrom<ConnectionConfig> for mullvad_types::ConnectionConfig {
                 let peer = config.peer.ok_or(FromProtobufTypeError::InvalidArgument(
                     "missing peer config",
                 ))?;
-
-                let public_key = bytes_to_pubkey(&peer.public_key)?;
+                let peer = wireguard::PeerConfig::try_from(peer)?;
+                let additional_peers = config
+                    .additional_peers
+                    .into_iter()
+                    .map(wireguard::PeerConfig::try_from)
+                    .collect::<Result<_, _>>()?;
 
                 let ipv4_gateway = match config.ipv4_gateway.parse() {
                     Ok(address) => address,
@@ -1260,15 +1778,6 @@ impl TryFrom<ConnectionConfig> for mullvad_types::ConnectionConfig {
                     None
                 };
 
-                let endpoint = match peer.endpoint.parse() {
-                    Ok(address) => address,
-                    Err(_) => {
-                        return Err(FromProtobufTypeError::InvalidArgument(
-                            "invalid peer address",
-                        ))
-                    }
-                };
-
                 let mut tunnel_addresses = Vec::new();
                 for address in tunnel.addresses {
                     let address = address
@@ -1277,27 +1786,15 @@ impl TryFrom<ConnectionConfig> for mullvad_types::ConnectionConfig {
                     tunnel_addresses.push(address);
                 }
 
-                let mut allowed_ips = Vec::new();
-                for address in peer.allowed_ips {
-                    let address = address
-                        .parse()
-                        .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid address"))?;
-                    allowed_ips.push(address);
-                }
-
                 Ok(mullvad_types::ConnectionConfig::Wireguard(
                     wireguard::ConnectionConfig {
                         tunnel: wireguard::TunnelConfig {
                             private_key: wireguard::PrivateKey::from(private_key),
                             addresses: tunnel_addresses,
                         },
-                        peer: wireguard::PeerConfig {
-                            public_key,
-                            allowed_ips,
-                            endpoint,
-                            psk: None,
-                        },
+                        peer,
                         exit_peer: None,
+                        additional_peers,
                         ipv4_gateway,
                         ipv6_gateway,
                     },
@@ -1307,6 +1804,71 @@ impl TryFrom<ConnectionConfig> for mullvad_types::ConnectionConfig {
     }
 }
 
+impl From<&wireguard::PeerConfig> for connection_config::wireguard_config::PeerConfig {
+    fn from(peer: &wireguard::PeerConfig) -> Self {
+        Self {
+            public_key: peer.public_key.as_bytes().to_vec(),
+            allowed_ips: peer
+                .allowed_ips
+                .iter()
+                .map(|address| address.to_string())
+                .collect(),
+            endpoint: peer.endpoint.to_string(),
+            psk: peer
+                .psk
+                .as_ref()
+                .map(|psk| psk.as_bytes().to_vec())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl TryFrom<connection_config::wireguard_config::PeerConfig> for wireguard::PeerConfig {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(
+        peer: connection_config::wireguard_config::PeerConfig,
+    ) -> Result<Self, Self::Error> {
+        let public_key = bytes_to_pubkey(&peer.public_key)?;
+        let psk = if !peer.psk.is_empty() {
+            if peer.psk.len() != 32 {
+                return Err(FromProtobufTypeError::InvalidArgument(
+                    "invalid preshared key",
+                ));
+            }
+            let mut psk = [0; 32];
+            psk.copy_from_slice(&peer.psk);
+            Some(wireguard::PresharedKey::from(psk))
+        } else {
+            None
+        };
+
+        let endpoint = match peer.endpoint.parse() {
+            Ok(address) => address,
+            Err(_) => {
+                return Err(FromProtobufTypeError::InvalidArgument(
+                    "invalid peer address",
+                ))
+            }
+        };
+
+        let mut allowed_ips = Vec::new();
+        for address in peer.allowed_ips {
+            let address = address
+                .parse()
+                .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid address"))?;
+            allowed_ips.push(address);
+        }
+
+        Ok(wireguard::PeerConfig {
+            public_key,
+            allowed_ips,
+            endpoint,
+            psk,
+        })
+    }
+}
+
 fn bytes_to_pubkey(bytes: &[u8]) -> Result<wireguard::PublicKey, FromProtobufTypeError> {
     if bytes.len() != 32 {
         return Err(FromProtobufTypeError::InvalidArgument("invalid public key"));
@@ -1421,6 +1983,7 @@ impl TryFrom<ObfuscationSettings> for mullvad_types::relay_constraints::Obfuscat
                 Some(IpcSelectedObfuscation::Auto) => SelectedObfuscation::Auto,
                 Some(IpcSelectedObfuscation::Off) => SelectedObfuscation::Off,
                 Some(IpcSelectedObfuscation::Udp2tcp) => SelectedObfuscation::Udp2Tcp,
+                Some(IpcSelectedObfuscation::Shadowsocks) => SelectedObfuscation::Shadowsocks,
                 None => {
                     return Err(FromProtobufTypeError::InvalidArgument(
                         "invalid selected obfuscator",
@@ -1439,9 +2002,20 @@ impl TryFrom<ObfuscationSettings> for mullvad_types::relay_constraints::Obfuscat
             }
         };
 
+        let shadowsocks = settings
+            .shadowsocks
+            .map(|settings| {
+                mullvad_types::relay_constraints::ShadowsocksObfuscationSettings::try_from(
+                    &settings,
+                )
+            })
+            .transpose()?
+            .unwrap_or_default();
+
         Ok(Self {
             selected_obfuscation,
             udp2tcp,
+            shadowsocks,
         })
     }
 }
@@ -1462,6 +2036,22 @@ impl TryFrom<&Udp2TcpObfuscationSettings>
     }
 }
 
+impl TryFrom<&ShadowsocksObfuscationSettings>
+    for mullvad_types::relay_constraints::ShadowsocksObfuscationSettings
+{
+    type Error = FromProtobufTypeError;
+
+    fn try_from(settings: &ShadowsocksObfuscationSettings) -> Result<Self, Self::Error> {
+        Ok(Self {
+            port: if settings.port == 0 {
+                Constraint::Any
+            } else {
+                Constraint::Only(settings.port as u16)
+            },
+        })
+    }
+}
+
 impl TryFrom<BridgeState> for mullvad_types::relay_constraints::BridgeState {
     type Error = FromProtobufTypeError;
 
@@ -1481,6 +2071,118 @@ impl TryFrom<BridgeState> for mullvad_types::relay_constraints::BridgeState {
     }
 }
 
+impl TryFrom<ForwardedInterface> for talpid_types::net::ForwardedInterface {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(forwarded: ForwardedInterface) -> Result<Self, Self::Error> {
+        use talpid_types::net::ForwardingMode;
+
+        if forwarded.interface.is_empty() {
+            return Err(FromProtobufTypeError::InvalidArgument(
+                "missing interface name",
+            ));
+        }
+        let mode = match forwarded_interface::Mode::from_i32(forwarded.mode) {
+            Some(forwarded_interface::Mode::Tunnel) => ForwardingMode::Tunnel,
+            Some(forwarded_interface::Mode::Local) => ForwardingMode::Local,
+            None => {
+                return Err(FromProtobufTypeError::InvalidArgument(
+                    "invalid forwarding mode",
+                ))
+            }
+        };
+        Ok(talpid_types::net::ForwardedInterface {
+            interface: forwarded.interface,
+            mode,
+        })
+    }
+}
+
+impl TryFrom<WireguardBackend> for Option<talpid_types::net::wireguard::Backend> {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(backend: WireguardBackend) -> Result<Self, Self::Error> {
+        try_wireguard_backend_from_i32(backend.backend)
+    }
+}
+
+fn try_wireguard_backend_from_i32(
+    backend: i32,
+) -> Result<Option<talpid_types::net::wireguard::Backend>, FromProtobufTypeError> {
+    use talpid_types::net::wireguard::Backend;
+
+    match wireguard_backend::Backend::from_i32(backend) {
+        Some(wireguard_backend::Backend::Auto) => Ok(None),
+        Some(wireguard_backend::Backend::Kernel) => Ok(Some(Backend::Kernel)),
+        Some(wireguard_backend::Backend::NetworkManager) => Ok(Some(Backend::NetworkManager)),
+        Some(wireguard_backend::Backend::Userspace) => Ok(Some(Backend::Userspace)),
+        None => Err(FromProtobufTypeError::InvalidArgument(
+            "invalid WireGuard backend",
+        )),
+    }
+}
+
+impl TryFrom<ConnectivityProfile> for talpid_types::net::wireguard::ConnectivityProfile {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(profile: ConnectivityProfile) -> Result<Self, Self::Error> {
+        use talpid_types::net::wireguard::{ConnectivityProfile as Profile, ConnectivityTimeouts};
+
+        match connectivity_profile::Preset::from_i32(profile.preset) {
+            Some(connectivity_profile::Preset::Default) => Ok(Profile::Default),
+            Some(connectivity_profile::Preset::Aggressive) => Ok(Profile::Aggressive),
+            Some(connectivity_profile::Preset::Tolerant) => Ok(Profile::Tolerant),
+            Some(connectivity_profile::Preset::Custom) => {
+                let to_timeout = |timeout: Option<Duration>| {
+                    timeout
+                        .and_then(|timeout| std::time::Duration::try_from(timeout).ok())
+                        .filter(|timeout| !timeout.is_zero())
+                        .ok_or(FromProtobufTypeError::InvalidArgument(
+                            "invalid connectivity timeout",
+                        ))
+                };
+                Ok(Profile::Custom(ConnectivityTimeouts {
+                    rx_timeout: to_timeout(profile.rx_timeout)?,
+                    ping_timeout: to_timeout(profile.ping_timeout)?,
+                    establish_timeout: to_timeout(profile.establish_timeout)?,
+                }))
+            }
+            None => Err(FromProtobufTypeError::InvalidArgument(
+                "invalid connectivity profile",
+            )),
+        }
+    }
+}
+
+pub fn try_probe_address_from_str(
+    address: &str,
+) -> Result<Option<std::net::Ipv4Addr>, FromProtobufTypeError> {
+    if address.is_empty() {
+        return Ok(None);
+    }
+    address
+        .parse()
+        .map(Some)
+        .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid probe address"))
+}
+
+impl TryFrom<Ipv6FirewallMode> for talpid_types::net::Ipv6FirewallMode {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(mode: Ipv6FirewallMode) -> Result<Self, Self::Error> {
+        use talpid_types::net::Ipv6FirewallMode as Mode;
+
+        match ipv6_firewall_mode::Mode::from_i32(mode.mode) {
+            Some(ipv6_firewall_mode::Mode::Default) => Ok(Mode::Default),
+            Some(ipv6_firewall_mode::Mode::BlockOutsideTunnel) => Ok(Mode::BlockOutsideTunnel),
+            Some(ipv6_firewall_mode::Mode::TunnelOnly) => Ok(Mode::TunnelOnly),
+            None => Err(FromProtobufTypeError::InvalidArgument(
+                "invalid IPv6 firewall mode",
+            )),
+        }
+    }
+}
+
 impl TryFrom<TunnelOptions> for mullvad_types::settings::TunnelOptions {
     type Error = FromProtobufTypeError;
 
@@ -1524,6 +2226,21 @@ impl TryFrom<TunnelOptions> for mullvad_types::settings::TunnelOptions {
                         None
                     },
                     use_pq_safe_psk: wireguard_options.use_pq_safe_psk,
+                    persistent_keepalive: if wireguard_options.persistent_keepalive != 0 {
+                        Some(wireguard_options.persistent_keepalive as u16)
+                    } else {
+                        None
+                    },
+                    #[cfg(target_os = "linux")]
+                    mtu_discovery: wireguard_options.mtu_discovery,
+                    #[cfg(target_os = "linux")]
+                    backend: try_wireguard_backend_from_i32(wireguard_options.backend)?,
+                    connectivity_profile: wireguard_options
+                        .connectivity_profile
+                        .map(net::wireguard::ConnectivityProfile::try_from)
+                        .transpose()?
+                        .unwrap_or_default(),
+                    probe_address: try_probe_address_from_str(&wireguard_options.probe_address)?,
                     #[cfg(windows)]
                     use_wireguard_nt: wireguard_options.use_wireguard_nt,
                 },
@@ -1592,6 +2309,23 @@ impl TryFrom<DnsOptions> for mullvad_types::settings::DnsOptions {
                 block_malware: default_options.block_malware,
                 block_adult_content: default_options.block_adult_content,
                 block_gambling: default_options.block_gambling,
+                #[cfg(target_os = "linux")]
+                encryption: match default_dns_options::Encryption::from_i32(
+                    default_options.encryption,
+                ) {
+                    Some(default_dns_options::Encryption::None) => None,
+                    Some(default_dns_options::Encryption::Tls) => {
+                        Some(talpid_types::net::dns::EncryptedDnsProtocol::Tls)
+                    }
+                    Some(default_dns_options::Encryption::Https) => {
+                        Some(talpid_types::net::dns::EncryptedDnsProtocol::Https)
+                    }
+                    None => {
+                        return Err(FromProtobufTypeError::InvalidArgument(
+                            "invalid default DNS encryption",
+                        ))
+                    }
+                },
             },
             custom_options: MullvadCustomDnsOptions {
                 addresses: custom_options
@@ -1603,11 +2337,147 @@ impl TryFrom<DnsOptions> for mullvad_types::settings::DnsOptions {
                         })
                     })
                     .collect::<Result<Vec<_>, _>>()?,
+                #[cfg(target_os = "linux")]
+                encrypted_servers: custom_options
+                    .encrypted_servers
+                    .into_iter()
+                    .map(talpid_types::net::dns::EncryptedDnsServer::try_from)
+                    .collect::<Result<Vec<_>, _>>()?,
             },
+            #[cfg(target_os = "linux")]
+            split_dns: options
+                .split_dns
+                .into_iter()
+                .map(talpid_types::net::dns::SplitDnsRule::try_from)
+                .collect::<Result<Vec<_>, _>>()?,
+            #[cfg(target_os = "linux")]
+            local_rules: options
+                .local_rules
+                .map(talpid_types::net::dns::LocalDnsRules::try_from)
+                .transpose()?
+                .unwrap_or_default(),
+            #[cfg(target_os = "linux")]
+            query_log: options.query_log,
+            #[cfg(target_os = "linux")]
+            blocked_state_servers: options
+                .blocked_state_servers
+                .into_iter()
+                .map(|addr| {
+                    addr.parse()
+                        .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid IP address"))
+                })
+                .collect::<Result<Vec<_>, _>>()?,
         })
     }
 }
 
+impl TryFrom<LocalDnsRules> for talpid_types::net::dns::LocalDnsRules {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(rules: LocalDnsRules) -> Result<Self, Self::Error> {
+        if rules.blocked_domains.iter().any(|domain| domain.is_empty()) {
+            return Err(FromProtobufTypeError::InvalidArgument(
+                "empty blocked domain",
+            ));
+        }
+        Ok(talpid_types::net::dns::LocalDnsRules {
+            blocked_domains: rules.blocked_domains,
+            overrides: rules
+                .overrides
+                .into_iter()
+                .map(|entry| {
+                    if entry.name.is_empty() {
+                        return Err(FromProtobufTypeError::InvalidArgument(
+                            "empty DNS override name",
+                        ));
+                    }
+                    Ok(talpid_types::net::dns::DnsOverride {
+                        name: entry.name,
+                        address: entry.address.parse().map_err(|_| {
+                            FromProtobufTypeError::InvalidArgument("invalid IP address")
+                        })?,
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        })
+    }
+}
+
+impl TryFrom<SplitDnsRule> for talpid_types::net::dns::SplitDnsRule {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(rule: SplitDnsRule) -> Result<Self, Self::Error> {
+        if rule.domain.is_empty() {
+            return Err(FromProtobufTypeError::InvalidArgument(
+                "missing split DNS domain",
+            ));
+        }
+        if rule.servers.is_empty() {
+            return Err(FromProtobufTypeError::InvalidArgument(
+                "missing split DNS servers",
+            ));
+        }
+        Ok(talpid_types::net::dns::SplitDnsRule {
+            domain: rule.domain,
+            servers: rule
+                .servers
+                .into_iter()
+                .map(|server| {
+                    server
+                        .parse()
+                        .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid IP address"))
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        })
+    }
+}
+
+impl TryFrom<EncryptedDnsServer> for talpid_types::net::dns::EncryptedDnsServer {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(server: EncryptedDnsServer) -> Result<Self, Self::Error> {
+        use talpid_types::net::dns::EncryptedDnsProtocol;
+
+        let protocol = match encrypted_dns_server::Protocol::from_i32(server.protocol) {
+            Some(encrypted_dns_server::Protocol::Tls) => EncryptedDnsProtocol::Tls,
+            Some(encrypted_dns_server::Protocol::Https) => EncryptedDnsProtocol::Https,
+            None => {
+                return Err(FromProtobufTypeError::InvalidArgument(
+                    "invalid encrypted DNS protocol",
+                ))
+            }
+        };
+        let port = match u16::try_from(server.port) {
+            Ok(0) => protocol.default_port(),
+            Ok(port) => port,
+            Err(_) => {
+                return Err(FromProtobufTypeError::InvalidArgument(
+                    "invalid encrypted DNS port",
+                ))
+            }
+        };
+        let server = talpid_types::net::dns::EncryptedDnsServer {
+            protocol,
+            hostname: server.hostname,
+            port,
+            bootstrap_addresses: server
+                .bootstrap_addresses
+                .into_iter()
+                .map(|address| {
+                    address
+                        .parse()
+                        .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid IP address"))
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            spki_pin: Some(server.spki_pin).filter(|pin| !pin.is_empty()),
+        };
+        server
+            .validate()
+            .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid encrypted DNS server"))?;
+        Ok(server)
+    }
+}
+
 impl TryFrom<TransportPort> for mullvad_types::relay_constraints::TransportPort {
     type Error = FromProtobufTypeError;
 
@@ -9,8 +9,8 @@ use mullvad_types::{
     relay_constraints::{
         BridgeSettings, BridgeState, Constraint, InternalBridgeConstraints, LocationConstraint,
         Match, ObfuscationSettings, OpenVpnConstraints, Ownership, Providers, RelayConstraints,
-        RelaySettings, SelectedObfuscation, Set, TransportPort, Udp2TcpObfuscationSettings,
-        WireguardConstraints,
+        RelaySettings, SelectedObfuscation, Set, ShadowsocksObfuscationSettings, TransportPort,
+        Udp2TcpObfuscationSettings, WireguardConstraints,
     },
     relay_list::{BridgeEndpointData, Relay, RelayEndpointData, RelayList},
     CustomTunnelEndpoint,
@@ -26,8 +26,8 @@ use std::{
 };
 use talpid_types::{
     net::{
-        obfuscation::ObfuscatorConfig, openvpn::ProxySettings, wireguard, IpVersion,
-        TransportProtocol, TunnelType,
+        obfuscation::ObfuscatorConfig, openvpn::ProxySettings, wireguard, IpAvailability,
+        IpVersion, TransportProtocol, TunnelType,
     },
     ErrorExt,
 };
@@ -214,9 +214,11 @@ impl RelaySelector {
     }
 
     /// Returns a random relay and relay endpoint matching the current constraints.
+    /// `ip_availability` holds the IP versions that the host can reach relays with, if known.
     pub fn get_relay(
         &self,
         retry_attempt: u32,
+        ip_availability: Option<IpAvailability>,
     ) -> Result<
         (
             SelectedRelay,
@@ -231,8 +233,12 @@ impl RelaySelector {
                 Ok((SelectedRelay::Custom(custom_relay.clone()), None, None))
             }
             RelaySettings::Normal(constraints) => {
-                let relay =
-                    self.get_tunnel_endpoint(constraints, config.bridge_state, retry_attempt)?;
+                let relay = self.get_tunnel_endpoint_for_ip_availability(
+                    constraints,
+                    config.bridge_state,
+                    retry_attempt,
+                    ip_availability,
+                )?;
                 let bridge = match relay.endpoint {
                     MullvadEndpoint::OpenVpn(endpoint)
                         if endpoint.protocol == TransportProtocol::Tcp =>
@@ -264,6 +270,31 @@ impl RelaySelector {
         }
     }
 
+    /// Like [Self::get_tunnel_endpoint], but prefers WireGuard relays with an IPv6 address when
+    /// the host can only reach the internet over IPv6. If no such relay matches, IPv4 relays are
+    /// selected and have to be reached through NAT64.
+    fn get_tunnel_endpoint_for_ip_availability(
+        &self,
+        relay_constraints: &RelayConstraints,
+        bridge_state: BridgeState,
+        retry_attempt: u32,
+        ip_availability: Option<IpAvailability>,
+    ) -> Result<NormalSelectedRelay, Error> {
+        if ip_availability == Some(IpAvailability::Ipv6)
+            && relay_constraints.wireguard_constraints.ip_version.is_any()
+            && relay_constraints.tunnel_protocol != Constraint::Only(TunnelType::OpenVpn)
+        {
+            let mut ipv6_constraints = relay_constraints.clone();
+            ipv6_constraints.tunnel_protocol = Constraint::Only(TunnelType::Wireguard);
+            ipv6_constraints.wireguard_constraints.ip_version = Constraint::Only(IpVersion::V6);
+            match self.get_tunnel_endpoint(&ipv6_constraints, bridge_state, retry_attempt) {
+                Ok(result) => return Ok(result),
+                Err(_) => log::debug!("No relay with an IPv6 address matches the constraints"),
+            }
+        }
+        self.get_tunnel_endpoint(relay_constraints, bridge_state, retry_attempt)
+    }
+
     /// Returns a random relay and relay endpoint matching the given constraints and with
     /// preferences applied.
     fn get_tunnel_endpoint(
@@ -862,6 +893,15 @@ impl RelaySelector {
                 )
                 .ok_or(Error::NoObfuscator)?,
             )),
+            SelectedObfuscation::Shadowsocks => Ok(Some(
+                self.get_shadowsocks_obfuscator(
+                    &config.obfuscation_settings.shadowsocks,
+                    relay,
+                    endpoint,
+                    retry_attempt,
+                )
+                .ok_or(Error::NoObfuscator)?,
+            )),
         }
     }
 
@@ -872,24 +912,23 @@ impl RelaySelector {
         endpoint: &MullvadWireguardEndpoint,
         retry_attempt: u32,
     ) -> Option<SelectedObfuscator> {
-        if !self.should_use_auto_obfuscator(retry_attempt) {
-            return None;
+        // Alternate between the obfuscators, and use a different port each time an obfuscator is
+        // tried again.
+        let port_retry_attempt = retry_attempt / 4;
+        match self.get_auto_obfuscator_retry_attempt(retry_attempt)? {
+            0 => self.get_udp2tcp_obfuscator(
+                &obfuscation_settings.udp2tcp,
+                relay,
+                endpoint,
+                port_retry_attempt,
+            ),
+            _ => self.get_shadowsocks_obfuscator(
+                &obfuscation_settings.shadowsocks,
+                relay,
+                endpoint,
+                port_retry_attempt,
+            ),
         }
-        // TODO FIX: The third obfuscator entry will never be chosen
-        // Because get_auto_obfuscator_retry_attempt() returns [0, 1]
-        // And the udp2tcp endpoints are defined in a vector with entries [0, 1, 2]
-        self.get_udp2tcp_obfuscator(
-            &obfuscation_settings.udp2tcp,
-            relay,
-            endpoint,
-            self.get_auto_obfuscator_retry_attempt(retry_attempt)
-                .unwrap(),
-        )
-    }
-
-    fn should_use_auto_obfuscator(&self, retry_attempt: u32) -> bool {
-        self.get_auto_obfuscator_retry_attempt(retry_attempt)
-            .is_some()
     }
 
     fn get_auto_obfuscator_retry_attempt(&self, retry_attempt: u32) -> Option<u32> {
@@ -924,6 +963,44 @@ impl RelaySelector {
             })
     }
 
+    fn get_shadowsocks_obfuscator(
+        &self,
+        obfuscation_settings: &ShadowsocksObfuscationSettings,
+        relay: &Relay,
+        endpoint: &MullvadWireguardEndpoint,
+        retry_attempt: u32,
+    ) -> Option<SelectedObfuscator> {
+        let parsed_relays = self.parsed_relays.lock();
+        // WireGuard only sends UDP packets, so only the UDP servers are of interest.
+        let shadowsocks_endpoints: Vec<_> = parsed_relays
+            .locations
+            .wireguard
+            .shadowsocks
+            .iter()
+            .filter(|candidate| candidate.protocol == TransportProtocol::Udp)
+            .collect();
+        let shadowsocks_endpoint = if obfuscation_settings.port.is_only() {
+            shadowsocks_endpoints
+                .iter()
+                .find(|candidate| obfuscation_settings.port == Constraint::Only(candidate.port))
+        } else if !shadowsocks_endpoints.is_empty() {
+            shadowsocks_endpoints.get(retry_attempt as usize % shadowsocks_endpoints.len())
+        } else {
+            None
+        };
+        shadowsocks_endpoint
+            .map(|shadowsocks_endpoint| ObfuscatorConfig::Shadowsocks {
+                endpoint: SocketAddr::new(endpoint.peer.endpoint.ip(), shadowsocks_endpoint.port),
+                wireguard_endpoint: endpoint.peer.endpoint,
+                cipher: shadowsocks_endpoint.cipher.clone(),
+                password: shadowsocks_endpoint.password.clone(),
+            })
+            .map(|config| SelectedObfuscator {
+                config,
+                relay: relay.clone(),
+            })
+    }
+
     /// Returns preferred constraints
     #[allow(unused_variables)]
     fn preferred_tunnel_constraints(
@@ -1209,7 +1286,7 @@ mod test {
         relay_constraints::{BridgeConstraints, RelayConstraints},
         relay_list::{
             OpenVpnEndpoint, OpenVpnEndpointData, Relay, RelayListCity, RelayListCountry,
-            WireguardEndpointData, WireguardRelayEndpointData,
+            ShadowsocksEndpointData, WireguardEndpointData, WireguardRelayEndpointData,
         },
     };
     use talpid_types::net::wireguard::PublicKey;
@@ -1297,6 +1374,26 @@ mod test {
                 ipv4_gateway: "10.64.0.1".parse().unwrap(),
                 ipv6_gateway: "fc00:bbbb:bbbb:bb01::1".parse().unwrap(),
                 udp2tcp_ports: vec![],
+                shadowsocks: vec![
+                    ShadowsocksEndpointData {
+                        port: 443,
+                        cipher: "aes-256-gcm".to_string(),
+                        password: "mullvad".to_string(),
+                        protocol: TransportProtocol::Udp,
+                    },
+                    ShadowsocksEndpointData {
+                        port: 1234,
+                        cipher: "aes-256-gcm".to_string(),
+                        password: "mullvad".to_string(),
+                        protocol: TransportProtocol::Tcp,
+                    },
+                    ShadowsocksEndpointData {
+                        port: 8443,
+                        cipher: "chacha20-ietf-poly1305".to_string(),
+                        password: "obfuscated".to_string(),
+                        protocol: TransportProtocol::Udp,
+                    },
+                ],
             },
         };
     }
@@ -1656,6 +1753,53 @@ mod test {
         // TODO: Verify that neither endpoint is using obfuscation for retry attempt 0
     }
 
+    #[test]
+    fn test_prefer_ipv6_when_only_ipv6_is_available() {
+        let relay_selector = new_relay_selector();
+
+        for attempt in 0..10 {
+            let result = relay_selector
+                .get_tunnel_endpoint_for_ip_availability(
+                    &RelayConstraints::default(),
+                    BridgeState::Auto,
+                    attempt,
+                    Some(IpAvailability::Ipv6),
+                )
+                .expect("Failed to select a relay");
+            assert!(result.endpoint.to_endpoint().address.is_ipv6());
+
+            let result = relay_selector
+                .get_tunnel_endpoint_for_ip_availability(
+                    &WIREGUARD_SINGLEHOP_CONSTRAINTS,
+                    BridgeState::Off,
+                    attempt,
+                    Some(IpAvailability::Ipv4AndIpv6),
+                )
+                .expect("Failed to select a relay");
+            assert!(result.endpoint.to_endpoint().address.is_ipv4());
+        }
+
+        // Fall back on IPv4 relays when no relay with an IPv6 address matches
+        let location = LocationConstraint::Hostname(
+            "se".to_string(),
+            "got".to_string(),
+            "se-got-001".to_string(),
+        );
+        let constraints = RelayConstraints {
+            location: Constraint::Only(location),
+            ..RelayConstraints::default()
+        };
+        let result = relay_selector
+            .get_tunnel_endpoint_for_ip_availability(
+                &constraints,
+                BridgeState::Auto,
+                0,
+                Some(IpAvailability::Ipv6),
+            )
+            .expect("Failed to select a relay");
+        assert!(result.endpoint.to_endpoint().address.is_ipv4());
+    }
+
     #[test]
     fn test_selecting_wg_endpoint_with_udp2tcp_obfuscation() {
         let relay_selector = new_relay_selector();
@@ -1710,10 +1854,121 @@ mod test {
             .unwrap()
             .is_none());
 
-        assert!(relay_selector
-            .get_obfuscator(&result.exit_relay, result.endpoint.unwrap_wireguard(), 2,)
+        assert!(matches!(
+            relay_selector
+                .get_obfuscator(&result.exit_relay, result.endpoint.unwrap_wireguard(), 2,)
+                .unwrap(),
+            Some(SelectedObfuscator {
+                config: ObfuscatorConfig::Udp2Tcp { .. },
+                ..
+            })
+        ));
+
+        assert!(matches!(
+            relay_selector
+                .get_obfuscator(&result.exit_relay, result.endpoint.unwrap_wireguard(), 3,)
+                .unwrap(),
+            Some(SelectedObfuscator {
+                config: ObfuscatorConfig::Shadowsocks { .. },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_selecting_wg_endpoint_with_shadowsocks_obfuscation() {
+        let relay_selector = new_relay_selector();
+
+        let result = relay_selector
+            .get_tunnel_endpoint(&WIREGUARD_SINGLEHOP_CONSTRAINTS, BridgeState::Off, 0)
+            .expect("Failed to select a WireGuard relay");
+
+        relay_selector.config.lock().obfuscation_settings = ObfuscationSettings {
+            selected_obfuscation: SelectedObfuscation::Shadowsocks,
+            shadowsocks: ShadowsocksObfuscationSettings {
+                port: Constraint::Only(8443),
+            },
+            ..ObfuscationSettings::default()
+        };
+
+        let obfs_config = relay_selector
+            .get_obfuscator(&result.exit_relay, result.endpoint.unwrap_wireguard(), 0)
             .unwrap()
-            .is_some());
+            .unwrap();
+
+        let peer_endpoint = result.endpoint.unwrap_wireguard().peer.endpoint;
+        match obfs_config.config {
+            ObfuscatorConfig::Shadowsocks {
+                endpoint,
+                wireguard_endpoint,
+                cipher,
+                password,
+            } => {
+                assert_eq!(endpoint, SocketAddr::new(peer_endpoint.ip(), 8443));
+                assert_eq!(wireguard_endpoint, peer_endpoint);
+                assert_eq!(cipher, "chacha20-ietf-poly1305");
+                assert_eq!(password, "obfuscated");
+            }
+            config => panic!("Unexpected obfuscator: {:?}", config),
+        }
+
+        // Only UDP servers can be used to obfuscate WireGuard
+        relay_selector
+            .config
+            .lock()
+            .obfuscation_settings
+            .shadowsocks
+            .port = Constraint::Only(1234);
+        assert!(matches!(
+            relay_selector.get_obfuscator(
+                &result.exit_relay,
+                result.endpoint.unwrap_wireguard(),
+                0
+            ),
+            Err(Error::NoObfuscator)
+        ));
+
+        relay_selector
+            .config
+            .lock()
+            .obfuscation_settings
+            .shadowsocks
+            .port = Constraint::Any;
+        let ports: Vec<u16> = (0..4)
+            .map(|retry_attempt| {
+                match relay_selector
+                    .get_obfuscator(
+                        &result.exit_relay,
+                        result.endpoint.unwrap_wireguard(),
+                        retry_attempt,
+                    )
+                    .unwrap()
+                    .unwrap()
+                    .config
+                {
+                    ObfuscatorConfig::Shadowsocks { endpoint, .. } => endpoint.port(),
+                    config => panic!("Unexpected obfuscator: {:?}", config),
+                }
+            })
+            .collect();
+        assert_eq!(ports, vec![443, 8443, 443, 8443]);
+
+        // Shadowsocks is unavailable if the relay list does not contain any servers
+        relay_selector
+            .parsed_relays
+            .lock()
+            .locations
+            .wireguard
+            .shadowsocks
+            .clear();
+        assert!(matches!(
+            relay_selector.get_obfuscator(
+                &result.exit_relay,
+                result.endpoint.unwrap_wireguard(),
+                0
+            ),
+            Err(Error::NoObfuscator)
+        ));
     }
 
     #[test]
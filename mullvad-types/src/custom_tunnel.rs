@@ -54,6 +54,7 @@ impl CustomTunnelEndpoint {
                 options: tunnel_options.openvpn.clone(),
                 generic_options: tunnel_options.generic,
                 proxy,
+                nat64: false,
             }
             .into(),
             ConnectionConfig::Wireguard(connection) => wireguard::TunnelParameters {
@@ -61,6 +62,7 @@ impl CustomTunnelEndpoint {
                 options: tunnel_options.wireguard.options.clone(),
                 generic_options: tunnel_options.generic,
                 obfuscation: None,
+                nat64: false,
             }
             .into(),
         };
@@ -10,6 +10,7 @@ pub mod relay_list;
 pub mod settings;
 pub mod states;
 pub mod version;
+pub mod wg_quick;
 pub mod wireguard;
 
 mod custom_tunnel;
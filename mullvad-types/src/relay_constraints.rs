@@ -489,6 +489,7 @@ pub enum SelectedObfuscation {
     #[default]
     Off,
     Udp2Tcp,
+    Shadowsocks,
 }
 
 impl fmt::Display for SelectedObfuscation {
@@ -497,6 +498,7 @@ impl fmt::Display for SelectedObfuscation {
             SelectedObfuscation::Auto => "auto".fmt(f),
             SelectedObfuscation::Off => "off".fmt(f),
             SelectedObfuscation::Udp2Tcp => "udp2tcp".fmt(f),
+            SelectedObfuscation::Shadowsocks => "shadowsocks".fmt(f),
         }
     }
 }
@@ -516,6 +518,21 @@ impl fmt::Display for Udp2TcpObfuscationSettings {
     }
 }
 
+#[derive(Default, Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ShadowsocksObfuscationSettings {
+    pub port: Constraint<u16>,
+}
+
+impl fmt::Display for ShadowsocksObfuscationSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.port {
+            Constraint::Any => write!(f, "any port"),
+            Constraint::Only(port) => write!(f, "port {}", port),
+        }
+    }
+}
+
 /// Contains obfuscation settings
 #[derive(Default, Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
@@ -523,6 +540,7 @@ impl fmt::Display for Udp2TcpObfuscationSettings {
 pub struct ObfuscationSettings {
     pub selected_obfuscation: SelectedObfuscation,
     pub udp2tcp: Udp2TcpObfuscationSettings,
+    pub shadowsocks: ShadowsocksObfuscationSettings,
 }
 
 /// Limits the set of bridge servers to use in `mullvad-daemon`.
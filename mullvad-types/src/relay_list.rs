@@ -126,6 +126,9 @@ pub struct WireguardEndpointData {
     pub ipv4_gateway: Ipv4Addr,
     pub ipv6_gateway: Ipv6Addr,
     pub udp2tcp_ports: Vec<u16>,
+    /// Shadowsocks servers on the relays, used to obfuscate WireGuard traffic
+    #[serde(default)]
+    pub shadowsocks: Vec<ShadowsocksEndpointData>,
 }
 
 impl Default for WireguardEndpointData {
@@ -135,6 +138,7 @@ impl Default for WireguardEndpointData {
             ipv4_gateway: "0.0.0.0".parse().unwrap(),
             ipv6_gateway: "::".parse().unwrap(),
             udp2tcp_ports: vec![],
+            shadowsocks: vec![],
         }
     }
 }
@@ -2,6 +2,10 @@
 use jnix::{jni::objects::JObject, FromJava, IntoJava, JnixEnv};
 use serde::{Deserialize, Serialize};
 use std::net::IpAddr;
+#[cfg(target_os = "linux")]
+use talpid_types::net::dns::{
+    EncryptedDnsProtocol, EncryptedDnsServer, LocalDnsRules, SplitDnsRule,
+};
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
 #[serde(rename_all = "snake_case")]
@@ -23,6 +27,21 @@ pub struct DnsOptions {
     pub default_options: DefaultDnsOptions,
     #[cfg_attr(target_os = "android", jnix(map = "|opts| opts.addresses"))]
     pub custom_options: CustomDnsOptions,
+    /// Domains whose queries are sent to resolvers of their own, through a local stub resolver.
+    #[cfg(target_os = "linux")]
+    pub split_dns: Vec<SplitDnsRule>,
+    /// Blocked domains and fixed addresses that are answered by the local stub resolver, in
+    /// addition to any server-side blocking.
+    #[cfg(target_os = "linux")]
+    pub local_rules: LocalDnsRules,
+    /// Keep an in-memory log of recent queries. Enabling this makes queries pass through the
+    /// local stub resolver.
+    #[cfg(target_os = "linux")]
+    pub query_log: bool,
+    /// Resolvers on the local network, such as the router, that may still be reached while
+    /// traffic is blocked outside the tunnel.
+    #[cfg(target_os = "linux")]
+    pub blocked_state_servers: Vec<IpAddr>,
 }
 
 #[cfg(target_os = "android")]
@@ -75,10 +94,18 @@ pub struct DefaultDnsOptions {
     pub block_malware: bool,
     pub block_adult_content: bool,
     pub block_gambling: bool,
+    /// Reach the resolver on the relay over DNS-over-TLS or DNS-over-HTTPS, through a local stub
+    /// resolver. Plain DNS is used while the encrypted resolver can't be reached.
+    #[cfg(target_os = "linux")]
+    pub encryption: Option<EncryptedDnsProtocol>,
 }
 
 /// Custom DNS config
 #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
 pub struct CustomDnsOptions {
     pub addresses: Vec<IpAddr>,
+    /// Resolvers reached over DNS-over-TLS or DNS-over-HTTPS, through a local stub resolver.
+    /// These replace `addresses` when set.
+    #[cfg(target_os = "linux")]
+    pub encrypted_servers: Vec<EncryptedDnsServer>,
 }
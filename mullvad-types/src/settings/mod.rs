@@ -19,7 +19,7 @@ mod dns;
 /// latest version that exists in `SettingsVersion`.
 /// This should be bumped when a new version is introduced along with a migration
 /// being added to `mullvad-daemon`.
-pub const CURRENT_SETTINGS_VERSION: SettingsVersion = SettingsVersion::V6;
+pub const CURRENT_SETTINGS_VERSION: SettingsVersion = SettingsVersion::V7;
 
 #[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
 #[repr(u32)]
@@ -29,6 +29,7 @@ pub enum SettingsVersion {
     V4 = 4,
     V5 = 5,
     V6 = 6,
+    V7 = 7,
 }
 
 impl<'de> Deserialize<'de> for SettingsVersion {
@@ -42,6 +43,7 @@ impl<'de> Deserialize<'de> for SettingsVersion {
             v if v == SettingsVersion::V4 as u32 => Ok(SettingsVersion::V4),
             v if v == SettingsVersion::V5 as u32 => Ok(SettingsVersion::V5),
             v if v == SettingsVersion::V6 as u32 => Ok(SettingsVersion::V6),
+            v if v == SettingsVersion::V7 as u32 => Ok(SettingsVersion::V7),
             v => Err(serde::de::Error::custom(format!(
                 "{} is not a valid SettingsVersion",
                 v
@@ -88,6 +90,15 @@ pub struct Settings {
     /// Split tunneling settings
     #[cfg(windows)]
     pub split_tunnel: SplitTunnelSettings,
+    /// Bridge interfaces, such as `docker0`, whose forwarded traffic should be allowed.
+    #[cfg(target_os = "linux")]
+    pub forwarded_interfaces: Vec<net::ForwardedInterface>,
+    /// IPv6 traffic that the firewall should allow outside the tunnel.
+    #[cfg(target_os = "linux")]
+    pub ipv6_firewall_mode: net::Ipv6FirewallMode,
+    /// Send packets rejected by the firewall to the drop log.
+    #[cfg(target_os = "linux")]
+    pub log_firewall_drops: bool,
     /// Specifies settings schema version
     #[cfg_attr(target_os = "android", jnix(skip))]
     settings_version: SettingsVersion,
@@ -122,6 +133,12 @@ impl Default for Settings {
             show_beta_releases: false,
             #[cfg(windows)]
             split_tunnel: SplitTunnelSettings::default(),
+            #[cfg(target_os = "linux")]
+            forwarded_interfaces: vec![],
+            #[cfg(target_os = "linux")]
+            ipv6_firewall_mode: net::Ipv6FirewallMode::default(),
+            #[cfg(target_os = "linux")]
+            log_firewall_drops: false,
             settings_version: CURRENT_SETTINGS_VERSION,
         }
     }
@@ -0,0 +1,564 @@
+//! Parser for the configuration files used by `wg-quick`, which consist of an `[Interface]` section
+//! and one or more `[Peer]` sections.
+
+use crate::{ConnectionConfig, CustomTunnelEndpoint};
+use ipnetwork::IpNetwork;
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    str::FromStr,
+};
+use talpid_types::net::wireguard;
+
+#[derive(err_derive::Error, Debug, PartialEq, Eq)]
+pub enum Error {
+    #[error(
+        display = "Line {}: expected a section header or a 'Key = Value' pair",
+        _0
+    )]
+    InvalidLine(usize),
+
+    #[error(display = "Line {}: unknown section [{}]", _0, _1)]
+    UnknownSection(usize, String),
+
+    #[error(display = "Line {}: {} is not inside a section", _0, _1)]
+    KeyOutsideSection(usize, String),
+
+    #[error(display = "Line {}: {} is not supported in [{}]", _0, _1, _2)]
+    UnsupportedKey(usize, String, &'static str),
+
+    #[error(display = "Line {}: {} is set more than once", _0, _1)]
+    DuplicateKey(usize, &'static str),
+
+    #[error(display = "Line {}: invalid {}: {}", _0, _1, _2)]
+    InvalidValue(usize, &'static str, String),
+
+    #[error(
+        display = "Only the first [Peer] may use a hostname as its endpoint: {}",
+        _0
+    )]
+    AdditionalPeerHostname(String),
+
+    #[error(display = "Missing [{}] section", _0)]
+    MissingSection(&'static str),
+
+    #[error(display = "Missing {} in [{}]", _0, _1)]
+    MissingKey(&'static str, &'static str),
+
+    #[error(display = "An IPv4 DNS server is required, since it is used as the tunnel gateway")]
+    NoIpv4Gateway,
+}
+
+/// Settings read from a `wg-quick` configuration file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WgQuickConfig {
+    pub endpoint: CustomTunnelEndpoint,
+    /// Servers from the `DNS` key. The first IPv4 and IPv6 servers are used as the tunnel
+    /// gateways, which are also the tunnel resolvers unless other DNS options are set.
+    pub dns_servers: Vec<IpAddr>,
+    /// MTU from the `MTU` key. It is not part of the relay, so importing the config ignores it.
+    pub mtu: Option<u16>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Interface,
+    Peer,
+}
+
+impl Section {
+    fn name(self) -> &'static str {
+        match self {
+            Section::Interface => "Interface",
+            Section::Peer => "Peer",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Interface {
+    private_key: Option<wireguard::PrivateKey>,
+    addresses: Vec<IpAddr>,
+    dns_servers: Vec<IpAddr>,
+    mtu: Option<u16>,
+}
+
+#[derive(Default)]
+struct Peer {
+    public_key: Option<wireguard::PublicKey>,
+    psk: Option<wireguard::PresharedKey>,
+    allowed_ips: Vec<IpNetwork>,
+    endpoint: Option<(String, u16)>,
+}
+
+impl FromStr for WgQuickConfig {
+    type Err = Error;
+
+    fn from_str(config: &str) -> Result<Self, Self::Err> {
+        let mut interface = None;
+        let mut peers: Vec<Peer> = vec![];
+        let mut section = None;
+
+        for (index, line) in config.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = match name.trim() {
+                    name if name.eq_ignore_ascii_case("Interface") => {
+                        interface.get_or_insert_with(Interface::default);
+                        Some(Section::Interface)
+                    }
+                    name if name.eq_ignore_ascii_case("Peer") => {
+                        peers.push(Peer::default());
+                        Some(Section::Peer)
+                    }
+                    name => return Err(Error::UnknownSection(line_number, name.to_owned())),
+                };
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .ok_or(Error::InvalidLine(line_number))?;
+
+            match section {
+                Some(Section::Interface) => {
+                    interface
+                        .as_mut()
+                        .unwrap()
+                        .parse_key(line_number, key, value)?
+                }
+                Some(Section::Peer) => {
+                    peers
+                        .last_mut()
+                        .unwrap()
+                        .parse_key(line_number, key, value)?
+                }
+                None => return Err(Error::KeyOutsideSection(line_number, key.to_owned())),
+            }
+        }
+
+        let interface = interface.ok_or(Error::MissingSection(Section::Interface.name()))?;
+        if peers.is_empty() {
+            return Err(Error::MissingSection(Section::Peer.name()));
+        }
+        Self::from_sections(interface, peers)
+    }
+}
+
+impl WgQuickConfig {
+    /// Returns the DNS servers that are not used, since only the first IPv4 and IPv6 servers
+    /// become tunnel gateways.
+    pub fn ignored_dns_servers(&self) -> impl Iterator<Item = &IpAddr> {
+        let first_ipv4 = self.dns_servers.iter().position(IpAddr::is_ipv4);
+        let first_ipv6 = self.dns_servers.iter().position(IpAddr::is_ipv6);
+        self.dns_servers
+            .iter()
+            .enumerate()
+            .filter(move |(index, _)| Some(*index) != first_ipv4 && Some(*index) != first_ipv6)
+            .map(|(_, server)| server)
+    }
+
+    fn from_sections(interface: Interface, peers: Vec<Peer>) -> Result<Self, Error> {
+        let missing_interface_key = |key| Error::MissingKey(key, Section::Interface.name());
+
+        let private_key = interface
+            .private_key
+            .ok_or_else(|| missing_interface_key("PrivateKey"))?;
+        if interface.addresses.is_empty() {
+            return Err(missing_interface_key("Address"));
+        }
+
+        let mut peers = peers.into_iter().map(Peer::into_peer_config);
+        // The address of the first peer is resolved from the host when connecting
+        let (peer, host) = peers.next().unwrap()?;
+        let additional_peers = peers
+            .map(|peer| match peer? {
+                (peer, host) if peer.endpoint.ip().is_unspecified() => {
+                    Err(Error::AdditionalPeerHostname(host))
+                }
+                (peer, _) => Ok(peer),
+            })
+            .collect::<Result<_, _>>()?;
+
+        let ipv4_gateway = interface
+            .dns_servers
+            .iter()
+            .find_map(|server| match server {
+                IpAddr::V4(server) => Some(*server),
+                IpAddr::V6(_) => None,
+            })
+            .ok_or(Error::NoIpv4Gateway)?;
+        let ipv6_gateway = interface
+            .dns_servers
+            .iter()
+            .find_map(|server| match server {
+                IpAddr::V4(_) => None,
+                IpAddr::V6(server) => Some(*server),
+            });
+
+        let connection = wireguard::ConnectionConfig {
+            tunnel: wireguard::TunnelConfig {
+                private_key,
+                addresses: interface.addresses,
+            },
+            peer,
+            exit_peer: None,
+            additional_peers,
+            ipv4_gateway,
+            ipv6_gateway,
+        };
+
+        Ok(WgQuickConfig {
+            endpoint: CustomTunnelEndpoint::new(host, ConnectionConfig::Wireguard(connection)),
+            dns_servers: interface.dns_servers,
+            mtu: interface.mtu,
+        })
+    }
+}
+
+impl Interface {
+    fn parse_key(&mut self, line: usize, key: &str, value: &str) -> Result<(), Error> {
+        match key.to_ascii_lowercase().as_str() {
+            "privatekey" => {
+                let private_key = wireguard::PrivateKey::from_base64(value)
+                    .map_err(|_| invalid_key(line, "PrivateKey"))?;
+                set_once(&mut self.private_key, private_key, line, "PrivateKey")
+            }
+            "address" => {
+                for address in split_list(value) {
+                    let network = IpNetwork::from_str(address)
+                        .map_err(|error| invalid_value(line, "Address", error))?;
+                    self.addresses.push(network.ip());
+                }
+                Ok(())
+            }
+            "dns" => {
+                for server in split_list(value) {
+                    // wg-quick also accepts search domains here, which we can't use
+                    let server = IpAddr::from_str(server)
+                        .map_err(|error| invalid_value(line, "DNS", error))?;
+                    self.dns_servers.push(server);
+                }
+                Ok(())
+            }
+            "mtu" => {
+                let mtu =
+                    u16::from_str(value).map_err(|error| invalid_value(line, "MTU", error))?;
+                set_once(&mut self.mtu, mtu, line, "MTU")
+            }
+            _ => Err(Error::UnsupportedKey(
+                line,
+                key.to_owned(),
+                Section::Interface.name(),
+            )),
+        }
+    }
+}
+
+impl Peer {
+    /// Returns the peer config, and the host of its endpoint. The endpoint address is left
+    /// unspecified unless the host is an IP address.
+    fn into_peer_config(self) -> Result<(wireguard::PeerConfig, String), Error> {
+        let missing_peer_key = |key| Error::MissingKey(key, Section::Peer.name());
+
+        let public_key = self
+            .public_key
+            .ok_or_else(|| missing_peer_key("PublicKey"))?;
+        let (host, port) = self.endpoint.ok_or_else(|| missing_peer_key("Endpoint"))?;
+        if self.allowed_ips.is_empty() {
+            return Err(missing_peer_key("AllowedIPs"));
+        }
+        let address = host.parse().unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+
+        let peer = wireguard::PeerConfig {
+            public_key,
+            allowed_ips: self.allowed_ips,
+            endpoint: SocketAddr::new(address, port),
+            psk: self.psk,
+        };
+        Ok((peer, host))
+    }
+
+    fn parse_key(&mut self, line: usize, key: &str, value: &str) -> Result<(), Error> {
+        match key.to_ascii_lowercase().as_str() {
+            "publickey" => {
+                let public_key = wireguard::PublicKey::from_base64(value)
+                    .map_err(|_| invalid_key(line, "PublicKey"))?;
+                set_once(&mut self.public_key, public_key, line, "PublicKey")
+            }
+            "presharedkey" => {
+                let psk = wireguard::PresharedKey::from_base64(value)
+                    .map_err(|_| invalid_key(line, "PresharedKey"))?;
+                set_once(&mut self.psk, psk, line, "PresharedKey")
+            }
+            "allowedips" => {
+                for network in split_list(value) {
+                    let network = IpNetwork::from_str(network)
+                        .map_err(|error| invalid_value(line, "AllowedIPs", error))?;
+                    self.allowed_ips.push(network);
+                }
+                Ok(())
+            }
+            "endpoint" => {
+                let endpoint = parse_endpoint(value)
+                    .ok_or_else(|| invalid_value(line, "Endpoint", "expected <host>:<port>"))?;
+                set_once(&mut self.endpoint, endpoint, line, "Endpoint")
+            }
+            _ => Err(Error::UnsupportedKey(
+                line,
+                key.to_owned(),
+                Section::Peer.name(),
+            )),
+        }
+    }
+}
+
+/// Splits an endpoint such as `example.com:51820` or `[2001:db8::1]:51820` into host and port.
+fn parse_endpoint(endpoint: &str) -> Option<(String, u16)> {
+    let (host, port) = endpoint.rsplit_once(':')?;
+    let host = match host.strip_prefix('[') {
+        Some(host) => host.strip_suffix(']')?,
+        None if host.contains(':') => return None,
+        None => host,
+    };
+    if host.is_empty() {
+        return None;
+    }
+    Some((host.to_owned(), port.parse().ok()?))
+}
+
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+fn set_once<T>(
+    field: &mut Option<T>,
+    value: T,
+    line: usize,
+    key: &'static str,
+) -> Result<(), Error> {
+    if field.replace(value).is_some() {
+        return Err(Error::DuplicateKey(line, key));
+    }
+    Ok(())
+}
+
+fn invalid_value(line: usize, key: &'static str, error: impl ToString) -> Error {
+    Error::InvalidValue(line, key, error.to_string())
+}
+
+fn invalid_key(line: usize, key: &'static str) -> Error {
+    invalid_value(line, key, "expected a base64 encoded 32 byte key")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use talpid_types::net::IpVersion;
+
+    const PRIVATE_KEY: &str = "mPue6Xt0pdz4NRAhfQSp/SLKo7kV7DW+2zvBq0N9iUI=";
+    const PUBLIC_KEY: &str = "7svBwGBefP7KVmH/yes+pZCfO6uSOYeGieYYa1+kZ0E=";
+    const PSK: &str = "/LQTRrRE2Ng1YA+2bDYIG8eEnmZmGN5D/7OMX09iFuk=";
+
+    #[test]
+    fn test_parse_config() {
+        let config = format!(
+            "# wg0
+[Interface]
+PrivateKey = {PRIVATE_KEY}
+Address = 10.64.10.2/32, fc00:bbbb:bbbb:bb01::a:2/128
+DNS = 10.64.0.1
+mtu = 1380
+
+[Peer]
+PublicKey = {PUBLIC_KEY}
+PresharedKey = {PSK} # optional
+AllowedIPs = 0.0.0.0/0
+AllowedIPs = ::/0
+Endpoint = se-got-wg-001.relays.mullvad.net:51820
+"
+        );
+        let config = WgQuickConfig::from_str(&config).unwrap();
+
+        assert_eq!(config.endpoint.host, "se-got-wg-001.relays.mullvad.net");
+        assert_eq!(
+            config.dns_servers,
+            vec!["10.64.0.1".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(config.mtu, Some(1380));
+
+        let connection = match config.endpoint.config {
+            ConnectionConfig::Wireguard(connection) => connection,
+            ConnectionConfig::OpenVpn(_) => unreachable!(),
+        };
+        assert_eq!(
+            connection.tunnel.private_key,
+            wireguard::PrivateKey::from_base64(PRIVATE_KEY).unwrap()
+        );
+        assert_eq!(
+            connection.tunnel.addresses,
+            vec![
+                "10.64.10.2".parse::<IpAddr>().unwrap(),
+                "fc00:bbbb:bbbb:bb01::a:2".parse().unwrap()
+            ]
+        );
+        assert_eq!(connection.peer.public_key.to_base64(), PUBLIC_KEY);
+        assert_eq!(
+            connection.peer.psk,
+            Some(wireguard::PresharedKey::from_base64(PSK).unwrap())
+        );
+        assert_eq!(
+            connection.peer.allowed_ips,
+            vec![
+                "0.0.0.0/0".parse::<IpNetwork>().unwrap(),
+                "::/0".parse().unwrap()
+            ]
+        );
+        assert_eq!(connection.peer.endpoint.port(), 51820);
+        assert_eq!(connection.ipv4_gateway, Ipv4Addr::new(10, 64, 0, 1));
+        assert_eq!(connection.ipv6_gateway, None);
+    }
+
+    #[test]
+    fn test_ignored_dns_servers() {
+        let servers: Vec<IpAddr> = vec![
+            "10.64.0.1".parse().unwrap(),
+            "1.1.1.1".parse().unwrap(),
+            "fc00::1".parse().unwrap(),
+            "2606:4700::1111".parse().unwrap(),
+        ];
+        let mut config = WgQuickConfig::from_str(&format!(
+            "[Interface]\nPrivateKey = {PRIVATE_KEY}\nAddress = 10.0.0.2/32\nDNS = 10.64.0.1\n\
+             [Peer]\nPublicKey = {PUBLIC_KEY}\nAllowedIPs = 0.0.0.0/0\nEndpoint = 192.0.2.1:51820"
+        ))
+        .unwrap();
+        config.dns_servers = servers.clone();
+
+        assert_eq!(
+            config.ignored_dns_servers().collect::<Vec<_>>(),
+            vec![&servers[1], &servers[3]]
+        );
+    }
+
+    #[test]
+    fn test_parse_endpoint() {
+        assert_eq!(
+            parse_endpoint("example.com:51820"),
+            Some(("example.com".to_owned(), 51820))
+        );
+        assert_eq!(
+            parse_endpoint("[2001:db8::1]:51820"),
+            Some(("2001:db8::1".to_owned(), 51820))
+        );
+        assert_eq!(parse_endpoint("2001:db8::1:51820"), None);
+        assert_eq!(parse_endpoint("example.com"), None);
+        assert_eq!(parse_endpoint(":51820"), None);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let interface = format!("[Interface]\nPrivateKey = {PRIVATE_KEY}\nAddress = 10.0.0.2/32\n");
+        let peer = format!("[Peer]\nPublicKey = {PUBLIC_KEY}\nAllowedIPs = 0.0.0.0/0\n");
+
+        let config = format!("{interface}PostUp = iptables -A FORWARD\n{peer}");
+        assert_eq!(
+            WgQuickConfig::from_str(&config),
+            Err(Error::UnsupportedKey(
+                4,
+                "PostUp".to_owned(),
+                Section::Interface.name()
+            ))
+        );
+
+        let config = format!("{interface}DNS = 10.0.0.1\n{peer}");
+        assert_eq!(
+            WgQuickConfig::from_str(&config),
+            Err(Error::MissingKey("Endpoint", Section::Peer.name()))
+        );
+
+        let config = format!("{interface}{peer}Endpoint = 192.0.2.1:51820\n");
+        assert_eq!(WgQuickConfig::from_str(&config), Err(Error::NoIpv4Gateway));
+
+        let config = format!(
+            "{interface}DNS = 10.0.0.1\n{peer}Endpoint = 192.0.2.1:51820\n\
+             {peer}Endpoint = vpn.example.com:51820\n"
+        );
+        assert_eq!(
+            WgQuickConfig::from_str(&config),
+            Err(Error::AdditionalPeerHostname("vpn.example.com".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_multiple_peers() {
+        let config = format!(
+            "[Interface]
+PrivateKey = {PRIVATE_KEY}
+Address = 10.0.0.2/32
+DNS = 10.0.0.1
+
+[Peer]
+PublicKey = {PUBLIC_KEY}
+AllowedIPs = 10.0.0.0/16
+Endpoint = vpn.example.com:51820
+
+[Peer]
+PublicKey = {PSK}
+AllowedIPs = 10.1.0.0/16, 10.2.0.0/16
+Endpoint = [2001:db8::1]:51821
+"
+        );
+        let config = WgQuickConfig::from_str(&config).unwrap();
+        assert_eq!(config.endpoint.host, "vpn.example.com");
+
+        let connection = match config.endpoint.config {
+            ConnectionConfig::Wireguard(connection) => connection,
+            ConnectionConfig::OpenVpn(_) => unreachable!(),
+        };
+        assert_eq!(connection.peer.public_key.to_base64(), PUBLIC_KEY);
+        assert_eq!(connection.additional_peers.len(), 1);
+
+        let additional_peer = &connection.additional_peers[0];
+        assert_eq!(additional_peer.public_key.to_base64(), PSK);
+        assert_eq!(
+            additional_peer.endpoint,
+            "[2001:db8::1]:51821".parse().unwrap()
+        );
+        assert_eq!(additional_peer.allowed_ips.len(), 2);
+        // No IPv6 destinations are routed through the tunnel
+        assert_eq!(connection.get_untunneled_ip_versions(), vec![IpVersion::V4]);
+
+        let config = format!(
+            "[Interface]
+PrivateKey = {PRIVATE_KEY}
+Address = 10.0.0.2/32, fc00::2/128
+DNS = 10.0.0.1
+
+[Peer]
+PublicKey = {PUBLIC_KEY}
+AllowedIPs = 0.0.0.0/0, fc00::/64
+Endpoint = 192.0.2.1:51820
+
+[Peer]
+PublicKey = {PSK}
+AllowedIPs = fd00::/8
+Endpoint = [2001:db8::1]:51821
+"
+        );
+        let config = WgQuickConfig::from_str(&config).unwrap();
+        let connection = match config.endpoint.config {
+            ConnectionConfig::Wireguard(connection) => connection,
+            ConnectionConfig::OpenVpn(_) => unreachable!(),
+        };
+        // All IPv4 destinations are routed through the tunnel, but only some IPv6 destinations
+        assert_eq!(connection.get_untunneled_ip_versions(), vec![IpVersion::V6]);
+    }
+}
@@ -2,13 +2,19 @@ mod network_manager;
 mod resolvconf;
 mod static_resolv_conf;
 pub(self) mod systemd_resolved;
+mod watchdog;
 
+pub use self::watchdog::DriftListeners;
 use self::{
-    network_manager::NetworkManager, resolvconf::Resolvconf, static_resolv_conf::StaticResolvConf,
+    network_manager::NetworkManager,
+    resolvconf::Resolvconf,
+    static_resolv_conf::StaticResolvConf,
     systemd_resolved::SystemdResolved,
+    watchdog::{AppliedDns, Watchdog},
 };
 use crate::routing::RouteManagerHandle;
-use std::{env, fmt, net::IpAddr};
+use parking_lot::Mutex;
+use std::{collections::HashSet, env, fmt, io, net::IpAddr, sync::Arc};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -34,22 +40,39 @@ pub enum Error {
     /// No suitable DNS monitor implementation detected
     #[error(display = "No suitable DNS monitor implementation detected")]
     NoDnsMonitor,
+
+    /// Failed to start the thread that re-applies DNS config changed by others
+    #[error(display = "Failed to start DNS watchdog")]
+    StartWatchdog(#[error(source)] io::Error),
 }
 
 pub struct DnsMonitor {
     route_manager: RouteManagerHandle,
     handle: tokio::runtime::Handle,
-    inner: Option<DnsMonitorHolder>,
+    applied: Arc<Mutex<Option<AppliedDns>>>,
+    _watchdog: Watchdog,
 }
 
 impl super::DnsMonitorT for DnsMonitor {
     type Error = Error;
 
-    fn new(handle: tokio::runtime::Handle, route_manager: RouteManagerHandle) -> Result<Self> {
+    fn new(
+        handle: tokio::runtime::Handle,
+        route_manager: RouteManagerHandle,
+        drift_listeners: DriftListeners,
+    ) -> Result<Self> {
+        let applied = Arc::new(Mutex::new(None));
+        let watchdog = Watchdog::start(
+            handle.clone(),
+            route_manager.clone(),
+            applied.clone(),
+            drift_listeners,
+        )?;
         Ok(DnsMonitor {
             route_manager,
             handle,
-            inner: None,
+            applied,
+            _watchdog: watchdog,
         })
     }
 
@@ -59,14 +82,15 @@ impl super::DnsMonitorT for DnsMonitor {
         let mut inner = DnsMonitorHolder::new(&self.handle)?;
         if !servers.is_empty() {
             inner.set(&self.handle, &self.route_manager, interface, servers)?;
-            self.inner = Some(inner);
+            *self.applied.lock() = Some(AppliedDns::new(inner, interface, servers));
         }
         Ok(())
     }
 
     fn reset(&mut self) -> Result<()> {
-        if let Some(mut inner) = self.inner.take() {
-            inner.reset(&self.handle)?;
+        let applied = self.applied.lock().take();
+        if let Some(applied) = applied {
+            applied.into_holder().reset(&self.handle)?;
         }
         Ok(())
     }
@@ -154,6 +178,27 @@ impl DnsMonitorHolder {
         Ok(())
     }
 
+    /// Returns whether the system still uses the given DNS config.
+    fn is_applied(
+        &self,
+        handle: &tokio::runtime::Handle,
+        interface: &str,
+        servers: &[IpAddr],
+    ) -> Result<bool> {
+        use self::DnsMonitorHolder::*;
+        let is_applied = match self {
+            Resolvconf(ref resolvconf) => resolvconf.is_applied(servers)?,
+            StaticResolvConf(ref static_resolv_conf) => static_resolv_conf.is_applied()?,
+            SystemdResolved(ref systemd_resolved) => {
+                handle.block_on(systemd_resolved.is_applied(interface, servers))?
+            }
+            NetworkManager(ref network_manager) => {
+                network_manager.is_applied(interface, servers)?
+            }
+        };
+        Ok(is_applied)
+    }
+
     fn reset(&mut self, handle: &tokio::runtime::Handle) -> Result<()> {
         use self::DnsMonitorHolder::*;
         match self {
@@ -168,6 +213,11 @@ impl DnsMonitorHolder {
     }
 }
 
+/// Returns whether two lists of DNS servers contain the same servers, in any order.
+fn same_servers(servers: &[IpAddr], other_servers: &[IpAddr]) -> bool {
+    servers.iter().collect::<HashSet<_>>() == other_servers.iter().collect::<HashSet<_>>()
+}
+
 /// Returns true if DnsMonitor will use NetworkManager to manage DNS.
 pub fn will_use_nm() -> bool {
     crate::dns::imp::SystemdResolved::new().is_err()
@@ -26,11 +26,20 @@ impl NetworkManager {
 
     pub fn set_dns(&mut self, interface_name: &str, servers: &[IpAddr]) -> Result<()> {
         let old_settings = self.connection.set_dns(interface_name, servers)?;
-        self.settings_backup = Some(old_settings);
+        // Keep the original settings when re-applying DNS that has been changed by others
+        if self.settings_backup.is_none() {
+            self.settings_backup = Some(old_settings);
+        }
         self.device = Some(interface_name.to_string());
         Ok(())
     }
 
+    /// Returns whether the connection applied to the device still uses the given servers.
+    pub fn is_applied(&self, interface_name: &str, servers: &[IpAddr]) -> Result<bool> {
+        let applied_servers = self.connection.get_dns(interface_name)?;
+        Ok(super::same_servers(&applied_servers, servers))
+    }
+
     pub fn reset(&mut self) -> Result<()> {
         if let Some(settings_backup) = self.settings_backup.take() {
             let device = match self.device.take() {
@@ -6,6 +6,7 @@ use std::{
     path::{Path, PathBuf},
 };
 
+use resolv_conf::ScopedIp;
 use which::which;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -32,6 +33,12 @@ pub enum Error {
 
     #[error(display = "Current /etc/resolv.conf is not generated by resolvconf")]
     ResolvconfNotInUse,
+
+    #[error(display = "Failed to read /etc/resolv.conf")]
+    ReadResolvConf(#[error(source)] io::Error),
+
+    #[error(display = "/etc/resolv.conf could not be parsed")]
+    ParseResolvConf(#[error(source)] resolv_conf::ParseError),
 }
 
 pub struct Resolvconf {
@@ -102,6 +109,20 @@ impl Resolvconf {
         Ok(())
     }
 
+    /// Returns whether /etc/resolv.conf lists all of the given servers. This cannot be checked
+    /// when resolvconf feeds dnsmasq, in which case it's assumed that the servers are in use.
+    pub fn is_applied(&self, servers: &[IpAddr]) -> Result<bool> {
+        if Self::is_dnsmasq_running() {
+            return Ok(true);
+        }
+
+        let contents = fs::read_to_string("/etc/resolv.conf").map_err(Error::ReadResolvConf)?;
+        let config = resolv_conf::Config::parse(&contents).map_err(Error::ParseResolvConf)?;
+        Ok(servers
+            .iter()
+            .all(|&server| config.nameservers.contains(&ScopedIp::from(server))))
+    }
+
     pub fn reset(&mut self) -> Result<()> {
         let mut result = Ok(());
 
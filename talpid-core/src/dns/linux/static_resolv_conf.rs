@@ -72,6 +72,14 @@ impl StaticResolvConf {
         write_config(&new_config)
     }
 
+    /// Returns whether /etc/resolv.conf still lists the servers that were last set.
+    pub fn is_applied(&self) -> Result<bool> {
+        match &*self.state.lock() {
+            Some(state) => Ok(read_config()?.nameservers == state.desired_config().nameservers),
+            None => Ok(true),
+        }
+    }
+
     pub fn reset(&mut self) -> Result<()> {
         if let Some(state) = self.state.lock().take() {
             write_config(&state.backup)?;
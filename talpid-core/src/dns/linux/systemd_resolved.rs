@@ -65,6 +65,17 @@ impl SystemdResolved {
         Ok(())
     }
 
+    /// Returns whether the tunnel link still uses the given servers for all domains.
+    pub async fn is_applied(&self, interface_name: &str, servers: &[IpAddr]) -> Result<bool> {
+        let tunnel_index = iface_index(interface_name)?;
+        let state = self.dbus_interface.get_dns(tunnel_index).await?;
+        let domains = self.dbus_interface.get_domains(tunnel_index).await?;
+        Ok(super::same_servers(&state.set_servers, servers)
+            && domains
+                .iter()
+                .any(|(domain, routing_only)| domain == "." && *routing_only))
+    }
+
     pub async fn reset(&mut self) -> Result<()> {
         if let Err(error) = self
             .dbus_interface
@@ -0,0 +1,140 @@
+//! Periodically checks that the DNS config that was set has not been changed by other programs,
+//! such as NetworkManager or a DHCP client rewriting `/etc/resolv.conf` on lease renewal, and
+//! re-applies it if it has.
+
+use super::DnsMonitorHolder;
+use crate::routing::RouteManagerHandle;
+use futures::channel::mpsc;
+use parking_lot::Mutex;
+use std::{
+    io,
+    net::IpAddr,
+    sync::{mpsc as sync_mpsc, Arc},
+    thread,
+    time::Duration,
+};
+use talpid_types::{net::dns::DnsConfigDrift, ErrorExt};
+
+/// Time between two checks of the DNS config.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Subscribers that are notified every time the DNS config is re-applied.
+#[derive(Clone, Default)]
+pub struct DriftListeners {
+    subscribers: Arc<Mutex<Vec<mpsc::UnboundedSender<DnsConfigDrift>>>>,
+}
+
+impl DriftListeners {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a channel that receives a notification every time the DNS config is re-applied.
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<DnsConfigDrift> {
+        let (tx, rx) = mpsc::unbounded();
+        self.subscribers.lock().push(tx);
+        rx
+    }
+
+    fn notify(&self, drift: DnsConfigDrift) {
+        self.subscribers
+            .lock()
+            .retain(|tx| tx.unbounded_send(drift.clone()).is_ok());
+    }
+}
+
+/// DNS config that has been set, and the DNS manager that it was set with.
+pub struct AppliedDns {
+    holder: DnsMonitorHolder,
+    interface: String,
+    servers: Vec<IpAddr>,
+    reapply_count: u32,
+}
+
+impl AppliedDns {
+    pub fn new(holder: DnsMonitorHolder, interface: &str, servers: &[IpAddr]) -> Self {
+        AppliedDns {
+            holder,
+            interface: interface.to_owned(),
+            servers: servers.to_vec(),
+            reapply_count: 0,
+        }
+    }
+
+    pub fn into_holder(self) -> DnsMonitorHolder {
+        self.holder
+    }
+
+    /// Re-applies the DNS config if it no longer is in use.
+    fn enforce(
+        &mut self,
+        handle: &tokio::runtime::Handle,
+        route_manager: &RouteManagerHandle,
+        listeners: &DriftListeners,
+    ) {
+        match self
+            .holder
+            .is_applied(handle, &self.interface, &self.servers)
+        {
+            Ok(true) => return,
+            Ok(false) => (),
+            Err(error) => {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to check the DNS config")
+                );
+                return;
+            }
+        }
+
+        self.reapply_count += 1;
+        log::warn!(
+            "DNS config set via {} was changed by another program, re-applying it",
+            self.holder
+        );
+        if let Err(error) = self
+            .holder
+            .set(handle, route_manager, &self.interface, &self.servers)
+        {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to re-apply the DNS config")
+            );
+        }
+
+        listeners.notify(DnsConfigDrift {
+            manager: self.holder.to_string(),
+            interface: self.interface.clone(),
+            reapply_count: self.reapply_count,
+        });
+    }
+}
+
+/// Checks the applied DNS config on a separate thread until it is dropped.
+pub struct Watchdog {
+    _stop_tx: sync_mpsc::Sender<()>,
+}
+
+impl Watchdog {
+    pub fn start(
+        handle: tokio::runtime::Handle,
+        route_manager: RouteManagerHandle,
+        applied: Arc<Mutex<Option<AppliedDns>>>,
+        listeners: DriftListeners,
+    ) -> io::Result<Self> {
+        let (stop_tx, stop_rx) = sync_mpsc::channel();
+        thread::Builder::new()
+            .name("dns-watchdog".to_owned())
+            .spawn(move || {
+                // The channel is disconnected once the watchdog is dropped
+                while let Err(sync_mpsc::RecvTimeoutError::Timeout) =
+                    stop_rx.recv_timeout(CHECK_INTERVAL)
+                {
+                    if let Some(applied) = applied.lock().as_mut() {
+                        applied.enforce(&handle, &route_manager, &listeners);
+                    }
+                }
+            })?;
+        Ok(Watchdog { _stop_tx: stop_tx })
+    }
+}
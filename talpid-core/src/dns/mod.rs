@@ -17,7 +17,7 @@ mod imp;
 mod imp;
 
 #[cfg(target_os = "linux")]
-pub use imp::will_use_nm;
+pub use imp::{will_use_nm, DriftListeners};
 
 #[cfg(windows)]
 #[path = "windows/mod.rs"]
@@ -39,6 +39,7 @@ impl DnsMonitor {
     pub fn new(
         #[cfg(target_os = "linux")] handle: tokio::runtime::Handle,
         #[cfg(target_os = "linux")] route_manager: RouteManagerHandle,
+        #[cfg(target_os = "linux")] drift_listeners: DriftListeners,
         #[cfg(target_os = "macos")] tx: Weak<UnboundedSender<TunnelCommand>>,
     ) -> Result<Self, Error> {
         Ok(DnsMonitor {
@@ -47,6 +48,8 @@ impl DnsMonitor {
                 handle,
                 #[cfg(target_os = "linux")]
                 route_manager,
+                #[cfg(target_os = "linux")]
+                drift_listeners,
                 #[cfg(target_os = "macos")]
                 tx,
             )?,
@@ -87,6 +90,7 @@ trait DnsMonitorT: Sized {
     fn new(
         #[cfg(target_os = "linux")] handle: tokio::runtime::Handle,
         #[cfg(target_os = "linux")] route_manager: RouteManagerHandle,
+        #[cfg(target_os = "linux")] drift_listeners: DriftListeners,
         #[cfg(target_os = "macos")] tx: Weak<UnboundedSender<TunnelCommand>>,
     ) -> Result<Self, Self::Error>;
 
@@ -0,0 +1,556 @@
+//! Reports packets rejected by the firewall. When enabled, the reject rules also send the packets
+//! to an NFLOG group. [`DropMonitor`] reads that group, aggregates the packets by destination,
+//! port and owner, and sends a summary to its subscribers at most once per
+//! [`REPORT_INTERVAL`].
+
+use futures::channel::mpsc;
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    env, io, mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    os::unix::io::RawFd,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use talpid_types::net::drop_log::DroppedPackets;
+
+/// NFLOG group that rejected packets are logged to.
+pub const NFLOG_GROUP: u16 = 0x4d56;
+
+/// Minimum time between two reports sent to subscribers.
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Number of bytes of each packet to copy from the kernel. Enough to cover the IP header and the
+/// ports of the transport header.
+const COPY_RANGE: u32 = 128;
+
+const RECV_BUFFER_SIZE: usize = 64 * 1024;
+
+const NFNL_SUBSYS_ULOG: u16 = 4;
+const NFULNL_MSG_PACKET: u16 = 0;
+const NFULNL_MSG_CONFIG: u16 = 1;
+
+const NFULA_CFG_CMD: u16 = 1;
+const NFULA_CFG_MODE: u16 = 2;
+const NFULA_CFG_FLAGS: u16 = 6;
+const NFULNL_CFG_CMD_BIND: u8 = 1;
+const NFULNL_COPY_PACKET: u8 = 2;
+/// Makes the kernel include `NFULA_UID` in packet messages.
+const NFULNL_CFG_F_UID: u16 = 0x0002;
+
+const NFULA_PAYLOAD: u16 = 9;
+const NFULA_UID: u16 = 11;
+
+const NLMSG_HDR_LEN: usize = 16;
+const NFGENMSG_LEN: usize = 4;
+const NLA_HDR_LEN: usize = 4;
+const NLA_TYPE_MASK: u16 = 0x3fff;
+
+lazy_static! {
+    static ref LOG_DROPS: bool = env::var("TALPID_FIREWALL_LOG_DROPS")
+        .map(|v| v != "0")
+        .unwrap_or(false);
+}
+
+/// Returns whether `TALPID_FIREWALL_LOG_DROPS` forces the firewall to log rejected packets,
+/// regardless of the `log_drops` field of the policy.
+pub fn is_forced() -> bool {
+    *LOG_DROPS
+}
+
+/// Errors that can happen when monitoring rejected packets.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Failed to open a netfilter netlink socket.
+    #[error(display = "Failed to open netfilter netlink socket")]
+    OpenSocket(#[error(source)] io::Error),
+
+    /// Failed to bind to the NFLOG group.
+    #[error(display = "Failed to bind to NFLOG group {}", NFLOG_GROUP)]
+    BindGroup(#[error(source)] io::Error),
+
+    /// Failed to spawn the monitor thread.
+    #[error(display = "Failed to spawn drop monitor thread")]
+    SpawnThread(#[error(source)] io::Error),
+}
+
+type Subscribers = Arc<Mutex<Vec<mpsc::UnboundedSender<Vec<DroppedPackets>>>>>;
+
+/// Reads packets rejected by the firewall and reports them to subscribers.
+pub struct DropMonitor {
+    subscribers: Subscribers,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl DropMonitor {
+    /// Binds to the NFLOG group and starts reading from it on a separate thread.
+    pub fn start() -> Result<Self, Error> {
+        let socket = NflogSocket::open()?;
+        socket.bind_group(NFLOG_GROUP)?;
+
+        let subscribers = Subscribers::default();
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let thread_subscribers = subscribers.clone();
+        let thread_shutdown = shutdown.clone();
+        let thread = thread::Builder::new()
+            .name("firewall-drop-monitor".to_owned())
+            .spawn(move || run_monitor(socket, thread_subscribers, thread_shutdown))
+            .map_err(Error::SpawnThread)?;
+
+        Ok(DropMonitor {
+            subscribers,
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns a channel that receives aggregated reports of rejected packets.
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<Vec<DroppedPackets>> {
+        let (tx, rx) = mpsc::unbounded();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+}
+
+impl Drop for DropMonitor {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run_monitor(socket: NflogSocket, subscribers: Subscribers, shutdown: Arc<AtomicBool>) {
+    let mut aggregator = DropAggregator::default();
+    let mut buffer = vec![0u8; RECV_BUFFER_SIZE];
+    let mut last_report = Instant::now();
+
+    while !shutdown.load(Ordering::SeqCst) {
+        match socket.recv(&mut buffer) {
+            Ok(len) => {
+                for packet in parse_messages(&buffer[..len]) {
+                    aggregator.record(packet);
+                }
+            }
+            Err(error)
+                if error.kind() == io::ErrorKind::WouldBlock
+                    || error.kind() == io::ErrorKind::TimedOut
+                    || error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => {
+                log::error!("Failed to read rejected packets: {}", error);
+                return;
+            }
+        }
+
+        if last_report.elapsed() >= REPORT_INTERVAL {
+            last_report = Instant::now();
+            let report = aggregator.drain();
+            if !report.is_empty() {
+                let mut subscribers = subscribers.lock().unwrap();
+                subscribers.retain(|tx| tx.unbounded_send(report.clone()).is_ok());
+            }
+        }
+    }
+}
+
+/// Identifies packets that are counted together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct DropKey {
+    destination: IpAddr,
+    protocol: u8,
+    port: Option<u16>,
+    uid: Option<u32>,
+}
+
+/// Counts rejected packets between two reports.
+#[derive(Debug, Default)]
+struct DropAggregator {
+    counts: HashMap<DropKey, u64>,
+}
+
+impl DropAggregator {
+    fn record(&mut self, packet: DropKey) {
+        *self.counts.entry(packet).or_insert(0) += 1;
+    }
+
+    /// Returns the packets counted since the last call, largest count first.
+    fn drain(&mut self) -> Vec<DroppedPackets> {
+        let mut report: Vec<_> = self
+            .counts
+            .drain()
+            .map(|(key, count)| DroppedPackets {
+                destination: key.destination,
+                protocol: key.protocol,
+                port: key.port,
+                uid: key.uid,
+                count,
+            })
+            .collect();
+        report.sort_by(|a, b| b.count.cmp(&a.count));
+        report
+    }
+}
+
+/// Netlink socket subscribed to an NFLOG group.
+struct NflogSocket {
+    fd: RawFd,
+}
+
+impl NflogSocket {
+    fn open() -> Result<Self, Error> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_NETFILTER,
+            )
+        };
+        if fd < 0 {
+            return Err(Error::OpenSocket(io::Error::last_os_error()));
+        }
+        let socket = NflogSocket { fd };
+
+        let mut address: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        let result = unsafe {
+            libc::bind(
+                fd,
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(Error::OpenSocket(io::Error::last_os_error()));
+        }
+
+        // Wake up regularly so that the monitor thread can notice when it should stop
+        let timeout = libc::timeval {
+            tv_sec: REPORT_INTERVAL.as_secs() as libc::time_t,
+            tv_usec: 0,
+        };
+        let result = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &timeout as *const libc::timeval as *const libc::c_void,
+                mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(Error::OpenSocket(io::Error::last_os_error()));
+        }
+
+        Ok(socket)
+    }
+
+    fn bind_group(&self, group: u16) -> Result<(), Error> {
+        for message in bind_messages(group) {
+            self.send(&message).map_err(Error::BindGroup)?;
+        }
+        Ok(())
+    }
+
+    fn send(&self, message: &[u8]) -> io::Result<()> {
+        let result = unsafe {
+            libc::send(
+                self.fd,
+                message.as_ptr() as *const libc::c_void,
+                message.len(),
+                0,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn recv(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        let result = unsafe {
+            libc::recv(
+                self.fd,
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+                0,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(result as usize)
+    }
+}
+
+impl Drop for NflogSocket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+const fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Returns the requests that bind to `group`, copy the start of each packet, and include the UID
+/// of the sending socket.
+fn bind_messages(group: u16) -> Vec<Vec<u8>> {
+    let mut mode = COPY_RANGE.to_be_bytes().to_vec();
+    mode.extend_from_slice(&[NFULNL_COPY_PACKET, 0]);
+    vec![
+        config_message(group, NFULA_CFG_CMD, &[NFULNL_CFG_CMD_BIND]),
+        config_message(group, NFULA_CFG_MODE, &mode),
+        config_message(group, NFULA_CFG_FLAGS, &NFULNL_CFG_F_UID.to_be_bytes()),
+    ]
+}
+
+/// Builds an NFULNL_MSG_CONFIG request for `group` carrying a single attribute.
+fn config_message(group: u16, attribute_type: u16, attribute: &[u8]) -> Vec<u8> {
+    let attribute_len = NLA_HDR_LEN + attribute.len();
+    let message_len = NLMSG_HDR_LEN + NFGENMSG_LEN + align(attribute_len);
+
+    let mut message = Vec::with_capacity(message_len);
+    message.extend_from_slice(&(message_len as u32).to_ne_bytes());
+    message.extend_from_slice(&((NFNL_SUBSYS_ULOG << 8) | NFULNL_MSG_CONFIG).to_ne_bytes());
+    message.extend_from_slice(&(libc::NLM_F_REQUEST as u16).to_ne_bytes());
+    message.extend_from_slice(&0u32.to_ne_bytes());
+    message.extend_from_slice(&0u32.to_ne_bytes());
+
+    message.push(libc::AF_UNSPEC as u8);
+    message.push(0);
+    message.extend_from_slice(&group.to_be_bytes());
+
+    message.extend_from_slice(&(attribute_len as u16).to_ne_bytes());
+    message.extend_from_slice(&attribute_type.to_ne_bytes());
+    message.extend_from_slice(attribute);
+    message.resize(message_len, 0);
+    message
+}
+
+/// Parses all NFULNL_MSG_PACKET messages in a buffer received from the kernel. Other messages
+/// and packets that can't be parsed are skipped.
+fn parse_messages(mut buffer: &[u8]) -> Vec<DropKey> {
+    let mut packets = vec![];
+
+    while buffer.len() >= NLMSG_HDR_LEN {
+        let message_len = u32::from_ne_bytes(buffer[0..4].try_into().unwrap()) as usize;
+        if message_len < NLMSG_HDR_LEN || message_len > buffer.len() {
+            break;
+        }
+        let message_type = u16::from_ne_bytes(buffer[4..6].try_into().unwrap());
+        if message_type == (NFNL_SUBSYS_ULOG << 8) | NFULNL_MSG_PACKET {
+            if let Some(packet) = parse_packet_message(&buffer[NLMSG_HDR_LEN..message_len]) {
+                packets.push(packet);
+            }
+        }
+        buffer = &buffer[align(message_len).min(buffer.len())..];
+    }
+
+    packets
+}
+
+fn parse_packet_message(message: &[u8]) -> Option<DropKey> {
+    let mut attributes = message.get(NFGENMSG_LEN..)?;
+    let mut payload = None;
+    let mut uid = None;
+
+    while attributes.len() >= NLA_HDR_LEN {
+        let attribute_len = u16::from_ne_bytes(attributes[0..2].try_into().unwrap()) as usize;
+        if attribute_len < NLA_HDR_LEN || attribute_len > attributes.len() {
+            break;
+        }
+        let attribute_type = u16::from_ne_bytes(attributes[2..4].try_into().unwrap());
+        let value = &attributes[NLA_HDR_LEN..attribute_len];
+        match attribute_type & NLA_TYPE_MASK {
+            NFULA_PAYLOAD => payload = Some(value),
+            NFULA_UID if value.len() == 4 => {
+                uid = Some(u32::from_be_bytes(value.try_into().unwrap()))
+            }
+            _ => (),
+        }
+        attributes = &attributes[align(attribute_len).min(attributes.len())..];
+    }
+
+    let (destination, protocol, port) = parse_ip_packet(payload?)?;
+    Some(DropKey {
+        destination,
+        protocol,
+        port,
+        uid,
+    })
+}
+
+/// Returns the destination, IP protocol and destination port of an IPv4 or IPv6 packet. IPv6
+/// extension headers are not followed.
+fn parse_ip_packet(packet: &[u8]) -> Option<(IpAddr, u8, Option<u16>)> {
+    let (destination, protocol, transport) = match packet.first()? >> 4 {
+        4 => {
+            let header_len = usize::from(packet[0] & 0x0f) * 4;
+            let destination: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            (
+                IpAddr::from(Ipv4Addr::from(destination)),
+                *packet.get(9)?,
+                packet.get(header_len..),
+            )
+        }
+        6 => {
+            let destination: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            (
+                IpAddr::from(Ipv6Addr::from(destination)),
+                *packet.get(6)?,
+                packet.get(40..),
+            )
+        }
+        _ => return None,
+    };
+
+    let port = match i32::from(protocol) {
+        libc::IPPROTO_TCP | libc::IPPROTO_UDP => transport
+            .and_then(|header| header.get(2..4))
+            .map(|port| u16::from_be_bytes([port[0], port[1]])),
+        _ => None,
+    };
+
+    Some((destination, protocol, port))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn packet_message(payload: &[u8], uid: Option<u32>) -> Vec<u8> {
+        let mut attributes = vec![];
+        let mut push_attribute = |attribute_type: u16, value: &[u8]| {
+            let len = NLA_HDR_LEN + value.len();
+            attributes.extend_from_slice(&(len as u16).to_ne_bytes());
+            attributes.extend_from_slice(&attribute_type.to_ne_bytes());
+            attributes.extend_from_slice(value);
+            attributes.resize(align(attributes.len()), 0);
+        };
+        // NFULA_PACKET_HDR, which is ignored
+        push_attribute(1, &[0x08, 0x00, 3, 0]);
+        push_attribute(NFULA_PAYLOAD, payload);
+        if let Some(uid) = uid {
+            push_attribute(NFULA_UID | 0x4000, &uid.to_be_bytes());
+        }
+
+        let len = NLMSG_HDR_LEN + NFGENMSG_LEN + attributes.len();
+        let mut message = vec![];
+        message.extend_from_slice(&(len as u32).to_ne_bytes());
+        message.extend_from_slice(&(NFNL_SUBSYS_ULOG << 8).to_ne_bytes());
+        message.extend_from_slice(&0u16.to_ne_bytes());
+        message.extend_from_slice(&[0; 8]);
+        message.extend_from_slice(&[libc::AF_INET as u8, 0]);
+        message.extend_from_slice(&NFLOG_GROUP.to_be_bytes());
+        message.extend_from_slice(&attributes);
+        message
+    }
+
+    fn ipv4_udp_packet(destination: Ipv4Addr, port: u16) -> Vec<u8> {
+        let mut packet = vec![0u8; 28];
+        packet[0] = 0x45;
+        packet[9] = libc::IPPROTO_UDP as u8;
+        packet[12..16].copy_from_slice(&[192, 168, 1, 2]);
+        packet[16..20].copy_from_slice(&destination.octets());
+        packet[20..22].copy_from_slice(&40000u16.to_be_bytes());
+        packet[22..24].copy_from_slice(&port.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn test_parse_packet_messages() {
+        let destination = Ipv4Addr::new(1, 1, 1, 1);
+        let mut buffer = packet_message(&ipv4_udp_packet(destination, 53), Some(1000));
+        buffer.extend(packet_message(&ipv4_udp_packet(destination, 443), None));
+
+        assert_eq!(
+            parse_messages(&buffer),
+            vec![
+                DropKey {
+                    destination: destination.into(),
+                    protocol: libc::IPPROTO_UDP as u8,
+                    port: Some(53),
+                    uid: Some(1000),
+                },
+                DropKey {
+                    destination: destination.into(),
+                    protocol: libc::IPPROTO_UDP as u8,
+                    port: Some(443),
+                    uid: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_ipv6_packet() {
+        let destination = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        let mut packet = vec![0u8; 40];
+        packet[0] = 0x60;
+        packet[6] = libc::IPPROTO_ICMPV6 as u8;
+        packet[24..40].copy_from_slice(&destination.octets());
+
+        assert_eq!(
+            parse_ip_packet(&packet),
+            Some((destination.into(), libc::IPPROTO_ICMPV6 as u8, None))
+        );
+    }
+
+    #[test]
+    fn test_config_message_layout() {
+        let message = config_message(NFLOG_GROUP, NFULA_CFG_MODE, &[0, 0, 0, 128, 2, 0]);
+
+        assert_eq!(message.len(), NLMSG_HDR_LEN + NFGENMSG_LEN + 12);
+        assert_eq!(&message[0..4], &(message.len() as u32).to_ne_bytes());
+        assert_eq!(&message[4..6], &0x0401u16.to_ne_bytes());
+        assert_eq!(&message[18..20], &NFLOG_GROUP.to_be_bytes());
+        assert_eq!(&message[20..22], &10u16.to_ne_bytes());
+        assert_eq!(&message[22..24], &NFULA_CFG_MODE.to_ne_bytes());
+        assert_eq!(&message[24..30], &[0, 0, 0, 128, 2, 0]);
+    }
+
+    #[test]
+    fn test_bind_requests_uid() {
+        let messages = bind_messages(NFLOG_GROUP);
+        assert_eq!(messages.len(), 3);
+
+        let flags = &messages[2];
+        assert_eq!(flags.len(), NLMSG_HDR_LEN + NFGENMSG_LEN + 8);
+        assert_eq!(&flags[18..20], &NFLOG_GROUP.to_be_bytes());
+        assert_eq!(&flags[20..22], &6u16.to_ne_bytes());
+        assert_eq!(&flags[22..24], &6u16.to_ne_bytes());
+        assert_eq!(&flags[24..26], &[0x00, 0x02]);
+    }
+
+    #[test]
+    fn test_aggregate_drops() {
+        let key = |port| DropKey {
+            destination: Ipv4Addr::new(10, 0, 0, 1).into(),
+            protocol: libc::IPPROTO_TCP as u8,
+            port: Some(port),
+            uid: Some(0),
+        };
+        let mut aggregator = DropAggregator::default();
+        aggregator.record(key(80));
+        aggregator.record(key(443));
+        aggregator.record(key(443));
+
+        let report = aggregator.drain();
+        assert_eq!(report.len(), 2);
+        assert_eq!(report[0].port, Some(443));
+        assert_eq!(report[0].count, 2);
+        assert_eq!(report[1].port, Some(80));
+        assert_eq!(report[1].count, 1);
+
+        assert!(aggregator.drain().is_empty());
+    }
+}
@@ -0,0 +1,1344 @@
+use super::{drop_log, is_local_dns_address, log_drops, Ipv6Rules};
+use crate::{
+    firewall::{self, FirewallPolicy},
+    split_tunnel, tunnel,
+};
+use ipnetwork::IpNetwork;
+use std::{
+    fmt::Write,
+    io,
+    net::{IpAddr, Ipv4Addr},
+};
+use talpid_types::net::{
+    AllowedTunnelTraffic, Endpoint, ForwardedInterface, ForwardingMode, IpVersion,
+    TransportProtocol,
+};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors that can happen when applying rules with iptables.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Failed to run an iptables command.
+    #[error(display = "Failed to run \"{}\"", _0)]
+    RunCommand(String, #[error(source)] io::Error),
+}
+
+const IN_CHAIN: &str = "mullvad-input";
+const OUT_CHAIN: &str = "mullvad-output";
+const FORWARD_CHAIN: &str = "mullvad-forward";
+const PREROUTING_CHAIN: &str = "mullvad-prerouting";
+const MANGLE_CHAIN: &str = "mullvad-mangle";
+const NAT_CHAIN: &str = "mullvad-nat";
+
+/// Chains managed by this backend, as `(table, built-in chain, chain)`. Each chain is jumped to
+/// from the beginning of its built-in chain.
+const CHAINS: [(&str, &str, &str); 6] = [
+    ("filter", "INPUT", IN_CHAIN),
+    ("filter", "OUTPUT", OUT_CHAIN),
+    ("filter", "FORWARD", FORWARD_CHAIN),
+    ("mangle", "PREROUTING", PREROUTING_CHAIN),
+    ("mangle", "OUTPUT", MANGLE_CHAIN),
+    ("nat", "POSTROUTING", NAT_CHAIN),
+];
+
+/// Runs the iptables binaries. Exists so that the commands issued by the backend can be tested.
+pub trait CommandRunner: Send {
+    /// Runs `program` with `args`, writing `stdin` to its standard input. Fails if the program
+    /// cannot be started or exits unsuccessfully.
+    fn run(&mut self, program: &str, args: &[&str], stdin: Option<&str>) -> io::Result<()>;
+}
+
+struct ProcessRunner;
+
+impl CommandRunner for ProcessRunner {
+    fn run(&mut self, program: &str, args: &[&str], stdin: Option<&str>) -> io::Result<()> {
+        let mut expression = duct::cmd(program, args.iter().copied())
+            .stdout_null()
+            .stderr_capture()
+            .unchecked();
+        if let Some(stdin) = stdin {
+            expression = expression.stdin_bytes(stdin.as_bytes().to_vec());
+        }
+        let output = expression.run()?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+            ))
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    fn program(self) -> &'static str {
+        match self {
+            Family::V4 => "iptables",
+            Family::V6 => "ip6tables",
+        }
+    }
+
+    fn restore_program(self) -> &'static str {
+        match self {
+            Family::V4 => "iptables-restore",
+            Family::V6 => "ip6tables-restore",
+        }
+    }
+
+    fn contains(self, ip: IpAddr) -> bool {
+        matches!(
+            (self, ip),
+            (Family::V4, IpAddr::V4(_)) | (Family::V6, IpAddr::V6(_))
+        )
+    }
+
+    fn matches_version(self, ip_version: IpVersion) -> bool {
+        matches!(
+            (self, ip_version),
+            (Family::V4, IpVersion::V4) | (Family::V6, IpVersion::V6)
+        )
+    }
+
+    fn contains_net(self, net: &IpNetwork) -> bool {
+        self.contains(net.ip())
+    }
+
+    fn reject_with(self) -> &'static str {
+        match self {
+            Family::V4 => "-j REJECT --reject-with icmp-port-unreachable",
+            Family::V6 => "-j REJECT --reject-with icmp6-port-unreachable",
+        }
+    }
+}
+
+/// Firewall backend using iptables and ip6tables, for kernels without nftables support.
+pub struct Firewall {
+    runner: Box<dyn CommandRunner>,
+}
+
+impl Firewall {
+    pub fn new() -> Self {
+        Self::with_runner(Box::new(ProcessRunner))
+    }
+
+    fn with_runner(runner: Box<dyn CommandRunner>) -> Self {
+        Firewall { runner }
+    }
+
+    pub fn apply_policy(&mut self, policy: &FirewallPolicy) -> Result<()> {
+        for family in [Family::V4, Family::V6] {
+            let ruleset = RulesetBuilder::new(family, policy).finalize(policy);
+            // Declaring the chains flushes them, so the whole ruleset is replaced atomically.
+            self.run(
+                family.restore_program(),
+                &["--noflush"],
+                Some(&ruleset.restore_input()),
+            )?;
+            self.add_jump_rules(family)?;
+        }
+        Ok(())
+    }
+
+    pub fn reset_policy(&mut self) -> Result<()> {
+        log::debug!("Removing chains from iptables");
+        for family in [Family::V4, Family::V6] {
+            for (table, builtin_chain, chain) in CHAINS {
+                self.remove_chain(family, table, builtin_chain, chain)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes `chain` and the rule jumping to it from `builtin_chain`, if they exist.
+    fn remove_chain(
+        &mut self,
+        family: Family,
+        table: &str,
+        builtin_chain: &str,
+        chain: &str,
+    ) -> Result<()> {
+        let program = family.program();
+        let chain_exists = self
+            .run(program, &["-w", "-t", table, "-n", "-L", chain], None)
+            .is_ok();
+        if !chain_exists {
+            return Ok(());
+        }
+        let jump_exists = self
+            .run(
+                program,
+                &["-w", "-t", table, "-C", builtin_chain, "-j", chain],
+                None,
+            )
+            .is_ok();
+        if jump_exists {
+            self.run(
+                program,
+                &["-w", "-t", table, "-D", builtin_chain, "-j", chain],
+                None,
+            )?;
+        }
+        self.run(program, &["-w", "-t", table, "-F", chain], None)?;
+        self.run(program, &["-w", "-t", table, "-X", chain], None)
+    }
+
+    fn add_jump_rules(&mut self, family: Family) -> Result<()> {
+        for (table, builtin_chain, chain) in CHAINS {
+            let exists = self
+                .run(
+                    family.program(),
+                    &["-w", "-t", table, "-C", builtin_chain, "-j", chain],
+                    None,
+                )
+                .is_ok();
+            if !exists {
+                self.run(
+                    family.program(),
+                    &["-w", "-t", table, "-I", builtin_chain, "1", "-j", chain],
+                    None,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, program: &str, args: &[&str], stdin: Option<&str>) -> Result<()> {
+        self.runner
+            .run(program, args, stdin)
+            .map_err(|error| Error::RunCommand(format!("{} {}", program, args.join(" ")), error))
+    }
+}
+
+/// Rules for a single IP family, grouped by table.
+#[derive(Debug, Default)]
+struct Ruleset {
+    filter: Vec<String>,
+    mangle: Vec<String>,
+    nat: Vec<String>,
+}
+
+impl Ruleset {
+    /// Formats the rules as input for `iptables-restore`.
+    fn restore_input(&self) -> String {
+        let mut input = String::new();
+        for (table, rules) in [
+            ("filter", &self.filter),
+            ("mangle", &self.mangle),
+            ("nat", &self.nat),
+        ] {
+            let _ = writeln!(input, "*{}", table);
+            for (chain_table, _, chain) in CHAINS {
+                if chain_table == table {
+                    let _ = writeln!(input, ":{} - [0:0]", chain);
+                }
+            }
+            for rule in rules {
+                let _ = writeln!(input, "{}", rule);
+            }
+            let _ = writeln!(input, "COMMIT");
+        }
+        input
+    }
+}
+
+/// Builds the iptables equivalent of the nftables policy, for one IP family at a time.
+struct RulesetBuilder {
+    family: Family,
+    ipv6_rules: Ipv6Rules,
+    /// Send rejected packets to the drop log NFLOG group before rejecting them.
+    log_drops: bool,
+    ruleset: Ruleset,
+}
+
+impl RulesetBuilder {
+    fn new(family: Family, policy: &FirewallPolicy) -> Self {
+        RulesetBuilder {
+            family,
+            ipv6_rules: Ipv6Rules::from_policy(policy),
+            log_drops: log_drops(policy),
+            ruleset: Ruleset::default(),
+        }
+    }
+
+    fn finalize(mut self, policy: &FirewallPolicy) -> Ruleset {
+        self.add_loopback_rules();
+        self.add_split_tunneling_rules(policy);
+        self.add_nat64_discovery_rules();
+        match self.family {
+            Family::V4 => self.add_dhcpv4_client_rules(),
+            Family::V6 => {
+                if self.ipv6_rules.dhcpv6_client {
+                    self.add_dhcpv6_client_rules();
+                }
+                if self.ipv6_rules.ndp {
+                    self.add_ndp_rules();
+                }
+            }
+        }
+        self.add_policy_specific_rules(policy);
+        self.ruleset
+    }
+
+    fn add_rule(&mut self, chain: &str, rule: impl AsRef<str>) {
+        let rules = match chain {
+            PREROUTING_CHAIN | MANGLE_CHAIN => &mut self.ruleset.mangle,
+            NAT_CHAIN => &mut self.ruleset.nat,
+            _ => &mut self.ruleset.filter,
+        };
+        rules.push(format!("-A {} {}", chain, rule.as_ref()));
+    }
+
+    /// Adds a rule rejecting packets that match `matches`, preceded by a rule logging them if
+    /// drop logging is enabled.
+    fn add_reject_rule(&mut self, chain: &str, matches: &str, reject_with: &str) {
+        let prefix = if matches.is_empty() {
+            String::new()
+        } else {
+            format!("{} ", matches)
+        };
+        if self.log_drops {
+            self.add_rule(
+                chain,
+                format!("{}-j NFLOG --nflog-group {}", prefix, drop_log::NFLOG_GROUP),
+            );
+        }
+        self.add_rule(chain, format!("{}{}", prefix, reject_with));
+    }
+
+    fn add_loopback_rules(&mut self) {
+        self.add_rule(OUT_CHAIN, "-o lo -j ACCEPT");
+        self.add_rule(IN_CHAIN, "-i lo -j ACCEPT");
+    }
+
+    fn add_split_tunneling_rules(&mut self, policy: &FirewallPolicy) {
+        // Send select DNS requests in the tunnel
+        if let FirewallPolicy::Connected {
+            tunnel,
+            dns_servers,
+            ..
+        } = policy
+        {
+            let family = self.family;
+            for server in dns_servers
+                .iter()
+                .filter(|server| family.contains(**server) && !is_local_dns_address(tunnel, server))
+            {
+                for protocol in [TransportProtocol::Udp, TransportProtocol::Tcp] {
+                    let rule = format!(
+                        "-o {} -d {} {} -j ACCEPT",
+                        tunnel.interface,
+                        server,
+                        port_match(protocol, End::Dst, 53)
+                    );
+                    self.add_rule(MANGLE_CHAIN, rule);
+                }
+            }
+        }
+
+        let cgroup = format!("-m cgroup --cgroup {:#x}", split_tunnel::NET_CLS_CLASSID);
+        self.add_rule(
+            MANGLE_CHAIN,
+            format!(
+                "{} -j CONNMARK --set-mark {:#x}",
+                cgroup,
+                split_tunnel::MARK
+            ),
+        );
+        self.add_rule(
+            MANGLE_CHAIN,
+            format!(
+                "{} -j MARK --set-mark {:#x}",
+                cgroup,
+                crate::linux::TUNNEL_FW_MARK
+            ),
+        );
+
+        let connmark = format!("-m connmark --mark {:#x}", split_tunnel::MARK);
+        // Block remaining marked outgoing in-tunnel traffic. iptables does not allow dropping
+        // packets in the nat table, so this is done before accepting marked traffic instead.
+        if let FirewallPolicy::Connected { tunnel, .. } = policy {
+            self.add_rule(
+                OUT_CHAIN,
+                format!("-o {} {} -j DROP", tunnel.interface, connmark),
+            );
+        }
+        for chain in [IN_CHAIN, OUT_CHAIN] {
+            self.add_rule(chain, format!("{} -j ACCEPT", connmark));
+        }
+
+        // Replace source IP address in rerouted packets.
+        // Don't masquerade packets on the loopback device.
+        self.add_rule(NAT_CHAIN, format!("! -o lo {} -j MASQUERADE", connmark));
+
+        // Route incoming traffic correctly to prevent strict rpf from rejecting packets
+        // for excluded processes
+        if let FirewallPolicy::Connected { tunnel, .. } = policy {
+            self.add_rule(
+                PREROUTING_CHAIN,
+                format!(
+                    "! -i {} {} -j MARK --set-mark {:#x}",
+                    tunnel.interface,
+                    connmark,
+                    crate::linux::TUNNEL_FW_MARK
+                ),
+            );
+        }
+    }
+
+    /// Treats DNS from sockets marked with `TUNNEL_FW_MARK` like traffic from excluded processes,
+    /// so that the NAT64 prefix of the network can be discovered while DNS is otherwise blocked.
+    /// Only processes with `CAP_NET_ADMIN` can mark their sockets.
+    fn add_nat64_discovery_rules(&mut self) {
+        for protocol in [TransportProtocol::Udp, TransportProtocol::Tcp] {
+            self.add_rule(
+                MANGLE_CHAIN,
+                format!(
+                    "{} -m mark --mark {:#x} -j CONNMARK --set-mark {:#x}",
+                    port_match(protocol, End::Dst, 53),
+                    crate::linux::TUNNEL_FW_MARK,
+                    split_tunnel::MARK
+                ),
+            );
+        }
+    }
+
+    fn add_dhcpv4_client_rules(&mut self) {
+        use self::TransportProtocol::Udp;
+        // Outgoing DHCPv4 request
+        for chain in [OUT_CHAIN, FORWARD_CHAIN] {
+            let rule = format!(
+                "-d {} {} --sport {} -j ACCEPT",
+                Ipv4Addr::BROADCAST,
+                port_match(Udp, End::Dst, firewall::DHCPV4_SERVER_PORT),
+                firewall::DHCPV4_CLIENT_PORT,
+            );
+            self.add_rule(chain, rule);
+        }
+        // Incoming DHCPv4 response
+        for chain in [IN_CHAIN, FORWARD_CHAIN] {
+            let rule = format!(
+                "{} --dport {} -j ACCEPT",
+                port_match(Udp, End::Src, firewall::DHCPV4_SERVER_PORT),
+                firewall::DHCPV4_CLIENT_PORT,
+            );
+            self.add_rule(chain, rule);
+        }
+    }
+
+    fn add_dhcpv6_client_rules(&mut self) {
+        use self::TransportProtocol::Udp;
+        let link_local = *firewall::IPV6_LINK_LOCAL;
+        for chain in [OUT_CHAIN, FORWARD_CHAIN] {
+            for dhcpv6_server in &*firewall::DHCPV6_SERVER_ADDRS {
+                let rule = format!(
+                    "-s {} -d {} {} --sport {} -j ACCEPT",
+                    link_local,
+                    dhcpv6_server,
+                    port_match(Udp, End::Dst, firewall::DHCPV6_SERVER_PORT),
+                    firewall::DHCPV6_CLIENT_PORT,
+                );
+                self.add_rule(chain, rule);
+            }
+        }
+        for chain in [IN_CHAIN, FORWARD_CHAIN] {
+            let rule = format!(
+                "-s {} -d {} {} --dport {} -j ACCEPT",
+                link_local,
+                link_local,
+                port_match(Udp, End::Src, firewall::DHCPV6_SERVER_PORT),
+                firewall::DHCPV6_CLIENT_PORT,
+            );
+            self.add_rule(chain, rule);
+        }
+    }
+
+    fn add_ndp_rules(&mut self) {
+        let link_local = firewall::IPV6_LINK_LOCAL.to_string();
+        let router_solicitation_dst = firewall::ROUTER_SOLICITATION_OUT_DST_ADDR.to_string();
+        let solicited_node = firewall::SOLICITED_NODE_MULTICAST.to_string();
+        let rules: [(&[&str], Option<(&str, &str)>, u8); 8] = [
+            // Outgoing Router solicitation
+            (
+                &[OUT_CHAIN, FORWARD_CHAIN],
+                Some(("-d", &router_solicitation_dst)),
+                133,
+            ),
+            // Incoming Router advertisement
+            (&[IN_CHAIN, FORWARD_CHAIN], Some(("-s", &link_local)), 134),
+            // Incoming Redirect
+            (&[IN_CHAIN, FORWARD_CHAIN], Some(("-s", &link_local)), 137),
+            // Outgoing Neighbor solicitation
+            (
+                &[OUT_CHAIN, FORWARD_CHAIN],
+                Some(("-d", &solicited_node)),
+                135,
+            ),
+            (&[OUT_CHAIN, FORWARD_CHAIN], Some(("-d", &link_local)), 135),
+            // Incoming Neighbor solicitation
+            (&[IN_CHAIN, FORWARD_CHAIN], Some(("-s", &link_local)), 135),
+            // Outgoing Neighbor advertisement
+            (&[OUT_CHAIN, FORWARD_CHAIN], Some(("-d", &link_local)), 136),
+            // Incoming Neighbor advertisement
+            (&[IN_CHAIN, FORWARD_CHAIN], None, 136),
+        ];
+        for (chains, address, icmp_type) in rules {
+            let address = address
+                .map(|(flag, address)| format!("{} {} ", flag, address))
+                .unwrap_or_default();
+            for chain in chains {
+                let rule = format!(
+                    "{}-p ipv6-icmp --icmpv6-type {}/0 -j ACCEPT",
+                    address, icmp_type
+                );
+                self.add_rule(chain, rule);
+            }
+        }
+    }
+
+    fn add_policy_specific_rules(&mut self, policy: &FirewallPolicy) {
+        let allow_lan = match policy {
+            FirewallPolicy::Connecting {
+                peer_endpoint,
+                tunnel,
+                allow_lan,
+                allowed_endpoint,
+                allowed_tunnel_traffic,
+                additional_peer_endpoints,
+                forwarded_interfaces,
+                ..
+            } => {
+                self.add_allow_tunnel_endpoint_rules(peer_endpoint);
+                for endpoint in additional_peer_endpoints {
+                    self.add_allow_tunnel_endpoint_rules(endpoint);
+                }
+                self.add_allow_endpoint_rules(&allowed_endpoint.endpoint);
+
+                // Important to block DNS after allow relay rule (so the relay can operate
+                // over port 53) but before allow LAN (so DNS does not leak to the LAN)
+                self.add_drop_dns_rule();
+
+                let mut forwarding_tunnel = None;
+                if let Some(tunnel) = tunnel {
+                    match allowed_tunnel_traffic {
+                        AllowedTunnelTraffic::All => {
+                            self.add_allow_tunnel_rules(&tunnel.interface);
+                            forwarding_tunnel = Some(tunnel.interface.as_str());
+                        }
+                        AllowedTunnelTraffic::None => (),
+                        AllowedTunnelTraffic::Only(endpoint) => {
+                            self.add_allow_in_tunnel_endpoint_rules(&tunnel.interface, endpoint);
+                        }
+                    }
+                    if *allow_lan {
+                        self.add_block_cve_2019_14899(tunnel);
+                    }
+                }
+                self.add_forwarded_interface_rules(forwarded_interfaces, forwarding_tunnel);
+                *allow_lan
+            }
+            FirewallPolicy::Connected {
+                peer_endpoint,
+                tunnel,
+                allow_lan,
+                dns_servers,
+                dns_stub,
+                additional_peer_endpoints,
+                untunneled_ip_versions,
+                forwarded_interfaces,
+                ..
+            } => {
+                self.add_allow_tunnel_endpoint_rules(peer_endpoint);
+                for endpoint in additional_peer_endpoints {
+                    self.add_allow_tunnel_endpoint_rules(endpoint);
+                }
+                self.add_allow_dns_rules(tunnel, dns_servers, *dns_stub);
+                // Important to block DNS *before* we allow the tunnel and allow LAN. So DNS
+                // can't leak to the wrong IPs in the tunnel or on the LAN.
+                self.add_drop_dns_rule();
+                self.add_allow_tunnel_rules(&tunnel.interface);
+                if *allow_lan {
+                    self.add_block_cve_2019_14899(tunnel);
+                }
+                self.add_forwarded_interface_rules(forwarded_interfaces, Some(&tunnel.interface));
+                if untunneled_ip_versions
+                    .iter()
+                    .any(|version| self.family.matches_version(*version))
+                {
+                    self.add_allow_untunneled_rules();
+                }
+                *allow_lan
+            }
+            FirewallPolicy::Blocked {
+                allow_lan,
+                allowed_endpoint,
+                forwarded_interfaces,
+                dns_servers,
+                ..
+            } => {
+                self.add_allow_endpoint_rules(&allowed_endpoint.endpoint);
+                self.add_allow_local_dns_rules(dns_servers);
+
+                // Important to drop DNS before allowing LAN (to stop DNS leaking to the LAN)
+                self.add_drop_dns_rule();
+                self.add_forwarded_interface_rules(forwarded_interfaces, None);
+                *allow_lan
+            }
+        };
+
+        if allow_lan {
+            self.add_allow_lan_rules();
+        }
+
+        // Reject any remaining outgoing traffic
+        for chain in [OUT_CHAIN, FORWARD_CHAIN] {
+            self.add_reject_rule(chain, "", self.family.reject_with());
+        }
+        // The built-in chains may accept by default, so drop anything else explicitly
+        for chain in [IN_CHAIN, FORWARD_CHAIN] {
+            self.add_rule(chain, "-j DROP");
+        }
+    }
+
+    fn add_allow_tunnel_endpoint_rules(&mut self, endpoint: &Endpoint) {
+        if !self.family.contains(endpoint.address.ip()) {
+            return;
+        }
+        self.add_rule(
+            PREROUTING_CHAIN,
+            format!(
+                "{} -j MARK --set-mark {:#x}",
+                endpoint_match(End::Src, endpoint),
+                crate::linux::TUNNEL_FW_MARK
+            ),
+        );
+        self.add_rule(
+            IN_CHAIN,
+            format!(
+                "{} -m conntrack --ctstate ESTABLISHED -j ACCEPT",
+                endpoint_match(End::Src, endpoint)
+            ),
+        );
+        self.add_rule(
+            OUT_CHAIN,
+            format!(
+                "{} -m mark --mark {:#x} -j ACCEPT",
+                endpoint_match(End::Dst, endpoint),
+                crate::linux::TUNNEL_FW_MARK
+            ),
+        );
+    }
+
+    /// Adds firewall rules allow traffic to flow to the API. Allows the app to reach the API in
+    /// blocked states. The owner match is only available for outgoing packets.
+    fn add_allow_endpoint_rules(&mut self, endpoint: &Endpoint) {
+        if !self.family.contains(endpoint.address.ip()) {
+            return;
+        }
+        self.add_rule(
+            IN_CHAIN,
+            format!(
+                "{} -m conntrack --ctstate ESTABLISHED -j ACCEPT",
+                endpoint_match(End::Src, endpoint)
+            ),
+        );
+        self.add_rule(
+            OUT_CHAIN,
+            format!(
+                "{} -m owner --uid-owner {} -j ACCEPT",
+                endpoint_match(End::Dst, endpoint),
+                firewall::ROOT_UID
+            ),
+        );
+    }
+
+    /// Allows DNS to `dns_servers`. With `dns_stub` set, only root may send queries to them, and
+    /// forwarded queries are not allowed.
+    fn add_allow_dns_rules(
+        &mut self,
+        tunnel: &tunnel::TunnelMetadata,
+        dns_servers: &[IpAddr],
+        dns_stub: bool,
+    ) {
+        let family = self.family;
+        let owner = if dns_stub {
+            format!(" -m owner --uid-owner {}", firewall::ROOT_UID)
+        } else {
+            String::new()
+        };
+        for server in dns_servers
+            .iter()
+            .filter(|server| family.contains(**server))
+        {
+            for protocol in [TransportProtocol::Udp, TransportProtocol::Tcp] {
+                if is_local_dns_address(tunnel, server) {
+                    let out_rule = format!(
+                        "! -o {} -d {} {}{} -j ACCEPT",
+                        tunnel.interface,
+                        server,
+                        port_match(protocol, End::Dst, 53),
+                        owner
+                    );
+                    let in_rule = format!(
+                        "! -i {} -s {} {} -j ACCEPT",
+                        tunnel.interface,
+                        server,
+                        port_match(protocol, End::Src, 53)
+                    );
+                    self.add_rule(OUT_CHAIN, &out_rule);
+                    self.add_rule(IN_CHAIN, &in_rule);
+                    if !dns_stub {
+                        self.add_rule(FORWARD_CHAIN, &out_rule);
+                        self.add_rule(FORWARD_CHAIN, &in_rule);
+                    }
+                } else {
+                    let rule = format!(
+                        "-o {} -d {} {}{} -j ACCEPT",
+                        tunnel.interface,
+                        server,
+                        port_match(protocol, End::Dst, 53),
+                        owner
+                    );
+                    self.add_rule(OUT_CHAIN, &rule);
+                    if !dns_stub {
+                        self.add_rule(FORWARD_CHAIN, &rule);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Allows DNS to resolvers on the local network while other traffic is blocked.
+    fn add_allow_local_dns_rules(&mut self, dns_servers: &[IpAddr]) {
+        let family = self.family;
+        for server in dns_servers
+            .iter()
+            .filter(|server| family.contains(**server))
+        {
+            for protocol in [TransportProtocol::Udp, TransportProtocol::Tcp] {
+                let out_rule = format!(
+                    "-d {} {} -j ACCEPT",
+                    server,
+                    port_match(protocol, End::Dst, 53)
+                );
+                let in_rule = format!(
+                    "-s {} {} -j ACCEPT",
+                    server,
+                    port_match(protocol, End::Src, 53)
+                );
+                self.add_rule(OUT_CHAIN, &out_rule);
+                self.add_rule(IN_CHAIN, &in_rule);
+                self.add_rule(FORWARD_CHAIN, &out_rule);
+                self.add_rule(FORWARD_CHAIN, &in_rule);
+            }
+        }
+    }
+
+    /// Blocks all outgoing DNS (port 53) on both TCP and UDP
+    fn add_drop_dns_rule(&mut self) {
+        for chain in [OUT_CHAIN, FORWARD_CHAIN] {
+            self.add_reject_rule(
+                chain,
+                &port_match(TransportProtocol::Udp, End::Dst, 53),
+                self.family.reject_with(),
+            );
+            self.add_reject_rule(
+                chain,
+                &port_match(TransportProtocol::Tcp, End::Dst, 53),
+                "-j REJECT --reject-with tcp-reset",
+            );
+        }
+    }
+
+    fn add_allow_in_tunnel_endpoint_rules(&mut self, tunnel_interface: &str, endpoint: &Endpoint) {
+        if !self.family.contains(endpoint.address.ip()) {
+            return;
+        }
+        self.add_rule(
+            OUT_CHAIN,
+            format!(
+                "-o {} {} -j ACCEPT",
+                tunnel_interface,
+                endpoint_match(End::Dst, endpoint)
+            ),
+        );
+        self.add_rule(
+            IN_CHAIN,
+            format!(
+                "-i {} {} -j ACCEPT",
+                tunnel_interface,
+                endpoint_match(End::Src, endpoint)
+            ),
+        );
+    }
+
+    fn add_allow_tunnel_rules(&mut self, tunnel_interface: &str) {
+        self.add_rule(OUT_CHAIN, format!("-o {} -j ACCEPT", tunnel_interface));
+        self.add_rule(FORWARD_CHAIN, format!("-o {} -j ACCEPT", tunnel_interface));
+        self.add_rule(IN_CHAIN, format!("-i {} -j ACCEPT", tunnel_interface));
+        self.add_rule(
+            FORWARD_CHAIN,
+            format!(
+                "-i {} -m conntrack --ctstate ESTABLISHED -j ACCEPT",
+                tunnel_interface
+            ),
+        );
+    }
+
+    /// Allows all traffic of this family outside the tunnel. See the nftables backend for
+    /// details.
+    fn add_allow_untunneled_rules(&mut self) {
+        self.add_rule(OUT_CHAIN, "-j ACCEPT");
+        self.add_rule(IN_CHAIN, "-m conntrack --ctstate ESTABLISHED -j ACCEPT");
+    }
+
+    /// Adds rules for stopping [CVE-2019-14899](https://seclists.org/oss-sec/2019/q4/122).
+    /// See the nftables backend for details.
+    fn add_block_cve_2019_14899(&mut self, tunnel: &tunnel::TunnelMetadata) {
+        let family = self.family;
+        for tunnel_ip in tunnel.ips.iter().filter(|ip| family.contains(**ip)) {
+            self.add_rule(IN_CHAIN, format!("-d {} -j DROP", tunnel_ip));
+        }
+    }
+
+    /// Allows traffic forwarded from bridge interfaces. Unlike nftables, iptables cannot match
+    /// the input interface in the nat table, so everything leaving the tunnel is masqueraded.
+    /// This does not affect local traffic, which already uses the tunnel address.
+    fn add_forwarded_interface_rules(
+        &mut self,
+        interfaces: &[ForwardedInterface],
+        tunnel_interface: Option<&str>,
+    ) {
+        let mut masquerade = false;
+        for forwarded in interfaces {
+            let bridge = forwarded.interface.as_str();
+
+            // Host <-> bridge
+            self.add_rule(OUT_CHAIN, format!("-o {} -j ACCEPT", bridge));
+            self.add_rule(IN_CHAIN, format!("-i {} -j ACCEPT", bridge));
+
+            // Return traffic to the bridge
+            self.add_rule(
+                FORWARD_CHAIN,
+                format!("-o {} -m conntrack --ctstate ESTABLISHED -j ACCEPT", bridge),
+            );
+
+            match (forwarded.mode, tunnel_interface) {
+                (ForwardingMode::Tunnel, Some(tunnel_interface)) => {
+                    self.add_rule(
+                        FORWARD_CHAIN,
+                        format!("-i {} -o {} -j ACCEPT", bridge, tunnel_interface),
+                    );
+                    masquerade = true;
+                }
+                (ForwardingMode::Tunnel, None) => (),
+                (ForwardingMode::Local, _) => {
+                    for net in self.lan_nets(&*firewall::ALLOWED_LAN_NETS) {
+                        self.add_rule(FORWARD_CHAIN, format!("-i {} -d {} -j ACCEPT", bridge, net));
+                    }
+                }
+            }
+        }
+        if let (true, Some(tunnel_interface)) = (masquerade, tunnel_interface) {
+            self.add_rule(NAT_CHAIN, format!("-o {} -j MASQUERADE", tunnel_interface));
+        }
+    }
+
+    fn add_allow_lan_rules(&mut self) {
+        let lan_nets = self.lan_nets(&*firewall::ALLOWED_LAN_NETS);
+        let multicast_nets = self.lan_nets(&*firewall::ALLOWED_LAN_MULTICAST_NETS);
+
+        // Output and forward chains
+        for chain in [OUT_CHAIN, FORWARD_CHAIN] {
+            // LAN -> LAN and LAN -> Multicast
+            for net in lan_nets.iter().chain(&multicast_nets) {
+                self.add_rule(chain, format!("-d {} -j ACCEPT", net));
+            }
+        }
+
+        // Input chain
+        // LAN -> LAN
+        for net in &lan_nets {
+            self.add_rule(IN_CHAIN, format!("-s {} -j ACCEPT", net));
+        }
+
+        if self.family == Family::V4 {
+            self.add_dhcp_server_rules();
+        }
+    }
+
+    fn add_dhcp_server_rules(&mut self) {
+        use TransportProtocol::Udp;
+        // Outgoing DHCPv4 response
+        self.add_rule(
+            OUT_CHAIN,
+            format!(
+                "{} --dport {} -j ACCEPT",
+                port_match(Udp, End::Src, firewall::DHCPV4_SERVER_PORT),
+                firewall::DHCPV4_CLIENT_PORT
+            ),
+        );
+        // Incoming DHCPv4 request
+        self.add_rule(
+            IN_CHAIN,
+            format!(
+                "-d {} {} --sport {} -j ACCEPT",
+                Ipv4Addr::BROADCAST,
+                port_match(Udp, End::Dst, firewall::DHCPV4_SERVER_PORT),
+                firewall::DHCPV4_CLIENT_PORT
+            ),
+        );
+    }
+
+    /// Returns the networks in `nets` that belong to this family and are allowed by the IPv6
+    /// firewall mode.
+    fn lan_nets(&self, nets: &[IpNetwork]) -> Vec<IpNetwork> {
+        self.ipv6_rules
+            .filter_nets(nets)
+            .filter(|net| self.family.contains_net(net))
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum End {
+    Src,
+    Dst,
+}
+
+fn port_match(protocol: TransportProtocol, end: End, port: u16) -> String {
+    let protocol = match protocol {
+        TransportProtocol::Udp => "udp",
+        TransportProtocol::Tcp => "tcp",
+    };
+    let flag = match end {
+        End::Src => "--sport",
+        End::Dst => "--dport",
+    };
+    format!("-p {} {} {}", protocol, flag, port)
+}
+
+fn endpoint_match(end: End, endpoint: &Endpoint) -> String {
+    let flag = match end {
+        End::Src => "-s",
+        End::Dst => "-d",
+    };
+    format!(
+        "{} {} {}",
+        flag,
+        endpoint.address.ip(),
+        port_match(endpoint.protocol, end, endpoint.address.port())
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        net::Ipv6Addr,
+        sync::{Arc, Mutex},
+    };
+    use talpid_types::net::{AllowedEndpoint, Ipv6FirewallMode};
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Invocation {
+        program: String,
+        args: Vec<String>,
+        stdin: Option<String>,
+    }
+
+    /// Records every command. `-C` checks fail, as if no jump rules existed, and so does any
+    /// command containing the `fail_on` argument.
+    #[derive(Clone, Default)]
+    struct MockRunner {
+        invocations: Arc<Mutex<Vec<Invocation>>>,
+        fail_on: Option<&'static str>,
+    }
+
+    impl CommandRunner for MockRunner {
+        fn run(&mut self, program: &str, args: &[&str], stdin: Option<&str>) -> io::Result<()> {
+            self.invocations.lock().unwrap().push(Invocation {
+                program: program.to_owned(),
+                args: args.iter().map(|arg| arg.to_string()).collect(),
+                stdin: stdin.map(str::to_owned),
+            });
+            if args.contains(&"-C") {
+                return Err(io::Error::new(io::ErrorKind::Other, "no such rule"));
+            }
+            if matches!(self.fail_on, Some(arg) if args.contains(&arg)) {
+                return Err(io::Error::new(io::ErrorKind::Other, "command failed"));
+            }
+            Ok(())
+        }
+    }
+
+    fn blocked_policy(allow_lan: bool, ipv6_mode: Ipv6FirewallMode) -> FirewallPolicy {
+        FirewallPolicy::Blocked {
+            allow_lan,
+            allowed_endpoint: AllowedEndpoint {
+                endpoint: Endpoint::new(Ipv4Addr::new(192, 0, 2, 1), 443, TransportProtocol::Tcp),
+            },
+            forwarded_interfaces: vec![],
+            ipv6_mode,
+            log_drops: false,
+            dns_servers: vec![],
+        }
+    }
+
+    fn connected_policy() -> FirewallPolicy {
+        FirewallPolicy::Connected {
+            peer_endpoint: Endpoint::new(
+                Ipv4Addr::new(198, 51, 100, 1),
+                51820,
+                TransportProtocol::Udp,
+            ),
+            tunnel: tunnel::TunnelMetadata {
+                interface: "wg-mullvad".to_owned(),
+                ips: vec![
+                    IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2)),
+                    IpAddr::V6(Ipv6Addr::new(0xfc00, 0xbbbb, 0xbbbb, 0xbb01, 0, 0, 0, 2)),
+                ],
+                ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+                ipv6_gateway: None,
+                mtu: None,
+                wireguard_backend: None,
+            },
+            allow_lan: false,
+            dns_servers: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1))],
+            dns_stub: false,
+            additional_peer_endpoints: vec![],
+            untunneled_ip_versions: vec![],
+            forwarded_interfaces: vec![ForwardedInterface {
+                interface: "docker0".to_owned(),
+                mode: ForwardingMode::Tunnel,
+            }],
+            ipv6_mode: Ipv6FirewallMode::Default,
+            log_drops: false,
+        }
+    }
+
+    fn build(family: Family, policy: &FirewallPolicy) -> Ruleset {
+        RulesetBuilder::new(family, policy).finalize(policy)
+    }
+
+    #[test]
+    fn test_blocked_ruleset() {
+        let policy = blocked_policy(false, Ipv6FirewallMode::Default);
+        let v4 = build(Family::V4, &policy);
+        assert!(v4.filter.contains(
+            &"-A mullvad-output -d 192.0.2.1 -p tcp --dport 443 -m owner --uid-owner 0 -j ACCEPT"
+                .to_owned()
+        ));
+        assert_eq!(
+            v4.filter
+                .iter()
+                .filter(|rule| rule.starts_with("-A mullvad-output"))
+                .last()
+                .unwrap(),
+            "-A mullvad-output -j REJECT --reject-with icmp-port-unreachable"
+        );
+        assert_eq!(v4.filter.last().unwrap(), "-A mullvad-forward -j DROP");
+        assert!(!v4.filter.iter().any(|rule| rule.contains("10.0.0.0/8")));
+
+        let v6 = build(Family::V6, &policy);
+        assert!(!v6.filter.iter().any(|rule| rule.contains("192.0.2.1")));
+        assert!(v6.filter.iter().any(|rule| rule.contains("ipv6-icmp")));
+    }
+
+    #[test]
+    fn test_blocked_dns_ruleset() {
+        let mut policy = blocked_policy(false, Ipv6FirewallMode::Default);
+        if let FirewallPolicy::Blocked { dns_servers, .. } = &mut policy {
+            dns_servers.push(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)));
+        }
+        let v4 = build(Family::V4, &policy);
+
+        let allow = v4
+            .filter
+            .iter()
+            .position(|rule| rule == "-A mullvad-output -d 192.168.1.1 -p udp --dport 53 -j ACCEPT")
+            .unwrap();
+        let reject = v4
+            .filter
+            .iter()
+            .position(|rule| {
+                rule == "-A mullvad-output -p udp --dport 53 -j REJECT --reject-with \
+                         icmp-port-unreachable"
+            })
+            .unwrap();
+        assert!(allow < reject);
+        assert!(v4
+            .filter
+            .contains(&"-A mullvad-input -s 192.168.1.1 -p tcp --sport 53 -j ACCEPT".to_owned()));
+
+        let v6 = build(Family::V6, &policy);
+        assert!(!v6.filter.iter().any(|rule| rule.contains("192.168.1.1")));
+    }
+
+    #[test]
+    fn test_nat64_discovery_ruleset() {
+        for policy in [
+            blocked_policy(false, Ipv6FirewallMode::Default),
+            connected_policy(),
+        ] {
+            for family in [Family::V4, Family::V6] {
+                let ruleset = build(family, &policy);
+                assert!(ruleset.mangle.contains(
+                    &"-A mullvad-mangle -p udp --dport 53 -m mark --mark 0x6d6f6c65 \
+                      -j CONNMARK --set-mark 0xf41"
+                        .to_owned()
+                ));
+
+                // Marked DNS is accepted as excluded traffic before any DNS is rejected
+                let accept_rule = format!(
+                    "-A mullvad-output -m connmark --mark {:#x} -j ACCEPT",
+                    split_tunnel::MARK
+                );
+                let accept = ruleset
+                    .filter
+                    .iter()
+                    .position(|rule| *rule == accept_rule)
+                    .unwrap();
+                let reject = ruleset
+                    .filter
+                    .iter()
+                    .position(|rule| {
+                        rule.starts_with("-A mullvad-output -p udp --dport 53 -j REJECT")
+                    })
+                    .unwrap();
+                assert!(accept < reject);
+            }
+        }
+    }
+
+    #[test]
+    fn test_log_drops_ruleset() {
+        let mut policy = blocked_policy(false, Ipv6FirewallMode::Default);
+        let v4 = build(Family::V4, &policy);
+        assert!(!v4.filter.iter().any(|rule| rule.contains("NFLOG")));
+
+        if let FirewallPolicy::Blocked { log_drops, .. } = &mut policy {
+            *log_drops = true;
+        }
+        let v4 = build(Family::V4, &policy);
+
+        let log_rule = format!(
+            "-A mullvad-output -p udp --dport 53 -j NFLOG --nflog-group {}",
+            drop_log::NFLOG_GROUP
+        );
+        let log = v4.filter.iter().position(|rule| *rule == log_rule).unwrap();
+        assert_eq!(
+            v4.filter[log + 1],
+            "-A mullvad-output -p udp --dport 53 -j REJECT --reject-with icmp-port-unreachable"
+        );
+        assert!(v4.filter.contains(&format!(
+            "-A mullvad-forward -j NFLOG --nflog-group {}",
+            drop_log::NFLOG_GROUP
+        )));
+    }
+
+    #[test]
+    fn test_connected_ruleset() {
+        let v4 = build(Family::V4, &connected_policy());
+        for rule in [
+            "-A mullvad-output -d 198.51.100.1 -p udp --dport 51820 -m mark --mark 0x6d6f6c65 -j ACCEPT",
+            "-A mullvad-output -o wg-mullvad -d 10.64.0.1 -p udp --dport 53 -j ACCEPT",
+            "-A mullvad-output -o wg-mullvad -j ACCEPT",
+            "-A mullvad-forward -i docker0 -o wg-mullvad -j ACCEPT",
+        ] {
+            assert!(v4.filter.contains(&rule.to_owned()), "missing {}", rule);
+        }
+        assert!(v4
+            .nat
+            .contains(&"-A mullvad-nat -o wg-mullvad -j MASQUERADE".to_owned()));
+
+        let dns_allow = v4
+            .filter
+            .iter()
+            .position(|rule| rule.contains("-d 10.64.0.1 -p udp --dport 53 -j ACCEPT"))
+            .unwrap();
+        let dns_reject = v4
+            .filter
+            .iter()
+            .position(|rule| rule.contains("-p udp --dport 53 -j REJECT"))
+            .unwrap();
+        assert!(dns_allow < dns_reject);
+    }
+
+    #[test]
+    fn test_split_tunnel_ruleset() {
+        let mut policy = connected_policy();
+        if let FirewallPolicy::Connected {
+            additional_peer_endpoints,
+            untunneled_ip_versions,
+            ..
+        } = &mut policy
+        {
+            additional_peer_endpoints.push(Endpoint::new(
+                Ipv4Addr::new(203, 0, 113, 1),
+                51820,
+                TransportProtocol::Udp,
+            ));
+            untunneled_ip_versions.push(IpVersion::V4);
+        }
+
+        let v4 = build(Family::V4, &policy);
+        assert!(v4.filter.contains(
+            &"-A mullvad-output -d 203.0.113.1 -p udp --dport 51820 -m mark --mark 0x6d6f6c65 -j ACCEPT"
+                .to_owned()
+        ));
+        let dns_reject = v4
+            .filter
+            .iter()
+            .position(|rule| rule.contains("-p udp --dport 53 -j REJECT"))
+            .unwrap();
+        let untunneled = v4
+            .filter
+            .iter()
+            .position(|rule| rule == "-A mullvad-output -j ACCEPT")
+            .unwrap();
+        assert!(dns_reject < untunneled);
+        assert!(v4
+            .filter
+            .contains(&"-A mullvad-input -m conntrack --ctstate ESTABLISHED -j ACCEPT".to_owned()));
+
+        let v6 = build(Family::V6, &policy);
+        assert!(!v6
+            .filter
+            .iter()
+            .any(|rule| rule == "-A mullvad-output -j ACCEPT"));
+    }
+
+    #[test]
+    fn test_dns_stub_ruleset() {
+        let mut policy = connected_policy();
+        if let FirewallPolicy::Connected { dns_stub, .. } = &mut policy {
+            *dns_stub = true;
+        }
+        let v4 = build(Family::V4, &policy);
+
+        assert!(v4.filter.contains(
+            &"-A mullvad-output -o wg-mullvad -d 10.64.0.1 -p udp --dport 53 -m owner --uid-owner 0 -j ACCEPT"
+                .to_owned()
+        ));
+        assert!(!v4
+            .filter
+            .iter()
+            .any(|rule| rule.starts_with("-A mullvad-forward")
+                && rule.contains("--dport 53 -j ACCEPT")));
+    }
+
+    #[test]
+    fn test_ipv6_modes() {
+        let v6 = build(
+            Family::V6,
+            &blocked_policy(true, Ipv6FirewallMode::BlockOutsideTunnel),
+        );
+        assert!(v6
+            .filter
+            .iter()
+            .any(|rule| rule.contains("--icmpv6-type 134/0")));
+        assert!(v6
+            .filter
+            .iter()
+            .any(|rule| rule.contains("--icmpv6-type 135/0")));
+        assert!(!v6.filter.iter().any(|rule| rule.contains("--dport 547")));
+        assert!(!v6.filter.iter().any(|rule| rule.contains("fc00::/7")));
+
+        let policy = blocked_policy(true, Ipv6FirewallMode::TunnelOnly);
+        let v6 = build(Family::V6, &policy);
+        assert!(v6.filter.iter().any(|rule| rule.contains("ipv6-icmp")));
+        assert!(!v6.filter.iter().any(|rule| rule.contains("fc00::/7")));
+        let v4 = build(Family::V4, &policy);
+        assert!(v4
+            .filter
+            .contains(&"-A mullvad-output -d 10.0.0.0/8 -j ACCEPT".to_owned()));
+    }
+
+    #[test]
+    fn test_restore_input() {
+        let input = build(
+            Family::V4,
+            &blocked_policy(false, Ipv6FirewallMode::Default),
+        )
+        .restore_input();
+        assert!(input.starts_with("*filter\n:mullvad-input - [0:0]\n"));
+        assert_eq!(input.matches("COMMIT\n").count(), 3);
+        assert!(input.contains("*nat\n:mullvad-nat - [0:0]\n"));
+    }
+
+    #[test]
+    fn test_apply_and_reset_commands() {
+        let runner = MockRunner::default();
+        let mut firewall = Firewall::with_runner(Box::new(runner.clone()));
+        firewall
+            .apply_policy(&blocked_policy(false, Ipv6FirewallMode::Default))
+            .unwrap();
+
+        let invocations = runner.invocations.lock().unwrap().clone();
+        let restores: Vec<_> = invocations
+            .iter()
+            .filter(|invocation| invocation.stdin.is_some())
+            .map(|invocation| invocation.program.as_str())
+            .collect();
+        assert_eq!(restores, ["iptables-restore", "ip6tables-restore"]);
+        let inserts = invocations
+            .iter()
+            .filter(|invocation| invocation.args.contains(&"-I".to_owned()))
+            .count();
+        assert_eq!(inserts, 2 * CHAINS.len());
+
+        runner.invocations.lock().unwrap().clear();
+        firewall.reset_policy().unwrap();
+        let invocations = runner.invocations.lock().unwrap().clone();
+        assert!(invocations
+            .iter()
+            .all(|invocation| invocation.stdin.is_none()));
+        assert!(invocations.contains(&Invocation {
+            program: "ip6tables".to_owned(),
+            args: ["-w", "-t", "nat", "-X", NAT_CHAIN]
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect(),
+            stdin: None,
+        }));
+    }
+
+    #[test]
+    fn test_reset_missing_chains() {
+        let runner = MockRunner {
+            fail_on: Some("-L"),
+            ..MockRunner::default()
+        };
+        let mut firewall = Firewall::with_runner(Box::new(runner.clone()));
+        firewall.reset_policy().unwrap();
+
+        let invocations = runner.invocations.lock().unwrap().clone();
+        assert_eq!(invocations.len(), 2 * CHAINS.len());
+        assert!(invocations
+            .iter()
+            .all(|invocation| invocation.args.contains(&"-L".to_owned())));
+    }
+
+    #[test]
+    fn test_reset_failure() {
+        let runner = MockRunner {
+            fail_on: Some("-X"),
+            ..MockRunner::default()
+        };
+        let mut firewall = Firewall::with_runner(Box::new(runner));
+        assert!(matches!(
+            firewall.reset_policy(),
+            Err(Error::RunCommand(command, _)) if command.ends_with(&format!("-X {}", IN_CHAIN))
+        ));
+    }
+}
@@ -0,0 +1,283 @@
+use super::{FirewallArguments, FirewallPolicy};
+use crate::tunnel;
+use ipnetwork::IpNetwork;
+use lazy_static::lazy_static;
+use std::{env, net::IpAddr};
+use talpid_types::{net::Ipv6FirewallMode, ErrorExt};
+
+pub mod drop_log;
+mod iptables;
+mod nftables;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors that can happen when applying firewall rules on Linux.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Error in the nftables firewall backend.
+    #[error(display = "Error in the nftables firewall backend")]
+    Nftables(#[error(source)] nftables::Error),
+
+    /// Error in the iptables firewall backend.
+    #[error(display = "Error in the iptables firewall backend")]
+    Iptables(#[error(source)] iptables::Error),
+}
+
+lazy_static! {
+    static ref DONT_SET_SRC_VALID_MARK: bool = env::var("TALPID_FIREWALL_DONT_SET_SRC_VALID_MARK")
+        .map(|v| v != "0")
+        .unwrap_or(false);
+}
+
+/// The Linux implementation for the firewall and DNS. Uses nftables when the kernel supports it,
+/// and falls back on iptables otherwise or if nftables cannot be initialized. The backend can be
+/// forced by setting `TALPID_FIREWALL_BACKEND` to `nftables` or `iptables`.
+pub enum Firewall {
+    Nftables(nftables::Firewall),
+    Iptables(iptables::Firewall),
+}
+
+impl Firewall {
+    pub fn from_args(_args: FirewallArguments) -> Result<Self> {
+        Self::new()
+    }
+
+    pub fn new() -> Result<Self> {
+        let backend = env::var_os("TALPID_FIREWALL_BACKEND");
+
+        let firewall = match backend
+            .as_ref()
+            .map(|value| value.to_string_lossy())
+            .as_deref()
+        {
+            Some("nftables") => {
+                Firewall::Nftables(nftables::Firewall::new().map_err(Error::Nftables)?)
+            }
+            Some("iptables") => Firewall::Iptables(iptables::Firewall::new()),
+            Some(backend) => {
+                log::warn!(
+                    "Ignoring unknown firewall backend {:?} in TALPID_FIREWALL_BACKEND. Expected \
+                     \"nftables\" or \"iptables\"",
+                    backend
+                );
+                Self::with_detected_backend()
+            }
+            None => Self::with_detected_backend(),
+        };
+        log::debug!("Using {} firewall backend", firewall.backend_name());
+        Ok(firewall)
+    }
+
+    fn with_detected_backend() -> Self {
+        if nftables::Firewall::is_available() {
+            match nftables::Firewall::new() {
+                Ok(firewall) => return Firewall::Nftables(firewall),
+                Err(error) => log::warn!(
+                    "{}",
+                    error.display_chain_with_msg(
+                        "Failed to initialize nftables backend, falling back on iptables"
+                    )
+                ),
+            }
+        }
+        Firewall::Iptables(iptables::Firewall::new())
+    }
+
+    fn backend_name(&self) -> &'static str {
+        match self {
+            Firewall::Nftables(_) => "nftables",
+            Firewall::Iptables(_) => "iptables",
+        }
+    }
+
+    pub fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<()> {
+        match self {
+            Firewall::Nftables(firewall) => {
+                firewall.apply_policy(&policy).map_err(Error::Nftables)?
+            }
+            Firewall::Iptables(firewall) => {
+                firewall.apply_policy(&policy).map_err(Error::Iptables)?
+            }
+        }
+        Self::apply_kernel_config(&policy);
+        Ok(())
+    }
+
+    pub fn reset_policy(&mut self) -> Result<()> {
+        match self {
+            Firewall::Nftables(firewall) => firewall.reset_policy().map_err(Error::Nftables),
+            Firewall::Iptables(firewall) => firewall.reset_policy().map_err(Error::Iptables),
+        }
+    }
+
+    fn apply_kernel_config(policy: &FirewallPolicy) {
+        if *DONT_SET_SRC_VALID_MARK {
+            log::debug!("Not setting src_valid_mark");
+            return;
+        }
+
+        if let FirewallPolicy::Connecting { .. } = policy {
+            if let Err(err) = crate::linux::set_src_valid_mark_sysctl() {
+                log::error!("Failed to apply src_valid_mark: {}", err);
+            }
+        }
+    }
+}
+
+/// IPv6 rules that are added outside the tunnel, as selected by an [`Ipv6FirewallMode`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Ipv6Rules {
+    /// Allow neighbor discovery and router solicitations.
+    ndp: bool,
+    /// Allow DHCPv6 client traffic.
+    dhcpv6_client: bool,
+    /// Allow IPv6 LAN and multicast networks.
+    lan: bool,
+}
+
+impl Ipv6Rules {
+    fn new(mode: Ipv6FirewallMode, allow_lan: bool) -> Self {
+        Ipv6Rules {
+            // Without neighbor discovery and router advertisements, IPv6 relays cannot be reached
+            ndp: true,
+            dhcpv6_client: mode.allows_dhcpv6(),
+            lan: allow_lan && mode.allows_lan(),
+        }
+    }
+
+    fn from_policy(policy: &FirewallPolicy) -> Self {
+        match policy {
+            FirewallPolicy::Connecting {
+                allow_lan,
+                ipv6_mode,
+                ..
+            }
+            | FirewallPolicy::Connected {
+                allow_lan,
+                ipv6_mode,
+                ..
+            }
+            | FirewallPolicy::Blocked {
+                allow_lan,
+                ipv6_mode,
+                ..
+            } => Self::new(*ipv6_mode, *allow_lan),
+        }
+    }
+
+    /// Returns the networks in `nets` that may be reached with these rules.
+    fn filter_nets<'b>(&self, nets: &'b [IpNetwork]) -> impl Iterator<Item = &'b IpNetwork> {
+        let allow_v6 = self.lan;
+        nets.iter().filter(move |net| net.is_ipv4() || allow_v6)
+    }
+}
+
+/// Returns whether rejected packets should be sent to the drop log. `TALPID_FIREWALL_LOG_DROPS`
+/// enables this regardless of the policy.
+fn log_drops(policy: &FirewallPolicy) -> bool {
+    let log_drops = match policy {
+        FirewallPolicy::Connecting { log_drops, .. }
+        | FirewallPolicy::Connected { log_drops, .. }
+        | FirewallPolicy::Blocked { log_drops, .. } => *log_drops,
+    };
+    log_drops || drop_log::is_forced()
+}
+
+fn is_local_dns_address(tunnel: &tunnel::TunnelMetadata, server: &IpAddr) -> bool {
+    super::is_local_address(server)
+        && server != &tunnel.ipv4_gateway
+        && Some(server) != tunnel.ipv6_gateway.map(IpAddr::from).as_ref()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use talpid_types::net::{AllowedEndpoint, Endpoint, TransportProtocol};
+
+    fn lan_nets(ipv6_rules: Ipv6Rules) -> Vec<IpNetwork> {
+        ipv6_rules
+            .filter_nets(&*crate::firewall::ALLOWED_LAN_NETS)
+            .chain(ipv6_rules.filter_nets(&*crate::firewall::ALLOWED_LAN_MULTICAST_NETS))
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn test_default_ipv6_rules() {
+        let rules = Ipv6Rules::new(Ipv6FirewallMode::Default, true);
+        assert_eq!(
+            rules,
+            Ipv6Rules {
+                ndp: true,
+                dhcpv6_client: true,
+                lan: true,
+            }
+        );
+        let nets = lan_nets(rules);
+        assert!(nets.iter().any(|net| net.is_ipv4()));
+        assert!(nets.iter().any(|net| net.is_ipv6()));
+
+        let rules = Ipv6Rules::new(Ipv6FirewallMode::Default, false);
+        assert!(rules.ndp && rules.dhcpv6_client);
+        assert!(!rules.lan);
+    }
+
+    #[test]
+    fn test_block_outside_tunnel_ipv6_rules() {
+        for allow_lan in [true, false] {
+            let rules = Ipv6Rules::new(Ipv6FirewallMode::BlockOutsideTunnel, allow_lan);
+            assert_eq!(
+                rules,
+                Ipv6Rules {
+                    ndp: true,
+                    dhcpv6_client: false,
+                    lan: false,
+                }
+            );
+            assert!(lan_nets(rules).iter().all(|net| net.is_ipv4()));
+        }
+    }
+
+    #[test]
+    fn test_tunnel_only_ipv6_rules() {
+        let rules = Ipv6Rules::new(Ipv6FirewallMode::TunnelOnly, true);
+        assert_eq!(
+            rules,
+            Ipv6Rules {
+                ndp: true,
+                dhcpv6_client: true,
+                lan: false,
+            }
+        );
+        let nets = lan_nets(rules);
+        assert!(nets.iter().all(|net| net.is_ipv4()));
+        assert_eq!(
+            nets.len(),
+            crate::firewall::ALLOWED_LAN_NETS
+                .iter()
+                .chain(&*crate::firewall::ALLOWED_LAN_MULTICAST_NETS)
+                .filter(|net| net.is_ipv4())
+                .count()
+        );
+    }
+
+    #[test]
+    fn test_ipv6_rules_from_policy() {
+        let policy = FirewallPolicy::Blocked {
+            allow_lan: true,
+            allowed_endpoint: AllowedEndpoint {
+                endpoint: Endpoint::new(Ipv4Addr::LOCALHOST, 443, TransportProtocol::Tcp),
+            },
+            forwarded_interfaces: vec![],
+            ipv6_mode: Ipv6FirewallMode::TunnelOnly,
+            log_drops: false,
+            dns_servers: vec![],
+        };
+        assert_eq!(
+            Ipv6Rules::from_policy(&policy),
+            Ipv6Rules::new(Ipv6FirewallMode::TunnelOnly, true)
+        );
+    }
+}
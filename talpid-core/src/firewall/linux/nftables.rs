@@ -1,5 +1,8 @@
-use super::{FirewallArguments, FirewallPolicy};
-use crate::{split_tunnel, tunnel};
+use super::{drop_log, is_local_dns_address, log_drops, Ipv6Rules};
+use crate::{
+    firewall::{self, FirewallPolicy},
+    split_tunnel, tunnel,
+};
 use ipnetwork::IpNetwork;
 use lazy_static::lazy_static;
 use libc;
@@ -9,12 +12,19 @@ use nftnl::{
     nft_expr, table, Batch, Chain, FinalizedBatch, ProtoFamily, Rule, Table,
 };
 use std::{
+    collections::HashSet,
     env,
     ffi::{CStr, CString},
     io,
     net::{IpAddr, Ipv4Addr},
 };
-use talpid_types::net::{AllowedTunnelTraffic, Endpoint, TransportProtocol};
+use talpid_types::{
+    net::{
+        AllowedTunnelTraffic, Endpoint, ForwardedInterface, ForwardingMode, IpVersion,
+        TransportProtocol,
+    },
+    ErrorExt,
+};
 
 /// Priority for rules that tag split tunneling packets. Equals NF_IP_PRI_MANGLE.
 const MANGLE_CHAIN_PRIORITY: i32 = libc::NF_IP_PRI_MANGLE;
@@ -77,10 +87,6 @@ lazy_static! {
     static ref ADD_COUNTERS: bool = env::var("TALPID_FIREWALL_DEBUG")
         .map(|v| v != "0")
         .unwrap_or(false);
-
-    static ref DONT_SET_SRC_VALID_MARK: bool = env::var("TALPID_FIREWALL_DONT_SET_SRC_VALID_MARK")
-        .map(|v| v != "0")
-        .unwrap_or(false);
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
@@ -95,7 +101,7 @@ enum End {
     Dst,
 }
 
-/// The Linux implementation for the firewall and DNS.
+/// Firewall backend using nftables.
 pub struct Firewall(());
 
 struct FirewallTables {
@@ -105,23 +111,32 @@ struct FirewallTables {
 }
 
 impl Firewall {
-    pub fn from_args(_args: FirewallArguments) -> Result<Self> {
+    pub fn new() -> Result<Self> {
         Ok(Firewall(()))
     }
 
-    pub fn new() -> Result<Self> {
-        Ok(Firewall(()))
+    /// Returns whether the kernel supports nftables, by trying to list the existing tables.
+    pub fn is_available() -> bool {
+        match Self::list_tables() {
+            Ok(_) => true,
+            Err(error) => {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg("nftables is unavailable")
+                );
+                false
+            }
+        }
     }
 
-    pub fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<()> {
+    pub fn apply_policy(&mut self, policy: &FirewallPolicy) -> Result<()> {
         let tables = FirewallTables {
             main: Table::new(&*TABLE_NAME, ProtoFamily::Inet),
             mangle_v4: Table::new(&*MANGLE_TABLE_NAME_V4, ProtoFamily::Ipv4),
             mangle_v6: Table::new(&*MANGLE_TABLE_NAME_V6, ProtoFamily::Ipv6),
         };
-        let batch = PolicyBatch::new(&tables).finalize(&policy)?;
+        let batch = PolicyBatch::new(&tables).finalize(policy)?;
         Self::send_and_process(&batch)?;
-        Self::apply_kernel_config(&policy);
         self.verify_tables(&[&TABLE_NAME, &MANGLE_TABLE_NAME_V4, &MANGLE_TABLE_NAME_V6])
     }
 
@@ -145,19 +160,6 @@ impl Firewall {
         Ok(())
     }
 
-    fn apply_kernel_config(policy: &FirewallPolicy) {
-        if *DONT_SET_SRC_VALID_MARK {
-            log::debug!("Not setting src_valid_mark");
-            return;
-        }
-
-        if let FirewallPolicy::Connecting { .. } = policy {
-            if let Err(err) = crate::linux::set_src_valid_mark_sysctl() {
-                log::error!("Failed to apply src_valid_mark: {}", err);
-            }
-        }
-    }
-
     fn send_and_process(batch: &FinalizedBatch) -> Result<()> {
         let socket = mnl::Socket::new(mnl::Bus::Netfilter).map_err(Error::NetlinkOpenError)?;
         socket.send_all(batch).map_err(Error::NetlinkSendError)?;
@@ -179,6 +181,20 @@ impl Firewall {
     }
 
     fn verify_tables(&self, expected_tables: &[&CStr]) -> Result<()> {
+        let table_set = Self::list_tables()?;
+        for expected_table in expected_tables {
+            if !table_set.contains(*expected_table) {
+                log::error!(
+                    "Expected '{}' netfilter table to be set, but it is not",
+                    expected_table.to_string_lossy()
+                );
+                return Err(Error::NetfilterTableNotSetError);
+            }
+        }
+        Ok(())
+    }
+
+    fn list_tables() -> Result<HashSet<CString>> {
         let socket = mnl::Socket::new(mnl::Bus::Netfilter).map_err(Error::NetlinkOpenError)?;
         let portid = socket.portid();
         let seq = 0;
@@ -188,7 +204,7 @@ impl Firewall {
             .send(&get_tables_msg)
             .map_err(Error::NetlinkSendError)?;
 
-        let mut table_set = std::collections::HashSet::new();
+        let mut table_set = HashSet::new();
         let mut msg_buffer = vec![0; nftnl::nft_nlmsg_maxsize() as usize];
 
         while let Some(message) = Self::socket_recv(&socket, &mut msg_buffer)? {
@@ -202,17 +218,7 @@ impl Firewall {
                 mnl::CbResult::Ok => log::trace!("cb_run OK"),
             }
         }
-
-        for expected_table in expected_tables {
-            if !table_set.contains(*expected_table) {
-                log::error!(
-                    "Expected '{}' netfilter table to be set, but it is not",
-                    expected_table.to_string_lossy()
-                );
-                return Err(Error::NetfilterTableNotSetError);
-            }
-        }
-        Ok(())
+        Ok(table_set)
     }
 
     fn socket_recv<'a>(socket: &mnl::Socket, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>> {
@@ -236,6 +242,8 @@ struct PolicyBatch<'a> {
     mangle_chain_v6: Chain<'a>,
     nat_chain_v4: Chain<'a>,
     nat_chain_v6: Chain<'a>,
+    /// Send rejected packets to the drop log NFLOG group before rejecting them.
+    log_drops: bool,
 }
 
 impl<'a> PolicyBatch<'a> {
@@ -302,6 +310,7 @@ impl<'a> PolicyBatch<'a> {
             mangle_chain_v6,
             nat_chain_v4,
             nat_chain_v6,
+            log_drops: false,
         }
     }
 
@@ -315,11 +324,19 @@ impl<'a> PolicyBatch<'a> {
     /// Finalize the nftnl message batch by adding every firewall rule needed to satisfy the given
     /// policy.
     pub fn finalize(mut self, policy: &FirewallPolicy) -> Result<FinalizedBatch> {
+        self.log_drops = log_drops(policy);
         self.add_loopback_rules()?;
         self.add_split_tunneling_rules(policy)?;
-        self.add_dhcp_client_rules();
-        self.add_ndp_rules();
-        self.add_policy_specific_rules(policy)?;
+        self.add_nat64_discovery_rules();
+        let ipv6_rules = Ipv6Rules::from_policy(policy);
+        self.add_dhcpv4_client_rules();
+        if ipv6_rules.dhcpv6_client {
+            self.add_dhcpv6_client_rules();
+        }
+        if ipv6_rules.ndp {
+            self.add_ndp_rules();
+        }
+        self.add_policy_specific_rules(policy, ipv6_rules)?;
 
         Ok(self.batch.finalize())
     }
@@ -346,6 +363,7 @@ impl<'a> PolicyBatch<'a> {
                     &tunnel.interface,
                     TransportProtocol::Udp,
                     *server,
+                    false,
                 )?;
                 self.batch.add(&allow_rule, nftnl::MsgType::Add);
                 let allow_rule = allow_tunnel_dns_rule(
@@ -353,6 +371,7 @@ impl<'a> PolicyBatch<'a> {
                     &tunnel.interface,
                     TransportProtocol::Tcp,
                     *server,
+                    false,
                 )?;
                 self.batch.add(&allow_rule, nftnl::MsgType::Add);
             }
@@ -427,6 +446,23 @@ impl<'a> PolicyBatch<'a> {
         Ok(())
     }
 
+    /// Treats DNS from sockets marked with `TUNNEL_FW_MARK` like traffic from excluded processes,
+    /// so that the NAT64 prefix of the network can be discovered while DNS is otherwise blocked.
+    /// Only processes with `CAP_NET_ADMIN` can mark their sockets.
+    fn add_nat64_discovery_rules(&mut self) {
+        for chain in [&self.mangle_chain_v4, &self.mangle_chain_v6] {
+            for protocol in [TransportProtocol::Udp, TransportProtocol::Tcp] {
+                let mut rule = Rule::new(chain);
+                check_port(&mut rule, protocol, End::Dst, 53);
+                rule.add_expr(&nft_expr!(meta mark));
+                rule.add_expr(&nft_expr!(cmp == crate::linux::TUNNEL_FW_MARK));
+                rule.add_expr(&nft_expr!(immediate data split_tunnel::MARK));
+                rule.add_expr(&nft_expr!(ct mark set));
+                self.batch.add(&rule, nftnl::MsgType::Add);
+            }
+        }
+    }
+
     fn add_loopback_rules(&mut self) -> Result<()> {
         const LOOPBACK_IFACE_NAME: &str = "lo";
         self.batch.add(
@@ -440,43 +476,46 @@ impl<'a> PolicyBatch<'a> {
         Ok(())
     }
 
-    fn add_dhcp_client_rules(&mut self) {
+    fn add_dhcpv4_client_rules(&mut self) {
         use self::TransportProtocol::Udp;
         // Outgoing DHCPv4 request
         for chain in &[&self.out_chain, &self.forward_chain] {
             let mut out_v4 = Rule::new(chain);
-            check_port(&mut out_v4, Udp, End::Src, super::DHCPV4_CLIENT_PORT);
+            check_port(&mut out_v4, Udp, End::Src, firewall::DHCPV4_CLIENT_PORT);
             check_ip(&mut out_v4, End::Dst, IpAddr::V4(Ipv4Addr::BROADCAST));
-            check_port(&mut out_v4, Udp, End::Dst, super::DHCPV4_SERVER_PORT);
+            check_port(&mut out_v4, Udp, End::Dst, firewall::DHCPV4_SERVER_PORT);
             add_verdict(&mut out_v4, &Verdict::Accept);
             self.batch.add(&out_v4, nftnl::MsgType::Add);
         }
         // Incoming DHCPv4 response
         for chain in &[&self.in_chain, &self.forward_chain] {
             let mut in_v4 = Rule::new(chain);
-            check_port(&mut in_v4, Udp, End::Src, super::DHCPV4_SERVER_PORT);
-            check_port(&mut in_v4, Udp, End::Dst, super::DHCPV4_CLIENT_PORT);
+            check_port(&mut in_v4, Udp, End::Src, firewall::DHCPV4_SERVER_PORT);
+            check_port(&mut in_v4, Udp, End::Dst, firewall::DHCPV4_CLIENT_PORT);
             add_verdict(&mut in_v4, &Verdict::Accept);
             self.batch.add(&in_v4, nftnl::MsgType::Add);
         }
+    }
 
+    fn add_dhcpv6_client_rules(&mut self) {
+        use self::TransportProtocol::Udp;
         for chain in &[&self.out_chain, &self.forward_chain] {
-            for dhcpv6_server in &*super::DHCPV6_SERVER_ADDRS {
+            for dhcpv6_server in &*firewall::DHCPV6_SERVER_ADDRS {
                 let mut out_v6 = Rule::new(chain);
-                check_net(&mut out_v6, End::Src, *super::IPV6_LINK_LOCAL);
-                check_port(&mut out_v6, Udp, End::Src, super::DHCPV6_CLIENT_PORT);
+                check_net(&mut out_v6, End::Src, *firewall::IPV6_LINK_LOCAL);
+                check_port(&mut out_v6, Udp, End::Src, firewall::DHCPV6_CLIENT_PORT);
                 check_ip(&mut out_v6, End::Dst, *dhcpv6_server);
-                check_port(&mut out_v6, Udp, End::Dst, super::DHCPV6_SERVER_PORT);
+                check_port(&mut out_v6, Udp, End::Dst, firewall::DHCPV6_SERVER_PORT);
                 add_verdict(&mut out_v6, &Verdict::Accept);
                 self.batch.add(&out_v6, nftnl::MsgType::Add);
             }
         }
         for chain in &[&self.in_chain, &self.forward_chain] {
             let mut in_v6 = Rule::new(chain);
-            check_net(&mut in_v6, End::Src, *super::IPV6_LINK_LOCAL);
-            check_port(&mut in_v6, Udp, End::Src, super::DHCPV6_SERVER_PORT);
-            check_net(&mut in_v6, End::Dst, *super::IPV6_LINK_LOCAL);
-            check_port(&mut in_v6, Udp, End::Dst, super::DHCPV6_CLIENT_PORT);
+            check_net(&mut in_v6, End::Src, *firewall::IPV6_LINK_LOCAL);
+            check_port(&mut in_v6, Udp, End::Src, firewall::DHCPV6_SERVER_PORT);
+            check_net(&mut in_v6, End::Dst, *firewall::IPV6_LINK_LOCAL);
+            check_port(&mut in_v6, Udp, End::Dst, firewall::DHCPV6_CLIENT_PORT);
             add_verdict(&mut in_v6, &Verdict::Accept);
             self.batch.add(&in_v6, nftnl::MsgType::Add);
         }
@@ -489,7 +528,7 @@ impl<'a> PolicyBatch<'a> {
             check_ip(
                 &mut rule,
                 End::Dst,
-                *super::ROUTER_SOLICITATION_OUT_DST_ADDR,
+                *firewall::ROUTER_SOLICITATION_OUT_DST_ADDR,
             );
             check_icmpv6(&mut rule, 133, 0);
             add_verdict(&mut rule, &Verdict::Accept);
@@ -498,7 +537,7 @@ impl<'a> PolicyBatch<'a> {
         // Incoming Router advertisement (part of NDP)
         for chain in &[&self.in_chain, &self.forward_chain] {
             let mut rule = Rule::new(chain);
-            check_net(&mut rule, End::Src, *super::IPV6_LINK_LOCAL);
+            check_net(&mut rule, End::Src, *firewall::IPV6_LINK_LOCAL);
             check_icmpv6(&mut rule, 134, 0);
             add_verdict(&mut rule, &Verdict::Accept);
             self.batch.add(&rule, nftnl::MsgType::Add);
@@ -506,7 +545,7 @@ impl<'a> PolicyBatch<'a> {
         // Incoming Redirect (part of NDP)
         for chain in &[&self.in_chain, &self.forward_chain] {
             let mut rule = Rule::new(chain);
-            check_net(&mut rule, End::Src, *super::IPV6_LINK_LOCAL);
+            check_net(&mut rule, End::Src, *firewall::IPV6_LINK_LOCAL);
             check_icmpv6(&mut rule, 137, 0);
             add_verdict(&mut rule, &Verdict::Accept);
             self.batch.add(&rule, nftnl::MsgType::Add);
@@ -514,14 +553,14 @@ impl<'a> PolicyBatch<'a> {
         // Outgoing Neighbor solicitation (part of NDP)
         for chain in &[&self.out_chain, &self.forward_chain] {
             let mut rule = Rule::new(chain);
-            check_net(&mut rule, End::Dst, *super::SOLICITED_NODE_MULTICAST);
+            check_net(&mut rule, End::Dst, *firewall::SOLICITED_NODE_MULTICAST);
             check_icmpv6(&mut rule, 135, 0);
             add_verdict(&mut rule, &Verdict::Accept);
             self.batch.add(&rule, nftnl::MsgType::Add);
         }
         for chain in &[&self.out_chain, &self.forward_chain] {
             let mut rule = Rule::new(chain);
-            check_net(&mut rule, End::Dst, *super::IPV6_LINK_LOCAL);
+            check_net(&mut rule, End::Dst, *firewall::IPV6_LINK_LOCAL);
             check_icmpv6(&mut rule, 135, 0);
             add_verdict(&mut rule, &Verdict::Accept);
             self.batch.add(&rule, nftnl::MsgType::Add);
@@ -529,7 +568,7 @@ impl<'a> PolicyBatch<'a> {
         // Incoming Neighbor solicitation (part of NDP)
         for chain in &[&self.in_chain, &self.forward_chain] {
             let mut rule = Rule::new(chain);
-            check_net(&mut rule, End::Src, *super::IPV6_LINK_LOCAL);
+            check_net(&mut rule, End::Src, *firewall::IPV6_LINK_LOCAL);
             check_icmpv6(&mut rule, 135, 0);
             add_verdict(&mut rule, &Verdict::Accept);
             self.batch.add(&rule, nftnl::MsgType::Add);
@@ -537,7 +576,7 @@ impl<'a> PolicyBatch<'a> {
         // Outgoing Neighbor advertisement (part of NDP)
         for chain in &[&self.out_chain, &self.forward_chain] {
             let mut rule = Rule::new(chain);
-            check_net(&mut rule, End::Dst, *super::IPV6_LINK_LOCAL);
+            check_net(&mut rule, End::Dst, *firewall::IPV6_LINK_LOCAL);
             check_icmpv6(&mut rule, 136, 0);
             add_verdict(&mut rule, &Verdict::Accept);
             self.batch.add(&rule, nftnl::MsgType::Add);
@@ -551,7 +590,11 @@ impl<'a> PolicyBatch<'a> {
         }
     }
 
-    fn add_policy_specific_rules(&mut self, policy: &FirewallPolicy) -> Result<()> {
+    fn add_policy_specific_rules(
+        &mut self,
+        policy: &FirewallPolicy,
+        ipv6_rules: Ipv6Rules,
+    ) -> Result<()> {
         let allow_lan = match policy {
             FirewallPolicy::Connecting {
                 peer_endpoint,
@@ -559,18 +602,26 @@ impl<'a> PolicyBatch<'a> {
                 allow_lan,
                 allowed_endpoint,
                 allowed_tunnel_traffic,
+                additional_peer_endpoints,
+                forwarded_interfaces,
+                ..
             } => {
                 self.add_allow_tunnel_endpoint_rules(peer_endpoint);
+                for endpoint in additional_peer_endpoints {
+                    self.add_allow_tunnel_endpoint_rules(endpoint);
+                }
                 self.add_allow_endpoint_rules(&allowed_endpoint.endpoint);
 
                 // Important to block DNS after allow relay rule (so the relay can operate
                 // over port 53) but before allow LAN (so DNS does not leak to the LAN)
                 self.add_drop_dns_rule();
 
+                let mut forwarding_tunnel = None;
                 if let Some(tunnel) = tunnel {
                     match allowed_tunnel_traffic {
                         AllowedTunnelTraffic::All => {
                             self.add_allow_tunnel_rules(&tunnel.interface)?;
+                            forwarding_tunnel = Some(tunnel.interface.as_str());
                         }
                         AllowedTunnelTraffic::None => (),
                         AllowedTunnelTraffic::Only(endpoint) => {
@@ -581,6 +632,11 @@ impl<'a> PolicyBatch<'a> {
                         self.add_block_cve_2019_14899(tunnel);
                     }
                 }
+                self.add_forwarded_interface_rules(
+                    forwarded_interfaces,
+                    forwarding_tunnel,
+                    ipv6_rules,
+                )?;
                 *allow_lan
             }
             FirewallPolicy::Connected {
@@ -588,10 +644,18 @@ impl<'a> PolicyBatch<'a> {
                 tunnel,
                 allow_lan,
                 dns_servers,
+                dns_stub,
+                additional_peer_endpoints,
+                untunneled_ip_versions,
+                forwarded_interfaces,
+                ..
             } => {
                 self.add_allow_tunnel_endpoint_rules(peer_endpoint);
-                self.add_allow_dns_rules(tunnel, dns_servers, TransportProtocol::Udp)?;
-                self.add_allow_dns_rules(tunnel, dns_servers, TransportProtocol::Tcp)?;
+                for endpoint in additional_peer_endpoints {
+                    self.add_allow_tunnel_endpoint_rules(endpoint);
+                }
+                self.add_allow_dns_rules(tunnel, dns_servers, *dns_stub, TransportProtocol::Udp)?;
+                self.add_allow_dns_rules(tunnel, dns_servers, *dns_stub, TransportProtocol::Tcp)?;
                 // Important to block DNS *before* we allow the tunnel and allow LAN. So DNS
                 // can't leak to the wrong IPs in the tunnel or on the LAN.
                 self.add_drop_dns_rule();
@@ -599,29 +663,47 @@ impl<'a> PolicyBatch<'a> {
                 if *allow_lan {
                     self.add_block_cve_2019_14899(tunnel);
                 }
+                self.add_forwarded_interface_rules(
+                    forwarded_interfaces,
+                    Some(&tunnel.interface),
+                    ipv6_rules,
+                )?;
+                for ip_version in untunneled_ip_versions {
+                    self.add_allow_untunneled_rules(*ip_version);
+                }
                 *allow_lan
             }
             FirewallPolicy::Blocked {
                 allow_lan,
                 allowed_endpoint,
+                forwarded_interfaces,
+                dns_servers,
+                ..
             } => {
                 self.add_allow_endpoint_rules(&allowed_endpoint.endpoint);
+                for server in dns_servers {
+                    for protocol in [TransportProtocol::Udp, TransportProtocol::Tcp] {
+                        self.add_allow_local_dns_rule(None, protocol, *server, false)?;
+                    }
+                }
 
                 // Important to drop DNS before allowing LAN (to stop DNS leaking to the LAN)
                 self.add_drop_dns_rule();
+                self.add_forwarded_interface_rules(forwarded_interfaces, None, ipv6_rules)?;
                 *allow_lan
             }
         };
 
         if allow_lan {
-            self.add_allow_lan_rules();
+            self.add_allow_lan_rules(ipv6_rules);
         }
 
         // Reject any remaining outgoing traffic
         for chain in &[&self.out_chain, &self.forward_chain] {
             let mut reject_rule = Rule::new(chain);
-            add_verdict(
+            add_reject_verdict(
                 &mut reject_rule,
+                self.log_drops,
                 &Verdict::Reject(RejectionType::Icmp(IcmpCode::PortUnreach)),
             );
             self.batch.add(&reject_rule, nftnl::MsgType::Add);
@@ -672,7 +754,7 @@ impl<'a> PolicyBatch<'a> {
         in_rule.add_expr(&nft_expr!(bitwise mask allowed_states, xor 0u32));
         in_rule.add_expr(&nft_expr!(cmp != 0u32));
         in_rule.add_expr(&nft_expr!(meta skuid));
-        in_rule.add_expr(&nft_expr!(cmp == super::ROOT_UID));
+        in_rule.add_expr(&nft_expr!(cmp == firewall::ROOT_UID));
 
         add_verdict(&mut in_rule, &Verdict::Accept);
 
@@ -681,16 +763,19 @@ impl<'a> PolicyBatch<'a> {
         let mut out_rule = Rule::new(&self.out_chain);
         check_endpoint(&mut out_rule, End::Dst, endpoint);
         out_rule.add_expr(&nft_expr!(meta skuid));
-        out_rule.add_expr(&nft_expr!(cmp == super::ROOT_UID));
+        out_rule.add_expr(&nft_expr!(cmp == firewall::ROOT_UID));
         add_verdict(&mut out_rule, &Verdict::Accept);
 
         self.batch.add(&out_rule, nftnl::MsgType::Add);
     }
 
+    /// Allows DNS to `dns_servers`. With `dns_stub` set, only root may send queries to them, and
+    /// forwarded queries are not allowed.
     fn add_allow_dns_rules(
         &mut self,
         tunnel: &tunnel::TunnelMetadata,
         dns_servers: &[IpAddr],
+        dns_stub: bool,
         protocol: TransportProtocol,
     ) -> Result<()> {
         let (local_resolvers, remote_resolvers): (Vec<IpAddr>, Vec<IpAddr>) = dns_servers
@@ -698,11 +783,11 @@ impl<'a> PolicyBatch<'a> {
             .partition(|server| is_local_dns_address(tunnel, server));
 
         for resolver in &local_resolvers {
-            self.add_allow_local_dns_rule(&tunnel.interface, protocol, *resolver)?;
+            self.add_allow_local_dns_rule(Some(&tunnel.interface), protocol, *resolver, dns_stub)?;
         }
 
         for resolver in &remote_resolvers {
-            self.add_allow_tunnel_dns_rule(&tunnel.interface, protocol, *resolver)?;
+            self.add_allow_tunnel_dns_rule(&tunnel.interface, protocol, *resolver, dns_stub)?;
         }
 
         Ok(())
@@ -713,9 +798,16 @@ impl<'a> PolicyBatch<'a> {
         interface: &str,
         protocol: TransportProtocol,
         host: IpAddr,
+        dns_stub: bool,
     ) -> Result<()> {
+        if dns_stub {
+            let allow_rule =
+                allow_tunnel_dns_rule(&self.out_chain, interface, protocol, host, true)?;
+            self.batch.add(&allow_rule, nftnl::MsgType::Add);
+            return Ok(());
+        }
         for chain in &[&self.out_chain, &self.forward_chain] {
-            let allow_rule = allow_tunnel_dns_rule(chain, interface, protocol, host)?;
+            let allow_rule = allow_tunnel_dns_rule(chain, interface, protocol, host, false)?;
             self.batch.add(&allow_rule, nftnl::MsgType::Add);
         }
         Ok(())
@@ -723,16 +815,24 @@ impl<'a> PolicyBatch<'a> {
 
     fn add_allow_local_dns_rule(
         &mut self,
-        tunnel_interface: &str,
+        tunnel_interface: Option<&str>,
         protocol: TransportProtocol,
         host: IpAddr,
+        dns_stub: bool,
     ) -> Result<()> {
-        let chains = [
-            (&self.out_chain, Direction::Out),
-            (&self.forward_chain, Direction::Out),
-            (&self.in_chain, Direction::In),
-            (&self.forward_chain, Direction::In),
-        ];
+        let chains = if dns_stub {
+            vec![
+                (&self.out_chain, Direction::Out),
+                (&self.in_chain, Direction::In),
+            ]
+        } else {
+            vec![
+                (&self.out_chain, Direction::Out),
+                (&self.forward_chain, Direction::Out),
+                (&self.in_chain, Direction::In),
+                (&self.forward_chain, Direction::In),
+            ]
+        };
 
         for (chain, direction) in &chains {
             let mut allow_rule = Rule::new(chain);
@@ -748,12 +848,18 @@ impl<'a> PolicyBatch<'a> {
                 Direction::Out => End::Dst,
             };
 
-            check_not_iface(&mut allow_rule, *direction, tunnel_interface)?;
+            if let Some(tunnel_interface) = tunnel_interface {
+                check_not_iface(&mut allow_rule, *direction, tunnel_interface)?;
+            }
             check_port(&mut allow_rule, protocol, port_dir, 53);
             check_l3proto(&mut allow_rule, host);
 
             allow_rule.add_expr(&addr);
             allow_rule.add_expr(&nft_expr!(cmp == host));
+            if dns_stub && *direction == Direction::Out {
+                allow_rule.add_expr(&nft_expr!(meta skuid));
+                allow_rule.add_expr(&nft_expr!(cmp == firewall::ROOT_UID));
+            }
             add_verdict(&mut allow_rule, &Verdict::Accept);
 
             self.batch.add(&allow_rule, nftnl::MsgType::Add);
@@ -767,15 +873,20 @@ impl<'a> PolicyBatch<'a> {
         for chain in &[&self.out_chain, &self.forward_chain] {
             let mut block_udp_rule = Rule::new(chain);
             check_port(&mut block_udp_rule, TransportProtocol::Udp, End::Dst, 53);
-            add_verdict(
+            add_reject_verdict(
                 &mut block_udp_rule,
+                self.log_drops,
                 &Verdict::Reject(RejectionType::Icmp(IcmpCode::PortUnreach)),
             );
             self.batch.add(&block_udp_rule, nftnl::MsgType::Add);
 
             let mut block_tcp_rule = Rule::new(chain);
             check_port(&mut block_tcp_rule, TransportProtocol::Tcp, End::Dst, 53);
-            add_verdict(&mut block_tcp_rule, &Verdict::Reject(RejectionType::TcpRst));
+            add_reject_verdict(
+                &mut block_tcp_rule,
+                self.log_drops,
+                &Verdict::Reject(RejectionType::TcpRst),
+            );
             self.batch.add(&block_tcp_rule, nftnl::MsgType::Add);
         }
     }
@@ -825,6 +936,31 @@ impl<'a> PolicyBatch<'a> {
         Ok(())
     }
 
+    /// Allows traffic of the given IP version outside the tunnel. Used when the tunnel does not
+    /// route all traffic of that version, since such traffic would otherwise be rejected.
+    fn add_allow_untunneled_rules(&mut self, ip_version: IpVersion) {
+        let nfproto = match ip_version {
+            IpVersion::V4 => libc::NFPROTO_IPV4 as u8,
+            IpVersion::V6 => libc::NFPROTO_IPV6 as u8,
+        };
+
+        let mut out_rule = Rule::new(&self.out_chain);
+        out_rule.add_expr(&nft_expr!(meta nfproto));
+        out_rule.add_expr(&nft_expr!(cmp == nfproto));
+        add_verdict(&mut out_rule, &Verdict::Accept);
+        self.batch.add(&out_rule, nftnl::MsgType::Add);
+
+        let mut in_rule = Rule::new(&self.in_chain);
+        in_rule.add_expr(&nft_expr!(meta nfproto));
+        in_rule.add_expr(&nft_expr!(cmp == nfproto));
+        in_rule.add_expr(&nft_expr!(ct state));
+        let allowed_states = nftnl::expr::ct::States::ESTABLISHED.bits();
+        in_rule.add_expr(&nft_expr!(bitwise mask allowed_states, xor 0u32));
+        in_rule.add_expr(&nft_expr!(cmp != 0u32));
+        add_verdict(&mut in_rule, &Verdict::Accept);
+        self.batch.add(&in_rule, nftnl::MsgType::Add);
+    }
+
     /// Adds rules for stopping [CVE-2019-14899](https://seclists.org/oss-sec/2019/q4/122).
     /// An attacker on the same local network as the VPN connected device could figure out
     /// the tunnel IP the device used if the device was set to not filter reverse path (rp_filter.)
@@ -839,11 +975,90 @@ impl<'a> PolicyBatch<'a> {
         }
     }
 
-    fn add_allow_lan_rules(&mut self) {
+    /// Allows traffic between the host and local bridge interfaces, such as the ones used by
+    /// containers and virtual machines. Traffic forwarded from a bridge is either routed through
+    /// the tunnel and masqueraded, or only allowed to reach local networks. Forwarding through the
+    /// tunnel is only possible when `tunnel_interface` is given.
+    fn add_forwarded_interface_rules(
+        &mut self,
+        interfaces: &[ForwardedInterface],
+        tunnel_interface: Option<&str>,
+        ipv6_rules: Ipv6Rules,
+    ) -> Result<()> {
+        for forwarded in interfaces {
+            let bridge = forwarded.interface.as_str();
+            // Bridges come and go with the services that create them, so a missing one should not
+            // prevent the rest of the policy from being applied.
+            if let Err(error) = crate::linux::iface_index(bridge) {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg(&format!(
+                        "Skipping rules for forwarded interface \"{}\"",
+                        bridge
+                    ))
+                );
+                continue;
+            }
+
+            // Host <-> bridge
+            self.batch.add(
+                &allow_interface_rule(&self.out_chain, Direction::Out, bridge)?,
+                nftnl::MsgType::Add,
+            );
+            self.batch.add(
+                &allow_interface_rule(&self.in_chain, Direction::In, bridge)?,
+                nftnl::MsgType::Add,
+            );
+
+            // Return traffic to the bridge
+            let mut return_rule = Rule::new(&self.forward_chain);
+            check_iface(&mut return_rule, Direction::Out, bridge)?;
+            return_rule.add_expr(&nft_expr!(ct state));
+            let allowed_states = nftnl::expr::ct::States::ESTABLISHED.bits();
+            return_rule.add_expr(&nft_expr!(bitwise mask allowed_states, xor 0u32));
+            return_rule.add_expr(&nft_expr!(cmp != 0u32));
+            add_verdict(&mut return_rule, &Verdict::Accept);
+            self.batch.add(&return_rule, nftnl::MsgType::Add);
+
+            match (forwarded.mode, tunnel_interface) {
+                (ForwardingMode::Tunnel, Some(tunnel_interface)) => {
+                    let mut rule = Rule::new(&self.forward_chain);
+                    check_iface(&mut rule, Direction::In, bridge)?;
+                    check_iface(&mut rule, Direction::Out, tunnel_interface)?;
+                    add_verdict(&mut rule, &Verdict::Accept);
+                    self.batch.add(&rule, nftnl::MsgType::Add);
+
+                    for chain in &[&self.nat_chain_v4, &self.nat_chain_v6] {
+                        let mut rule = Rule::new(chain);
+                        check_iface(&mut rule, Direction::In, bridge)?;
+                        check_iface(&mut rule, Direction::Out, tunnel_interface)?;
+                        rule.add_expr(&nft_expr!(masquerade));
+                        if *ADD_COUNTERS {
+                            rule.add_expr(&nft_expr!(counter));
+                        }
+                        self.batch.add(&rule, nftnl::MsgType::Add);
+                    }
+                }
+                (ForwardingMode::Tunnel, None) => (),
+                (ForwardingMode::Local, _) => {
+                    for net in ipv6_rules.filter_nets(&*firewall::ALLOWED_LAN_NETS) {
+                        let mut rule = Rule::new(&self.forward_chain);
+                        check_iface(&mut rule, Direction::In, bridge)?;
+                        check_net(&mut rule, End::Dst, *net);
+                        add_verdict(&mut rule, &Verdict::Accept);
+                        self.batch.add(&rule, nftnl::MsgType::Add);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add_allow_lan_rules(&mut self, ipv6_rules: Ipv6Rules) {
         // Output and forward chains
         for chain in &[&self.out_chain, &self.forward_chain] {
             // LAN -> LAN
-            for net in &*super::ALLOWED_LAN_NETS {
+            for net in ipv6_rules.filter_nets(&*firewall::ALLOWED_LAN_NETS) {
                 let mut out_rule = Rule::new(chain);
                 check_net(&mut out_rule, End::Dst, *net);
                 add_verdict(&mut out_rule, &Verdict::Accept);
@@ -851,7 +1066,7 @@ impl<'a> PolicyBatch<'a> {
             }
 
             // LAN -> Multicast
-            for net in &*super::ALLOWED_LAN_MULTICAST_NETS {
+            for net in ipv6_rules.filter_nets(&*firewall::ALLOWED_LAN_MULTICAST_NETS) {
                 let mut rule = Rule::new(chain);
                 check_net(&mut rule, End::Dst, *net);
                 add_verdict(&mut rule, &Verdict::Accept);
@@ -861,7 +1076,7 @@ impl<'a> PolicyBatch<'a> {
 
         // Input chain
         // LAN -> LAN
-        for net in &*super::ALLOWED_LAN_NETS {
+        for net in ipv6_rules.filter_nets(&*firewall::ALLOWED_LAN_NETS) {
             let mut in_rule = Rule::new(&self.in_chain);
             check_net(&mut in_rule, End::Src, *net);
             add_verdict(&mut in_rule, &Verdict::Accept);
@@ -875,19 +1090,19 @@ impl<'a> PolicyBatch<'a> {
         // Outgoing DHCPv4 response
         {
             let mut out_v4 = Rule::new(&self.out_chain);
-            check_port(&mut out_v4, Udp, End::Src, super::DHCPV4_SERVER_PORT);
-            check_port(&mut out_v4, Udp, End::Dst, super::DHCPV4_CLIENT_PORT);
+            check_port(&mut out_v4, Udp, End::Src, firewall::DHCPV4_SERVER_PORT);
+            check_port(&mut out_v4, Udp, End::Dst, firewall::DHCPV4_CLIENT_PORT);
             add_verdict(&mut out_v4, &Verdict::Accept);
             self.batch.add(&out_v4, nftnl::MsgType::Add);
         }
         // Incoming DHCPv4 request
         {
             let mut in_v4 = Rule::new(&self.in_chain);
-            check_port(&mut in_v4, Udp, End::Src, super::DHCPV4_CLIENT_PORT);
+            check_port(&mut in_v4, Udp, End::Src, firewall::DHCPV4_CLIENT_PORT);
             check_endpoint(
                 &mut in_v4,
                 End::Dst,
-                &Endpoint::new(Ipv4Addr::BROADCAST, super::DHCPV4_SERVER_PORT, Udp),
+                &Endpoint::new(Ipv4Addr::BROADCAST, firewall::DHCPV4_SERVER_PORT, Udp),
             );
             add_verdict(&mut in_v4, &Verdict::Accept);
             self.batch.add(&in_v4, nftnl::MsgType::Add);
@@ -895,17 +1110,12 @@ impl<'a> PolicyBatch<'a> {
     }
 }
 
-fn is_local_dns_address(tunnel: &tunnel::TunnelMetadata, server: &IpAddr) -> bool {
-    super::is_local_address(server)
-        && server != &tunnel.ipv4_gateway
-        && Some(server) != tunnel.ipv6_gateway.map(IpAddr::from).as_ref()
-}
-
 fn allow_tunnel_dns_rule<'a>(
     chain: &'a Chain<'_>,
     iface: &str,
     protocol: TransportProtocol,
     host: IpAddr,
+    root_only: bool,
 ) -> Result<Rule<'a>> {
     let mut rule = Rule::new(chain);
     check_iface(&mut rule, Direction::Out, iface)?;
@@ -921,6 +1131,10 @@ fn allow_tunnel_dns_rule<'a>(
 
     rule.add_expr(&daddr);
     rule.add_expr(&nft_expr!(cmp == host));
+    if root_only {
+        rule.add_expr(&nft_expr!(meta skuid));
+        rule.add_expr(&nft_expr!(cmp == firewall::ROOT_UID));
+    }
     add_verdict(&mut rule, &Verdict::Accept);
 
     Ok(rule)
@@ -1057,3 +1271,299 @@ fn add_verdict(rule: &mut Rule<'_>, verdict: &expr::Verdict) {
     }
     rule.add_expr(verdict);
 }
+
+/// Adds a reject verdict, preceded by sending the packet to the drop log if `log_drops` is set.
+fn add_reject_verdict(rule: &mut Rule<'_>, log_drops: bool, verdict: &expr::Verdict) {
+    if log_drops {
+        let group = expr::LogGroup(drop_log::NFLOG_GROUP);
+        rule.add_expr(&nft_expr!(log group group));
+    }
+    add_verdict(rule, verdict);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::TryInto;
+    use talpid_types::net::{wireguard, AllowedEndpoint, Ipv6FirewallMode};
+
+    /// A netlink message in a batch, without its sequence number and port ID.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct Message {
+        kind: u16,
+        flags: u16,
+        payload: Vec<u8>,
+    }
+
+    const NLMSG_HDRLEN: usize = 16;
+
+    fn parse_messages(batch: &FinalizedBatch) -> Vec<Message> {
+        let bytes: Vec<u8> = batch.into_iter().flatten().copied().collect();
+        let mut messages = vec![];
+        let mut offset = 0;
+        while offset + NLMSG_HDRLEN <= bytes.len() {
+            let header = &bytes[offset..offset + NLMSG_HDRLEN];
+            let len = u32::from_ne_bytes(header[0..4].try_into().unwrap()) as usize;
+            messages.push(Message {
+                kind: u16::from_ne_bytes(header[4..6].try_into().unwrap()),
+                flags: u16::from_ne_bytes(header[6..8].try_into().unwrap()),
+                payload: bytes[offset + NLMSG_HDRLEN..offset + len].to_vec(),
+            });
+            offset += (len + 3) & !3;
+        }
+        messages
+    }
+
+    fn tables() -> FirewallTables {
+        FirewallTables {
+            main: Table::new(&*TABLE_NAME, ProtoFamily::Inet),
+            mangle_v4: Table::new(&*MANGLE_TABLE_NAME_V4, ProtoFamily::Ipv4),
+            mangle_v6: Table::new(&*MANGLE_TABLE_NAME_V6, ProtoFamily::Ipv6),
+        }
+    }
+
+    /// Returns the messages of the batch that applies `policy`.
+    fn policy_messages(policy: &FirewallPolicy) -> Vec<Message> {
+        let tables = tables();
+        parse_messages(&PolicyBatch::new(&tables).finalize(policy).unwrap())
+    }
+
+    /// Returns the rules that `add_rules` adds to an otherwise empty batch.
+    fn rules_added_by(add_rules: impl FnOnce(&mut PolicyBatch<'_>)) -> Vec<Message> {
+        let tables = tables();
+        let empty = parse_messages(&PolicyBatch::new(&tables).batch.finalize());
+        let mut batch = PolicyBatch::new(&tables);
+        add_rules(&mut batch);
+        let messages = parse_messages(&batch.batch.finalize());
+        // Both batches start with the same table and chain messages, and end with a batch end
+        // message.
+        messages[empty.len() - 1..messages.len() - 1].to_vec()
+    }
+
+    fn contains_all(messages: &[Message], rules: &[Message]) -> bool {
+        rules.iter().all(|rule| messages.contains(rule))
+    }
+
+    fn contains_none(messages: &[Message], rules: &[Message]) -> bool {
+        !rules.iter().any(|rule| messages.contains(rule))
+    }
+
+    fn blocked_policy(allow_lan: bool, ipv6_mode: Ipv6FirewallMode) -> FirewallPolicy {
+        FirewallPolicy::Blocked {
+            allow_lan,
+            allowed_endpoint: AllowedEndpoint {
+                endpoint: Endpoint::new(Ipv4Addr::new(192, 0, 2, 1), 443, TransportProtocol::Tcp),
+            },
+            forwarded_interfaces: vec![],
+            ipv6_mode,
+            log_drops: false,
+            dns_servers: vec![],
+        }
+    }
+
+    fn connected_policy(untunneled_ip_versions: Vec<IpVersion>) -> FirewallPolicy {
+        FirewallPolicy::Connected {
+            peer_endpoint: Endpoint::new(
+                Ipv4Addr::new(192, 0, 2, 1),
+                51820,
+                TransportProtocol::Udp,
+            ),
+            tunnel: tunnel::TunnelMetadata {
+                interface: "lo".to_owned(),
+                ips: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2))],
+                ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+                ipv6_gateway: None,
+                mtu: None,
+                wireguard_backend: None,
+            },
+            allow_lan: false,
+            dns_servers: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1))],
+            dns_stub: false,
+            additional_peer_endpoints: vec![],
+            untunneled_ip_versions,
+            forwarded_interfaces: vec![],
+            ipv6_mode: Ipv6FirewallMode::Default,
+            log_drops: false,
+        }
+    }
+
+    fn connection_config(allowed_ips: &[&str]) -> wireguard::ConnectionConfig {
+        wireguard::ConnectionConfig {
+            tunnel: wireguard::TunnelConfig {
+                private_key: wireguard::PrivateKey::from([1; 32]),
+                addresses: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2))],
+            },
+            peer: wireguard::PeerConfig {
+                public_key: wireguard::PublicKey::from([2; 32]),
+                allowed_ips: allowed_ips.iter().map(|net| net.parse().unwrap()).collect(),
+                endpoint: "192.0.2.1:51820".parse().unwrap(),
+                psk: None,
+            },
+            exit_peer: None,
+            additional_peers: vec![],
+            ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+            ipv6_gateway: None,
+        }
+    }
+
+    /// Returns the IPv4 and IPv6 LAN rules.
+    fn lan_rules() -> (Vec<Message>, Vec<Message>) {
+        let v4_only = Ipv6Rules {
+            ndp: false,
+            dhcpv6_client: false,
+            lan: false,
+        };
+        let v4_rules = rules_added_by(|batch| batch.add_allow_lan_rules(v4_only));
+        let all_rules = rules_added_by(|batch| {
+            batch.add_allow_lan_rules(Ipv6Rules {
+                lan: true,
+                ..v4_only
+            })
+        });
+        let v6_rules: Vec<_> = all_rules
+            .into_iter()
+            .filter(|rule| !v4_rules.contains(rule))
+            .collect();
+        assert!(!v4_rules.is_empty() && !v6_rules.is_empty());
+        (v4_rules, v6_rules)
+    }
+
+    #[test]
+    fn test_default_ipv6_ruleset() {
+        let ndp_rules = rules_added_by(|batch| batch.add_ndp_rules());
+        let dhcpv6_rules = rules_added_by(|batch| batch.add_dhcpv6_client_rules());
+        let (lan_v4_rules, lan_v6_rules) = lan_rules();
+
+        let messages = policy_messages(&blocked_policy(true, Ipv6FirewallMode::Default));
+        assert!(contains_all(&messages, &ndp_rules));
+        assert!(contains_all(&messages, &dhcpv6_rules));
+        assert!(contains_all(&messages, &lan_v4_rules));
+        assert!(contains_all(&messages, &lan_v6_rules));
+    }
+
+    #[test]
+    fn test_block_outside_tunnel_ruleset() {
+        let ndp_rules = rules_added_by(|batch| batch.add_ndp_rules());
+        let dhcpv6_rules = rules_added_by(|batch| batch.add_dhcpv6_client_rules());
+        let (lan_v4_rules, lan_v6_rules) = lan_rules();
+
+        let messages = policy_messages(&blocked_policy(true, Ipv6FirewallMode::BlockOutsideTunnel));
+        assert!(contains_none(&messages, &dhcpv6_rules));
+        assert!(contains_none(&messages, &lan_v6_rules));
+        assert!(contains_all(&messages, &lan_v4_rules));
+    }
+
+    #[test]
+    fn test_block_outside_tunnel_ndp_ruleset() {
+        let ndp_rules = rules_added_by(|batch| batch.add_ndp_rules());
+        assert!(!ndp_rules.is_empty());
+
+        // IPv6 relays must remain reachable, so router and neighbor discovery is never blocked
+        for allow_lan in [true, false] {
+            let policy = blocked_policy(allow_lan, Ipv6FirewallMode::BlockOutsideTunnel);
+            assert!(contains_all(&policy_messages(&policy), &ndp_rules));
+        }
+    }
+
+    #[test]
+    fn test_tunnel_only_ruleset() {
+        let ndp_rules = rules_added_by(|batch| batch.add_ndp_rules());
+        let dhcpv6_rules = rules_added_by(|batch| batch.add_dhcpv6_client_rules());
+        let (lan_v4_rules, lan_v6_rules) = lan_rules();
+
+        let messages = policy_messages(&blocked_policy(true, Ipv6FirewallMode::TunnelOnly));
+        assert!(contains_none(&messages, &lan_v6_rules));
+        assert!(contains_all(&messages, &lan_v4_rules));
+        // The link is still configured, so that an IPv6 relay can be reached
+        assert!(contains_all(&messages, &ndp_rules));
+        assert!(contains_all(&messages, &dhcpv6_rules));
+    }
+
+    #[test]
+    fn test_blocked_dns_ruleset() {
+        let server = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1));
+        let allow_rules = rules_added_by(|batch| {
+            for protocol in [TransportProtocol::Udp, TransportProtocol::Tcp] {
+                batch
+                    .add_allow_local_dns_rule(None, protocol, server, false)
+                    .unwrap();
+            }
+        });
+        let drop_rules = rules_added_by(|batch| batch.add_drop_dns_rule());
+
+        let mut policy = blocked_policy(false, Ipv6FirewallMode::Default);
+        let messages = policy_messages(&policy);
+        assert!(contains_none(&messages, &allow_rules));
+        assert!(contains_all(&messages, &drop_rules));
+
+        if let FirewallPolicy::Blocked { dns_servers, .. } = &mut policy {
+            dns_servers.push(server);
+        }
+        let messages = policy_messages(&policy);
+        let position = |rule| messages.iter().position(|message| message == rule).unwrap();
+        let first_drop = drop_rules.iter().map(position).min().unwrap();
+        for rule in &allow_rules {
+            assert!(position(rule) < first_drop);
+        }
+    }
+
+    #[test]
+    fn test_nat64_discovery_ruleset() {
+        let discovery_rules = rules_added_by(|batch| batch.add_nat64_discovery_rules());
+        let drop_rules = rules_added_by(|batch| batch.add_drop_dns_rule());
+
+        for policy in [
+            blocked_policy(false, Ipv6FirewallMode::Default),
+            connected_policy(vec![]),
+        ] {
+            // Marked DNS is accepted as excluded traffic before any DNS is rejected
+            let accept_rules =
+                rules_added_by(|batch| batch.add_split_tunneling_rules(&policy).unwrap());
+            let messages = policy_messages(&policy);
+            assert!(contains_all(&messages, &discovery_rules));
+            let position = |rule| messages.iter().position(|message| message == rule).unwrap();
+            let first_drop = drop_rules.iter().map(position).min().unwrap();
+            for rule in &accept_rules {
+                assert!(position(rule) < first_drop);
+            }
+        }
+    }
+
+    #[test]
+    fn test_log_drops_ruleset() {
+        let logged_rules = rules_added_by(|batch| {
+            batch.log_drops = true;
+            batch.add_drop_dns_rule();
+        });
+
+        let mut policy = blocked_policy(false, Ipv6FirewallMode::Default);
+        assert!(contains_none(&policy_messages(&policy), &logged_rules));
+
+        if let FirewallPolicy::Blocked { log_drops, .. } = &mut policy {
+            *log_drops = true;
+        }
+        assert!(contains_all(&policy_messages(&policy), &logged_rules));
+    }
+
+    #[test]
+    fn test_untunneled_ruleset() {
+        let v4_rules = rules_added_by(|batch| batch.add_allow_untunneled_rules(IpVersion::V4));
+        let v6_rules = rules_added_by(|batch| batch.add_allow_untunneled_rules(IpVersion::V6));
+
+        // IPv4 is only partly routed through the tunnel, and IPv6 is not routed at all
+        let config = connection_config(&["10.0.0.0/8"]);
+        let messages = policy_messages(&connected_policy(config.get_untunneled_ip_versions()));
+        assert!(contains_all(&messages, &v4_rules));
+        assert!(contains_none(&messages, &v6_rules));
+
+        let config = connection_config(&["10.0.0.0/8", "0.0.0.0/0", "fc00::/7"]);
+        let messages = policy_messages(&connected_policy(config.get_untunneled_ip_versions()));
+        assert!(contains_none(&messages, &v4_rules));
+        assert!(contains_all(&messages, &v6_rules));
+
+        let config = connection_config(&["0.0.0.0/0", "::/0"]);
+        let messages = policy_messages(&connected_policy(config.get_untunneled_ip_versions()));
+        assert!(contains_none(&messages, &v4_rules));
+        assert!(contains_none(&messages, &v6_rules));
+    }
+}
@@ -9,13 +9,15 @@ use std::{
     net::{Ipv4Addr, Ipv6Addr},
 };
 use talpid_types::net::{AllowedEndpoint, AllowedTunnelTraffic, Endpoint};
+#[cfg(target_os = "linux")]
+use talpid_types::net::{ForwardedInterface, IpVersion, Ipv6FirewallMode};
 
 #[cfg(target_os = "macos")]
 #[path = "macos.rs"]
 mod imp;
 
 #[cfg(target_os = "linux")]
-#[path = "linux.rs"]
+#[path = "linux/mod.rs"]
 mod imp;
 
 #[cfg(windows)]
@@ -28,6 +30,9 @@ mod imp;
 
 pub use self::imp::Error;
 
+#[cfg(target_os = "linux")]
+pub use self::imp::drop_log;
+
 lazy_static! {
     /// When "allow local network" is enabled the app will allow traffic to and from these networks.
     pub(crate) static ref ALLOWED_LAN_NETS: [IpNetwork; 6] = [
@@ -111,6 +116,18 @@ pub enum FirewallPolicy {
         allowed_endpoint: AllowedEndpoint,
         /// Networks for which to permit in-tunnel traffic.
         allowed_tunnel_traffic: AllowedTunnelTraffic,
+        /// Endpoints of additional peers of a custom WireGuard tunnel.
+        #[cfg(target_os = "linux")]
+        additional_peer_endpoints: Vec<Endpoint>,
+        /// Bridge interfaces whose forwarded traffic should be allowed.
+        #[cfg(target_os = "linux")]
+        forwarded_interfaces: Vec<ForwardedInterface>,
+        /// IPv6 traffic that should be allowed outside the tunnel.
+        #[cfg(target_os = "linux")]
+        ipv6_mode: Ipv6FirewallMode,
+        /// Send rejected packets to the drop log before rejecting them.
+        #[cfg(target_os = "linux")]
+        log_drops: bool,
         /// A process that is allowed to send packets to the relay.
         #[cfg(windows)]
         relay_client: PathBuf,
@@ -127,6 +144,26 @@ pub enum FirewallPolicy {
         /// Servers that are allowed to respond to DNS requests.
         #[cfg(not(target_os = "android"))]
         dns_servers: Vec<IpAddr>,
+        /// Only let the local stub resolver reach `dns_servers`. The stub runs as root, and all
+        /// other processes send their queries to it over the loopback interface.
+        #[cfg(target_os = "linux")]
+        dns_stub: bool,
+        /// Endpoints of additional peers of a custom WireGuard tunnel.
+        #[cfg(target_os = "linux")]
+        additional_peer_endpoints: Vec<Endpoint>,
+        /// IP versions that the tunnel only routes some destinations of, and whose other traffic
+        /// is therefore allowed outside the tunnel.
+        #[cfg(target_os = "linux")]
+        untunneled_ip_versions: Vec<IpVersion>,
+        /// Bridge interfaces whose forwarded traffic should be allowed.
+        #[cfg(target_os = "linux")]
+        forwarded_interfaces: Vec<ForwardedInterface>,
+        /// IPv6 traffic that should be allowed outside the tunnel.
+        #[cfg(target_os = "linux")]
+        ipv6_mode: Ipv6FirewallMode,
+        /// Send rejected packets to the drop log before rejecting them.
+        #[cfg(target_os = "linux")]
+        log_drops: bool,
         /// A process that is allowed to send packets to the relay.
         #[cfg(windows)]
         relay_client: PathBuf,
@@ -138,6 +175,18 @@ pub enum FirewallPolicy {
         allow_lan: bool,
         /// Host that should be reachable while in the blocked state.
         allowed_endpoint: AllowedEndpoint,
+        /// Bridge interfaces whose forwarded traffic should be allowed.
+        #[cfg(target_os = "linux")]
+        forwarded_interfaces: Vec<ForwardedInterface>,
+        /// IPv6 traffic that should be allowed outside the tunnel.
+        #[cfg(target_os = "linux")]
+        ipv6_mode: Ipv6FirewallMode,
+        /// Send rejected packets to the drop log before rejecting them.
+        #[cfg(target_os = "linux")]
+        log_drops: bool,
+        /// Resolvers on the local network that DNS may still be sent to.
+        #[cfg(target_os = "linux")]
+        dns_servers: Vec<IpAddr>,
         /// Desination port for DNS traffic redirection. Traffic destined to `127.0.0.1:53` will be
         /// redirected to `127.0.0.1:$dns_redirect_port`.
         #[cfg(target_os = "macos")]
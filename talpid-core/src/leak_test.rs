@@ -0,0 +1,345 @@
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use talpid_types::net::{
+    leak_test::{LeakProbe, LeakProbeOutcome, LeakProbeResult},
+    AllowedEndpoint, Endpoint, TransportProtocol,
+};
+
+/// How long to wait for a TCP handshake before assuming that the SYN was dropped.
+const TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+
+/// Leak test destinations. These are documentation addresses (RFC 5737 and RFC 3849) that no host
+/// responds on, so a rejected probe can only have been rejected by the local firewall.
+const PROBE_HOSTS_V4: [Ipv4Addr; 2] = [
+    Ipv4Addr::new(198, 51, 100, 1),
+    Ipv4Addr::new(203, 0, 113, 1),
+];
+const PROBE_HOSTS_V6: [Ipv6Addr; 1] = [Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)];
+
+/// Returns the default set of probes: UDP, TCP, ICMP and DNS over both IPv4 and IPv6.
+pub fn default_probes() -> Vec<LeakProbe> {
+    let hosts = PROBE_HOSTS_V4
+        .iter()
+        .map(|host| IpAddr::from(*host))
+        .chain(PROBE_HOSTS_V6.iter().map(|host| IpAddr::from(*host)));
+
+    let mut probes = vec![];
+    for host in hosts {
+        probes.push(LeakProbe::Transport(Endpoint::new(
+            host,
+            443,
+            TransportProtocol::Tcp,
+        )));
+        probes.push(LeakProbe::Transport(Endpoint::new(
+            host,
+            443,
+            TransportProtocol::Udp,
+        )));
+        probes.push(LeakProbe::Icmp(host));
+        probes.push(LeakProbe::Dns(Endpoint::new(
+            host,
+            53,
+            TransportProtocol::Udp,
+        )));
+    }
+    probes
+}
+
+/// Sends every probe outside the tunnel and reports whether the firewall stopped it.
+/// Probes that target `allowed_endpoint` are expected to get through.
+pub async fn run(
+    probes: Vec<LeakProbe>,
+    allowed_endpoint: Option<AllowedEndpoint>,
+) -> Vec<LeakProbeResult> {
+    let handles: Vec<_> = probes
+        .into_iter()
+        .map(|probe| {
+            let expected_allowed = allowed_endpoint
+                .as_ref()
+                .map(|allowed| is_allowed(&probe, &allowed.endpoint))
+                .unwrap_or(false);
+            let handle = tokio::task::spawn_blocking(move || run_probe(&probe));
+            (probe, expected_allowed, handle)
+        })
+        .collect();
+
+    let mut results = Vec::with_capacity(handles.len());
+    for (probe, expected_allowed, handle) in handles {
+        let outcome = match handle.await {
+            Ok(outcome) => outcome,
+            Err(error) => LeakProbeOutcome::Failed(error.to_string()),
+        };
+        results.push(LeakProbeResult {
+            probe,
+            outcome,
+            expected_allowed,
+        });
+    }
+    results
+}
+
+fn is_allowed(probe: &LeakProbe, allowed: &Endpoint) -> bool {
+    match probe {
+        LeakProbe::Transport(endpoint) | LeakProbe::Dns(endpoint) => endpoint == allowed,
+        LeakProbe::Icmp(_) => false,
+    }
+}
+
+fn run_probe(probe: &LeakProbe) -> LeakProbeOutcome {
+    let result = match probe {
+        LeakProbe::Transport(endpoint) => match endpoint.protocol {
+            TransportProtocol::Tcp => probe_tcp(endpoint.address),
+            TransportProtocol::Udp => probe_udp(endpoint.address, &[0u8; 32]),
+        },
+        LeakProbe::Icmp(address) => probe_icmp(*address),
+        LeakProbe::Dns(endpoint) => match endpoint.protocol {
+            TransportProtocol::Udp => probe_udp(endpoint.address, &dns_query()),
+            TransportProtocol::Tcp => probe_tcp(endpoint.address),
+        },
+    };
+    match result {
+        Ok(outcome) => outcome,
+        Err(error) => classify_error(error),
+    }
+}
+
+/// Maps a socket error to a probe outcome. The firewall rejects outgoing packets, which shows up
+/// as `EPERM` when sending or as a refused connection when the rejection arrives. Since nothing
+/// responds on the probe destinations, a refused connection cannot come from the remote end.
+fn classify_error(error: io::Error) -> LeakProbeOutcome {
+    match error.raw_os_error() {
+        Some(libc::EPERM) | Some(libc::EACCES) | Some(libc::ECONNREFUSED) => {
+            LeakProbeOutcome::Blocked
+        }
+        Some(libc::ENETUNREACH) | Some(libc::EHOSTUNREACH) | Some(libc::EADDRNOTAVAIL) => {
+            LeakProbeOutcome::Unreachable
+        }
+        _ => LeakProbeOutcome::Failed(error.to_string()),
+    }
+}
+
+fn new_socket(address: IpAddr, ty: Type, protocol: Protocol) -> io::Result<Socket> {
+    let domain = match address {
+        IpAddr::V4(_) => Domain::IPV4,
+        IpAddr::V6(_) => Domain::IPV6,
+    };
+    let socket = Socket::new(domain, ty, Some(protocol))?;
+    // Route the probe outside the tunnel, the same way tunnel traffic is routed.
+    socket.set_mark(crate::linux::TUNNEL_FW_MARK)?;
+    Ok(socket)
+}
+
+fn probe_tcp(address: SocketAddr) -> io::Result<LeakProbeOutcome> {
+    let socket = new_socket(address.ip(), Type::STREAM, Protocol::TCP)?;
+    match socket.connect_timeout(&SockAddr::from(address), TCP_CONNECT_TIMEOUT) {
+        Ok(()) => Ok(LeakProbeOutcome::Leaked),
+        // Without any response, the SYN can't be told apart from one dropped by the firewall
+        Err(error) if error.kind() == io::ErrorKind::TimedOut => Ok(LeakProbeOutcome::Blocked),
+        Err(error) => Err(error),
+    }
+}
+
+fn probe_udp(address: SocketAddr, payload: &[u8]) -> io::Result<LeakProbeOutcome> {
+    let socket = new_socket(address.ip(), Type::DGRAM, Protocol::UDP)?;
+    socket.connect(&SockAddr::from(address))?;
+    socket.send(payload)?;
+    Ok(LeakProbeOutcome::Leaked)
+}
+
+fn probe_icmp(address: IpAddr) -> io::Result<LeakProbeOutcome> {
+    let (protocol, message_type) = match address {
+        IpAddr::V4(_) => (Protocol::ICMPV4, ICMP_ECHO_REQUEST),
+        IpAddr::V6(_) => (Protocol::ICMPV6, ICMPV6_ECHO_REQUEST),
+    };
+    let socket = new_socket(address, Type::RAW, protocol)?;
+    let packet = icmp_echo_request(message_type, rand::random(), 1);
+    socket.send_to(&packet, &SockAddr::from(SocketAddr::new(address, 0)))?;
+    Ok(LeakProbeOutcome::Leaked)
+}
+
+/// Constructs an ICMP or ICMPv6 echo request. The kernel computes the checksum for ICMPv6 raw
+/// sockets, so the checksum field is only meaningful for ICMPv4.
+fn icmp_echo_request(message_type: u8, id: u16, sequence: u16) -> Vec<u8> {
+    let mut packet = vec![message_type, 0, 0, 0];
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&sequence.to_be_bytes());
+    packet.extend_from_slice(b"mullvad-leak-test");
+    if message_type == ICMP_ECHO_REQUEST {
+        let checksum = internet_checksum::checksum(&packet);
+        packet[2..4].copy_from_slice(&checksum);
+    }
+    packet
+}
+
+/// Constructs a recursive DNS query for `am.i.mullvad.net` of type A.
+fn dns_query() -> Vec<u8> {
+    let mut query = vec![
+        // ID
+        0x4d, 0x56, //
+        // Flags: recursion desired
+        0x01, 0x00, //
+        // QDCOUNT, ANCOUNT, NSCOUNT, ARCOUNT
+        0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+    for label in ["am", "i", "mullvad", "net"] {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    // QTYPE A, QCLASS IN
+    query.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]);
+    query
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::firewall::{Firewall, FirewallArguments, FirewallPolicy, InitialFirewallState};
+
+    const CAP_NET_ADMIN: u32 = 12;
+    const CAP_SYS_ADMIN: u32 = 21;
+
+    /// Returns whether the namespace tests should be skipped. They need `CAP_SYS_ADMIN` and
+    /// `CAP_NET_ADMIN` to create and configure network namespaces, and are skipped without them
+    /// unless `TALPID_TEST_NETNS` is set, in which case they fail instead.
+    fn skip_namespace_test() -> bool {
+        if has_capability(CAP_SYS_ADMIN) && has_capability(CAP_NET_ADMIN)
+            || std::env::var_os("TALPID_TEST_NETNS").is_some()
+        {
+            return false;
+        }
+        eprintln!("Skipping test that requires CAP_SYS_ADMIN and CAP_NET_ADMIN");
+        true
+    }
+
+    fn has_capability(capability: u32) -> bool {
+        std::fs::read_to_string("/proc/self/status")
+            .ok()
+            .and_then(|status| {
+                let effective = status
+                    .lines()
+                    .find_map(|line| line.strip_prefix("CapEff:"))?;
+                u64::from_str_radix(effective.trim(), 16).ok()
+            })
+            .map(|effective| effective & (1 << capability) != 0)
+            .unwrap_or(false)
+    }
+
+    /// Runs `f` in a thread that has its own network namespace, so that firewall rules and routes
+    /// don't affect the host. Panics if the namespace cannot be set up.
+    fn in_network_namespace<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+        std::thread::spawn(move || {
+            if unsafe { libc::unshare(libc::CLONE_NEWNET) } != 0 {
+                panic!(
+                    "Failed to create network namespace: {}",
+                    io::Error::last_os_error()
+                );
+            }
+            setup_namespace_network().expect("Failed to set up namespace network");
+            f()
+        })
+        .join()
+        .expect("leak test thread panicked")
+    }
+
+    /// Creates a dummy interface with default routes. Packets sent through it are discarded, so
+    /// any probe that isn't blocked by the firewall is reported as leaked.
+    fn setup_namespace_network() -> io::Result<()> {
+        let commands: &[&[&str]] = &[
+            &["link", "set", "lo", "up"],
+            &["link", "add", "leak0", "type", "dummy"],
+            &["link", "set", "leak0", "up"],
+            &["addr", "add", "192.0.2.2/24", "dev", "leak0"],
+            &[
+                "-6",
+                "addr",
+                "add",
+                "2001:db8::2/64",
+                "dev",
+                "leak0",
+                "nodad",
+            ],
+            &["route", "add", "default", "dev", "leak0"],
+            &["-6", "route", "add", "default", "dev", "leak0"],
+        ];
+        for args in commands {
+            duct::cmd("ip", *args).stdout_null().stderr_null().run()?;
+        }
+        Ok(())
+    }
+
+    fn run_blocking(probes: Vec<LeakProbe>) -> Vec<LeakProbeResult> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(run(probes, None))
+    }
+
+    fn udp_and_icmp_probes() -> Vec<LeakProbe> {
+        // TCP probes are skipped since they wait for the connect timeout
+        default_probes()
+            .into_iter()
+            .filter(|probe| match probe {
+                LeakProbe::Transport(endpoint) => endpoint.protocol != TransportProtocol::Tcp,
+                _ => true,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_probes_leak_without_firewall() {
+        if skip_namespace_test() {
+            return;
+        }
+        let results = in_network_namespace(|| run_blocking(udp_and_icmp_probes()));
+        for result in results {
+            assert_eq!(result.outcome, LeakProbeOutcome::Leaked, "{}", result.probe);
+        }
+    }
+
+    #[test]
+    fn test_probes_blocked_by_firewall() {
+        if skip_namespace_test() {
+            return;
+        }
+        let results = in_network_namespace(|| {
+            let mut firewall = Firewall::from_args(FirewallArguments {
+                initial_state: InitialFirewallState::None,
+                allow_lan: false,
+            })
+            .expect("Failed to create firewall");
+            let allowed_endpoint = AllowedEndpoint {
+                endpoint: Endpoint::new(Ipv4Addr::new(192, 0, 2, 1), 443, TransportProtocol::Tcp),
+            };
+            firewall
+                .apply_policy(FirewallPolicy::Blocked {
+                    allow_lan: false,
+                    allowed_endpoint,
+                    forwarded_interfaces: vec![],
+                    ipv6_mode: Default::default(),
+                    log_drops: false,
+                    dns_servers: vec![],
+                })
+                .expect("Failed to apply firewall policy");
+            let results = run_blocking(udp_and_icmp_probes());
+            let _ = firewall.reset_policy();
+            results
+        });
+        for result in results {
+            assert_eq!(
+                result.outcome,
+                LeakProbeOutcome::Blocked,
+                "{}",
+                result.probe
+            );
+        }
+    }
+}
@@ -63,9 +63,13 @@ mod mktemp;
 #[cfg(target_os = "linux")]
 mod linux;
 
+/// Self-test that checks whether the firewall lets traffic escape outside the tunnel
+#[cfg(target_os = "linux")]
+pub mod leak_test;
+
 /// A pair of functions to monitor and establish connectivity with ICMP
 pub mod ping_monitor;
 
 /// A resolver that's controlled by the tunnel state machine
-#[cfg(target_os = "macos")]
+#[cfg(any(target_os = "linux", target_os = "macos"))]
 pub mod resolver;
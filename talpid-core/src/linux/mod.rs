@@ -1,3 +1,4 @@
+use futures::future::abortable;
 use std::{
     ffi::{self, CString},
     fs, io,
@@ -28,6 +29,37 @@ pub enum IfaceIndexLookupError {
     InterfaceLookupError(String, #[error(source)] io::Error),
 }
 
+/// Sets the MTU of the interface with the given name.
+pub async fn set_mtu(name: &str, mtu: u16) -> Result<(), SetMtuError> {
+    let index = iface_index(name).map_err(SetMtuError::InterfaceIndex)?;
+
+    let (connection, handle, _messages) =
+        rtnetlink::new_connection().map_err(SetMtuError::NetlinkSocket)?;
+    let (connection, abort_handle) = abortable(connection);
+    tokio::spawn(connection);
+
+    let result = handle
+        .link()
+        .set(index)
+        .mtu(u32::from(mtu))
+        .execute()
+        .await
+        .map_err(SetMtuError::SetMtu);
+    abort_handle.abort();
+    result
+}
+
+#[derive(Debug, err_derive::Error)]
+#[error(no_from)]
+pub enum SetMtuError {
+    #[error(display = "Failed to get index of interface")]
+    InterfaceIndex(#[error(source)] IfaceIndexLookupError),
+    #[error(display = "Failed to open netlink socket")]
+    NetlinkSocket(#[error(source)] io::Error),
+    #[error(display = "Failed to set interface MTU")]
+    SetMtu(#[error(source)] rtnetlink::Error),
+}
+
 // b"mole" is [ 0x6d, 0x6f 0x6c, 0x65 ]
 pub const TUNNEL_FW_MARK: u32 = 0x6d6f6c65;
 pub const TUNNEL_TABLE_ID: u32 = 0x6d6f6c65;
@@ -1,10 +1,21 @@
-use crate::routing::{self, RouteManagerHandle};
-use futures::{channel::mpsc::UnboundedSender, StreamExt};
+use super::nat64;
+use crate::routing::{self, Route, RouteManagerHandle};
+use futures::{
+    channel::mpsc::{self, UnboundedSender},
+    StreamExt,
+};
+use parking_lot::Mutex;
 use std::{
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use talpid_types::{
+    net::{nat64::Prefix, IpAvailability, NetworkChange, NetworkState},
+    ErrorExt,
 };
-use talpid_types::ErrorExt;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -15,8 +26,38 @@ pub enum Error {
     RouteManagerError(#[error(source)] routing::Error),
 }
 
+/// Subscribers that are notified when the tunnel is kept up across a change of network.
+#[derive(Clone, Default)]
+pub struct NetworkChangeListeners {
+    subscribers: Arc<Mutex<Vec<mpsc::UnboundedSender<NetworkChange>>>>,
+}
+
+impl NetworkChangeListeners {
+    /// Creates a set of listeners without any subscribers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a channel that receives a notification every time the tunnel is kept up across a
+    /// change of network.
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<NetworkChange> {
+        let (tx, rx) = mpsc::unbounded();
+        self.subscribers.lock().push(tx);
+        rx
+    }
+
+    pub(crate) fn notify(&self, change: NetworkChange) {
+        self.subscribers
+            .lock()
+            .retain(|tx| tx.unbounded_send(change.clone()).is_ok());
+    }
+}
+
 pub struct MonitorHandle {
     route_manager: RouteManagerHandle,
+    /// Incremented whenever the default routes change.
+    generation: Arc<AtomicU64>,
+    nat64_discovery: Nat64Discovery,
     _notify_tx: Arc<UnboundedSender<bool>>,
 }
 
@@ -26,8 +67,8 @@ const PUBLIC_INTERNET_ADDRESS_V6: IpAddr =
 
 impl MonitorHandle {
     pub async fn host_is_offline(&self) -> bool {
-        match public_ip_unreachable(&self.route_manager).await {
-            Ok(is_offline) => is_offline,
+        match DefaultRoutes::get(&self.route_manager).await {
+            Ok(routes) => routes.is_offline(),
             Err(err) => {
                 log::error!(
                     "Failed to verify offline state: {}. Presuming connectivity",
@@ -37,13 +78,90 @@ impl MonitorHandle {
             }
         }
     }
+
+    /// Returns the IP versions that have a default route outside the tunnel, the generation of
+    /// the default routes, and the NAT64 prefix if only IPv6 is available.
+    pub async fn network_state(&self) -> Option<NetworkState> {
+        match DefaultRoutes::get(&self.route_manager).await {
+            Ok(routes) => {
+                let ip_availability =
+                    IpAvailability::new(routes.v4.is_some(), routes.v6.is_some())?;
+                // Read after the routes, so that it is never older than them
+                let generation = self.generation.load(Ordering::SeqCst);
+                let nat64_prefix = if routes.is_ipv6_only() {
+                    self.nat64_discovery.prefix(generation).await
+                } else {
+                    None
+                };
+                Some(NetworkState {
+                    ip_availability,
+                    generation,
+                    nat64_prefix,
+                })
+            }
+            Err(err) => {
+                log::error!(
+                    "{}",
+                    err.display_chain_with_msg("Failed to determine available IP versions")
+                );
+                None
+            }
+        }
+    }
+}
+
+/// Discovers the NAT64 prefix of IPv6-only networks.
+#[derive(Clone, Default)]
+struct Nat64Discovery {
+    /// The last prefix found, and the generation of the default routes it was found for.
+    prefix: Arc<Mutex<Option<(Prefix, u64)>>>,
 }
 
+impl Nat64Discovery {
+    /// Returns the prefix found for `generation` of the default routes, or looks it up again if
+    /// none was found.
+    async fn prefix(&self, generation: u64) -> Option<Prefix> {
+        let cached = *self.prefix.lock();
+        match cached {
+            Some((prefix, found_for)) if found_for == generation => Some(prefix),
+            _ => self.discover(generation).await,
+        }
+    }
+
+    /// Looks up the prefix of the network that `generation` of the default routes leads to.
+    async fn discover(&self, generation: u64) -> Option<Prefix> {
+        let prefix = nat64::discover(Some(crate::linux::TUNNEL_FW_MARK)).await;
+        match prefix {
+            Some(prefix) => {
+                log::info!("Found NAT64 prefix {}", prefix);
+                let mut cached = self.prefix.lock();
+                // Don't replace a prefix found for newer routes
+                if !matches!(*cached, Some((_, found_for)) if found_for > generation) {
+                    *cached = Some((prefix, generation));
+                }
+            }
+            None => log::debug!("No NAT64 gateway was found"),
+        }
+        prefix
+    }
+
+    /// Looks up the prefix in the background, so that it is known before the next tunnel is set
+    /// up.
+    fn spawn_discover(&self, generation: u64) {
+        let discovery = self.clone();
+        tokio::spawn(async move { discovery.discover(generation).await });
+    }
+}
+
+/// Spawns a monitor that sends the offline state on `notify_tx` whenever it changes, and the
+/// interface of the new default route on `route_change_tx` whenever the default route changes
+/// while the host remains online.
 pub async fn spawn_monitor(
     notify_tx: UnboundedSender<bool>,
+    route_change_tx: UnboundedSender<Option<String>>,
     route_manager: RouteManagerHandle,
 ) -> Result<MonitorHandle> {
-    let mut is_offline = public_ip_unreachable(&route_manager).await?;
+    let mut default_routes = DefaultRoutes::get(&route_manager).await?;
 
     let mut listener = route_manager
         .change_listener()
@@ -52,8 +170,15 @@ pub async fn spawn_monitor(
 
     let notify_tx = Arc::new(notify_tx);
     let sender = Arc::downgrade(&notify_tx);
+    let generation = Arc::new(AtomicU64::new(0));
+    let nat64_discovery = Nat64Discovery::default();
+    if default_routes.is_ipv6_only() {
+        nat64_discovery.spawn_discover(0);
+    }
     let monitor_handle = MonitorHandle {
         route_manager: route_manager.clone(),
+        generation: generation.clone(),
+        nat64_discovery: nat64_discovery.clone(),
         _notify_tx: notify_tx,
     };
 
@@ -61,19 +186,27 @@ pub async fn spawn_monitor(
         while let Some(_event) = listener.next().await {
             match sender.upgrade() {
                 Some(sender) => {
-                    let new_offline_state = public_ip_unreachable(&route_manager)
+                    let new_default_routes = DefaultRoutes::get(&route_manager)
                         .await
                         .unwrap_or_else(|err| {
                             log::error!(
                                 "{}",
                                 err.display_chain_with_msg("Failed to infer offline state")
                             );
-                            false
+                            DefaultRoutes::unknown()
                         });
-                    if new_offline_state != is_offline {
-                        is_offline = new_offline_state;
-                        let _ = sender.unbounded_send(is_offline);
+                    if new_default_routes.is_offline() != default_routes.is_offline() {
+                        let _ = sender.unbounded_send(new_default_routes.is_offline());
+                    } else if default_routes.network_changed(&new_default_routes) {
+                        let _ = route_change_tx.unbounded_send(new_default_routes.interface());
                     }
+                    if new_default_routes != default_routes {
+                        let new_generation = generation.fetch_add(1, Ordering::SeqCst) + 1;
+                        if new_default_routes.is_ipv6_only() {
+                            nat64_discovery.spawn_discover(new_generation);
+                        }
+                    }
+                    default_routes = new_default_routes;
                 }
                 None => return,
             }
@@ -83,15 +216,120 @@ pub async fn spawn_monitor(
     Ok(monitor_handle)
 }
 
-async fn public_ip_unreachable(handle: &RouteManagerHandle) -> Result<bool> {
-    Ok(handle
-        .get_destination_route(PUBLIC_INTERNET_ADDRESS_V4, true)
-        .await
-        .map_err(Error::RouteManagerError)?
-        .is_none()
-        && handle
-            .get_destination_route(PUBLIC_INTERNET_ADDRESS_V6, true)
-            .await
-            .unwrap_or(None)
-            .is_none())
+/// Routes that traffic to public IPv4 and IPv6 addresses would take outside the tunnel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DefaultRoutes {
+    v4: Option<Route>,
+    v6: Option<Route>,
+    /// Set when the routes could not be retrieved, in which case connectivity is presumed.
+    unknown: bool,
+}
+
+impl DefaultRoutes {
+    async fn get(handle: &RouteManagerHandle) -> Result<Self> {
+        Ok(DefaultRoutes {
+            v4: handle
+                .get_destination_route(PUBLIC_INTERNET_ADDRESS_V4, true)
+                .await
+                .map_err(Error::RouteManagerError)?,
+            v6: handle
+                .get_destination_route(PUBLIC_INTERNET_ADDRESS_V6, true)
+                .await
+                .unwrap_or(None),
+            unknown: false,
+        })
+    }
+
+    fn unknown() -> Self {
+        DefaultRoutes {
+            v4: None,
+            v6: None,
+            unknown: true,
+        }
+    }
+
+    fn is_offline(&self) -> bool {
+        !self.unknown && self.v4.is_none() && self.v6.is_none()
+    }
+
+    fn is_ipv6_only(&self) -> bool {
+        self.v4.is_none() && self.v6.is_some()
+    }
+
+    /// Returns whether the host stayed online but uses `new` instead of these routes. Changes to
+    /// or from unknown routes are not reported.
+    fn network_changed(&self, new: &DefaultRoutes) -> bool {
+        !self.is_offline() && !new.is_offline() && !self.unknown && !new.unknown && self != new
+    }
+
+    /// Returns the interface of the default route, preferring IPv4.
+    fn interface(&self) -> Option<String> {
+        self.v4
+            .iter()
+            .chain(self.v6.iter())
+            .find_map(|route| route.get_node().get_device())
+            .map(str::to_owned)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::routing::Node;
+
+    fn routes(v4_interface: Option<&str>, v6_interface: Option<&str>) -> DefaultRoutes {
+        let route = |gateway: IpAddr, prefix: &str, interface: &str| {
+            Route::new(
+                Node::new(gateway, interface.to_owned()),
+                prefix.parse().unwrap(),
+            )
+        };
+        DefaultRoutes {
+            v4: v4_interface.map(|interface| {
+                route(Ipv4Addr::new(192, 168, 1, 1).into(), "0.0.0.0/0", interface)
+            }),
+            v6: v6_interface.map(|interface| {
+                route(
+                    Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1).into(),
+                    "::/0",
+                    interface,
+                )
+            }),
+            unknown: false,
+        }
+    }
+
+    #[test]
+    fn test_network_changed() {
+        let wired = routes(Some("eth0"), Some("eth0"));
+        let wireless = routes(Some("wlan0"), None);
+        let offline = routes(None, None);
+
+        assert!(wired.network_changed(&wireless));
+        assert!(wireless.network_changed(&wired));
+        assert!(wired.network_changed(&routes(Some("eth0"), None)));
+        assert!(!wired.network_changed(&wired));
+
+        // Going offline or coming back online is reported as such instead
+        assert!(offline.is_offline());
+        assert!(!wired.network_changed(&offline));
+        assert!(!offline.network_changed(&wired));
+
+        assert!(!DefaultRoutes::unknown().is_offline());
+        assert!(!wired.network_changed(&DefaultRoutes::unknown()));
+        assert!(!DefaultRoutes::unknown().network_changed(&wired));
+    }
+
+    #[test]
+    fn test_default_route_interface() {
+        assert_eq!(
+            routes(Some("wlan0"), Some("eth0")).interface(),
+            Some("wlan0".to_owned())
+        );
+        assert_eq!(
+            routes(None, Some("eth0")).interface(),
+            Some("eth0".to_owned())
+        );
+        assert_eq!(routes(None, None).interface(), None);
+    }
 }
@@ -5,6 +5,7 @@ use crate::windows::window::PowerManagementListener;
 use futures::channel::mpsc::UnboundedSender;
 #[cfg(target_os = "android")]
 use talpid_types::android::AndroidContext;
+use talpid_types::net::NetworkState;
 
 #[cfg(target_os = "macos")]
 #[path = "macos.rs"]
@@ -22,6 +23,9 @@ mod imp;
 #[path = "android.rs"]
 mod imp;
 
+#[cfg(target_os = "linux")]
+mod nat64;
+
 lazy_static::lazy_static! {
     /// Disables offline monitor
     static ref FORCE_DISABLE_OFFLINE_MONITOR: bool = std::env::var("TALPID_DISABLE_OFFLINE_MONITOR")
@@ -30,6 +34,8 @@ lazy_static::lazy_static! {
 }
 
 pub use self::imp::Error;
+#[cfg(target_os = "linux")]
+pub use self::imp::NetworkChangeListeners;
 
 pub struct MonitorHandle(Option<imp::MonitorHandle>);
 
@@ -40,10 +46,21 @@ impl MonitorHandle {
             None => false,
         }
     }
+
+    /// Returns the network that the host reaches the internet through outside the tunnel, or
+    /// `None` if this is unknown.
+    pub async fn network_state(&self) -> Option<NetworkState> {
+        #[cfg(target_os = "linux")]
+        if let Some(monitor) = self.0.as_ref() {
+            return monitor.network_state().await;
+        }
+        None
+    }
 }
 
 pub async fn spawn_monitor(
     sender: UnboundedSender<bool>,
+    #[cfg(target_os = "linux")] route_change_sender: UnboundedSender<Option<String>>,
     #[cfg(target_os = "linux")] route_manager: RouteManagerHandle,
     #[cfg(target_os = "android")] android_context: AndroidContext,
     #[cfg(target_os = "windows")] power_mgmt_rx: PowerManagementListener,
@@ -53,6 +70,8 @@ pub async fn spawn_monitor(
             imp::spawn_monitor(
                 sender,
                 #[cfg(target_os = "linux")]
+                route_change_sender,
+                #[cfg(target_os = "linux")]
                 route_manager,
                 #[cfg(target_os = "android")]
                 android_context,
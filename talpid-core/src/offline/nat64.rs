@@ -0,0 +1,245 @@
+//! Discovery of the NAT64 prefix of IPv6-only networks, using DNS64 as described in RFC 7050.
+
+use futures::{stream::FuturesUnordered, StreamExt};
+use resolv_conf::ScopedIp;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    fs, io,
+    net::{SocketAddr, SocketAddrV6},
+    time::Duration,
+};
+use talpid_types::{
+    net::nat64::{self, Prefix},
+    ErrorExt,
+};
+use tokio::net::UdpSocket;
+use trust_dns_server::proto::{
+    op::{Message, MessageType, Query},
+    rr::{domain::Name, record_data::RData, RecordType},
+};
+
+/// Files that list the resolvers of the network. The backup holds the original resolvers while
+/// /etc/resolv.conf points to the resolver of the tunnel.
+const RESOLV_CONF_PATHS: [&str; 3] = [
+    "/etc/resolv.conf.mullvadbackup",
+    "/etc/resolv.conf",
+    "/run/systemd/resolve/resolv.conf",
+];
+const DNS_PORT: u16 = 53;
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(3);
+/// Maximum size of a DNS message over UDP without EDNS.
+const MAX_RESPONSE_SIZE: usize = 512;
+
+/// Looks up the NAT64 prefix of the network by asking its resolvers for the addresses of
+/// `ipv4only.arpa`. The system resolver can't be used, since it may forward the query into the
+/// tunnel, and since the firewall blocks DNS outside the tunnel. The queries are sent from sockets
+/// marked with `fwmark`, which routes them outside the tunnel and lets them through the firewall.
+pub async fn discover(fwmark: Option<u32>) -> Option<Prefix> {
+    discover_using(&network_resolvers(), fwmark).await
+}
+
+/// Queries all `resolvers` at once, and returns the first prefix found.
+async fn discover_using(resolvers: &[SocketAddr], fwmark: Option<u32>) -> Option<Prefix> {
+    let mut lookups: FuturesUnordered<_> = resolvers
+        .iter()
+        .map(|&resolver| async move {
+            query(resolver, fwmark).await.unwrap_or_else(|error| {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg(&format!(
+                        "Failed to query {} for the NAT64 prefix",
+                        resolver
+                    ))
+                );
+                None
+            })
+        })
+        .collect();
+    let first_prefix = async {
+        while let Some(result) = lookups.next().await {
+            if result.is_some() {
+                return result;
+            }
+        }
+        None
+    };
+    tokio::time::timeout(LOOKUP_TIMEOUT, first_prefix)
+        .await
+        .unwrap_or_else(|_| {
+            log::debug!("Timed out looking up the NAT64 prefix");
+            None
+        })
+}
+
+/// Asks `resolver` for the AAAA records of `ipv4only.arpa`, and returns the prefix they were
+/// synthesized with, if any.
+async fn query(resolver: SocketAddr, fwmark: Option<u32>) -> io::Result<Option<Prefix>> {
+    let socket = Socket::new(
+        Domain::for_address(resolver),
+        Type::DGRAM,
+        Some(Protocol::UDP),
+    )?;
+    if let Some(fwmark) = fwmark {
+        socket.set_mark(fwmark)?;
+    }
+    socket.set_nonblocking(true)?;
+    let socket = UdpSocket::from_std(socket.into())?;
+    socket.connect(resolver).await?;
+
+    let id = rand::random();
+    socket.send(&query_message(id)?).await?;
+
+    let mut buffer = [0u8; MAX_RESPONSE_SIZE];
+    loop {
+        let len = socket.recv(&mut buffer).await?;
+        let response = match Message::from_vec(&buffer[..len]) {
+            Ok(response) => response,
+            Err(_) => continue,
+        };
+        if response.id() != id || response.message_type() != MessageType::Response {
+            continue;
+        }
+        return Ok(response
+            .answers()
+            .iter()
+            .find_map(|record| match record.data() {
+                Some(RData::AAAA(addr)) => Prefix::from_synthesized(*addr),
+                _ => None,
+            }));
+    }
+}
+
+fn query_message(id: u16) -> io::Result<Vec<u8>> {
+    let name = Name::from_ascii(nat64::IPV4_ONLY_NAME)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+    let mut message = Message::new();
+    message
+        .set_id(id)
+        .set_message_type(MessageType::Query)
+        .set_recursion_desired(true)
+        .add_query(Query::query(name, RecordType::AAAA));
+    message
+        .to_vec()
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))
+}
+
+/// Returns the resolvers of the network, excluding local stub resolvers.
+fn network_resolvers() -> Vec<SocketAddr> {
+    let mut resolvers = vec![];
+    for path in RESOLV_CONF_PATHS {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(_) => continue,
+        };
+        let config = match resolv_conf::Config::parse(&contents) {
+            Ok(config) => config,
+            Err(error) => {
+                log::debug!("Failed to parse {}: {}", path, error);
+                continue;
+            }
+        };
+        for resolver in config.nameservers.iter().filter_map(resolver_address) {
+            if !resolver.ip().is_loopback() && !resolvers.contains(&resolver) {
+                resolvers.push(resolver);
+            }
+        }
+    }
+    resolvers
+}
+
+fn resolver_address(ip: &ScopedIp) -> Option<SocketAddr> {
+    match ip {
+        ScopedIp::V4(addr) => Some(SocketAddr::new((*addr).into(), DNS_PORT)),
+        ScopedIp::V6(addr, scope) => {
+            let scope_id = match scope {
+                Some(scope) => scope
+                    .parse()
+                    .ok()
+                    .or_else(|| crate::linux::iface_index(scope).ok())?,
+                None => 0,
+            };
+            Some(SocketAddrV6::new(*addr, DNS_PORT, 0, scope_id).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use trust_dns_server::proto::rr::Record;
+
+    /// Answers every query for `ipv4only.arpa` with `answer`, like a DNS64 resolver.
+    async fn spawn_resolver(answer: Ipv6Addr) -> SocketAddr {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let address = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buffer = [0u8; MAX_RESPONSE_SIZE];
+            loop {
+                let (len, client) = socket.recv_from(&mut buffer).await.unwrap();
+                let mut message = Message::from_vec(&buffer[..len]).unwrap();
+                let name = message.queries()[0].name().clone();
+                message
+                    .set_message_type(MessageType::Response)
+                    .add_answer(Record::from_rdata(name, 60, RData::AAAA(answer)));
+                socket
+                    .send_to(&message.to_vec().unwrap(), client)
+                    .await
+                    .unwrap();
+            }
+        });
+        address
+    }
+
+    /// Returns an address on which nothing listens, so that queries to it are rejected, like
+    /// DNS blocked by the firewall.
+    fn rejecting_resolver() -> SocketAddr {
+        let socket = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        socket.local_addr().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_discover() {
+        let resolver = spawn_resolver("64:ff9b::c000:aa".parse().unwrap()).await;
+        let prefix = discover_using(&[resolver], None).await.unwrap();
+        assert_eq!(prefix.to_string(), "64:ff9b::/96");
+    }
+
+    #[tokio::test]
+    async fn test_discover_without_dns64() {
+        let resolver = spawn_resolver("2001:db8::1".parse().unwrap()).await;
+        assert_eq!(discover_using(&[resolver], None).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_discover_blocked_dns() {
+        assert_eq!(discover_using(&[rejecting_resolver()], None).await, None);
+
+        let resolver = spawn_resolver("64:ff9b::c000:ab".parse().unwrap()).await;
+        let prefix = discover_using(&[rejecting_resolver(), resolver], None)
+            .await
+            .unwrap();
+        assert_eq!(prefix.to_string(), "64:ff9b::/96");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_discover_timeout() {
+        // Bound, but never answers
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let resolver = socket.local_addr().unwrap();
+        assert_eq!(discover_using(&[resolver], None).await, None);
+    }
+
+    #[test]
+    fn test_resolver_address() {
+        let address = |ip: &str| resolver_address(&ip.parse().unwrap());
+        assert_eq!(
+            address("192.168.1.1"),
+            Some("192.168.1.1:53".parse().unwrap())
+        );
+        assert_eq!(
+            address("fe80::1%2"),
+            Some(SocketAddrV6::new("fe80::1".parse().unwrap(), DNS_PORT, 0, 2).into())
+        );
+    }
+}
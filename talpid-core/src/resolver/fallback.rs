@@ -0,0 +1,130 @@
+//! Plain DNS fallback for encrypted resolvers. While the encrypted resolvers can't be reached,
+//! queries are sent over plain DNS instead, and the encrypted resolvers are tried again after
+//! [`RETRY_INTERVAL`].
+
+use futures::channel::mpsc;
+use parking_lot::Mutex;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use talpid_types::net::dns::EncryptedDnsFallback;
+use trust_dns_server::{
+    client::rr::{LowerName, RecordType},
+    resolver::{
+        error::{ResolveError, ResolveErrorKind},
+        lookup::Lookup,
+        TokioAsyncResolver,
+    },
+};
+
+/// Time to keep using plain DNS after the encrypted resolvers failed.
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Subscribers that are notified when queries start or stop falling back to plain DNS.
+#[derive(Clone, Default)]
+pub struct FallbackListeners {
+    subscribers: Arc<Mutex<Vec<mpsc::UnboundedSender<EncryptedDnsFallback>>>>,
+}
+
+impl FallbackListeners {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a channel that receives a notification every time queries start or stop falling
+    /// back to plain DNS.
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<EncryptedDnsFallback> {
+        let (tx, rx) = mpsc::unbounded();
+        self.subscribers.lock().push(tx);
+        rx
+    }
+
+    fn notify(&self, event: EncryptedDnsFallback) {
+        self.subscribers
+            .lock()
+            .retain(|tx| tx.unbounded_send(event.clone()).is_ok());
+    }
+}
+
+/// An encrypted resolver, and a plain resolver that is used while it can't be reached.
+#[derive(Clone)]
+pub struct FallbackResolver {
+    encrypted: TokioAsyncResolver,
+    plain: TokioAsyncResolver,
+    /// When the encrypted resolver last failed, if it has not answered since.
+    failed_at: Arc<Mutex<Option<Instant>>>,
+    listeners: FallbackListeners,
+}
+
+impl FallbackResolver {
+    pub fn new(
+        encrypted: TokioAsyncResolver,
+        plain: TokioAsyncResolver,
+        listeners: FallbackListeners,
+    ) -> Self {
+        FallbackResolver {
+            encrypted,
+            plain,
+            failed_at: Arc::new(Mutex::new(None)),
+            listeners,
+        }
+    }
+
+    /// Looks up `name` with the encrypted resolver, or with the plain resolver if the encrypted
+    /// one has failed recently or fails now. Returns whether the plain resolver was used.
+    pub async fn lookup(
+        &self,
+        name: LowerName,
+        query_type: RecordType,
+    ) -> (Result<Lookup, ResolveError>, bool) {
+        let failed_recently = self
+            .failed_at
+            .lock()
+            .map(|failed_at| failed_at.elapsed() < RETRY_INTERVAL)
+            .unwrap_or(false);
+
+        if !failed_recently {
+            match self
+                .encrypted
+                .lookup(name.clone(), query_type, Default::default())
+                .await
+            {
+                Err(error) if !is_answer(&error) => self.on_failure(&error),
+                result => {
+                    self.on_success();
+                    return (result, false);
+                }
+            }
+        }
+
+        let result = self
+            .plain
+            .lookup(name, query_type, Default::default())
+            .await;
+        (result, true)
+    }
+
+    fn on_failure(&self, error: &ResolveError) {
+        let mut failed_at = self.failed_at.lock();
+        if failed_at.is_none() {
+            log::warn!("Encrypted DNS failed, falling back to plain DNS: {}", error);
+            self.listeners
+                .notify(EncryptedDnsFallback::Started(error.to_string()));
+        }
+        *failed_at = Some(Instant::now());
+    }
+
+    fn on_success(&self) {
+        if self.failed_at.lock().take().is_some() {
+            log::info!("Encrypted DNS is working again");
+            self.listeners.notify(EncryptedDnsFallback::Stopped);
+        }
+    }
+}
+
+/// Returns whether the error is an answer from the resolver, such as NXDOMAIN, rather than a
+/// failure to reach it.
+fn is_answer(error: &ResolveError) -> bool {
+    matches!(error.kind(), ResolveErrorKind::NoRecordsFound { .. })
+}
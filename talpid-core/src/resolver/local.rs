@@ -0,0 +1,202 @@
+//! Answers for names that the stub resolver resolves by itself, from local blocklists and
+//! overrides.
+
+use super::{ForwardLookup, TTL_SECONDS};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use talpid_types::net::dns::LocalDnsRules;
+use trust_dns_server::{
+    authority::{EmptyLookup, LookupObject},
+    client::{
+        op::LowerQuery,
+        rr::{LowerName, RecordType},
+    },
+    proto::rr::{domain::Name, record_data::RData, Record},
+    resolver::lookup::Lookup,
+};
+
+/// Prefix of blocklist entries that also block all subdomains.
+const WILDCARD_PREFIX: &str = "*.";
+
+/// Local blocklist and overrides, indexed for lookups. Blocked names map to the blocklist entry
+/// that blocks them.
+#[derive(Default)]
+pub struct LocalAnswers {
+    blocked_names: HashMap<LowerName, String>,
+    blocked_zones: HashMap<LowerName, String>,
+    overrides: HashMap<LowerName, Vec<IpAddr>>,
+}
+
+/// How a name is answered locally.
+#[derive(Debug, PartialEq)]
+pub enum LocalAnswer<'a> {
+    /// Blocked by the given blocklist entry.
+    Blocked(&'a str),
+    Override(&'a [IpAddr]),
+}
+
+impl LocalAnswers {
+    /// Indexes `rules`. Entries with invalid names are skipped.
+    pub fn new(rules: &LocalDnsRules) -> Self {
+        let mut answers = Self::default();
+
+        for entry in &rules.blocked_domains {
+            match entry.strip_prefix(WILDCARD_PREFIX) {
+                Some(zone) => {
+                    if let Some(zone) = parse_name(zone) {
+                        answers.blocked_zones.insert(zone, entry.clone());
+                    }
+                }
+                None => {
+                    if let Some(name) = parse_name(entry) {
+                        answers.blocked_names.insert(name, entry.clone());
+                    }
+                }
+            }
+        }
+
+        for entry in &rules.overrides {
+            if let Some(name) = parse_name(&entry.name) {
+                answers
+                    .overrides
+                    .entry(name)
+                    .or_default()
+                    .push(entry.address);
+            }
+        }
+
+        answers
+    }
+
+    /// Returns how `name` should be answered, or `None` if the query should be forwarded.
+    /// Overrides take precedence over the blocklist.
+    pub fn answer(&self, name: &LowerName) -> Option<LocalAnswer<'_>> {
+        if let Some(addresses) = self.overrides.get(name) {
+            return Some(LocalAnswer::Override(addresses));
+        }
+        self.blocking_entry(name).map(LocalAnswer::Blocked)
+    }
+
+    fn blocking_entry(&self, name: &LowerName) -> Option<&str> {
+        if let Some(entry) = self.blocked_names.get(name) {
+            return Some(entry);
+        }
+        if self.blocked_zones.is_empty() {
+            return None;
+        }
+        let mut zone = name.clone();
+        loop {
+            if let Some(entry) = self.blocked_zones.get(&zone) {
+                return Some(entry);
+            }
+            if zone.is_root() {
+                return None;
+            }
+            zone = zone.base_name();
+        }
+    }
+}
+
+/// Builds the response to `query` from a local answer. Blocked names get an empty response,
+/// and overridden names only get the addresses that match the query type.
+pub fn local_lookup(query: &LowerQuery, answer: LocalAnswer<'_>) -> Box<dyn LookupObject> {
+    let addresses = match answer {
+        LocalAnswer::Blocked(_) => return Box::new(EmptyLookup),
+        LocalAnswer::Override(addresses) => addresses,
+    };
+
+    let return_query = query.original().clone();
+    let records = addresses
+        .iter()
+        .filter_map(|address| match (address, return_query.query_type()) {
+            (IpAddr::V4(address), RecordType::A) => Some(RData::A(*address)),
+            (IpAddr::V6(address), RecordType::AAAA) => Some(RData::AAAA(*address)),
+            _ => None,
+        })
+        .map(|data| {
+            let mut record = Record::with(
+                return_query.name().clone(),
+                return_query.query_type(),
+                TTL_SECONDS,
+            );
+            record.set_data(Some(data));
+            record
+        })
+        .collect::<Vec<_>>();
+    if records.is_empty() {
+        return Box::new(EmptyLookup);
+    }
+
+    Box::new(ForwardLookup(Lookup::new_with_deadline(
+        return_query,
+        Arc::from(records),
+        Instant::now() + Duration::from_secs(u64::from(TTL_SECONDS)),
+    )))
+}
+
+fn parse_name(name: &str) -> Option<LowerName> {
+    match Name::from_str(name) {
+        Ok(mut parsed) => {
+            parsed.set_fqdn(true);
+            Some(LowerName::from(parsed))
+        }
+        Err(_) => {
+            log::warn!("Ignoring invalid local DNS name: {}", name);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use talpid_types::net::dns::DnsOverride;
+
+    fn name(name: &str) -> LowerName {
+        LowerName::from(Name::from_str(name).unwrap())
+    }
+
+    #[test]
+    fn test_local_answers() {
+        let nas_address = IpAddr::from([192, 168, 1, 10]);
+        let answers = LocalAnswers::new(&LocalDnsRules {
+            blocked_domains: vec![
+                "tracker.example".to_owned(),
+                "*.ads.example".to_owned(),
+                "*.lan".to_owned(),
+            ],
+            overrides: vec![DnsOverride {
+                name: "NAS.lan".to_owned(),
+                address: nas_address,
+            }],
+        });
+
+        assert_eq!(
+            answers.answer(&name("tracker.example.")),
+            Some(LocalAnswer::Blocked("tracker.example"))
+        );
+        assert_eq!(answers.answer(&name("cdn.tracker.example.")), None);
+        assert_eq!(
+            answers.answer(&name("ads.example.")),
+            Some(LocalAnswer::Blocked("*.ads.example"))
+        );
+        assert_eq!(
+            answers.answer(&name("a.b.ADS.example.")),
+            Some(LocalAnswer::Blocked("*.ads.example"))
+        );
+        assert_eq!(answers.answer(&name("notads.example.")), None);
+        assert_eq!(
+            answers.answer(&name("nas.lan.")),
+            Some(LocalAnswer::Override(&[nas_address][..]))
+        );
+        assert_eq!(
+            answers.answer(&name("printer.lan.")),
+            Some(LocalAnswer::Blocked("*.lan"))
+        );
+    }
+}
@@ -0,0 +1,900 @@
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr},
+    str::FromStr,
+    sync::{Arc, Weak},
+};
+
+use std::time::{Duration, Instant};
+
+use futures::{
+    channel::{mpsc, oneshot},
+    SinkExt, StreamExt,
+};
+
+#[cfg(target_os = "linux")]
+use std::net::IpAddr;
+#[cfg(target_os = "linux")]
+use talpid_types::net::dns::{
+    EncryptedDnsProtocol, EncryptedDnsServer, LocalDnsRules, SplitDnsRule,
+};
+#[cfg(target_os = "linux")]
+use trust_dns_server::resolver::{
+    config::{
+        NameServerConfig, NameServerConfigGroup, Protocol, ResolverConfig, ResolverOpts,
+        TlsClientConfig,
+    },
+    error::{ResolveError, ResolveErrorKind},
+    TokioAsyncResolver,
+};
+
+use trust_dns_server::{
+    authority::{
+        EmptyLookup, LookupObject, MessageRequest, MessageResponse, MessageResponseBuilder,
+    },
+    client::{
+        op::LowerQuery,
+        rr::{LowerName, RecordType},
+    },
+    proto::{
+        op::{header::MessageType, op_code::OpCode, Header, ResponseCode},
+        rr::{domain::Name, record_data::RData, Record},
+    },
+    resolver::lookup::Lookup,
+    server::{Request, RequestHandler, ResponseHandler, ResponseInfo},
+    ServerFuture,
+};
+
+const ALLOWED_RECORD_TYPES: &[RecordType] = &[RecordType::A, RecordType::AAAA, RecordType::CNAME];
+const CAPTIVE_PORTAL_DOMAIN: &str = "captive.apple.com";
+const TTL_SECONDS: u32 = 3;
+/// An IP address to be used in the DNS response to the captive domain query. The address itself
+/// belongs to the documentation range so should never be reachable.
+const RESOLVED_ADDR: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 1);
+
+#[cfg(target_os = "linux")]
+mod fallback;
+#[cfg(target_os = "linux")]
+pub use fallback::FallbackListeners;
+#[cfg(target_os = "linux")]
+mod local;
+#[cfg(target_os = "linux")]
+mod query_log;
+#[cfg(target_os = "linux")]
+pub use query_log::QueryLog;
+#[cfg(target_os = "linux")]
+mod tls;
+
+/// Name that is looked up when checking whether a resolver answers queries.
+#[cfg(target_os = "linux")]
+const PROBE_DOMAIN: &str = "mullvad.net.";
+/// Time to wait for a resolver to answer a test query.
+#[cfg(target_os = "linux")]
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Address that the stub resolver listens on. The system resolver can't be pointed at a custom
+/// port on Linux, so the stub uses port 53 on a loopback address of its own.
+#[cfg(target_os = "linux")]
+pub const STUB_RESOLVER_ADDRESS: Ipv4Addr = Ipv4Addr::new(127, 0, 0, 153);
+
+/// Starts a resolver. Returns a cloneable handle, which can activate, deactivate and shut down the
+/// resolver. When all instances of a handle are dropped, the server will stop.
+pub(crate) async fn start_resolver() -> Result<ResolverHandle, Error> {
+    let (resolver, resolver_handle) =
+        FilteringResolver::new(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0), false).await?;
+    tokio::spawn(resolver.run());
+    Ok(resolver_handle)
+}
+
+/// Starts a resolver listening on port 53 of [`STUB_RESOLVER_ADDRESS`], over both UDP and TCP.
+/// Queries are forwarded once upstream resolvers are set with
+/// [`ResolverHandle::set_forwarding`], and recorded in `query_log` if it is enabled.
+/// `fallback_listeners` are notified when queries fall back to plain DNS.
+#[cfg(target_os = "linux")]
+pub(crate) async fn start_stub_resolver(
+    query_log: QueryLog,
+    fallback_listeners: FallbackListeners,
+) -> Result<ResolverHandle, Error> {
+    let (mut resolver, resolver_handle) =
+        FilteringResolver::new(SocketAddr::new(STUB_RESOLVER_ADDRESS.into(), 53), true).await?;
+    resolver.query_log = query_log;
+    resolver.fallback_listeners = fallback_listeners;
+    tokio::spawn(resolver.run());
+    Ok(resolver_handle)
+}
+
+/// Upstream resolvers that a stub resolver forwards queries to.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForwardingConfig {
+    /// Resolvers for all queries that don't match a split DNS rule.
+    pub default_servers: Vec<IpAddr>,
+    /// Encrypted resolvers to use instead of `default_servers`, if any.
+    pub encrypted_servers: Vec<EncryptedDnsServer>,
+    /// Send queries to `default_servers` over plain DNS while the encrypted resolvers can't be
+    /// reached, instead of failing them.
+    pub encrypted_fallback: bool,
+    /// Domains whose queries are sent to resolvers of their own.
+    pub split_rules: Vec<SplitDnsRule>,
+    /// Names that are blocked or overridden instead of being forwarded.
+    pub local_rules: LocalDnsRules,
+}
+
+/// Resolver errors
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Failed to bind UDP socket
+    #[error(display = "Failed to bind UDP socket")]
+    UdpBindError(#[error(source)] io::Error),
+
+    /// Failed to get local address of a bound UDP socket
+    #[error(display = "Failed to get local address of a bound UDP socket")]
+    GetSocketAddrError(#[error(source)] io::Error),
+
+    /// Failed to bind TCP socket
+    #[cfg(target_os = "linux")]
+    #[error(display = "Failed to bind TCP socket")]
+    TcpBindError(#[error(source)] io::Error),
+
+    /// A split DNS rule contains an invalid domain
+    #[cfg(target_os = "linux")]
+    #[error(display = "Invalid split DNS domain: {}", _0)]
+    InvalidDomain(String),
+
+    /// An encrypted resolver has an invalid public key pin
+    #[cfg(target_os = "linux")]
+    #[error(display = "Invalid SPKI pin: {}", _0)]
+    InvalidSpkiPin(String),
+
+    /// Failed to create a resolver for upstream servers
+    #[cfg(target_os = "linux")]
+    #[error(display = "Failed to create upstream resolver")]
+    UpstreamResolverError(#[error(source)] ResolveError),
+
+    /// A resolver did not answer a test query
+    #[cfg(target_os = "linux")]
+    #[error(display = "The resolver did not answer the test query")]
+    ProbeError(#[error(source)] ResolveError),
+
+    /// The resolver has stopped
+    #[cfg(target_os = "linux")]
+    #[error(display = "The resolver has stopped")]
+    ResolverStopped,
+}
+
+/// A filtering resolver. Listens on a specified port for DNS queries and responds queries for
+/// `catpive.apple.com`. Can be toggled to unbind, be bound but not respond or bound and responding
+/// to some queries.
+struct FilteringResolver {
+    rx: mpsc::Receiver<ResolverMessage>,
+    dns_server: Option<(tokio::task::JoinHandle<()>, oneshot::Receiver<()>)>,
+    /// When set, all queries are forwarded to these resolvers instead of being filtered.
+    #[cfg(target_os = "linux")]
+    upstreams: Option<Upstreams>,
+    /// Log of forwarded queries.
+    #[cfg(target_os = "linux")]
+    query_log: QueryLog,
+    /// Notified when queries fall back to plain DNS.
+    #[cfg(target_os = "linux")]
+    fallback_listeners: FallbackListeners,
+}
+
+/// The answer to a query, and the response code to send with it.
+type LookupResponse = (ResponseCode, Box<dyn LookupObject>);
+
+/// The `FilteringResolver` is an actor responding to DNS queries.
+enum ResolverMessage {
+    /// Resolve a query.
+    Query(LowerQuery, oneshot::Sender<LookupResponse>),
+    /// Start forwarding queries to upstream resolvers, or stop forwarding them.
+    #[cfg(target_os = "linux")]
+    SetForwarding(Option<ForwardingConfig>, oneshot::Sender<Result<(), Error>>),
+}
+
+/// A handle to control a filtering resolver. When all resolver handles are dropped, custom
+/// resolver will stop.
+#[derive(Clone)]
+pub(crate) struct ResolverHandle {
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    tx: Arc<mpsc::Sender<ResolverMessage>>,
+    listening_port: u16,
+}
+
+impl ResolverHandle {
+    fn new(tx: Arc<mpsc::Sender<ResolverMessage>>, listening_port: u16) -> Self {
+        Self { tx, listening_port }
+    }
+
+    /// Get listening port for resolver handle
+    pub fn listening_port(&self) -> u16 {
+        self.listening_port
+    }
+
+    /// Forward all queries to the given upstream resolvers. If `config` is `None`, queries are
+    /// filtered instead.
+    #[cfg(target_os = "linux")]
+    pub async fn set_forwarding(&self, config: Option<ForwardingConfig>) -> Result<(), Error> {
+        let (result_tx, result_rx) = oneshot::channel();
+        let mut tx = (*self.tx).clone();
+        tx.send(ResolverMessage::SetForwarding(config, result_tx))
+            .await
+            .map_err(|_| Error::ResolverStopped)?;
+        result_rx.await.map_err(|_| Error::ResolverStopped)?
+    }
+}
+
+impl FilteringResolver {
+    /// Constructs a new filtering resolver and it's handle.
+    async fn new(
+        bind_address: SocketAddr,
+        #[cfg_attr(not(target_os = "linux"), allow(unused_variables))] listen_tcp: bool,
+    ) -> Result<(Self, ResolverHandle), Error> {
+        let (tx, rx) = mpsc::channel(0);
+        let command_tx = Arc::new(tx);
+
+        let mut server = ServerFuture::new(ResolverImpl {
+            tx: Arc::downgrade(&command_tx),
+        });
+
+        let server_listening_socket = tokio::net::UdpSocket::bind(bind_address)
+            .await
+            .map_err(Error::UdpBindError)?;
+        let port = server_listening_socket
+            .local_addr()
+            .map_err(Error::GetSocketAddrError)?
+            .port();
+        server.register_socket(server_listening_socket);
+
+        #[cfg(target_os = "linux")]
+        if listen_tcp {
+            let listener = tokio::net::TcpListener::bind(SocketAddr::new(bind_address.ip(), port))
+                .await
+                .map_err(Error::TcpBindError)?;
+            server.register_listener(listener, TCP_TIMEOUT);
+        }
+
+        let (server_done_tx, server_done_rx) = oneshot::channel();
+        let server_handle = tokio::spawn(async move {
+            if let Err(err) = server.block_until_done().await {
+                log::error!("DNS server stopped: {}", err);
+            }
+
+            let _ = server_done_tx.send(());
+        });
+        let resolver = Self {
+            rx,
+            dns_server: Some((server_handle, server_done_rx)),
+            #[cfg(target_os = "linux")]
+            upstreams: None,
+            #[cfg(target_os = "linux")]
+            query_log: QueryLog::new(),
+            #[cfg(target_os = "linux")]
+            fallback_listeners: FallbackListeners::new(),
+        };
+
+        Ok((resolver, ResolverHandle::new(command_tx, port)))
+    }
+
+    /// Runs the filtering resolver as an actor, listening for new queries instances.  When all
+    /// related [ResolverHandle] instances are dropped, this function will return, closing the DNS
+    /// server.
+    async fn run(mut self) {
+        while let Some(message) = self.rx.next().await {
+            match message {
+                ResolverMessage::Query(query, tx) => self.resolve(query, tx),
+                #[cfg(target_os = "linux")]
+                ResolverMessage::SetForwarding(config, tx) => {
+                    let _ = tx.send(self.set_forwarding(config));
+                }
+            }
+        }
+
+        if let Some((server_handle, done_rx)) = self.dns_server.take() {
+            server_handle.abort();
+            let _ = done_rx.await;
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_forwarding(&mut self, config: Option<ForwardingConfig>) -> Result<(), Error> {
+        self.upstreams = config
+            .as_ref()
+            .map(|config| Upstreams::new(config, &self.fallback_listeners))
+            .transpose()?;
+        Ok(())
+    }
+
+    /// Resolvers a query to nothing or a documentation address, or forwards it to an upstream
+    /// resolver if forwarding is enabled.
+    fn resolve(&mut self, query: LowerQuery, tx: oneshot::Sender<LookupResponse>) {
+        #[cfg(target_os = "linux")]
+        if let Some(upstreams) = &self.upstreams {
+            let start = query_log::QueryTimer::start(&self.query_log);
+            if let Some(answer) = upstreams.local.answer(query.name()) {
+                if let Some(start) = start {
+                    let blocked_by = match answer {
+                        local::LocalAnswer::Blocked(entry) => Some(entry.to_owned()),
+                        local::LocalAnswer::Override(_) => None,
+                    };
+                    self.query_log.record(start.finish(
+                        &query,
+                        None,
+                        blocked_by,
+                        ResponseCode::NoError,
+                    ));
+                }
+                let _ = tx.send((ResponseCode::NoError, local::local_lookup(&query, answer)));
+                return;
+            }
+            let upstream = upstreams.upstream_for(query.name()).clone();
+            let query_log = self.query_log.clone();
+            tokio::spawn(async move {
+                let (result, upstream_name) = upstream.lookup(&query).await;
+                let (response_code, lookup): (_, Box<dyn LookupObject>) = match result {
+                    Ok(lookup) => (ResponseCode::NoError, Box::new(ForwardLookup(lookup))),
+                    Err(error) => {
+                        log::trace!("Upstream lookup of {} failed: {}", query.name(), error);
+                        (error_response_code(&error), Box::new(EmptyLookup))
+                    }
+                };
+                if let Some(start) = start {
+                    query_log.record(start.finish(
+                        &query,
+                        Some(upstream_name),
+                        None,
+                        response_code,
+                    ));
+                }
+                let _ = tx.send((response_code, lookup));
+            });
+            return;
+        }
+
+        if !self.allow_query(&query) {
+            let _ = tx.send((ResponseCode::NoError, Box::new(EmptyLookup)));
+            return;
+        }
+
+        let return_query = query.original().clone();
+        let mut return_record = Record::with(
+            return_query.name().clone(),
+            return_query.query_type(),
+            TTL_SECONDS,
+        );
+        return_record.set_data(Some(RData::A(RESOLVED_ADDR)));
+
+        let lookup = Lookup::new_with_deadline(
+            return_query,
+            Arc::new([return_record]),
+            Instant::now() + Duration::from_secs(3),
+        );
+        let _ = tx.send((ResponseCode::NoError, Box::new(ForwardLookup(lookup))));
+    }
+
+    /// Determines whether a DNS query is allowable. Currently, this implies that the query is
+    /// either a `A`, `AAAA` or a `CNAME` query for `captive.apple.com`.
+    fn allow_query(&self, query: &LowerQuery) -> bool {
+        let captive_apple_com: LowerName =
+            LowerName::from(Name::from_str(CAPTIVE_PORTAL_DOMAIN).unwrap());
+        ALLOWED_RECORD_TYPES.contains(&query.query_type()) && query.name() == &captive_apple_com
+    }
+}
+
+/// Time after which idle TCP connections to the stub resolver are closed.
+#[cfg(target_os = "linux")]
+const TCP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Resolvers that a forwarding resolver sends queries to.
+#[cfg(target_os = "linux")]
+struct Upstreams {
+    default: Upstream,
+    /// Resolvers for split DNS domains, ordered with the most specific domain first.
+    split: Vec<(LowerName, Upstream)>,
+    /// Names that are answered without asking any resolver.
+    local: local::LocalAnswers,
+}
+
+#[cfg(target_os = "linux")]
+impl Upstreams {
+    fn new(
+        config: &ForwardingConfig,
+        fallback_listeners: &FallbackListeners,
+    ) -> Result<Self, Error> {
+        let default = if config.encrypted_servers.is_empty() {
+            Upstream {
+                name: "default".to_owned(),
+                resolver: UpstreamResolver::Plain(upstream_resolver(&config.default_servers)?),
+            }
+        } else if config.encrypted_fallback {
+            Upstream {
+                name: "encrypted".to_owned(),
+                resolver: UpstreamResolver::Fallback(fallback::FallbackResolver::new(
+                    encrypted_upstream_resolver(&config.encrypted_servers)?,
+                    upstream_resolver(&config.default_servers)?,
+                    fallback_listeners.clone(),
+                )),
+            }
+        } else {
+            Upstream {
+                name: "encrypted".to_owned(),
+                resolver: UpstreamResolver::Plain(encrypted_upstream_resolver(
+                    &config.encrypted_servers,
+                )?),
+            }
+        };
+        let mut split = config
+            .split_rules
+            .iter()
+            .map(|rule| {
+                let upstream = Upstream {
+                    name: format!("split DNS for {}", rule.domain),
+                    resolver: UpstreamResolver::Plain(upstream_resolver(&rule.servers)?),
+                };
+                Ok((parse_domain(&rule.domain)?, upstream))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        split.sort_by_key(|(domain, _)| std::cmp::Reverse(domain.num_labels()));
+
+        Ok(Upstreams {
+            default,
+            split,
+            local: local::LocalAnswers::new(&config.local_rules),
+        })
+    }
+
+    fn upstream_for(&self, name: &LowerName) -> &Upstream {
+        select_split_domain(&self.split, name).unwrap_or(&self.default)
+    }
+}
+
+/// A resolver, and the name it is described by in the query log.
+#[cfg(target_os = "linux")]
+#[derive(Clone)]
+struct Upstream {
+    name: String,
+    resolver: UpstreamResolver,
+}
+
+#[cfg(target_os = "linux")]
+#[derive(Clone)]
+enum UpstreamResolver {
+    Plain(TokioAsyncResolver),
+    /// Encrypted resolvers that fall back to plain DNS when they can't be reached.
+    Fallback(fallback::FallbackResolver),
+}
+
+#[cfg(target_os = "linux")]
+impl Upstream {
+    /// Forwards a query. Returns the result and the name to log the resolver as.
+    async fn lookup(&self, query: &LowerQuery) -> (Result<Lookup, ResolveError>, String) {
+        match &self.resolver {
+            UpstreamResolver::Plain(resolver) => {
+                let result = resolver
+                    .lookup(query.name().clone(), query.query_type(), Default::default())
+                    .await;
+                (result, self.name.clone())
+            }
+            UpstreamResolver::Fallback(resolver) => {
+                let (result, used_plain) = resolver
+                    .lookup(query.name().clone(), query.query_type())
+                    .await;
+                if used_plain {
+                    (result, format!("{} (plain fallback)", self.name))
+                } else {
+                    (result, self.name.clone())
+                }
+            }
+        }
+    }
+}
+
+/// Returns the response code to answer and log a failed upstream lookup with.
+#[cfg(target_os = "linux")]
+fn error_response_code(error: &ResolveError) -> ResponseCode {
+    match error.kind() {
+        ResolveErrorKind::NoRecordsFound { response_code, .. } => *response_code,
+        _ => ResponseCode::ServFail,
+    }
+}
+
+/// Returns the value of the first entry whose domain is `name` or one of its parents.
+#[cfg(target_os = "linux")]
+fn select_split_domain<'a, T>(entries: &'a [(LowerName, T)], name: &LowerName) -> Option<&'a T> {
+    entries
+        .iter()
+        .find(|(domain, _)| domain.zone_of(name))
+        .map(|(_, value)| value)
+}
+
+#[cfg(target_os = "linux")]
+fn parse_domain(domain: &str) -> Result<LowerName, Error> {
+    Name::from_str(domain)
+        .map(LowerName::from)
+        .map_err(|_| Error::InvalidDomain(domain.to_owned()))
+}
+
+#[cfg(target_os = "linux")]
+fn upstream_resolver(servers: &[IpAddr]) -> Result<TokioAsyncResolver, Error> {
+    let config = ResolverConfig::from_parts(
+        None,
+        vec![],
+        NameServerConfigGroup::from_ips_clear(servers, 53, true),
+    );
+    let mut options = ResolverOpts::default();
+    options.use_hosts_file = false;
+    TokioAsyncResolver::tokio(config, options).map_err(Error::UpstreamResolverError)
+}
+
+/// Returns a resolver that sends queries over DNS-over-TLS or DNS-over-HTTPS. The servers are
+/// reached through their bootstrap addresses, since their hostnames can't be resolved yet.
+#[cfg(target_os = "linux")]
+fn encrypted_upstream_resolver(
+    servers: &[EncryptedDnsServer],
+) -> Result<TokioAsyncResolver, Error> {
+    let mut config = ResolverConfig::new();
+    for server in servers {
+        let tls_config = TlsClientConfig(tls::client_config(server)?);
+        let protocol = match server.protocol {
+            EncryptedDnsProtocol::Tls => Protocol::Tls,
+            EncryptedDnsProtocol::Https => Protocol::Https,
+        };
+        for address in &server.bootstrap_addresses {
+            config.add_name_server(NameServerConfig {
+                socket_addr: SocketAddr::new(*address, server.port),
+                protocol,
+                tls_dns_name: Some(server.hostname.clone()),
+                trust_nx_responses: true,
+                tls_config: Some(tls_config.clone()),
+                bind_addr: None,
+            });
+        }
+    }
+    let mut options = ResolverOpts::default();
+    options.use_hosts_file = false;
+    TokioAsyncResolver::tokio(config, options).map_err(Error::UpstreamResolverError)
+}
+
+/// Sends a single query for [`PROBE_DOMAIN`] to `server` and returns how long it took to get an
+/// answer. Error responses, such as NXDOMAIN, still count as answers.
+#[cfg(target_os = "linux")]
+pub async fn probe_server(server: IpAddr) -> Result<Duration, Error> {
+    let config = ResolverConfig::from_parts(
+        None,
+        vec![],
+        NameServerConfigGroup::from_ips_clear(&[server], 53, true),
+    );
+    let mut options = ResolverOpts::default();
+    options.use_hosts_file = false;
+    options.cache_size = 0;
+    options.attempts = 1;
+    options.timeout = PROBE_TIMEOUT;
+    let resolver =
+        TokioAsyncResolver::tokio(config, options).map_err(Error::UpstreamResolverError)?;
+
+    let start = Instant::now();
+    match resolver.ipv4_lookup(PROBE_DOMAIN).await {
+        Ok(_) => Ok(start.elapsed()),
+        Err(error) => match error.kind() {
+            ResolveErrorKind::NoRecordsFound { .. } => Ok(start.elapsed()),
+            _ => Err(Error::ProbeError(error)),
+        },
+    }
+}
+
+/// An implementation of [trust_dns_server::server::RequestHandler] that forwards queries to
+/// `FilteringResolver`.
+struct ResolverImpl {
+    tx: Weak<mpsc::Sender<ResolverMessage>>,
+}
+
+impl ResolverImpl {
+    fn build_response<'a>(
+        message: &'a MessageRequest,
+        response_code: ResponseCode,
+        lookup: &'a mut Box<dyn LookupObject>,
+    ) -> MessageResponse<
+        'a,
+        'a,
+        Box<dyn Iterator<Item = &'a Record> + Send + 'a>,
+        std::iter::Empty<&'a Record>,
+        std::iter::Empty<&'a Record>,
+        std::iter::Empty<&'a Record>,
+    > {
+        let mut response_header = Header::new();
+        response_header.set_id(message.id());
+        response_header.set_op_code(OpCode::Query);
+        response_header.set_message_type(MessageType::Response);
+        response_header.set_authoritative(false);
+        response_header.set_response_code(response_code);
+
+        MessageResponseBuilder::from_message_request(message).build(
+            response_header,
+            lookup.iter(),
+            // forwarder responses only contain query answers, no ns,soa or additionals
+            std::iter::empty(),
+            std::iter::empty(),
+            std::iter::empty(),
+        )
+    }
+
+    async fn lookup<R: ResponseHandler>(&self, message: &Request, mut response_handler: R) {
+        if let Some(tx_ref) = self.tx.upgrade() {
+            let mut tx = (&*tx_ref).clone();
+            let query = message.query();
+            let (lookup_tx, lookup_rx) = oneshot::channel();
+            let _ = tx
+                .send(ResolverMessage::Query(query.clone(), lookup_tx))
+                .await;
+            let (response_code, mut lookup_result) = lookup_rx
+                .await
+                .unwrap_or_else(|_| (ResponseCode::ServFail, Box::new(EmptyLookup)));
+            let response = Self::build_response(&message, response_code, &mut lookup_result);
+
+            if let Err(err) = response_handler.send_response(response).await {
+                log::error!("Failed to send response: {}", err);
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RequestHandler for ResolverImpl {
+    async fn handle_request<R: ResponseHandler>(
+        &self,
+        request: &Request,
+        response_handle: R,
+    ) -> ResponseInfo {
+        if !request.src().ip().is_loopback() {
+            log::error!("Dropping a stray request from outside: {}", request.src());
+            return Header::new().into();
+        }
+        if let MessageType::Query = request.message_type() {
+            match request.op_code() {
+                OpCode::Query => {
+                    self.lookup(request, response_handle).await;
+                }
+                _ => {
+                    log::trace!("Dropping non-query request: {:?}", request);
+                }
+            };
+        }
+
+        return Header::new().into();
+    }
+}
+
+struct ForwardLookup(Lookup);
+
+/// This trait has to be reimplemented for the Lookup so that it can be sent back to the
+/// RequestHandler implementation.
+impl LookupObject for ForwardLookup {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Record> + Send + 'a> {
+        Box::new(self.0.record_iter())
+    }
+
+    fn take_additionals(&mut self) -> Option<Box<dyn LookupObject>> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{mem, net::UdpSocket, thread, time::Duration};
+    use trust_dns_server::resolver::{
+        config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+        TokioAsyncResolver,
+    };
+
+    async fn start_resolver() -> ResolverHandle {
+        super::start_resolver().await.unwrap()
+    }
+
+    async fn get_test_resolver(port: u16) -> trust_dns_server::resolver::TokioAsyncResolver {
+        let resolver_config = ResolverConfig::from_parts(
+            None,
+            vec![],
+            NameServerConfigGroup::from_ips_clear(&[Ipv4Addr::LOCALHOST.into()], port, true),
+        );
+        TokioAsyncResolver::tokio(resolver_config, ResolverOpts::default()).unwrap()
+    }
+
+    #[test]
+    fn test_successful_lookup() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let handle = rt.block_on(start_resolver());
+        let test_resolver = rt.block_on(get_test_resolver(handle.listening_port()));
+
+        let captive_portal_domain = LowerName::from(Name::from_str(CAPTIVE_PORTAL_DOMAIN).unwrap());
+        let resolver_result = rt.block_on(async move {
+            let dns_request =
+                test_resolver.lookup(captive_portal_domain, RecordType::A, Default::default());
+
+            dns_request.await
+        });
+        resolver_result.expect("Failed to resolve test domain");
+    }
+
+    #[test]
+    fn test_failed_lookup() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+
+        let handle = rt.block_on(start_resolver());
+        let test_resolver = rt.block_on(get_test_resolver(handle.listening_port()));
+
+        let captive_portal_domain = LowerName::from(Name::from_str("apple.com").unwrap());
+        let resolver_result = rt.block_on(async move {
+            test_resolver
+                .lookup(captive_portal_domain, RecordType::A, Default::default())
+                .await
+        });
+        assert!(
+            resolver_result.is_err(),
+            "Non-whitelisted DNS request should fail"
+        )
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_select_split_domain() {
+        let entries = vec![
+            (parse_domain("eu.corp.example").unwrap(), "eu"),
+            (parse_domain("corp.example").unwrap(), "corp"),
+        ];
+        let select = |name: &str| {
+            select_split_domain(&entries, &LowerName::from(Name::from_str(name).unwrap())).copied()
+        };
+
+        assert_eq!(select("corp.example."), Some("corp"));
+        assert_eq!(select("intranet.CORP.example."), Some("corp"));
+        assert_eq!(select("mail.eu.corp.example."), Some("eu"));
+        assert_eq!(select("notcorp.example."), None);
+        assert_eq!(select("example."), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_invalid_split_domain() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let _guard = rt.enter();
+
+        let config = ForwardingConfig {
+            default_servers: vec![Ipv4Addr::new(10, 64, 0, 1).into()],
+            encrypted_servers: vec![],
+            encrypted_fallback: false,
+            local_rules: LocalDnsRules::default(),
+            split_rules: vec![SplitDnsRule {
+                domain: format!("{}.example", "a".repeat(64)),
+                servers: vec![Ipv4Addr::new(10, 0, 0, 53).into()],
+            }],
+        };
+        assert!(matches!(
+            Upstreams::new(&config, &FallbackListeners::new()),
+            Err(Error::InvalidDomain(_))
+        ));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_encrypted_fallback() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let handle = rt.block_on(start_resolver());
+        let plain = rt.block_on(get_test_resolver(handle.listening_port()));
+
+        let mut options = ResolverOpts::default();
+        options.timeout = Duration::from_millis(500);
+        options.attempts = 1;
+        let _guard = rt.enter();
+        let unreachable = TokioAsyncResolver::tokio(
+            ResolverConfig::from_parts(
+                None,
+                vec![],
+                NameServerConfigGroup::from_ips_clear(&[Ipv4Addr::LOCALHOST.into()], 1, true),
+            ),
+            options,
+        )
+        .unwrap();
+
+        let listeners = FallbackListeners::new();
+        let mut events = listeners.subscribe();
+        let resolver = fallback::FallbackResolver::new(unreachable, plain, listeners);
+
+        let captive_portal_domain = LowerName::from(Name::from_str(CAPTIVE_PORTAL_DOMAIN).unwrap());
+        let (result, used_plain) =
+            rt.block_on(resolver.lookup(captive_portal_domain, RecordType::A));
+        result.expect("Failed to resolve test domain over plain DNS");
+        assert!(used_plain);
+        assert!(matches!(
+            events.try_next(),
+            Ok(Some(talpid_types::net::dns::EncryptedDnsFallback::Started(
+                _
+            )))
+        ));
+    }
+
+    /// Starts a DNS server that answers every query with `response_code` and no records.
+    #[cfg(target_os = "linux")]
+    async fn start_failing_upstream(response_code: ResponseCode) -> u16 {
+        use trust_dns_server::proto::op::Message;
+
+        let socket = tokio::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let port = socket.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buffer = [0u8; 512];
+            while let Ok((len, from)) = socket.recv_from(&mut buffer).await {
+                let request = match Message::from_vec(&buffer[..len]) {
+                    Ok(request) => request,
+                    Err(_) => continue,
+                };
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .set_op_code(OpCode::Query)
+                    .set_recursion_desired(request.recursion_desired())
+                    .set_recursion_available(true)
+                    .set_response_code(response_code)
+                    .add_queries(request.queries().to_vec());
+                let _ = socket.send_to(&response.to_vec().unwrap(), from).await;
+            }
+        });
+        port
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_forwarded_error_response_code() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let _guard = rt.enter();
+
+        let upstream_port = rt.block_on(start_failing_upstream(ResponseCode::NXDomain));
+        let upstream = rt.block_on(get_test_resolver(upstream_port));
+
+        let (mut resolver, handle) = rt
+            .block_on(FilteringResolver::new(
+                SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0),
+                false,
+            ))
+            .unwrap();
+        resolver.upstreams = Some(Upstreams {
+            default: Upstream {
+                name: "default".to_owned(),
+                resolver: UpstreamResolver::Plain(upstream),
+            },
+            split: vec![],
+            local: local::LocalAnswers::new(&LocalDnsRules::default()),
+        });
+        rt.spawn(resolver.run());
+
+        let test_resolver = rt.block_on(get_test_resolver(handle.listening_port()));
+        let name = LowerName::from(Name::from_str("nonexistent.example.").unwrap());
+        let result = rt.block_on(test_resolver.lookup(name, RecordType::A, Default::default()));
+
+        let error = result.expect_err("Lookup of nonexistent name should fail");
+        match error.kind() {
+            ResolveErrorKind::NoRecordsFound { response_code, .. } => {
+                assert_eq!(*response_code, ResponseCode::NXDomain)
+            }
+            kind => panic!("Unexpected lookup error: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+
+        let handle = rt.block_on(start_resolver());
+        let port = handle.listening_port();
+        mem::drop(handle);
+        thread::sleep(Duration::from_millis(300));
+        UdpSocket::bind((Ipv4Addr::LOCALHOST, port))
+            .expect("Failed to bind to a port that should have been removed");
+    }
+}
@@ -0,0 +1,161 @@
+//! In-memory log of the queries that pass through the stub resolver. The log is opt-in, holds at
+//! most [`CAPACITY`] entries, and is never written to disk.
+
+use futures::channel::mpsc;
+use parking_lot::Mutex;
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Instant, SystemTime},
+};
+use talpid_types::net::dns::DnsQueryLogEntry;
+use trust_dns_server::{client::op::LowerQuery, proto::op::ResponseCode};
+
+/// Maximum number of entries kept in the log. The oldest entries are dropped first.
+pub const CAPACITY: usize = 1000;
+
+/// Cloneable handle to the query log.
+#[derive(Clone, Default)]
+pub struct QueryLog {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    enabled: bool,
+    entries: VecDeque<DnsQueryLogEntry>,
+    subscribers: Vec<mpsc::UnboundedSender<DnsQueryLogEntry>>,
+}
+
+impl QueryLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts or stops logging. Stopping clears the log and ends all subscriptions.
+    pub fn set_enabled(&self, enabled: bool) {
+        let mut inner = self.inner.lock();
+        inner.enabled = enabled;
+        if !enabled {
+            inner.entries.clear();
+            inner.subscribers.clear();
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.inner.lock().enabled
+    }
+
+    /// Adds an entry to the log and sends it to all subscribers, if logging is enabled.
+    pub fn record(&self, entry: DnsQueryLogEntry) {
+        let mut inner = self.inner.lock();
+        if !inner.enabled {
+            return;
+        }
+        inner
+            .subscribers
+            .retain(|subscriber| subscriber.unbounded_send(entry.clone()).is_ok());
+        if inner.entries.len() == CAPACITY {
+            inner.entries.pop_front();
+        }
+        inner.entries.push_back(entry);
+    }
+
+    /// Returns the current entries, oldest first, and a receiver of all later entries. Returns
+    /// `None` if logging is disabled.
+    pub fn subscribe(
+        &self,
+    ) -> Option<(
+        Vec<DnsQueryLogEntry>,
+        mpsc::UnboundedReceiver<DnsQueryLogEntry>,
+    )> {
+        let mut inner = self.inner.lock();
+        if !inner.enabled {
+            return None;
+        }
+        let (tx, rx) = mpsc::unbounded();
+        inner.subscribers.push(tx);
+        Some((inner.entries.iter().cloned().collect(), rx))
+    }
+}
+
+/// Measures how long it takes to answer a query. Only started while logging is enabled.
+#[derive(Clone, Copy)]
+pub struct QueryTimer {
+    time: SystemTime,
+    start: Instant,
+}
+
+impl QueryTimer {
+    pub fn start(log: &QueryLog) -> Option<Self> {
+        if !log.is_enabled() {
+            return None;
+        }
+        Some(Self {
+            time: SystemTime::now(),
+            start: Instant::now(),
+        })
+    }
+
+    /// Returns the log entry for a query that has been answered.
+    pub fn finish(
+        self,
+        query: &LowerQuery,
+        upstream: Option<String>,
+        blocked_by: Option<String>,
+        response_code: ResponseCode,
+    ) -> DnsQueryLogEntry {
+        DnsQueryLogEntry {
+            time: self.time,
+            name: query.name().to_string(),
+            query_type: query.query_type().to_string(),
+            upstream,
+            blocked_by,
+            response_code: format!("{:?}", response_code),
+            latency: self.start.elapsed(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::StreamExt;
+    use std::time::{Duration, SystemTime};
+
+    fn entry(name: &str) -> DnsQueryLogEntry {
+        DnsQueryLogEntry {
+            time: SystemTime::UNIX_EPOCH,
+            name: name.to_owned(),
+            query_type: "A".to_owned(),
+            upstream: Some("default".to_owned()),
+            blocked_by: None,
+            response_code: "NoError".to_owned(),
+            latency: Duration::from_millis(10),
+        }
+    }
+
+    #[test]
+    fn test_query_log() {
+        let log = QueryLog::new();
+        log.record(entry("disabled.example."));
+        assert!(log.subscribe().is_none());
+
+        log.set_enabled(true);
+        for i in 0..CAPACITY + 1 {
+            log.record(entry(&format!("{}.example.", i)));
+        }
+        let (entries, mut rx) = log.subscribe().unwrap();
+        assert_eq!(entries.len(), CAPACITY);
+        assert_eq!(entries[0].name, "1.example.");
+
+        log.record(entry("live.example."));
+        assert_eq!(
+            futures::executor::block_on(rx.next()).unwrap().name,
+            "live.example."
+        );
+
+        log.set_enabled(false);
+        assert!(futures::executor::block_on(rx.next()).is_none());
+    }
+}
@@ -0,0 +1,174 @@
+//! TLS configuration for encrypted upstream resolvers. Server certificates are verified against
+//! the bundled web PKI roots and, if the server is pinned, against a digest of its public key.
+
+use super::Error;
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier},
+    Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName,
+};
+use std::{mem, sync::Arc, time::SystemTime};
+use talpid_types::net::dns::{decode_spki_pin, EncryptedDnsProtocol, EncryptedDnsServer};
+
+/// ALPN protocol that DNS-over-HTTPS servers must support.
+const ALPN_H2: &[u8] = b"h2";
+
+/// Returns the TLS configuration to use for connecting to `server`.
+pub fn client_config(server: &EncryptedDnsServer) -> Result<Arc<ClientConfig>, Error> {
+    let mut root_store = RootCertStore::empty();
+    root_store.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(
+        |trust_anchor| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                trust_anchor.subject,
+                trust_anchor.spki,
+                trust_anchor.name_constraints,
+            )
+        },
+    ));
+
+    let mut config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(root_store.clone())
+        .with_no_client_auth();
+
+    if let Some(pin) = &server.spki_pin {
+        let pin = decode_spki_pin(pin).map_err(|_| Error::InvalidSpkiPin(pin.clone()))?;
+        config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(PinnedVerifier {
+                inner: WebPkiVerifier::new(root_store, None),
+                pin,
+            }));
+    }
+
+    if server.protocol == EncryptedDnsProtocol::Https {
+        config.alpn_protocols = vec![ALPN_H2.to_vec()];
+    }
+
+    Ok(Arc::new(config))
+}
+
+/// Verifies certificates like [`WebPkiVerifier`], but also requires the public key of the server
+/// certificate to match a pinned SHA-256 digest.
+struct PinnedVerifier {
+    inner: WebPkiVerifier,
+    pin: [u8; 32],
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+
+        let spki = subject_public_key_info(&end_entity.0)
+            .ok_or(rustls::Error::InvalidCertificateEncoding)?;
+        let digest = ring::digest::digest(&ring::digest::SHA256, spki);
+        if digest.as_ref() != self.pin {
+            return Err(rustls::Error::InvalidCertificateData(
+                "Server public key does not match the pinned key".to_owned(),
+            ));
+        }
+
+        Ok(verified)
+    }
+}
+
+/// Returns the DER encoded SubjectPublicKeyInfo of a DER encoded X.509 certificate.
+fn subject_public_key_info(certificate: &[u8]) -> Option<&[u8]> {
+    const EXPLICIT_VERSION_TAG: u8 = 0xa0;
+
+    let (_, certificate, _) = split_der_element(certificate)?;
+    let (_, tbs_certificate, _) = split_der_element(certificate)?;
+
+    let mut fields = tbs_certificate;
+    if fields.first() == Some(&EXPLICIT_VERSION_TAG) {
+        fields = split_der_element(fields)?.2;
+    }
+    // Skip serialNumber, signature, issuer, validity and subject.
+    for _ in 0..5 {
+        fields = split_der_element(fields)?.2;
+    }
+
+    let (spki, _, _) = split_der_element(fields)?;
+    Some(spki)
+}
+
+/// Splits the first DER element off `input`. Returns the whole element, its contents, and the
+/// remaining input.
+fn split_der_element(input: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+    let first_length_byte = *input.get(1)?;
+    let (header_length, content_length) = if first_length_byte & 0x80 == 0 {
+        (2, usize::from(first_length_byte))
+    } else {
+        let length_size = usize::from(first_length_byte & 0x7f);
+        if length_size == 0 || length_size > mem::size_of::<usize>() {
+            return None;
+        }
+        let length = input
+            .get(2..2 + length_size)?
+            .iter()
+            .fold(0usize, |length, byte| (length << 8) | usize::from(*byte));
+        (2 + length_size, length)
+    };
+
+    let end = header_length.checked_add(content_length)?;
+    if end > input.len() {
+        return None;
+    }
+    Some((&input[..end], &input[header_length..end], &input[end..]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+        let mut element = vec![tag];
+        if contents.len() < 0x80 {
+            element.push(contents.len() as u8);
+        } else {
+            element.extend_from_slice(&[0x82, (contents.len() >> 8) as u8, contents.len() as u8]);
+        }
+        element.extend_from_slice(contents);
+        element
+    }
+
+    #[test]
+    fn test_subject_public_key_info() {
+        let spki = der(0x30, &[0x03, 0x03, 0x00, 0xaa, 0xbb]);
+        let tbs_certificate = [
+            der(0xa0, &der(0x02, &[2])),
+            der(0x02, &[1]),
+            der(0x30, &[]),
+            der(0x30, &[0x05; 200]),
+            der(0x30, &[]),
+            der(0x30, &[]),
+            spki.clone(),
+            der(0xa3, &[]),
+        ]
+        .concat();
+        let certificate = der(
+            0x30,
+            &[der(0x30, &tbs_certificate), der(0x30, &[]), der(0x03, &[0])].concat(),
+        );
+
+        assert_eq!(subject_public_key_info(&certificate), Some(&spki[..]));
+        assert_eq!(
+            subject_public_key_info(&certificate[..certificate.len() - 1]),
+            None
+        );
+    }
+}
@@ -33,7 +33,7 @@ pub struct Route {
 }
 
 impl Route {
-    fn new(node: Node, prefix: IpNetwork) -> Self {
+    pub(crate) fn new(node: Node, prefix: IpNetwork) -> Self {
         Self {
             node,
             prefix,
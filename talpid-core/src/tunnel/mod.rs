@@ -1,6 +1,9 @@
 use self::tun_provider::TunProvider;
 use crate::{logging, routing::RouteManagerHandle};
-use futures::{channel::oneshot, future::BoxFuture};
+use futures::{
+    channel::{mpsc, oneshot},
+    future::BoxFuture,
+};
 use std::{
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
     path::{Path, PathBuf},
@@ -94,6 +97,10 @@ pub struct TunnelMetadata {
     pub ipv4_gateway: Ipv4Addr,
     /// The IP to the IPv6 default gateway on the tunnel interface.
     pub ipv6_gateway: Option<Ipv6Addr>,
+    /// MTU found by path MTU discovery, if it was performed.
+    pub mtu: Option<u16>,
+    /// WireGuard implementation used by the tunnel, if it is known.
+    pub wireguard_backend: Option<wireguard_types::BackendInfo>,
 }
 
 /// Abstraction for monitoring a generic VPN tunnel.
@@ -121,6 +128,17 @@ where
     pub retry_attempt: u32,
     /// Route manager handle.
     pub route_manager: RouteManagerHandle,
+    /// Receiver of changes to apply to a running WireGuard tunnel.
+    pub config_update_rx: mpsc::UnboundedReceiver<WireguardConfigUpdate>,
+}
+
+/// A change that is applied to a running WireGuard tunnel without reconnecting.
+#[derive(Debug, Clone)]
+pub enum WireguardConfigUpdate {
+    /// Replace the private key of the tunnel.
+    PrivateKey(wireguard_types::PrivateKey),
+    /// Set the interval in seconds at which keepalive packets are sent to the peers.
+    PersistentKeepalive(Option<u16>),
 }
 
 // TODO(emilsp) move most of the openvpn tunnel details to OpenVpnTunnelMonitor
@@ -999,6 +999,8 @@ mod event_server {
                 ips,
                 ipv4_gateway,
                 ipv6_gateway,
+                mtu: None,
+                wireguard_backend: None,
             })
         }
     }
@@ -1,7 +1,8 @@
 use std::{
     borrow::Cow,
     ffi::CString,
-    net::{Ipv4Addr, Ipv6Addr},
+    fmt::Write,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
 };
 use talpid_types::net::{obfuscation::ObfuscatorConfig, wireguard, GenericTunnelOptions};
 
@@ -18,12 +19,24 @@ pub struct Config {
     pub ipv6_gateway: Option<Ipv6Addr>,
     /// Maximum transmission unit for the tunnel
     pub mtu: u16,
+    /// Interval in seconds at which keepalive packets are sent to the peers
+    pub persistent_keepalive: Option<u16>,
+    /// Timeouts used to decide whether the tunnel is working
+    pub connectivity_profile: wireguard::ConnectivityProfile,
+    /// Address pinged to check connectivity. The IPv4 gateway is used if `None`
+    pub probe_address: Option<Ipv4Addr>,
     /// Firewall mark
     #[cfg(target_os = "linux")]
     pub fwmark: u32,
     /// Enable IPv6 routing rules
     #[cfg(target_os = "linux")]
     pub enable_ipv6: bool,
+    /// Lower the MTU to the largest packet size that passes through the tunnel after connecting
+    #[cfg(target_os = "linux")]
+    pub mtu_discovery: bool,
+    /// WireGuard implementation to prefer. Picked automatically if `None`
+    #[cfg(target_os = "linux")]
+    pub backend: Option<wireguard::Backend>,
     /// Temporary switch for wireguard-nt
     #[cfg(target_os = "windows")]
     pub use_wireguard_nt: bool,
@@ -39,6 +52,10 @@ const DEFAULT_MTU: u16 = 1380;
 #[cfg(target_os = "android")]
 const DEFAULT_MTU: u16 = 1280;
 
+/// Largest number of bytes that WireGuard adds to a packet: an IPv6 header, a UDP header and the
+/// WireGuard header.
+const WIREGUARD_OVERHEAD: u16 = 80;
+
 /// Configuration errors
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -59,10 +76,7 @@ impl Config {
     /// Constructs a Config from parameters
     pub fn from_parameters(params: &wireguard::TunnelParameters) -> Result<Config, Error> {
         let tunnel = params.connection.tunnel.clone();
-        let mut peers = vec![params.connection.peer.clone()];
-        if let Some(exit_peer) = &params.connection.exit_peer {
-            peers.push(exit_peer.clone());
-        }
+        let peers = params.connection.peers().cloned().collect();
         Self::new(
             tunnel,
             peers,
@@ -119,16 +133,136 @@ impl Config {
             ipv4_gateway: connection_config.ipv4_gateway,
             ipv6_gateway,
             mtu,
+            persistent_keepalive: wg_options.persistent_keepalive,
+            connectivity_profile: wg_options.connectivity_profile,
+            probe_address: wg_options.probe_address,
             #[cfg(target_os = "linux")]
             fwmark: crate::linux::TUNNEL_FW_MARK,
             #[cfg(target_os = "linux")]
             enable_ipv6: generic_options.enable_ipv6,
+            #[cfg(target_os = "linux")]
+            mtu_discovery: wg_options.mtu_discovery,
+            #[cfg(target_os = "linux")]
+            backend: wg_options.backend,
             #[cfg(target_os = "windows")]
             use_wireguard_nt: wg_options.use_wireguard_nt,
             obfuscator_config,
         })
     }
 
+    /// Returns the config in the format read by `wg-quick`. The private key and any preshared
+    /// keys are only included if `include_private_key` is set.
+    ///
+    /// `wg-quick` can't send a tunnel through another tunnel on the same interface, so a multihop
+    /// config is returned as two configs: one for the entry relay, which only routes traffic to
+    /// the exit relay, followed by one for the exit relay.
+    pub fn to_wg_quick_format(
+        &self,
+        dns_servers: &[IpAddr],
+        include_private_key: bool,
+    ) -> Vec<String> {
+        match &self.peers[..] {
+            // The exit relay is reached through the entry relay
+            [entry, exit]
+                if entry
+                    .allowed_ips
+                    .iter()
+                    .any(|net| net.contains(exit.endpoint.ip())) =>
+            {
+                vec![
+                    self.wg_quick_config(
+                        "Multihop entry relay. Bring this up before the exit relay config.",
+                        std::slice::from_ref(entry),
+                        &[],
+                        self.mtu,
+                        include_private_key,
+                    ),
+                    self.wg_quick_config(
+                        "Multihop exit relay, reached through the entry relay config.",
+                        std::slice::from_ref(exit),
+                        dns_servers,
+                        self.mtu.saturating_sub(WIREGUARD_OVERHEAD),
+                        include_private_key,
+                    ),
+                ]
+            }
+            peers => vec![self.wg_quick_config(
+                "Exported from the Mullvad VPN app.",
+                peers,
+                dns_servers,
+                self.mtu,
+                include_private_key,
+            )],
+        }
+    }
+
+    fn wg_quick_config(
+        &self,
+        comment: &str,
+        peers: &[wireguard::PeerConfig],
+        dns_servers: &[IpAddr],
+        mtu: u16,
+        include_private_key: bool,
+    ) -> String {
+        let mut config = String::new();
+        let _ = writeln!(config, "# {}", comment);
+        if self.obfuscator_config.is_some() {
+            let _ = writeln!(
+                config,
+                "# Obfuscation is not supported by wg-quick, so the relay is reached directly."
+            );
+        }
+
+        let _ = writeln!(config, "[Interface]");
+        if include_private_key {
+            let _ = writeln!(
+                config,
+                "PrivateKey = {}",
+                self.tunnel.private_key.to_base64()
+            );
+        } else {
+            let _ = writeln!(config, "# PrivateKey = (not included)");
+        }
+        let addresses: Vec<String> = self
+            .tunnel
+            .addresses
+            .iter()
+            .map(|address| match address {
+                IpAddr::V4(address) => format!("{}/32", address),
+                IpAddr::V6(address) => format!("{}/128", address),
+            })
+            .collect();
+        let _ = writeln!(config, "Address = {}", addresses.join(", "));
+        if !dns_servers.is_empty() {
+            let servers: Vec<String> = dns_servers.iter().map(IpAddr::to_string).collect();
+            let _ = writeln!(config, "DNS = {}", servers.join(", "));
+        }
+        let _ = writeln!(config, "MTU = {}", mtu);
+
+        for peer in peers {
+            let _ = writeln!(config, "\n[Peer]");
+            let _ = writeln!(config, "PublicKey = {}", peer.public_key.to_base64());
+            match &peer.psk {
+                Some(psk) if include_private_key => {
+                    let _ = writeln!(config, "PresharedKey = {}", psk.to_base64());
+                }
+                Some(_) => {
+                    let _ = writeln!(config, "# PresharedKey = (not included)");
+                }
+                None => (),
+            }
+            let allowed_ips: Vec<String> =
+                peer.allowed_ips.iter().map(|ip| ip.to_string()).collect();
+            let _ = writeln!(config, "AllowedIPs = {}", allowed_ips.join(", "));
+            let _ = writeln!(config, "Endpoint = {}", peer.endpoint);
+            if let Some(interval) = self.persistent_keepalive {
+                let _ = writeln!(config, "PersistentKeepalive = {}", interval);
+            }
+        }
+
+        config
+    }
+
     /// Returns a CString with the appropriate config for WireGuard-go
     // TODO: Consider outputting both overriding and additive configs
     pub fn to_userspace_format(&self) -> CString {
@@ -148,6 +282,12 @@ impl Config {
                 .add("public_key", peer.public_key.as_bytes().as_ref())
                 .add("endpoint", peer.endpoint.to_string().as_str())
                 .add("replace_allowed_ips", "true");
+            if let Some(interval) = self.persistent_keepalive {
+                wg_conf.add(
+                    "persistent_keepalive_interval",
+                    interval.to_string().as_str(),
+                );
+            }
             if let Some(ref psk) = peer.psk {
                 wg_conf.add("preshared_key", psk.as_bytes().as_ref());
             }
@@ -209,3 +349,74 @@ impl WgConfigBuffer {
         self.buf
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn peer(endpoint: &str, allowed_ip: &str) -> wireguard::PeerConfig {
+        wireguard::PeerConfig {
+            public_key: wireguard::PublicKey::from([1; 32]),
+            allowed_ips: vec![allowed_ip.parse().unwrap()],
+            endpoint: endpoint.parse().unwrap(),
+            psk: None,
+        }
+    }
+
+    #[test]
+    fn test_wg_quick_multihop() {
+        let private_key = wireguard::PrivateKey::from([2; 32]);
+        let connection = wireguard::ConnectionConfig {
+            tunnel: wireguard::TunnelConfig {
+                private_key: private_key.clone(),
+                addresses: vec!["10.64.0.2".parse().unwrap(), "fc00::2".parse().unwrap()],
+            },
+            peer: peer("192.0.2.1:51820", "192.0.2.2/32"),
+            exit_peer: Some(peer("192.0.2.2:51820", "0.0.0.0/0")),
+            additional_peers: vec![],
+            ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+            ipv6_gateway: None,
+        };
+        let config = Config::from_parameters(&wireguard::TunnelParameters {
+            connection,
+            options: wireguard::TunnelOptions {
+                mtu: Some(1380),
+                persistent_keepalive: Some(25),
+                ..Default::default()
+            },
+            generic_options: GenericTunnelOptions { enable_ipv6: false },
+            obfuscation: None,
+            nat64: false,
+        })
+        .unwrap();
+
+        let configs = config.to_wg_quick_format(&["10.64.0.1".parse().unwrap()], false);
+        assert_eq!(configs.len(), 2);
+
+        let (entry, exit) = (&configs[0], &configs[1]);
+        assert!(entry.contains("Address = 10.64.0.2/32\n"));
+        assert!(entry.contains("Endpoint = 192.0.2.1:51820\n"));
+        assert!(entry.contains("AllowedIPs = 192.0.2.2/32\n"));
+        assert!(entry.contains("MTU = 1380\n"));
+        assert!(!entry.contains("DNS"));
+        assert!(exit.contains("Endpoint = 192.0.2.2:51820\n"));
+        assert!(exit.contains("AllowedIPs = 0.0.0.0/0\n"));
+        assert!(exit.contains("DNS = 10.64.0.1\n"));
+        assert!(exit.contains("MTU = 1300\n"));
+        assert!(exit.contains("PersistentKeepalive = 25\n"));
+
+        for config in &configs {
+            assert!(!config.contains(&private_key.to_base64()));
+        }
+        let configs = config.to_wg_quick_format(&[], true);
+        assert!(configs[0].contains(&format!("PrivateKey = {}\n", private_key.to_base64())));
+
+        let userspace = config.to_userspace_format().into_string().unwrap();
+        assert_eq!(
+            userspace
+                .matches("persistent_keepalive_interval=25\n")
+                .count(),
+            2
+        );
+    }
+}
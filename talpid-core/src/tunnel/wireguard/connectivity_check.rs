@@ -1,13 +1,15 @@
 use crate::{
     ping_monitor::{new_pinger, Pinger},
-    tunnel::wireguard::stats::StatsMap,
+    tunnel::wireguard::stats::{Stats, StatsMap},
 };
 use std::{
     cmp,
+    collections::HashMap,
     net::Ipv4Addr,
     sync::{mpsc, Mutex, Weak},
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
+use talpid_types::net::wireguard::ConnectivityTimeouts;
 
 use super::{Tunnel, TunnelError};
 
@@ -16,24 +18,16 @@ const DELAY_ON_INITIAL_SETUP: Duration = Duration::from_millis(50);
 /// Sleep time used when checking if an established connection is still working.
 const REGULAR_LOOP_SLEEP: Duration = Duration::from_secs(1);
 
-/// Timeout for waiting on receiving traffic after sending outgoing traffic.  Once this timeout is
-/// hit, a ping will be sent every `SECONDS_PER_PING` until `PING_TIMEOUT` is reached, or traffic
-/// is received.
-const BYTES_RX_TIMEOUT: Duration = Duration::from_secs(5);
 /// Timeout for waiting on receiving or sending any traffic.  Once this timeout is hit, a ping will
-/// be sent every `SECONDS_PER_PING` until `PING_TIMEOUT` is reached or traffic is received.
+/// be sent every `SECONDS_PER_PING` until the ping timeout is reached or traffic is received.
 const TRAFFIC_TIMEOUT: Duration = Duration::from_secs(120);
-/// Timeout for waiting on receiving traffic after sending the first ICMP packet.  Once this
-/// timeout is reached, it is assumed that the connection is lost.
-const PING_TIMEOUT: Duration = Duration::from_secs(15);
-/// Timeout for receiving traffic when establishing a connection.
-const ESTABLISH_TIMEOUT: Duration = Duration::from_secs(4);
-/// `ESTABLISH_TIMEOUT` is multiplied by this after each failed connection attempt.
+/// The establish timeout is multiplied by this after each failed connection attempt.
 const ESTABLISH_TIMEOUT_MULTIPLIER: u32 = 2;
-/// Maximum timeout for establishing a connection.
-const MAX_ESTABLISH_TIMEOUT: Duration = PING_TIMEOUT;
 /// Number of seconds to wait between sending ICMP packets
 const SECONDS_PER_PING: Duration = Duration::from_secs(3);
+/// Time after which the session keys of a peer expire, unless a new handshake has completed.
+/// WireGuard initiates a new handshake when traffic is sent to a peer after this.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(180);
 
 /// Connectivity monitor errors
 #[derive(err_derive::Error, Debug)]
@@ -60,15 +54,22 @@ pub enum Error {
 /// timeout. A connection is considered to be established the first time an increase in incoming
 /// traffic is observed.
 ///
-/// The connectivity monitor will start sending pings and start the countdown to `PING_TIMEOUT` in
-/// the following cases:
+/// The connectivity monitor will start sending pings and start the countdown to the ping timeout
+/// in the following cases:
 /// - In case that we have observed a bump in the outgoing traffic but no coressponding incoming
-/// traffic for longer than `BYTES_RX_TIMEOUT`, then the monitor will start pinging.
+/// traffic for longer than the rx timeout, then the monitor will start pinging.
 /// - In case that no increase in outgoing or incoming traffic has been observed for longer than
 /// `TRAFFIC_TIMEOUT`, then the monitor will start pinging as well.
 ///
 /// Once a connection established, a connection is only considered broken once the connectivity
-/// monitor has started pinging and no traffic has been received for a duration of `PING_TIMEOUT`.
+/// monitor has started pinging and no traffic has been received for the duration of the ping
+/// timeout.
+///
+/// A connection is also considered broken if traffic has been sent to a peer whose last handshake
+/// is older than `HANDSHAKE_TIMEOUT`, and no new handshake completes within the ping timeout. Idle
+/// peers are not affected, since WireGuard only performs handshakes when there is traffic to send.
+///
+/// The rx, ping and establish timeouts are given by [`ConnectivityTimeouts`].
 pub struct ConnectivityMonitor {
     tunnel_handle: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
     conn_state: ConnState,
@@ -76,6 +77,7 @@ pub struct ConnectivityMonitor {
     num_pings_sent: u32,
     pinger: Box<dyn Pinger>,
     close_receiver: mpsc::Receiver<()>,
+    timeouts: ConnectivityTimeouts,
 }
 
 impl ConnectivityMonitor {
@@ -84,6 +86,7 @@ impl ConnectivityMonitor {
         #[cfg(any(target_os = "macos", target_os = "linux"))] interface: String,
         tunnel_handle: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
         close_receiver: mpsc::Receiver<()>,
+        timeouts: ConnectivityTimeouts,
     ) -> Result<Self, Error> {
         let pinger = new_pinger(
             addr,
@@ -101,6 +104,7 @@ impl ConnectivityMonitor {
             num_pings_sent: 0,
             pinger,
             close_receiver,
+            timeouts,
         })
     }
 
@@ -111,9 +115,9 @@ impl ConnectivityMonitor {
         self.pinger.send_icmp().map_err(Error::PingError)?;
         self.establish_connectivity_inner(
             retry_attempt,
-            ESTABLISH_TIMEOUT,
+            self.timeouts.establish_timeout,
             ESTABLISH_TIMEOUT_MULTIPLIER,
-            MAX_ESTABLISH_TIMEOUT,
+            self.timeouts.ping_timeout,
         )
     }
 
@@ -184,7 +188,7 @@ impl ConnectivityMonitor {
 
     /// Returns true if connection is established
     fn check_connectivity(&mut self, now: Instant) -> Result<bool, Error> {
-        self.check_connectivity_interval(now, PING_TIMEOUT)
+        self.check_connectivity_interval(now, self.timeouts.ping_timeout)
     }
 
     /// Returns true if connection is established
@@ -198,7 +202,18 @@ impl ConnectivityMonitor {
             Some(new_stats) => {
                 let new_stats = new_stats?;
 
-                if self.conn_state.update(now, new_stats) {
+                let rx_incremented = self.conn_state.update(now, new_stats);
+                if self
+                    .conn_state
+                    .handshake_timed_out(now, self.timeouts.ping_timeout)
+                {
+                    log::warn!(
+                        "No WireGuard handshake has completed in {} seconds",
+                        (HANDSHAKE_TIMEOUT + self.timeouts.ping_timeout).as_secs()
+                    );
+                    return Ok(false);
+                }
+                if rx_incremented {
                     self.reset_pinger();
                     return Ok(true);
                 }
@@ -224,7 +239,9 @@ impl ConnectivityMonitor {
         // Only send out a ping if we haven't received a byte in a while or no traffic has flowed
         // in the last 2 minutes, but if a ping already has been sent out, only send one out every
         // 3 seconds.
-        if (self.conn_state.rx_timed_out() || self.conn_state.traffic_timed_out())
+        let rx_timeout = self.timeouts.rx_timeout;
+        if (self.conn_state.rx_timed_out(rx_timeout)
+            || self.conn_state.traffic_timed_out(rx_timeout))
             && self
                 .initial_ping_timestamp
                 .map(|initial_ping_timestamp| {
@@ -265,6 +282,9 @@ enum ConnState {
         rx_timestamp: Instant,
         tx_timestamp: Instant,
         stats: StatsMap,
+        /// Peers that were sent traffic after their handshake expired, and the time at which this
+        /// was first observed.
+        expired_handshakes: HashMap<[u8; 32], Instant>,
     },
 }
 
@@ -291,6 +311,7 @@ impl ConnState {
                         rx_timestamp: now,
                         tx_timestamp,
                         stats: new_stats,
+                        expired_handshakes: HashMap::new(),
                     };
                     *self = connected_state;
                     return true;
@@ -313,7 +334,26 @@ impl ConnState {
                 rx_timestamp,
                 tx_timestamp,
                 stats,
+                expired_handshakes,
             } => {
+                let expired_handshakes = new_stats
+                    .iter()
+                    .filter(|(_, peer_stats)| handshake_expired(peer_stats))
+                    .filter_map(|(key, peer_stats)| {
+                        if let Some(timestamp) = expired_handshakes.get(key) {
+                            return Some((*key, *timestamp));
+                        }
+                        let tx_incremented = stats
+                            .get(key)
+                            .map(|old_stats| peer_stats.tx_bytes > old_stats.tx_bytes)
+                            .unwrap_or(false);
+                        if tx_incremented {
+                            Some((*key, now))
+                        } else {
+                            None
+                        }
+                    })
+                    .collect();
                 let rx_incremented = stats.iter().all(|(key, peer_stats)| {
                     new_stats
                         .get(key)
@@ -332,6 +372,7 @@ impl ConnState {
                     rx_timestamp,
                     tx_timestamp,
                     stats: new_stats,
+                    expired_handshakes,
                 };
 
                 rx_incremented
@@ -342,17 +383,19 @@ impl ConnState {
     pub fn reset_after_suspension(&mut self, now: Instant) {
         if let ConnState::Connected {
             ref mut rx_timestamp,
+            ref mut expired_handshakes,
             ..
         } = self
         {
             *rx_timestamp = now;
+            expired_handshakes.clear();
         }
     }
 
     // check if last time data was received is too long ago
-    pub fn rx_timed_out(&self) -> bool {
+    pub fn rx_timed_out(&self, rx_timeout: Duration) -> bool {
         match self {
-            ConnState::Connecting { start, .. } => start.elapsed() >= BYTES_RX_TIMEOUT,
+            ConnState::Connecting { start, .. } => start.elapsed() >= rx_timeout,
             ConnState::Connected {
                 rx_timestamp,
                 tx_timestamp,
@@ -360,16 +403,16 @@ impl ConnState {
             } => {
                 // if last sent bytes were sent after or at the same time as last received bytes
                 tx_timestamp >= rx_timestamp &&
-                    // and the response hasn't been seen for BYTES_RX_TIMEOUT
-                    rx_timestamp.elapsed() >= BYTES_RX_TIMEOUT
+                    // and the response hasn't been seen for rx_timeout
+                    rx_timestamp.elapsed() >= rx_timeout
             }
         }
     }
 
     // check if no bytes have been sent or received in a while
-    pub fn traffic_timed_out(&self) -> bool {
+    pub fn traffic_timed_out(&self, rx_timeout: Duration) -> bool {
         match self {
-            ConnState::Connecting { .. } => self.rx_timed_out(),
+            ConnState::Connecting { .. } => self.rx_timed_out(rx_timeout),
             ConnState::Connected {
                 rx_timestamp,
                 tx_timestamp,
@@ -381,11 +424,34 @@ impl ConnState {
         }
     }
 
+    // check if traffic was sent to a peer with an expired handshake, and no new handshake has
+    // completed since
+    pub fn handshake_timed_out(&self, now: Instant, retry_timeout: Duration) -> bool {
+        match self {
+            ConnState::Connecting { .. } => false,
+            ConnState::Connected {
+                expired_handshakes, ..
+            } => expired_handshakes
+                .values()
+                .any(|timestamp| now.saturating_duration_since(*timestamp) >= retry_timeout),
+        }
+    }
+
     pub fn connected(&self) -> bool {
         matches!(self, ConnState::Connected { .. })
     }
 }
 
+/// Returns whether the last handshake with a peer is older than `HANDSHAKE_TIMEOUT`. Backends that
+/// don't report handshakes are never considered expired.
+fn handshake_expired(stats: &Stats) -> bool {
+    stats
+        .last_handshake
+        .and_then(|last_handshake| SystemTime::now().duration_since(last_handshake).ok())
+        .map(|age| age > HANDSHAKE_TIMEOUT)
+        .unwrap_or(false)
+}
+
 #[cfg(test)]
 mod test {
     use futures::Future;
@@ -405,6 +471,10 @@ mod test {
         time::{Duration, Instant},
     };
 
+    const BYTES_RX_TIMEOUT: Duration = ConnectivityTimeouts::DEFAULT.rx_timeout;
+    const PING_TIMEOUT: Duration = ConnectivityTimeouts::DEFAULT.ping_timeout;
+    const HANDSHAKE_RETRY_TIMEOUT: Duration = PING_TIMEOUT;
+
     /// Test if a newly created ConnState won't have timed out or consider itself connected
     #[test]
     fn test_conn_state_no_timeout_on_start() {
@@ -412,8 +482,8 @@ mod test {
         let conn_state = ConnState::new(now, Default::default());
 
         assert!(!conn_state.connected());
-        assert!(!conn_state.rx_timed_out());
-        assert!(!conn_state.traffic_timed_out());
+        assert!(!conn_state.rx_timed_out(BYTES_RX_TIMEOUT));
+        assert!(!conn_state.traffic_timed_out(BYTES_RX_TIMEOUT));
     }
 
     /// Test if ConnState::Connecting will timeout after not receiving any traffic after
@@ -424,8 +494,19 @@ mod test {
         let conn_state = ConnState::new(now, Default::default());
 
         assert!(!conn_state.connected());
-        assert!(conn_state.rx_timed_out());
-        assert!(conn_state.traffic_timed_out());
+        assert!(conn_state.rx_timed_out(BYTES_RX_TIMEOUT));
+        assert!(conn_state.traffic_timed_out(BYTES_RX_TIMEOUT));
+    }
+
+    /// Test if a longer rx timeout, as used by the tolerant profile, delays the timeout
+    #[test]
+    fn test_conn_state_tolerant_rx_timeout() {
+        let rx_timeout = ConnectivityTimeouts::TOLERANT.rx_timeout;
+        let now = Instant::now().checked_sub(BYTES_RX_TIMEOUT).unwrap();
+        let conn_state = ConnState::new(now, Default::default());
+
+        assert!(!conn_state.rx_timed_out(rx_timeout));
+        assert!(!conn_state.traffic_timed_out(rx_timeout));
     }
 
     /// Test if ConnState::Connecting correctly transitions into ConnState::Connected if traffic is
@@ -440,13 +521,14 @@ mod test {
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 0,
+                last_handshake: None,
             },
         );
         conn_state.update(Instant::now(), stats);
 
         assert!(conn_state.connected());
-        assert!(!conn_state.rx_timed_out());
-        assert!(!conn_state.traffic_timed_out());
+        assert!(!conn_state.rx_timed_out(BYTES_RX_TIMEOUT));
+        assert!(!conn_state.traffic_timed_out(BYTES_RX_TIMEOUT));
     }
 
     /// Test if ConnState::Connected correctly times out after TRAFFIC_TIMEOUT when no traffic is
@@ -465,13 +547,14 @@ mod test {
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 0,
+                last_handshake: None,
             },
         );
         conn_state.update(connect_time, stats);
 
         assert!(conn_state.connected());
-        assert!(!conn_state.rx_timed_out());
-        assert!(conn_state.traffic_timed_out());
+        assert!(!conn_state.rx_timed_out(BYTES_RX_TIMEOUT));
+        assert!(conn_state.traffic_timed_out(BYTES_RX_TIMEOUT));
     }
 
     /// Test if ConnState::Connected correctly times out after BYTES_RX_TIMEOUT when no incoming
@@ -489,6 +572,7 @@ mod test {
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 0,
+                last_handshake: None,
             },
         );
         conn_state.update(start, stats);
@@ -500,13 +584,14 @@ mod test {
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 1,
+                last_handshake: None,
             },
         );
         conn_state.update(update_time, stats);
 
         assert!(conn_state.connected());
-        assert!(conn_state.rx_timed_out());
-        assert!(!conn_state.traffic_timed_out());
+        assert!(conn_state.rx_timed_out(BYTES_RX_TIMEOUT));
+        assert!(!conn_state.traffic_timed_out(BYTES_RX_TIMEOUT));
     }
 
     #[derive(Default)]
@@ -543,6 +628,33 @@ mod test {
                 stats::Stats {
                     tx_bytes: 0,
                     rx_bytes: 0,
+                    last_handshake: None,
+                },
+            );
+            let peers = Mutex::new(map);
+            Self {
+                on_get_stats: Box::new(move || {
+                    let mut peers = peers.lock().unwrap();
+                    for traffic in peers.values_mut() {
+                        traffic.tx_bytes += 1;
+                        traffic.rx_bytes += 1;
+                    }
+                    Ok(peers.clone())
+                }),
+            }
+        }
+
+        /// Returns a tunnel whose traffic counters always increment, but whose last handshake
+        /// happened `handshake_age` ago.
+        fn incrementing_with_handshake_age(handshake_age: Duration) -> Self {
+            let last_handshake = SystemTime::now() - handshake_age;
+            let mut map = stats::StatsMap::new();
+            map.insert(
+                Self::PEER,
+                stats::Stats {
+                    tx_bytes: 0,
+                    rx_bytes: 0,
+                    last_handshake: Some(last_handshake),
                 },
             );
             let peers = Mutex::new(map);
@@ -567,6 +679,7 @@ mod test {
                         stats::Stats {
                             tx_bytes: 0,
                             rx_bytes: 0,
+                            last_handshake: None,
                         },
                     );
                     Ok(map)
@@ -621,6 +734,7 @@ mod test {
             pinger,
             close_receiver,
             tunnel_handle,
+            timeouts: ConnectivityTimeouts::DEFAULT,
         }
     }
 
@@ -632,12 +746,14 @@ mod test {
             stats::Stats {
                 tx_bytes: 0,
                 rx_bytes: 0,
+                last_handshake: None,
             },
         );
         ConnState::Connected {
             rx_timestamp: timestamp,
             tx_timestamp: timestamp,
             stats,
+            expired_handshakes: HashMap::new(),
         }
     }
 
@@ -690,6 +806,64 @@ mod test {
         assert!(monitor.check_connectivity(now).unwrap())
     }
 
+    #[test]
+    /// Verify that `check_connectivity()` returns `false` once traffic has been sent for
+    /// `HANDSHAKE_RETRY_TIMEOUT` without a handshake, after the last one expired.
+    fn test_handshake_times_out() {
+        let (_tunnel_anchor, tunnel) =
+            MockTunnel::incrementing_with_handshake_age(HANDSHAKE_TIMEOUT + Duration::from_secs(1))
+                .into_locked();
+        let (_tx, rx) = mpsc::channel();
+        let pinger = MockPinger::default();
+        let now = Instant::now();
+        let start = now - Duration::from_secs(1);
+        let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, rx);
+
+        // Mock the state - connectivity has been established
+        monitor.conn_state = connected_state(start);
+
+        assert!(monitor.check_connectivity(now).unwrap());
+        assert!(!monitor
+            .conn_state
+            .handshake_timed_out(now, HANDSHAKE_RETRY_TIMEOUT));
+        assert!(!monitor
+            .check_connectivity(now + HANDSHAKE_RETRY_TIMEOUT)
+            .unwrap());
+    }
+
+    #[test]
+    /// Verify that a recent handshake, or an old one on an idle tunnel, is not considered a lost
+    /// connection.
+    fn test_handshake_no_timeout() {
+        let (_tunnel_anchor, tunnel) =
+            MockTunnel::incrementing_with_handshake_age(Duration::from_secs(10)).into_locked();
+        let (_tx, rx) = mpsc::channel();
+        let now = Instant::now();
+        let start = now - Duration::from_secs(1);
+        let mut monitor = mock_monitor(start, Box::new(MockPinger::default()), tunnel, rx);
+        monitor.conn_state = connected_state(start);
+        assert!(monitor.check_connectivity(now).unwrap());
+        assert!(monitor
+            .check_connectivity(now + HANDSHAKE_RETRY_TIMEOUT)
+            .unwrap());
+
+        let mut conn_state = connected_state(start);
+        let mut stats = StatsMap::new();
+        stats.insert(
+            [0u8; 32],
+            Stats {
+                tx_bytes: 0,
+                rx_bytes: 0,
+                last_handshake: Some(SystemTime::now() - HANDSHAKE_TIMEOUT * 2),
+            },
+        );
+        conn_state.update(now, stats.clone());
+        conn_state.update(now + HANDSHAKE_RETRY_TIMEOUT, stats);
+        assert!(
+            !conn_state.handshake_timed_out(now + HANDSHAKE_RETRY_TIMEOUT, HANDSHAKE_RETRY_TIMEOUT)
+        );
+    }
+
     #[test]
     /// Verify that the connectivity monitor doesn't fail if the tunnel constantly sends traffic,
     /// and it shuts down properly.
@@ -730,6 +904,7 @@ mod test {
             stats::Stats {
                 tx_bytes: 0,
                 rx_bytes: 0,
+                last_handshake: None,
             },
         );
         let tunnel_stats = Mutex::new(map);
@@ -781,6 +956,7 @@ mod test {
             stats::Stats {
                 tx_bytes: 0,
                 rx_bytes: 0,
+                last_handshake: None,
             },
         );
 
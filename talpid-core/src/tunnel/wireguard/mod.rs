@@ -1,11 +1,12 @@
 use self::config::Config;
 #[cfg(not(windows))]
 use super::tun_provider;
-use super::{tun_provider::TunProvider, TunnelArgs, TunnelEvent, TunnelMetadata};
+use super::{
+    tun_provider::TunProvider, TunnelArgs, TunnelEvent, TunnelMetadata, WireguardConfigUpdate,
+};
 use crate::routing::{self, RequiredRoute};
 use futures::future::{abortable, AbortHandle as FutureAbortHandle, BoxFuture, Future};
-#[cfg(windows)]
-use futures::{channel::mpsc, StreamExt};
+use futures::{channel::mpsc, FutureExt, StreamExt};
 #[cfg(target_os = "linux")]
 use lazy_static::lazy_static;
 #[cfg(target_os = "linux")]
@@ -21,26 +22,32 @@ use std::{
     path::Path,
     pin::Pin,
     sync::{mpsc as sync_mpsc, Arc, Mutex},
-    time::Duration,
+    time::{Duration, SystemTime},
 };
+#[cfg(target_os = "linux")]
+use talpid_types::net::wireguard::{Backend, BackendReason};
 #[cfg(windows)]
 use talpid_types::BoxedError;
 use talpid_types::{
     net::{
-        obfuscation::ObfuscatorConfig, wireguard::PublicKey, AllowedTunnelTraffic, Endpoint,
-        TransportProtocol,
+        obfuscation::ObfuscatorConfig,
+        wireguard::{BackendInfo, PublicKey},
+        AllowedTunnelTraffic, Endpoint, TransportProtocol,
     },
     ErrorExt,
 };
 use tokio::sync::Mutex as AsyncMutex;
 use tunnel_obfuscation::{
-    create_obfuscator, Error as ObfuscationError, Settings as ObfuscationSettings, Udp2TcpSettings,
+    create_obfuscator, Error as ObfuscationError, Settings as ObfuscationSettings,
+    ShadowsocksSettings, Udp2TcpSettings,
 };
 
 /// WireGuard config data-types
 pub mod config;
 mod connectivity_check;
 mod logging;
+#[cfg(target_os = "linux")]
+mod mtu_detection;
 mod stats;
 mod wireguard_go;
 #[cfg(target_os = "linux")]
@@ -112,6 +119,18 @@ const INITIAL_PSK_EXCHANGE_TIMEOUT: Duration = Duration::from_secs(4);
 const MAX_PSK_EXCHANGE_TIMEOUT: Duration = Duration::from_secs(15);
 const PSK_EXCHANGE_TIMEOUT_MULTIPLIER: u32 = 2;
 
+/// How long to wait for a handshake using a rotated private key before giving up on it.
+const KEY_ROTATION_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(20);
+/// How often to check for a handshake after rotating the private key.
+const KEY_ROTATION_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Smallest MTU that path MTU discovery may settle on when IPv6 is disabled.
+#[cfg(target_os = "linux")]
+const MIN_IPV4_MTU: u16 = 576;
+/// Smallest MTU that path MTU discovery may settle on when IPv6 is enabled.
+#[cfg(target_os = "linux")]
+const MIN_IPV6_MTU: u16 = 1280;
+
 /// Simple wrapper that automatically cancels the future which runs an obfuscator.
 struct ObfuscatorHandle {
     abort_handle: FutureAbortHandle,
@@ -139,56 +158,67 @@ lazy_static! {
     static ref FORCE_USERSPACE_WIREGUARD: bool = env::var("TALPID_FORCE_USERSPACE_WIREGUARD")
         .map(|v| v != "0")
         .unwrap_or(false);
-
-    static ref FORCE_NM_WIREGUARD: bool = env::var("TALPID_FORCE_NM_WIREGUARD")
-        .map(|v| v != "0")
-        .unwrap_or(false);
 }
 
 async fn maybe_create_obfuscator(
     config: &mut Config,
     close_msg_sender: sync_mpsc::Sender<CloseMsg>,
 ) -> Result<Option<ObfuscatorHandle>> {
-    // There are one or two peers.
+    // There are one or more peers.
     // The first one is always the entry relay.
     let mut first_peer = config.peers.get_mut(0).expect("missing peer");
 
-    if let Some(ref obfuscator_config) = config.obfuscator_config {
-        match obfuscator_config {
-            ObfuscatorConfig::Udp2Tcp { endpoint } => {
-                log::trace!("Connecting to Udp2Tcp endpoint {:?}", *endpoint);
-                let settings = Udp2TcpSettings {
-                    peer: *endpoint,
-                    #[cfg(target_os = "linux")]
-                    fwmark: Some(crate::linux::TUNNEL_FW_MARK),
-                };
-                let obfuscator = create_obfuscator(&ObfuscationSettings::Udp2Tcp(settings))
-                    .await
-                    .map_err(Error::CreateObfuscatorError)?;
-                let endpoint = obfuscator.endpoint();
-                log::trace!("Patching first WireGuard peer to become {:?}", endpoint);
-                first_peer.endpoint = endpoint;
-                let (runner, abort_handle) = abortable(async move {
-                    match obfuscator.run().await {
-                        Ok(_) => {
-                            let _ = close_msg_sender.send(CloseMsg::ObfuscatorExpired);
-                        }
-                        Err(error) => {
-                            log::error!(
-                                "{}",
-                                error.display_chain_with_msg("Obfuscation controller failed")
-                            );
-                            let _ = close_msg_sender
-                                .send(CloseMsg::ObfuscatorFailed(Error::ObfuscatorError(error)));
-                        }
-                    }
-                });
-                tokio::spawn(runner);
-                return Ok(Some(ObfuscatorHandle::new(abort_handle)));
+    let settings = match &config.obfuscator_config {
+        Some(ObfuscatorConfig::Udp2Tcp { endpoint }) => {
+            log::trace!("Connecting to Udp2Tcp endpoint {:?}", endpoint);
+            ObfuscationSettings::Udp2Tcp(Udp2TcpSettings {
+                peer: *endpoint,
+                #[cfg(target_os = "linux")]
+                fwmark: Some(crate::linux::TUNNEL_FW_MARK),
+            })
+        }
+        Some(ObfuscatorConfig::Shadowsocks {
+            endpoint,
+            wireguard_endpoint,
+            cipher,
+            password,
+        }) => {
+            log::trace!("Connecting to Shadowsocks endpoint {:?}", endpoint);
+            ObfuscationSettings::Shadowsocks(ShadowsocksSettings {
+                shadowsocks_endpoint: *endpoint,
+                wireguard_endpoint: *wireguard_endpoint,
+                cipher: cipher.clone(),
+                password: password.clone(),
+                #[cfg(target_os = "linux")]
+                fwmark: Some(crate::linux::TUNNEL_FW_MARK),
+            })
+        }
+        None => return Ok(None),
+    };
+
+    let obfuscator = create_obfuscator(&settings)
+        .await
+        .map_err(Error::CreateObfuscatorError)?;
+    let endpoint = obfuscator.endpoint();
+    log::trace!("Patching first WireGuard peer to become {:?}", endpoint);
+    first_peer.endpoint = endpoint;
+    let (runner, abort_handle) = abortable(async move {
+        match obfuscator.run().await {
+            Ok(_) => {
+                let _ = close_msg_sender.send(CloseMsg::ObfuscatorExpired);
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Obfuscation controller failed")
+                );
+                let _ = close_msg_sender
+                    .send(CloseMsg::ObfuscatorFailed(Error::ObfuscatorError(error)));
             }
         }
-    }
-    Ok(None)
+    });
+    tokio::spawn(runner);
+    Ok(Some(ObfuscatorHandle::new(abort_handle)))
 }
 
 impl WireguardMonitor {
@@ -219,7 +249,7 @@ impl WireguardMonitor {
         #[cfg(target_os = "windows")]
         let (setup_done_tx, setup_done_rx) = mpsc::channel(0);
 
-        let tunnel = Self::open_tunnel(
+        let (tunnel, backend) = Self::open_tunnel(
             args.runtime.clone(),
             &Self::patch_allowed_ips(&config, psk_negotiation.is_some()),
             log_path,
@@ -241,18 +271,23 @@ impl WireguardMonitor {
             obfuscator: Arc::new(AsyncMutex::new(obfuscator)),
         };
 
-        let gateway = config.ipv4_gateway;
+        let probe_address = config.probe_address.unwrap_or(config.ipv4_gateway);
         let mut connectivity_monitor = connectivity_check::ConnectivityMonitor::new(
-            gateway,
+            probe_address,
             #[cfg(any(target_os = "macos", target_os = "linux"))]
             iface_name.clone(),
             Arc::downgrade(&monitor.tunnel),
             pinger_rx,
+            config.connectivity_profile.timeouts(),
         )
         .map_err(Error::ConnectivityMonitorError)?;
 
-        let metadata = Self::tunnel_metadata(&iface_name, &config);
+        let metadata = Self::tunnel_metadata(&iface_name, &config, backend);
         let tunnel = monitor.tunnel.clone();
+        #[cfg(target_os = "linux")]
+        let mtu_tunnel = monitor.tunnel.clone();
+        let config_update_tunnel = monitor.tunnel.clone();
+        let config_update_rx = args.config_update_rx;
         let obfs_handle = monitor.obfuscator.clone();
         let obfs_close_sender = close_msg_sender.clone();
 
@@ -325,6 +360,16 @@ impl WireguardMonitor {
             .await
             .unwrap()?;
 
+            #[cfg(target_os = "linux")]
+            let metadata = if config.mtu_discovery {
+                TunnelMetadata {
+                    mtu: Self::discover_mtu(mtu_tunnel, &iface_name, &config).await,
+                    ..metadata
+                }
+            } else {
+                metadata
+            };
+
             // Add any default route(s) that may exist.
             args.route_manager
                 .add_routes(Self::get_post_tunnel_routes(&iface_name, &config).collect())
@@ -334,18 +379,26 @@ impl WireguardMonitor {
 
             (on_event)(TunnelEvent::Up(metadata)).await;
 
-            tokio::task::spawn_blocking(move || {
+            let connectivity_fut = tokio::task::spawn_blocking(move || {
                 if let Err(error) = connectivity_monitor.run() {
                     log::error!(
                         "{}",
                         error.display_chain_with_msg("Connectivity monitor failed")
                     );
                 }
+                CloseMsg::PingErr
             })
-            .await
-            .unwrap();
+            .map(Result::unwrap);
+            let config_update_fut =
+                Self::apply_config_updates(config_update_tunnel, config, config_update_rx);
+
+            futures::pin_mut!(connectivity_fut, config_update_fut);
+            let close_msg = futures::future::select(connectivity_fut, config_update_fut)
+                .await
+                .factor_first()
+                .0;
 
-            Err::<Infallible, CloseMsg>(CloseMsg::PingErr)
+            Err::<Infallible, CloseMsg>(close_msg)
         };
 
         let close_sender = close_msg_sender.clone();
@@ -493,6 +546,152 @@ impl WireguardMonitor {
         Ok(())
     }
 
+    /// Applies changes received on `update_rx` to the running tunnel. Resolves if a change
+    /// cannot be applied, or if the relay does not complete a handshake using a new key, so that
+    /// the tunnel is reconnected.
+    async fn apply_config_updates(
+        tunnel: Arc<Mutex<Option<Box<dyn Tunnel>>>>,
+        mut config: Config,
+        mut update_rx: mpsc::UnboundedReceiver<WireguardConfigUpdate>,
+    ) -> CloseMsg {
+        while let Some(update) = update_rx.next().await {
+            let rotated_key = match update {
+                WireguardConfigUpdate::PrivateKey(private_key) => {
+                    log::info!(
+                        "Applying rotated WireGuard key: {}",
+                        private_key.public_key()
+                    );
+                    config.tunnel.private_key = private_key;
+                    true
+                }
+                WireguardConfigUpdate::PersistentKeepalive(interval) => {
+                    log::debug!("Setting WireGuard persistent keepalive to {:?}", interval);
+                    config.persistent_keepalive = interval;
+                    false
+                }
+            };
+            let applied_at = SystemTime::now();
+
+            let set_config_future = tunnel
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(|tunnel| tunnel.set_config(config.clone()));
+            if let Some(f) = set_config_future {
+                if let Err(error) = f.await {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to update WireGuard config")
+                    );
+                    return CloseMsg::PingErr;
+                }
+            }
+
+            if rotated_key {
+                if !Self::wait_for_handshake(&tunnel, applied_at).await {
+                    log::warn!("No handshake after rotating WireGuard key. Reconnecting");
+                    return CloseMsg::PingErr;
+                }
+                log::debug!("Completed handshake using rotated WireGuard key");
+            }
+        }
+        futures::future::pending().await
+    }
+
+    /// Returns whether any peer completes a handshake after `since`, within
+    /// [`KEY_ROTATION_HANDSHAKE_TIMEOUT`].
+    async fn wait_for_handshake(
+        tunnel: &Arc<Mutex<Option<Box<dyn Tunnel>>>>,
+        since: SystemTime,
+    ) -> bool {
+        let poll = async {
+            loop {
+                let stats = match tunnel.lock().unwrap().as_ref() {
+                    Some(tunnel) => tunnel.get_tunnel_stats(),
+                    None => return false,
+                };
+                match stats {
+                    Ok(stats) => {
+                        if stats
+                            .values()
+                            .any(|peer| peer.last_handshake.map_or(false, |time| time >= since))
+                        {
+                            return true;
+                        }
+                    }
+                    Err(error) => {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg("Failed to obtain tunnel stats")
+                        );
+                        return false;
+                    }
+                }
+                tokio::time::sleep(KEY_ROTATION_POLL_INTERVAL).await;
+            }
+        };
+        tokio::time::timeout(KEY_ROTATION_HANDSHAKE_TIMEOUT, poll)
+            .await
+            .unwrap_or(false)
+    }
+
+    /// Finds the largest packet size that passes through the tunnel and lowers the MTU of the
+    /// tunnel interface to it. Failures are logged and leave the MTU unchanged.
+    #[cfg(target_os = "linux")]
+    async fn discover_mtu(
+        tunnel: Arc<Mutex<Option<Box<dyn Tunnel>>>>,
+        iface_name: &str,
+        config: &Config,
+    ) -> Option<u16> {
+        let gateway = config.ipv4_gateway;
+        let max_mtu = config.mtu;
+        let min_mtu = if config.enable_ipv6 {
+            MIN_IPV6_MTU
+        } else {
+            MIN_IPV4_MTU
+        };
+        let interface = iface_name.to_owned();
+        let result = tokio::task::spawn_blocking(move || {
+            mtu_detection::discover_mtu(gateway, &interface, max_mtu, min_mtu)
+        })
+        .await
+        .unwrap();
+
+        let mtu = match result {
+            Ok(Some(mtu)) => mtu,
+            Ok(None) => {
+                log::warn!("Path MTU discovery found no working MTU");
+                return None;
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Path MTU discovery failed")
+                );
+                return None;
+            }
+        };
+        log::info!("Path MTU discovery found an MTU of {}", mtu);
+
+        if mtu < max_mtu {
+            let set_mtu_future = tunnel
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(|tunnel| tunnel.set_mtu(mtu));
+            if let Some(f) = set_mtu_future {
+                if let Err(error) = f.await {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to lower tunnel MTU")
+                    );
+                    return None;
+                }
+            }
+        }
+        Some(mtu)
+    }
+
     #[allow(unused_variables)]
     fn open_tunnel(
         runtime: tokio::runtime::Handle,
@@ -501,41 +700,35 @@ impl WireguardMonitor {
         resource_dir: &Path,
         tun_provider: Arc<Mutex<TunProvider>>,
         #[cfg(windows)] setup_done_tx: mpsc::Sender<std::result::Result<(), BoxedError>>,
-    ) -> Result<Box<dyn Tunnel>> {
+    ) -> Result<(Box<dyn Tunnel>, Option<BackendInfo>)> {
         #[cfg(target_os = "linux")]
-        if !*FORCE_USERSPACE_WIREGUARD {
-            if crate::dns::will_use_nm() {
-                match wireguard_kernel::NetworkManagerTunnel::new(runtime, config) {
-                    Ok(tunnel) => {
-                        log::debug!("Using NetworkManager to use kernel WireGuard implementation");
-                        return Ok(Box::new(tunnel));
-                    }
-                    Err(err) => {
-                        log::error!(
-                            "{}",
-                            err.display_chain_with_msg(
-                                "Failed to initialize WireGuard tunnel via NetworkManager"
-                            )
-                        );
-                    }
-                };
-            } else {
-                match wireguard_kernel::NetlinkTunnel::new(runtime, config) {
-                    Ok(tunnel) => {
-                        log::debug!("Using kernel WireGuard implementation");
-                        return Ok(Box::new(tunnel));
-                    }
-                    Err(error) => {
-                        log::error!(
-                            "{}",
-                            error.display_chain_with_msg(
-                                "Failed to setup kernel WireGuard device, falling back to the userspace implementation"
-                            )
-                        );
-                    }
-                };
+        let reason = {
+            let (preferred, reason) = Self::preferred_backend(config);
+            match Self::open_kernel_tunnel(runtime, config, preferred) {
+                None => reason,
+                Some(Ok(tunnel)) => {
+                    log::debug!("Using {} WireGuard backend", preferred);
+                    return Ok((
+                        tunnel,
+                        Some(BackendInfo {
+                            backend: preferred,
+                            reason,
+                        }),
+                    ));
+                }
+                Some(Err(error)) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg(&format!(
+                            "Failed to set up WireGuard device using the {} backend, \
+                             falling back to the userspace implementation",
+                            preferred
+                        ))
+                    );
+                    BackendReason::Fallback(preferred)
+                }
             }
-        }
+        };
 
         #[cfg(target_os = "windows")]
         if config.use_wireguard_nt {
@@ -547,7 +740,7 @@ impl WireguardMonitor {
             ) {
                 Ok(tunnel) => {
                     log::debug!("Using WireGuardNT");
-                    return Ok(Box::new(tunnel));
+                    return Ok((Box::new(tunnel), None));
                 }
                 Err(error) => {
                     log::error!(
@@ -560,19 +753,64 @@ impl WireguardMonitor {
 
         #[cfg(any(target_os = "linux", windows))]
         log::debug!("Using userspace WireGuard implementation");
-        Ok(Box::new(
-            WgGoTunnel::start_tunnel(
-                config,
-                log_path,
-                #[cfg(not(windows))]
-                tun_provider,
-                #[cfg(not(windows))]
-                Self::get_tunnel_destinations(config).flat_map(Self::replace_default_prefixes),
-                #[cfg(windows)]
-                setup_done_tx,
-            )
-            .map_err(Error::TunnelError)?,
-        ))
+        let tunnel = WgGoTunnel::start_tunnel(
+            config,
+            log_path,
+            #[cfg(not(windows))]
+            tun_provider,
+            #[cfg(not(windows))]
+            Self::get_tunnel_destinations(config).flat_map(Self::replace_default_prefixes),
+            #[cfg(windows)]
+            setup_done_tx,
+        )
+        .map_err(Error::TunnelError)?;
+
+        #[cfg(target_os = "linux")]
+        let backend = Some(BackendInfo {
+            backend: Backend::Userspace,
+            reason,
+        });
+        #[cfg(not(target_os = "linux"))]
+        let backend = None;
+        Ok((Box::new(tunnel), backend))
+    }
+
+    /// Returns the WireGuard backend to try first, and why.
+    #[cfg(target_os = "linux")]
+    fn preferred_backend(config: &Config) -> (Backend, BackendReason) {
+        if *FORCE_USERSPACE_WIREGUARD {
+            log::debug!("Userspace WireGuard is forced by TALPID_FORCE_USERSPACE_WIREGUARD");
+            return (Backend::Userspace, BackendReason::Forced);
+        }
+        match config.backend {
+            Some(backend) => (backend, BackendReason::Selected),
+            None if crate::dns::will_use_nm() => {
+                (Backend::NetworkManager, BackendReason::Automatic)
+            }
+            None => (Backend::Kernel, BackendReason::Automatic),
+        }
+    }
+
+    /// Creates a tunnel device using the kernel module, either directly or through
+    /// NetworkManager. Returns `None` for the userspace backend, which does not use the kernel
+    /// module.
+    #[cfg(target_os = "linux")]
+    fn open_kernel_tunnel(
+        runtime: tokio::runtime::Handle,
+        config: &Config,
+        backend: Backend,
+    ) -> Option<std::result::Result<Box<dyn Tunnel>, wireguard_kernel::Error>> {
+        match backend {
+            Backend::Kernel => Some(
+                wireguard_kernel::NetlinkTunnel::new(runtime, config)
+                    .map(|tunnel| Box::new(tunnel) as Box<dyn Tunnel>),
+            ),
+            Backend::NetworkManager => Some(
+                wireguard_kernel::NetworkManagerTunnel::new(runtime, config)
+                    .map(|tunnel| Box::new(tunnel) as Box<dyn Tunnel>),
+            ),
+            Backend::Userspace => None,
+        }
     }
 
     /// Blocks the current thread until tunnel disconnects
@@ -724,12 +962,18 @@ impl WireguardMonitor {
         vec![network]
     }
 
-    fn tunnel_metadata(interface_name: &str, config: &Config) -> TunnelMetadata {
+    fn tunnel_metadata(
+        interface_name: &str,
+        config: &Config,
+        backend: Option<BackendInfo>,
+    ) -> TunnelMetadata {
         TunnelMetadata {
             interface: interface_name.to_string(),
             ips: config.tunnel.addresses.clone(),
             ipv4_gateway: config.ipv4_gateway,
             ipv6_gateway: config.ipv6_gateway,
+            mtu: None,
+            wireguard_backend: backend,
         }
     }
 }
@@ -751,6 +995,18 @@ pub(crate) trait Tunnel: Send {
         &self,
         _config: Config,
     ) -> Pin<Box<dyn Future<Output = std::result::Result<(), TunnelError>> + Send>>;
+    #[cfg(target_os = "linux")]
+    fn set_mtu(
+        &self,
+        mtu: u16,
+    ) -> Pin<Box<dyn Future<Output = std::result::Result<(), TunnelError>> + Send>> {
+        let interface = self.get_interface_name();
+        Box::pin(async move {
+            crate::linux::set_mtu(&interface, mtu)
+                .await
+                .map_err(TunnelError::SetMtuError)
+        })
+    }
 }
 
 /// Errors to be returned from WireGuard implementations, namely implementers of the Tunnel trait
@@ -791,6 +1047,11 @@ pub enum TunnelError {
     #[error(display = "Failed to set config of WireGuard tunnel")]
     SetConfigError,
 
+    /// Failed to change the MTU of the tunnel interface
+    #[cfg(target_os = "linux")]
+    #[error(display = "Failed to set MTU of WireGuard tunnel")]
+    SetMtuError(#[error(source)] crate::linux::SetMtuError),
+
     /// Failed to duplicate tunnel file descriptor for wireguard-go
     #[cfg(any(target_os = "linux", target_os = "macos", target_os = "android"))]
     #[error(display = "Failed to duplicate tunnel file descriptor for wireguard-go")]
@@ -0,0 +1,242 @@
+//! Path MTU discovery through a WireGuard tunnel.
+//!
+//! ICMP echo requests with the "don't fragment" bit set are sent to the tunnel gateway, starting
+//! at the configured MTU and decreasing in size until the gateway replies. The size of the first
+//! packet that makes it through is the path MTU.
+
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    io,
+    mem::MaybeUninit,
+    net::{Ipv4Addr, SocketAddr},
+    os::unix::io::AsRawFd,
+    time::{Duration, Instant},
+};
+
+/// Size of an IPv4 header without options.
+const IPV4_HEADER_SIZE: u16 = 20;
+/// Size of an ICMP echo header.
+const ICMP_HEADER_SIZE: u16 = 8;
+/// Amount by which the probe size is lowered after each failed attempt.
+const PROBE_STEP: u16 = 20;
+/// Number of echo requests sent for each packet size.
+const PROBES_PER_SIZE: usize = 2;
+/// Time to wait for a reply to a single echo request.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_ECHO_REPLY: u8 = 0;
+
+/// Path MTU discovery errors
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Failed to open raw socket
+    #[error(display = "Failed to open ICMP socket")]
+    OpenError(#[error(source)] io::Error),
+
+    /// Failed to set socket options
+    #[error(display = "Failed to set socket options")]
+    SocketOptError(#[error(source)] io::Error),
+
+    /// Failed to write to raw socket
+    #[error(display = "Failed to write to socket")]
+    WriteError(#[error(source)] io::Error),
+
+    /// Failed to read from raw socket
+    #[error(display = "Failed to read ICMP socket")]
+    ReadError(#[error(source)] io::Error),
+}
+
+/// Returns the largest MTU in `min_mtu..=max_mtu` for which an unfragmented packet reaches
+/// `gateway` through `interface`, or `None` if not even `min_mtu` works.
+pub fn discover_mtu(
+    gateway: Ipv4Addr,
+    interface: &str,
+    max_mtu: u16,
+    min_mtu: u16,
+) -> Result<Option<u16>, Error> {
+    let mut prober = Prober::new(gateway, interface)?;
+    for mtu in probe_sizes(max_mtu, min_mtu) {
+        if prober.probe(mtu)? {
+            return Ok(Some(mtu));
+        }
+        log::trace!("No reply to MTU probe of size {}", mtu);
+    }
+    Ok(None)
+}
+
+/// Returns the packet sizes to try, in the order they should be tried.
+fn probe_sizes(max_mtu: u16, min_mtu: u16) -> impl Iterator<Item = u16> {
+    std::iter::successors(Some(max_mtu), |mtu| mtu.checked_sub(PROBE_STEP))
+        .take_while(move |mtu| *mtu > min_mtu)
+        .chain(std::iter::once(min_mtu))
+}
+
+struct Prober {
+    sock: Socket,
+    addr: SocketAddr,
+    id: u16,
+    seq: u16,
+}
+
+impl Prober {
+    fn new(gateway: Ipv4Addr, interface: &str) -> Result<Self, Error> {
+        let sock = Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))
+            .map_err(Error::OpenError)?;
+        sock.bind_device(Some(interface.as_bytes()))
+            .map_err(Error::SocketOptError)?;
+        set_dont_fragment(&sock).map_err(Error::SocketOptError)?;
+
+        Ok(Self {
+            sock,
+            addr: SocketAddr::new(gateway.into(), 0),
+            id: rand::random(),
+            seq: 0,
+        })
+    }
+
+    /// Returns whether an IP packet of `mtu` bytes got a reply from the gateway.
+    fn probe(&mut self, mtu: u16) -> Result<bool, Error> {
+        let icmp_size = mtu.saturating_sub(IPV4_HEADER_SIZE);
+        for _ in 0..PROBES_PER_SIZE {
+            let seq = self.seq;
+            self.seq = self.seq.wrapping_add(1);
+
+            let request = echo_request(self.id, seq, usize::from(icmp_size));
+            match self.sock.send_to(&request, &self.addr.into()) {
+                Ok(_) => (),
+                // The packet is larger than the MTU known by the kernel.
+                Err(error) if error.raw_os_error() == Some(libc::EMSGSIZE) => return Ok(false),
+                Err(error) => return Err(Error::WriteError(error)),
+            }
+            if self.wait_for_reply(self.id, seq, icmp_size)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn wait_for_reply(&self, id: u16, seq: u16, icmp_size: u16) -> Result<bool, Error> {
+        let deadline = Instant::now() + PROBE_TIMEOUT;
+        let mut buffer = vec![MaybeUninit::<u8>::uninit(); usize::from(u16::MAX)];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(false);
+            }
+            self.sock
+                .set_read_timeout(Some(remaining))
+                .map_err(Error::SocketOptError)?;
+            let len = match self.sock.recv(&mut buffer) {
+                Ok(len) => len,
+                Err(error)
+                    if error.kind() == io::ErrorKind::WouldBlock
+                        || error.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Ok(false);
+                }
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(Error::ReadError(error)),
+            };
+            // SAFETY: `recv` initialized the first `len` bytes.
+            let packet = unsafe { std::slice::from_raw_parts(buffer.as_ptr() as *const u8, len) };
+            if is_echo_reply(packet, id, seq, icmp_size) {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+/// Sets `IP_PMTUDISC_DO` so that the kernel never fragments the probes.
+fn set_dont_fragment(sock: &Socket) -> io::Result<()> {
+    let value: libc::c_int = libc::IP_PMTUDISC_DO;
+    let result = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            &value as *const _ as *const libc::c_void,
+            std::mem::size_of_val(&value) as libc::socklen_t,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Constructs an ICMP echo request of `size` bytes, including the ICMP header.
+fn echo_request(id: u16, seq: u16, size: usize) -> Vec<u8> {
+    let mut packet = vec![0u8; size.max(usize::from(ICMP_HEADER_SIZE))];
+    packet[0] = ICMP_ECHO_REQUEST;
+    packet[4..6].copy_from_slice(&id.to_be_bytes());
+    packet[6..8].copy_from_slice(&seq.to_be_bytes());
+    let checksum = internet_checksum::checksum(&packet);
+    packet[2..4].copy_from_slice(&checksum);
+    packet
+}
+
+/// Returns whether `packet`, an IPv4 packet read from a raw socket, is the reply to the echo
+/// request with the given ID, sequence number, and size.
+fn is_echo_reply(packet: &[u8], id: u16, seq: u16, icmp_size: u16) -> bool {
+    let header_len = match packet.first() {
+        Some(first) => usize::from(first & 0x0f) * 4,
+        None => return false,
+    };
+    let icmp = match packet.get(header_len..) {
+        Some(icmp) if icmp.len() >= usize::from(ICMP_HEADER_SIZE) => icmp,
+        _ => return false,
+    };
+    icmp[0] == ICMP_ECHO_REPLY
+        && icmp[4..6] == id.to_be_bytes()
+        && icmp[6..8] == seq.to_be_bytes()
+        && icmp.len() == usize::from(icmp_size)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_probe_sizes() {
+        let sizes: Vec<_> = probe_sizes(1380, 1300).collect();
+        assert_eq!(sizes, vec![1380, 1360, 1340, 1320, 1300]);
+
+        let sizes: Vec<_> = probe_sizes(1380, 1290).collect();
+        assert_eq!(sizes, vec![1380, 1360, 1340, 1320, 1300, 1290]);
+
+        let sizes: Vec<_> = probe_sizes(1280, 1280).collect();
+        assert_eq!(sizes, vec![1280]);
+    }
+
+    #[test]
+    fn test_echo_request() {
+        let packet = echo_request(0x1dcd, 0x0001, 64);
+        assert_eq!(packet.len(), 64);
+        assert_eq!(
+            &packet[..8],
+            &[0x08, 0x00, 0xda, 0x31, 0x1d, 0xcd, 0x00, 0x01]
+        );
+        assert_eq!(internet_checksum::checksum(&packet), [0, 0]);
+    }
+
+    #[test]
+    fn test_is_echo_reply() {
+        let mut reply = vec![0u8; 20];
+        reply[0] = 0x45;
+        let mut icmp = echo_request(0x1dcd, 0x0001, 64);
+        icmp[0] = ICMP_ECHO_REPLY;
+        reply.extend_from_slice(&icmp);
+
+        assert!(is_echo_reply(&reply, 0x1dcd, 0x0001, 64));
+        assert!(!is_echo_reply(&reply, 0x1dcd, 0x0002, 64));
+        assert!(!is_echo_reply(&reply, 0x1dce, 0x0001, 64));
+        assert!(!is_echo_reply(&reply, 0x1dcd, 0x0001, 1000));
+        assert!(!is_echo_reply(&reply[..24], 0x1dcd, 0x0001, 64));
+
+        reply[20] = ICMP_ECHO_REQUEST;
+        assert!(!is_echo_reply(&reply, 0x1dcd, 0x0001, 64));
+    }
+}
@@ -74,7 +74,7 @@ pub fn parse_timespec(buffer: &[u8]) -> Result<TimeSpec, DecodeError> {
     Ok(TimeSpec::from(libc::timespec {
         tv_sec: NativeEndian::read_i64(buffer),
         // TODO: become compatible with 32-bit systems maybe?
-        tv_nsec: NativeEndian::read_i64(buffer),
+        tv_nsec: NativeEndian::read_i64(&buffer[8..]),
     }))
 }
 
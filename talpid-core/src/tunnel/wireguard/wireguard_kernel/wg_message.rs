@@ -90,6 +90,9 @@ impl DeviceMessage {
             if let Some(psk) = peer.psk.as_ref() {
                 peer_nlas.push(PeerNla::PresharedKey(*psk.as_bytes()));
             }
+            if let Some(interval) = config.persistent_keepalive {
+                peer_nlas.push(PeerNla::PersistentKeepaliveInterval(interval));
+            }
             peers.push(PeerMessage(peer_nlas));
         }
 
@@ -175,6 +175,9 @@ pub struct WgNtTunnel {
 
 const WIREGUARD_KEY_LENGTH: usize = 32;
 
+/// Number of 100 ns intervals between 1601-01-01, the epoch used by `FILETIME`, and the Unix epoch.
+const FILETIME_UNIX_EPOCH: u64 = 116_444_736_000_000_000;
+
 /// See `WIREGUARD_ALLOWED_IP` at https://git.zx2c4.com/wireguard-nt/tree/api/wireguard.h.
 #[derive(Clone, Copy)]
 #[repr(C, align(8))]
@@ -814,6 +817,16 @@ fn load_wg_nt_dll(resource_dir: &Path) -> Result<Arc<WgNtDll>> {
     }
 }
 
+/// Converts a handshake time given in 100 ns intervals since 1601-01-01 (`FILETIME`). Zero means
+/// that no handshake has taken place.
+fn handshake_from_filetime(filetime: u64) -> Option<std::time::SystemTime> {
+    let unix_time = filetime.checked_sub(FILETIME_UNIX_EPOCH)?;
+    super::stats::handshake_time(
+        unix_time / 10_000_000,
+        (unix_time % 10_000_000) as u32 * 100,
+    )
+}
+
 fn serialize_config(config: &Config) -> Result<Vec<MaybeUninit<u8>>> {
     let mut buffer = vec![];
 
@@ -828,11 +841,14 @@ fn serialize_config(config: &Config) -> Result<Vec<MaybeUninit<u8>>> {
     buffer.extend(windows::as_uninit_byte_slice(&header));
 
     for peer in &config.peers {
-        let flags = if peer.psk.is_some() {
+        let mut flags = if peer.psk.is_some() {
             WgPeerFlag::HAS_PRESHARED_KEY | WgPeerFlag::HAS_PUBLIC_KEY | WgPeerFlag::HAS_ENDPOINT
         } else {
             WgPeerFlag::HAS_PUBLIC_KEY | WgPeerFlag::HAS_ENDPOINT
         };
+        if config.persistent_keepalive.is_some() {
+            flags |= WgPeerFlag::HAS_PERSISTENT_KEEPALIVE;
+        }
         let wg_peer = WgPeer {
             flags,
             reserved: 0,
@@ -842,7 +858,7 @@ fn serialize_config(config: &Config) -> Result<Vec<MaybeUninit<u8>>> {
                 .as_ref()
                 .map(|psk| psk.as_bytes().clone())
                 .unwrap_or([0u8; WIREGUARD_KEY_LENGTH]),
-            persistent_keepalive: 0,
+            persistent_keepalive: config.persistent_keepalive.unwrap_or(0),
             endpoint: windows::inet_sockaddr_from_socketaddr(peer.endpoint).into(),
             tx_bytes: 0,
             rx_bytes: 0,
@@ -952,6 +968,7 @@ impl Tunnel for WgNtTunnel {
                     Stats {
                         tx_bytes: peer.tx_bytes,
                         rx_bytes: peer.rx_bytes,
+                        last_handshake: handshake_from_filetime(peer.last_handshake),
                     },
                 );
             }
@@ -1020,6 +1037,9 @@ mod tests {
                 ipv4_gateway: "0.0.0.0".parse().unwrap(),
                 ipv6_gateway: None,
                 mtu: 0,
+                persistent_keepalive: None,
+                connectivity_profile: Default::default(),
+                probe_address: None,
                 use_wireguard_nt: true,
                 obfuscator_config: None,
             }
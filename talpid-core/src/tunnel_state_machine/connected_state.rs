@@ -1,19 +1,25 @@
 use super::{
     AfterDisconnect, ConnectingState, DisconnectingState, ErrorState, EventConsequence,
-    EventResult, SharedTunnelStateValues, TunnelCommand, TunnelCommandReceiver, TunnelState,
+    SharedTunnelStateValues, TunnelCommand, TunnelCommandReceiver, TunnelState,
     TunnelStateTransition, TunnelStateWrapper,
 };
+#[cfg(target_os = "linux")]
+use crate::resolver::{self, ForwardingConfig};
 use crate::{
     firewall::FirewallPolicy,
-    tunnel::{TunnelEvent, TunnelMetadata},
+    tunnel::{TunnelEvent, TunnelMetadata, WireguardConfigUpdate},
 };
 use cfg_if::cfg_if;
 use futures::{
     channel::{mpsc, oneshot},
     stream::Fuse,
-    StreamExt,
+    FutureExt, StreamExt,
 };
 use std::net::IpAddr;
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+#[cfg(target_os = "linux")]
+use talpid_types::net::NetworkChange;
 use talpid_types::{
     net::TunnelParameters,
     tunnel::{ErrorStateCause, FirewallPolicyError},
@@ -25,6 +31,11 @@ use crate::tunnel::TunnelMonitor;
 
 use super::connecting_state::TunnelCloseEvent;
 
+/// How long a WireGuard tunnel is kept up while the host is offline, in case it comes back online
+/// on the same or another network.
+#[cfg(target_os = "linux")]
+const OFFLINE_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
 pub(crate) type TunnelEventsReceiver =
     Fuse<mpsc::UnboundedReceiver<(TunnelEvent, oneshot::Sender<()>)>>;
 
@@ -34,6 +45,17 @@ pub struct ConnectedStateBootstrap {
     pub tunnel_parameters: TunnelParameters,
     pub tunnel_close_event: TunnelCloseEvent,
     pub tunnel_close_tx: oneshot::Sender<()>,
+    pub config_update_tx: mpsc::UnboundedSender<WireguardConfigUpdate>,
+}
+
+/// Like [`super::EventResult`], but the connected state also waits for the host to come back
+/// online.
+enum ConnectedEventResult {
+    Command(Option<TunnelCommand>),
+    Event(Option<(TunnelEvent, oneshot::Sender<()>)>),
+    Close(Result<Option<ErrorStateCause>, oneshot::Canceled>),
+    /// The host has been offline for longer than the tunnel is kept up for it to come back.
+    OfflineTimeout,
 }
 
 /// The tunnel is up and working.
@@ -43,6 +65,49 @@ pub struct ConnectedState {
     tunnel_parameters: TunnelParameters,
     tunnel_close_event: TunnelCloseEvent,
     tunnel_close_tx: oneshot::Sender<()>,
+    /// Sends changes to apply to the running WireGuard tunnel to the tunnel monitor.
+    config_update_tx: mpsc::UnboundedSender<WireguardConfigUpdate>,
+    offline_grace: OfflineGracePeriod,
+}
+
+/// Tracks how long the tunnel is kept up while the host is offline.
+#[derive(Default)]
+struct OfflineGracePeriod {
+    /// When to give up on the host coming back online and block, if it is offline.
+    deadline: Option<tokio::time::Instant>,
+}
+
+impl OfflineGracePeriod {
+    /// Starts the grace period. Returns `false` if it was already running.
+    #[cfg(target_os = "linux")]
+    fn start(&mut self) -> bool {
+        if self.deadline.is_some() {
+            return false;
+        }
+        self.deadline = Some(tokio::time::Instant::now() + OFFLINE_GRACE_PERIOD);
+        true
+    }
+
+    /// Stops the grace period. Returns `false` if it was not running.
+    fn stop(&mut self) -> bool {
+        self.deadline.take().is_some()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn is_running(&self) -> bool {
+        self.deadline.is_some()
+    }
+
+    /// Resolves when the grace period runs out, or never if it is not running.
+    fn expired(&self) -> impl std::future::Future<Output = ()> {
+        let deadline = self.deadline;
+        async move {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => futures::future::pending().await,
+            }
+        }
+    }
 }
 
 impl ConnectedState {
@@ -53,6 +118,8 @@ impl ConnectedState {
             tunnel_parameters: bootstrap.tunnel_parameters,
             tunnel_close_event: bootstrap.tunnel_close_event,
             tunnel_close_tx: bootstrap.tunnel_close_tx,
+            config_update_tx: bootstrap.config_update_tx,
+            offline_grace: OfflineGracePeriod::default(),
         }
     }
 
@@ -104,13 +171,61 @@ impl ConnectedState {
         }
     }
 
+    /// Returns the servers that the firewall should allow DNS queries to. When the stub resolver
+    /// is used, this includes the resolvers of all split DNS rules. Encrypted resolvers are not
+    /// included, since they are not reached over plain DNS, and neither are the regular servers
+    /// unless encrypted DNS may fall back to them.
+    #[cfg(target_os = "linux")]
+    fn get_firewall_dns_servers(&self, shared_values: &SharedTunnelStateValues) -> Vec<IpAddr> {
+        let mut dns_servers = if shared_values.encrypted_dns_servers.is_empty()
+            || shared_values.encrypted_dns_fallback
+        {
+            self.get_dns_servers(shared_values)
+        } else {
+            vec![]
+        };
+        for rule in &shared_values.split_dns_rules {
+            for server in &rule.servers {
+                if !dns_servers.contains(server) {
+                    dns_servers.push(*server);
+                }
+            }
+        }
+        dns_servers
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "linux")))]
+    fn get_firewall_dns_servers(&self, shared_values: &SharedTunnelStateValues) -> Vec<IpAddr> {
+        self.get_dns_servers(shared_values)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn uses_dns_stub(shared_values: &SharedTunnelStateValues) -> bool {
+        !shared_values.split_dns_rules.is_empty()
+            || !shared_values.encrypted_dns_servers.is_empty()
+            || !shared_values.local_dns_rules.is_empty()
+            || shared_values.dns_query_log.is_enabled()
+    }
+
     fn get_firewall_policy(&self, shared_values: &SharedTunnelStateValues) -> FirewallPolicy {
         FirewallPolicy::Connected {
             peer_endpoint: self.tunnel_parameters.get_next_hop_endpoint(),
             tunnel: self.metadata.clone(),
             allow_lan: shared_values.allow_lan,
             #[cfg(not(target_os = "android"))]
-            dns_servers: self.get_dns_servers(shared_values),
+            dns_servers: self.get_firewall_dns_servers(shared_values),
+            #[cfg(target_os = "linux")]
+            dns_stub: Self::uses_dns_stub(shared_values),
+            #[cfg(target_os = "linux")]
+            additional_peer_endpoints: self.tunnel_parameters.get_additional_endpoints(),
+            #[cfg(target_os = "linux")]
+            untunneled_ip_versions: self.tunnel_parameters.get_untunneled_ip_versions(),
+            #[cfg(target_os = "linux")]
+            forwarded_interfaces: shared_values.forwarded_interfaces.clone(),
+            #[cfg(target_os = "linux")]
+            ipv6_mode: shared_values.ipv6_firewall_mode,
+            #[cfg(target_os = "linux")]
+            log_drops: shared_values.log_firewall_drops,
             #[cfg(windows)]
             relay_client: TunnelMonitor::get_relay_client(
                 &shared_values.resource_dir,
@@ -122,6 +237,15 @@ impl ConnectedState {
     fn set_dns(&self, shared_values: &mut SharedTunnelStateValues) -> Result<(), BoxedError> {
         let dns_ips = self.get_dns_servers(shared_values);
 
+        #[cfg(target_os = "linux")]
+        if Self::uses_dns_stub(shared_values) {
+            return self.set_dns_stub(shared_values, dns_ips);
+        }
+        #[cfg(target_os = "linux")]
+        {
+            shared_values.dns_stub = None;
+        }
+
         #[cfg(any(target_os = "linux", target_os = "windows"))]
         let dns_ips = dns_ips
             .into_iter()
@@ -140,10 +264,58 @@ impl ConnectedState {
         Ok(())
     }
 
+    /// Points the system at the stub resolver, which forwards queries to `default_servers`, the
+    /// encrypted resolvers, or the resolvers of a matching split DNS rule, unless the name is
+    /// blocked or overridden locally. The stub is started if it isn't running.
+    #[cfg(target_os = "linux")]
+    fn set_dns_stub(
+        &self,
+        shared_values: &mut SharedTunnelStateValues,
+        default_servers: Vec<IpAddr>,
+    ) -> Result<(), BoxedError> {
+        let runtime = shared_values.runtime.clone();
+        let dns_stub = match &shared_values.dns_stub {
+            Some(dns_stub) => dns_stub.clone(),
+            None => {
+                let dns_stub = runtime
+                    .block_on(resolver::start_stub_resolver(
+                        shared_values.dns_query_log.clone(),
+                        shared_values.dns_fallback_listeners.clone(),
+                    ))
+                    .map_err(BoxedError::new)?;
+                shared_values.dns_stub = Some(dns_stub.clone());
+                dns_stub
+            }
+        };
+
+        let config = ForwardingConfig {
+            default_servers,
+            encrypted_servers: shared_values.encrypted_dns_servers.clone(),
+            encrypted_fallback: shared_values.encrypted_dns_fallback,
+            split_rules: shared_values.split_dns_rules.clone(),
+            local_rules: shared_values.local_dns_rules.clone(),
+        };
+        runtime
+            .block_on(dns_stub.set_forwarding(Some(config)))
+            .map_err(BoxedError::new)?;
+
+        shared_values
+            .dns_monitor
+            .set(
+                &self.metadata.interface,
+                &[resolver::STUB_RESOLVER_ADDRESS.into()],
+            )
+            .map_err(BoxedError::new)
+    }
+
     fn reset_dns(shared_values: &mut SharedTunnelStateValues) {
         if let Err(error) = shared_values.dns_monitor.reset() {
             log::error!("{}", error.display_chain_with_msg("Unable to reset DNS"));
         }
+        #[cfg(target_os = "linux")]
+        {
+            shared_values.dns_stub = None;
+        }
     }
 
     fn reset_routes(shared_values: &mut SharedTunnelStateValues) {
@@ -180,8 +352,63 @@ impl ConnectedState {
         ))
     }
 
-    fn handle_commands(
+    /// Applies changes to the stub resolver configuration to the firewall and DNS config.
+    #[cfg(target_os = "linux")]
+    fn update_dns_stub(self, shared_values: &mut SharedTunnelStateValues) -> EventConsequence {
+        if let Err(error) = self.set_firewall_policy(shared_values) {
+            return self.disconnect(
+                shared_values,
+                AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+            );
+        }
+        match self.set_dns(shared_values) {
+            Ok(()) => EventConsequence::SameState(self.into()),
+            Err(error) => {
+                log::error!("{}", error.display_chain_with_msg("Failed to set DNS"));
+                self.disconnect(
+                    shared_values,
+                    AfterDisconnect::Block(ErrorStateCause::SetDnsError),
+                )
+            }
+        }
+    }
+
+    /// Returns whether the tunnel can be kept up when the host changes networks. WireGuard picks
+    /// a new source address for the relay endpoint by itself, so only the firewall policy has to
+    /// be refreshed.
+    #[cfg(target_os = "linux")]
+    fn can_roam(&self) -> bool {
+        matches!(self.tunnel_parameters, TunnelParameters::Wireguard(_))
+    }
+
+    /// Refreshes the firewall policy after the default route changed, keeping the tunnel up,
+    /// and notifies the network change listeners. Routes through the tunnel do not depend on the
+    /// default route, since traffic to the relay is routed using a firewall mark.
+    #[cfg(target_os = "linux")]
+    fn handle_network_change(
         self,
+        interface: Option<String>,
+        was_offline: bool,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> EventConsequence {
+        if let Err(error) = self.set_firewall_policy(shared_values) {
+            return self.disconnect(
+                shared_values,
+                AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+            );
+        }
+
+        let change = NetworkChange {
+            interface,
+            was_offline,
+        };
+        log::info!("{}", change);
+        shared_values.network_change_listeners.notify(change);
+        EventConsequence::SameState(self.into())
+    }
+
+    fn handle_commands(
+        mut self,
         command: Option<TunnelCommand>,
         shared_values: &mut SharedTunnelStateValues,
     ) -> EventConsequence {
@@ -242,10 +469,99 @@ impl ConnectedState {
                     self.disconnect(shared_values, AfterDisconnect::Block(error_cause))
                 }
             },
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::BlockedStateDns(servers)) => {
+                shared_values.blocked_state_dns_servers = servers;
+                SameState(self.into())
+            }
             Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self.into())
             }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::Ipv6FirewallMode(mode)) => {
+                shared_values.ipv6_firewall_mode = mode;
+                match self.set_firewall_policy(shared_values) {
+                    Ok(()) => SameState(self.into()),
+                    Err(error) => self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                    ),
+                }
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::LogFirewallDrops(enabled)) => {
+                shared_values.log_firewall_drops = enabled;
+                match self.set_firewall_policy(shared_values) {
+                    Ok(()) => SameState(self.into()),
+                    Err(error) => self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                    ),
+                }
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SplitDns(rules)) => {
+                if shared_values.split_dns_rules == rules {
+                    return SameState(self.into());
+                }
+                shared_values.split_dns_rules = rules;
+                self.update_dns_stub(shared_values)
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::EncryptedDns(servers)) => {
+                if shared_values.encrypted_dns_servers == servers {
+                    return SameState(self.into());
+                }
+                shared_values.encrypted_dns_servers = servers;
+                self.update_dns_stub(shared_values)
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::EncryptedDnsFallback(fallback)) => {
+                if shared_values.encrypted_dns_fallback == fallback {
+                    return SameState(self.into());
+                }
+                shared_values.encrypted_dns_fallback = fallback;
+                self.update_dns_stub(shared_values)
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::LocalDnsRules(rules)) => {
+                if shared_values.local_dns_rules == rules {
+                    return SameState(self.into());
+                }
+                shared_values.local_dns_rules = rules;
+                self.update_dns_stub(shared_values)
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::DnsQueryLog(enabled)) => {
+                if shared_values.dns_query_log.is_enabled() == enabled {
+                    return SameState(self.into());
+                }
+                shared_values.dns_query_log.set_enabled(enabled);
+                self.update_dns_stub(shared_values)
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::ForwardedInterfaces(interfaces)) => {
+                shared_values.forwarded_interfaces = interfaces;
+                match self.set_firewall_policy(shared_values) {
+                    Ok(()) => SameState(self.into()),
+                    Err(error) => self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                    ),
+                }
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::IsOffline(true)) if self.can_roam() => {
+                shared_values.is_offline = true;
+                if self.offline_grace.start() {
+                    log::info!(
+                        "Host is offline. Keeping the tunnel up for {} seconds",
+                        OFFLINE_GRACE_PERIOD.as_secs()
+                    );
+                }
+                SameState(self.into())
+            }
             Some(TunnelCommand::IsOffline(is_offline)) => {
                 shared_values.is_offline = is_offline;
                 if is_offline {
@@ -253,10 +569,66 @@ impl ConnectedState {
                         shared_values,
                         AfterDisconnect::Block(ErrorStateCause::IsOffline),
                     )
+                } else if self.offline_grace.stop() {
+                    cfg_if! {
+                        if #[cfg(target_os = "linux")] {
+                            self.handle_network_change(None, true, shared_values)
+                        } else {
+                            SameState(self.into())
+                        }
+                    }
                 } else {
                     SameState(self.into())
                 }
             }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::NetworkChanged(interface)) => {
+                if self.can_roam() && !self.offline_grace.is_running() {
+                    self.handle_network_change(interface, false, shared_values)
+                } else {
+                    SameState(self.into())
+                }
+            }
+            Some(TunnelCommand::RotateWireguardKey(private_key)) => {
+                let params = match &mut self.tunnel_parameters {
+                    TunnelParameters::Wireguard(params) => params,
+                    _ => return SameState(self.into()),
+                };
+                if params.connection.tunnel.private_key == private_key {
+                    return SameState(self.into());
+                }
+                if params.options.use_pq_safe_psk {
+                    // The tunnel uses an ephemeral key that was negotiated using the old key.
+                    return self.disconnect(shared_values, AfterDisconnect::Reconnect(0));
+                }
+                params.connection.tunnel.private_key = private_key.clone();
+                if self
+                    .config_update_tx
+                    .unbounded_send(WireguardConfigUpdate::PrivateKey(private_key))
+                    .is_err()
+                {
+                    log::warn!("Tunnel monitor stopped before receiving rotated key");
+                }
+                SameState(self.into())
+            }
+            Some(TunnelCommand::SetWireguardPersistentKeepalive(interval)) => {
+                let params = match &mut self.tunnel_parameters {
+                    TunnelParameters::Wireguard(params) => params,
+                    _ => return SameState(self.into()),
+                };
+                if params.options.persistent_keepalive == interval {
+                    return SameState(self.into());
+                }
+                params.options.persistent_keepalive = interval;
+                if self
+                    .config_update_tx
+                    .unbounded_send(WireguardConfigUpdate::PersistentKeepalive(interval))
+                    .is_err()
+                {
+                    log::warn!("Tunnel monitor stopped before receiving keepalive interval");
+                }
+                SameState(self.into())
+            }
             Some(TunnelCommand::Connect) => {
                 self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
             }
@@ -323,7 +695,9 @@ impl TunnelState for ConnectedState {
         bootstrap: Self::Bootstrap,
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
         let connected_state = ConnectedState::from(bootstrap);
-        let tunnel_endpoint = connected_state.tunnel_parameters.get_tunnel_endpoint();
+        let mut tunnel_endpoint = connected_state.tunnel_parameters.get_tunnel_endpoint();
+        tunnel_endpoint.mtu = connected_state.metadata.mtu;
+        tunnel_endpoint.wireguard_backend = connected_state.metadata.wireguard_backend;
 
         if let Err(error) = connected_state.set_firewall_policy(shared_values) {
             DisconnectingState::enter(
@@ -358,24 +732,73 @@ impl TunnelState for ConnectedState {
         commands: &mut TunnelCommandReceiver,
         shared_values: &mut SharedTunnelStateValues,
     ) -> EventConsequence {
+        let offline_timeout = self.offline_grace.expired();
         let result = runtime.block_on(async {
+            let offline_timeout = offline_timeout.fuse();
+            futures::pin_mut!(offline_timeout);
+
             futures::select! {
-                command = commands.next() => EventResult::Command(command),
-                event = self.tunnel_events.next() => EventResult::Event(event),
-                result = &mut self.tunnel_close_event => EventResult::Close(result),
+                command = commands.next() => ConnectedEventResult::Command(command),
+                event = self.tunnel_events.next() => ConnectedEventResult::Event(event),
+                result = &mut self.tunnel_close_event => ConnectedEventResult::Close(result),
+                _ = offline_timeout => ConnectedEventResult::OfflineTimeout,
             }
         });
 
         match result {
-            EventResult::Command(command) => self.handle_commands(command, shared_values),
-            EventResult::Event(event) => self.handle_tunnel_events(event, shared_values),
-            EventResult::Close(result) => {
+            ConnectedEventResult::Command(command) => self.handle_commands(command, shared_values),
+            ConnectedEventResult::Event(event) => self.handle_tunnel_events(event, shared_values),
+            ConnectedEventResult::Close(result) => {
                 if result.is_err() {
                     log::warn!("Tunnel monitor thread has stopped unexpectedly");
                 }
                 let block_reason = result.unwrap_or(None);
                 self.handle_tunnel_close_event(block_reason, shared_values)
             }
+            ConnectedEventResult::OfflineTimeout => {
+                log::info!("Host is still offline. Blocking");
+                self.disconnect(
+                    shared_values,
+                    AfterDisconnect::Block(ErrorStateCause::IsOffline),
+                )
+            }
         }
     }
 }
+
+#[cfg(test)]
+#[cfg(target_os = "linux")]
+mod test {
+    use super::*;
+    use tokio::time::{advance, timeout};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_offline_grace_period() {
+        let mut grace = OfflineGracePeriod::default();
+        assert!(timeout(Duration::from_secs(60), grace.expired())
+            .await
+            .is_err());
+
+        assert!(grace.start());
+        advance(OFFLINE_GRACE_PERIOD / 2).await;
+        // Going offline again does not extend the grace period
+        assert!(!grace.start());
+        assert!(timeout(
+            OFFLINE_GRACE_PERIOD / 2 - Duration::from_millis(1),
+            grace.expired()
+        )
+        .await
+        .is_err());
+        assert!(timeout(Duration::from_millis(1), grace.expired())
+            .await
+            .is_ok());
+
+        // Coming back online stops the grace period
+        assert!(grace.stop());
+        assert!(!grace.is_running());
+        assert!(!grace.stop());
+        assert!(timeout(Duration::from_secs(60), grace.expired())
+            .await
+            .is_err());
+    }
+}
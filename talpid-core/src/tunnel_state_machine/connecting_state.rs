@@ -8,6 +8,7 @@ use crate::{
     routing::RouteManager,
     tunnel::{
         self, tun_provider::TunProvider, TunnelArgs, TunnelEvent, TunnelMetadata, TunnelMonitor,
+        WireguardConfigUpdate,
     },
 };
 use cfg_if::cfg_if;
@@ -52,6 +53,7 @@ pub struct ConnectingState {
     allowed_tunnel_traffic: AllowedTunnelTraffic,
     tunnel_close_event: TunnelCloseEvent,
     tunnel_close_tx: oneshot::Sender<()>,
+    config_update_tx: mpsc::UnboundedSender<WireguardConfigUpdate>,
     retry_attempt: u32,
 }
 
@@ -73,6 +75,14 @@ impl ConnectingState {
             allow_lan: shared_values.allow_lan,
             allowed_endpoint: shared_values.allowed_endpoint.clone(),
             allowed_tunnel_traffic,
+            #[cfg(target_os = "linux")]
+            additional_peer_endpoints: params.get_additional_endpoints(),
+            #[cfg(target_os = "linux")]
+            forwarded_interfaces: shared_values.forwarded_interfaces.clone(),
+            #[cfg(target_os = "linux")]
+            ipv6_mode: shared_values.ipv6_firewall_mode,
+            #[cfg(target_os = "linux")]
+            log_drops: shared_values.log_firewall_drops,
             #[cfg(windows)]
             relay_client: TunnelMonitor::get_relay_client(&shared_values.resource_dir, &params),
         };
@@ -119,6 +129,7 @@ impl ConnectingState {
 
         let (tunnel_close_tx, tunnel_close_rx) = oneshot::channel();
         let (tunnel_close_event_tx, tunnel_close_event_rx) = oneshot::channel();
+        let (config_update_tx, config_update_rx) = mpsc::unbounded();
 
         let mut tunnel_parameters = parameters.clone();
 
@@ -152,6 +163,7 @@ impl ConnectingState {
                 tun_provider,
                 retry_attempt,
                 route_manager: route_manager_handle,
+                config_update_rx,
             };
 
             let block_reason = match TunnelMonitor::start(&mut tunnel_parameters, &log_dir, args) {
@@ -215,6 +227,7 @@ impl ConnectingState {
             allowed_tunnel_traffic: AllowedTunnelTraffic::None,
             tunnel_close_event: tunnel_close_event_rx.fuse(),
             tunnel_close_tx,
+            config_update_tx,
             retry_attempt,
         }
     }
@@ -259,6 +272,7 @@ impl ConnectingState {
             tunnel_parameters: self.tunnel_parameters,
             tunnel_close_event: self.tunnel_close_event,
             tunnel_close_tx: self.tunnel_close_tx,
+            config_update_tx: self.config_update_tx,
         }
     }
 
@@ -319,7 +333,7 @@ impl ConnectingState {
     }
 
     fn handle_commands(
-        self,
+        mut self,
         command: Option<TunnelCommand>,
         shared_values: &mut SharedTunnelStateValues,
     ) -> EventConsequence {
@@ -358,10 +372,55 @@ impl ConnectingState {
                 Ok(_) => SameState(self.into()),
                 Err(cause) => self.disconnect(shared_values, AfterDisconnect::Block(cause)),
             },
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::BlockedStateDns(servers)) => {
+                shared_values.blocked_state_dns_servers = servers;
+                SameState(self.into())
+            }
             Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self.into())
             }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::Ipv6FirewallMode(mode)) => {
+                shared_values.ipv6_firewall_mode = mode;
+                self.reset_firewall(shared_values)
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::LogFirewallDrops(enabled)) => {
+                shared_values.log_firewall_drops = enabled;
+                self.reset_firewall(shared_values)
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::ForwardedInterfaces(interfaces)) => {
+                shared_values.forwarded_interfaces = interfaces;
+                self.reset_firewall(shared_values)
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SplitDns(rules)) => {
+                shared_values.split_dns_rules = rules;
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::EncryptedDns(servers)) => {
+                shared_values.encrypted_dns_servers = servers;
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::EncryptedDnsFallback(fallback)) => {
+                shared_values.encrypted_dns_fallback = fallback;
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::LocalDnsRules(rules)) => {
+                shared_values.local_dns_rules = rules;
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::DnsQueryLog(enabled)) => {
+                shared_values.dns_query_log.set_enabled(enabled);
+                SameState(self.into())
+            }
             Some(TunnelCommand::IsOffline(is_offline)) => {
                 shared_values.is_offline = is_offline;
                 if is_offline {
@@ -373,6 +432,26 @@ impl ConnectingState {
                     SameState(self.into())
                 }
             }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::NetworkChanged(_)) => SameState(self.into()),
+            Some(TunnelCommand::RotateWireguardKey(private_key)) => match &self.tunnel_parameters {
+                TunnelParameters::Wireguard(params)
+                    if params.connection.tunnel.private_key != private_key =>
+                {
+                    self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+                }
+                _ => SameState(self.into()),
+            },
+            Some(TunnelCommand::SetWireguardPersistentKeepalive(interval)) => {
+                if let TunnelParameters::Wireguard(params) = &mut self.tunnel_parameters {
+                    params.options.persistent_keepalive = interval;
+                    // Applied once the tunnel is up
+                    let _ = self
+                        .config_update_tx
+                        .unbounded_send(WireguardConfigUpdate::PersistentKeepalive(interval));
+                }
+                SameState(self.into())
+            }
             Some(TunnelCommand::Connect) => {
                 self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
             }
@@ -544,10 +623,13 @@ impl TunnelState for ConnectingState {
         if shared_values.is_offline {
             return ErrorState::enter(shared_values, ErrorStateCause::IsOffline);
         }
+        let network = shared_values
+            .runtime
+            .block_on(shared_values.offline_monitor.network_state());
         match shared_values.runtime.block_on(
             shared_values
                 .tunnel_parameters_generator
-                .generate(retry_attempt),
+                .generate(retry_attempt, network),
         ) {
             Err(err) => {
                 ErrorState::enter(shared_values, ErrorStateCause::TunnelParameterError(err))
@@ -24,6 +24,14 @@ impl DisconnectedState {
             let policy = FirewallPolicy::Blocked {
                 allow_lan: shared_values.allow_lan,
                 allowed_endpoint: shared_values.allowed_endpoint.clone(),
+                #[cfg(target_os = "linux")]
+                forwarded_interfaces: shared_values.forwarded_interfaces.clone(),
+                #[cfg(target_os = "linux")]
+                ipv6_mode: shared_values.ipv6_firewall_mode,
+                #[cfg(target_os = "linux")]
+                log_drops: shared_values.log_firewall_drops,
+                #[cfg(target_os = "linux")]
+                dns_servers: shared_values.blocked_state_dns_servers.clone(),
                 #[cfg(target_os = "macos")]
                 dns_redirect_port: shared_values.filtering_resolver.listening_port(),
             };
@@ -155,6 +163,55 @@ impl TunnelState for DisconnectedState {
                 let _ = tx.send(());
                 SameState(self.into())
             }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::Ipv6FirewallMode(mode)) => {
+                if shared_values.ipv6_firewall_mode != mode {
+                    shared_values.ipv6_firewall_mode = mode;
+                    Self::set_firewall_policy(shared_values, false);
+                }
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::LogFirewallDrops(enabled)) => {
+                if shared_values.log_firewall_drops != enabled {
+                    shared_values.log_firewall_drops = enabled;
+                    Self::set_firewall_policy(shared_values, false);
+                }
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::ForwardedInterfaces(interfaces)) => {
+                if shared_values.forwarded_interfaces != interfaces {
+                    shared_values.forwarded_interfaces = interfaces;
+                    Self::set_firewall_policy(shared_values, false);
+                }
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SplitDns(rules)) => {
+                shared_values.split_dns_rules = rules;
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::EncryptedDns(servers)) => {
+                shared_values.encrypted_dns_servers = servers;
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::EncryptedDnsFallback(fallback)) => {
+                shared_values.encrypted_dns_fallback = fallback;
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::LocalDnsRules(rules)) => {
+                shared_values.local_dns_rules = rules;
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::DnsQueryLog(enabled)) => {
+                shared_values.dns_query_log.set_enabled(enabled);
+                SameState(self.into())
+            }
             Some(TunnelCommand::Dns(servers)) => {
                 // Same situation as allow LAN above.
                 shared_values
@@ -163,6 +220,14 @@ impl TunnelState for DisconnectedState {
 
                 SameState(self.into())
             }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::BlockedStateDns(servers)) => {
+                shared_values.blocked_state_dns_servers = servers;
+                if shared_values.block_when_disconnected {
+                    Self::set_firewall_policy(shared_values, false);
+                }
+                SameState(self.into())
+            }
             Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 if shared_values.block_when_disconnected != block_when_disconnected {
                     shared_values.block_when_disconnected = block_when_disconnected;
@@ -191,6 +256,10 @@ impl TunnelState for DisconnectedState {
                 shared_values.is_offline = is_offline;
                 SameState(self.into())
             }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::NetworkChanged(_)) => SameState(self.into()),
+            Some(TunnelCommand::RotateWireguardKey(_))
+            | Some(TunnelCommand::SetWireguardPersistentKeepalive(_)) => SameState(self.into()),
             Some(TunnelCommand::Connect) => NewState(ConnectingState::enter(shared_values, 0)),
             Some(TunnelCommand::Block(reason)) => {
                 Self::reset_dns(shared_values);
@@ -36,14 +36,65 @@ impl DisconnectingState {
                     let _ = shared_values.set_dns_servers(servers);
                     AfterDisconnect::Nothing
                 }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::BlockedStateDns(servers)) => {
+                    shared_values.blocked_state_dns_servers = servers;
+                    AfterDisconnect::Nothing
+                }
                 Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                     shared_values.block_when_disconnected = block_when_disconnected;
                     AfterDisconnect::Nothing
                 }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::Ipv6FirewallMode(mode)) => {
+                    shared_values.ipv6_firewall_mode = mode;
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::LogFirewallDrops(enabled)) => {
+                    shared_values.log_firewall_drops = enabled;
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::ForwardedInterfaces(interfaces)) => {
+                    shared_values.forwarded_interfaces = interfaces;
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::SplitDns(rules)) => {
+                    shared_values.split_dns_rules = rules;
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::EncryptedDns(servers)) => {
+                    shared_values.encrypted_dns_servers = servers;
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::EncryptedDnsFallback(fallback)) => {
+                    shared_values.encrypted_dns_fallback = fallback;
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::LocalDnsRules(rules)) => {
+                    shared_values.local_dns_rules = rules;
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::DnsQueryLog(enabled)) => {
+                    shared_values.dns_query_log.set_enabled(enabled);
+                    AfterDisconnect::Nothing
+                }
                 Some(TunnelCommand::IsOffline(is_offline)) => {
                     shared_values.is_offline = is_offline;
                     AfterDisconnect::Nothing
                 }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::NetworkChanged(_)) => AfterDisconnect::Nothing,
+                Some(TunnelCommand::RotateWireguardKey(_))
+                | Some(TunnelCommand::SetWireguardPersistentKeepalive(_)) => {
+                    AfterDisconnect::Nothing
+                }
                 Some(TunnelCommand::Connect) => AfterDisconnect::Reconnect(0),
                 Some(TunnelCommand::Disconnect) | None => AfterDisconnect::Nothing,
                 Some(TunnelCommand::Block(reason)) => AfterDisconnect::Block(reason),
@@ -72,10 +123,55 @@ impl DisconnectingState {
                     let _ = shared_values.set_dns_servers(servers);
                     AfterDisconnect::Block(reason)
                 }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::BlockedStateDns(servers)) => {
+                    shared_values.blocked_state_dns_servers = servers;
+                    AfterDisconnect::Block(reason)
+                }
                 Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                     shared_values.block_when_disconnected = block_when_disconnected;
                     AfterDisconnect::Block(reason)
                 }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::Ipv6FirewallMode(mode)) => {
+                    shared_values.ipv6_firewall_mode = mode;
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::LogFirewallDrops(enabled)) => {
+                    shared_values.log_firewall_drops = enabled;
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::ForwardedInterfaces(interfaces)) => {
+                    shared_values.forwarded_interfaces = interfaces;
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::SplitDns(rules)) => {
+                    shared_values.split_dns_rules = rules;
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::EncryptedDns(servers)) => {
+                    shared_values.encrypted_dns_servers = servers;
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::EncryptedDnsFallback(fallback)) => {
+                    shared_values.encrypted_dns_fallback = fallback;
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::LocalDnsRules(rules)) => {
+                    shared_values.local_dns_rules = rules;
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::DnsQueryLog(enabled)) => {
+                    shared_values.dns_query_log.set_enabled(enabled);
+                    AfterDisconnect::Block(reason)
+                }
                 Some(TunnelCommand::IsOffline(is_offline)) => {
                     shared_values.is_offline = is_offline;
                     if !is_offline && reason == ErrorStateCause::IsOffline {
@@ -84,6 +180,12 @@ impl DisconnectingState {
                         AfterDisconnect::Block(reason)
                     }
                 }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::NetworkChanged(_)) => AfterDisconnect::Block(reason),
+                Some(TunnelCommand::RotateWireguardKey(_))
+                | Some(TunnelCommand::SetWireguardPersistentKeepalive(_)) => {
+                    AfterDisconnect::Block(reason)
+                }
                 Some(TunnelCommand::Connect) => AfterDisconnect::Reconnect(0),
                 Some(TunnelCommand::Disconnect) => AfterDisconnect::Nothing,
                 Some(TunnelCommand::Block(new_reason)) => AfterDisconnect::Block(new_reason),
@@ -113,10 +215,55 @@ impl DisconnectingState {
                     let _ = shared_values.set_dns_servers(servers);
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::BlockedStateDns(servers)) => {
+                    shared_values.blocked_state_dns_servers = servers;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
                 Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                     shared_values.block_when_disconnected = block_when_disconnected;
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::Ipv6FirewallMode(mode)) => {
+                    shared_values.ipv6_firewall_mode = mode;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::LogFirewallDrops(enabled)) => {
+                    shared_values.log_firewall_drops = enabled;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::ForwardedInterfaces(interfaces)) => {
+                    shared_values.forwarded_interfaces = interfaces;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::SplitDns(rules)) => {
+                    shared_values.split_dns_rules = rules;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::EncryptedDns(servers)) => {
+                    shared_values.encrypted_dns_servers = servers;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::EncryptedDnsFallback(fallback)) => {
+                    shared_values.encrypted_dns_fallback = fallback;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::LocalDnsRules(rules)) => {
+                    shared_values.local_dns_rules = rules;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::DnsQueryLog(enabled)) => {
+                    shared_values.dns_query_log.set_enabled(enabled);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
                 Some(TunnelCommand::IsOffline(is_offline)) => {
                     shared_values.is_offline = is_offline;
                     if is_offline {
@@ -125,6 +272,12 @@ impl DisconnectingState {
                         AfterDisconnect::Reconnect(retry_attempt)
                     }
                 }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::NetworkChanged(_)) => AfterDisconnect::Reconnect(retry_attempt),
+                Some(TunnelCommand::RotateWireguardKey(_))
+                | Some(TunnelCommand::SetWireguardPersistentKeepalive(_)) => {
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
                 Some(TunnelCommand::Connect) => AfterDisconnect::Reconnect(retry_attempt),
                 Some(TunnelCommand::Disconnect) | None => AfterDisconnect::Nothing,
                 Some(TunnelCommand::Block(reason)) => AfterDisconnect::Block(reason),
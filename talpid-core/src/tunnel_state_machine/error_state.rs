@@ -23,6 +23,14 @@ impl ErrorState {
         let policy = FirewallPolicy::Blocked {
             allow_lan: shared_values.allow_lan,
             allowed_endpoint: shared_values.allowed_endpoint.clone(),
+            #[cfg(target_os = "linux")]
+            forwarded_interfaces: shared_values.forwarded_interfaces.clone(),
+            #[cfg(target_os = "linux")]
+            ipv6_mode: shared_values.ipv6_firewall_mode,
+            #[cfg(target_os = "linux")]
+            log_drops: shared_values.log_firewall_drops,
+            #[cfg(target_os = "linux")]
+            dns_servers: shared_values.blocked_state_dns_servers.clone(),
             #[cfg(target_os = "macos")]
             dns_redirect_port: shared_values.filtering_resolver.listening_port(),
         };
@@ -148,6 +156,59 @@ impl TunnelState for ErrorState {
                     SameState(self.into())
                 }
             }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::Ipv6FirewallMode(mode)) => {
+                shared_values.ipv6_firewall_mode = mode;
+                let _ = Self::set_firewall_policy(shared_values);
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::LogFirewallDrops(enabled)) => {
+                shared_values.log_firewall_drops = enabled;
+                match Self::set_firewall_policy(shared_values) {
+                    Ok(()) => SameState(self.into()),
+                    Err(error) => NewState(Self::enter(
+                        shared_values,
+                        ErrorStateCause::SetFirewallPolicyError(error),
+                    )),
+                }
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::ForwardedInterfaces(interfaces)) => {
+                shared_values.forwarded_interfaces = interfaces;
+                match Self::set_firewall_policy(shared_values) {
+                    Ok(()) => SameState(self.into()),
+                    Err(error) => NewState(Self::enter(
+                        shared_values,
+                        ErrorStateCause::SetFirewallPolicyError(error),
+                    )),
+                }
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SplitDns(rules)) => {
+                shared_values.split_dns_rules = rules;
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::EncryptedDns(servers)) => {
+                shared_values.encrypted_dns_servers = servers;
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::EncryptedDnsFallback(fallback)) => {
+                shared_values.encrypted_dns_fallback = fallback;
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::LocalDnsRules(rules)) => {
+                shared_values.local_dns_rules = rules;
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::DnsQueryLog(enabled)) => {
+                shared_values.dns_query_log.set_enabled(enabled);
+                SameState(self.into())
+            }
             Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
                 if shared_values.allowed_endpoint != endpoint {
                     shared_values.allowed_endpoint = endpoint;
@@ -172,6 +233,12 @@ impl TunnelState for ErrorState {
                     SameState(self.into())
                 }
             }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::BlockedStateDns(servers)) => {
+                shared_values.blocked_state_dns_servers = servers;
+                let _ = Self::set_firewall_policy(shared_values);
+                SameState(self.into())
+            }
             Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self.into())
@@ -185,6 +252,10 @@ impl TunnelState for ErrorState {
                     SameState(self.into())
                 }
             }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::NetworkChanged(_)) => SameState(self.into()),
+            Some(TunnelCommand::RotateWireguardKey(_))
+            | Some(TunnelCommand::SetWireguardPersistentKeepalive(_)) => SameState(self.into()),
             Some(TunnelCommand::Connect) => {
                 Self::reset_dns(shared_values);
 
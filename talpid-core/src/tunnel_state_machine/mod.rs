@@ -11,6 +11,8 @@ use self::{
     disconnecting_state::{AfterDisconnect, DisconnectingState},
     error_state::ErrorState,
 };
+#[cfg(target_os = "linux")]
+pub use crate::offline::NetworkChangeListeners;
 #[cfg(windows)]
 use crate::split_tunnel;
 use crate::{
@@ -40,10 +42,15 @@ use std::{
     sync::{Arc, Mutex},
     time::Duration,
 };
+#[cfg(target_os = "linux")]
+use talpid_types::net::{
+    dns::{EncryptedDnsServer, LocalDnsRules, SplitDnsRule},
+    ForwardedInterface, Ipv6FirewallMode,
+};
 #[cfg(target_os = "android")]
 use talpid_types::{android::AndroidContext, ErrorExt};
 use talpid_types::{
-    net::{AllowedEndpoint, TunnelParameters},
+    net::{wireguard, AllowedEndpoint, NetworkState, TunnelParameters},
     tunnel::{ErrorStateCause, ParameterGenerationError, TunnelStateTransition},
 };
 
@@ -100,6 +107,44 @@ pub struct InitialTunnelState {
     pub allowed_endpoint: AllowedEndpoint,
     /// Whether to reset any existing firewall rules when initializing the disconnected state.
     pub reset_firewall: bool,
+    /// Resolvers on the local network that may be reached in the blocking states.
+    #[cfg(target_os = "linux")]
+    pub blocked_state_dns_servers: Vec<IpAddr>,
+    /// Bridge interfaces whose forwarded traffic should be allowed by the firewall.
+    #[cfg(target_os = "linux")]
+    pub forwarded_interfaces: Vec<ForwardedInterface>,
+    /// IPv6 traffic that the firewall should allow outside the tunnel.
+    #[cfg(target_os = "linux")]
+    pub ipv6_firewall_mode: Ipv6FirewallMode,
+    /// Whether the firewall should send rejected packets to the drop log.
+    #[cfg(target_os = "linux")]
+    pub log_firewall_drops: bool,
+    /// Domains whose queries are sent to resolvers of their own by the local stub resolver.
+    #[cfg(target_os = "linux")]
+    pub split_dns_rules: Vec<SplitDnsRule>,
+    /// Encrypted resolvers that the local stub resolver sends queries to instead of the regular
+    /// DNS servers.
+    #[cfg(target_os = "linux")]
+    pub encrypted_dns_servers: Vec<EncryptedDnsServer>,
+    /// Whether the local stub resolver may send queries to the regular DNS servers while the
+    /// encrypted resolvers can't be reached.
+    #[cfg(target_os = "linux")]
+    pub encrypted_dns_fallback: bool,
+    /// Names that the local stub resolver blocks or resolves to fixed addresses.
+    #[cfg(target_os = "linux")]
+    pub local_dns_rules: LocalDnsRules,
+    /// Log that the local stub resolver records queries in, if it is enabled.
+    #[cfg(target_os = "linux")]
+    pub dns_query_log: crate::resolver::QueryLog,
+    /// Notified when DNS config that was changed by other programs is re-applied.
+    #[cfg(target_os = "linux")]
+    pub dns_drift_listeners: crate::dns::DriftListeners,
+    /// Notified when the local stub resolver falls back to plain DNS.
+    #[cfg(target_os = "linux")]
+    pub dns_fallback_listeners: crate::resolver::FallbackListeners,
+    /// Notified when the tunnel is kept up across a change of network.
+    #[cfg(target_os = "linux")]
+    pub network_change_listeners: NetworkChangeListeners,
     /// Programs to exclude from the tunnel using the split tunnel driver.
     #[cfg(windows)]
     pub exclude_paths: Vec<OsString>,
@@ -180,10 +225,47 @@ pub enum TunnelCommand {
     AllowEndpoint(AllowedEndpoint, oneshot::Sender<()>),
     /// Set DNS servers to use.
     Dns(Option<Vec<IpAddr>>),
+    /// Set resolvers on the local network that may be reached in the blocking states.
+    #[cfg(target_os = "linux")]
+    BlockedStateDns(Vec<IpAddr>),
     /// Enable or disable the block_when_disconnected feature.
     BlockWhenDisconnected(bool),
+    /// Set bridge interfaces whose forwarded traffic should be allowed by the firewall.
+    #[cfg(target_os = "linux")]
+    ForwardedInterfaces(Vec<ForwardedInterface>),
+    /// Set which IPv6 traffic the firewall should allow outside the tunnel.
+    #[cfg(target_os = "linux")]
+    Ipv6FirewallMode(Ipv6FirewallMode),
+    /// Set whether the firewall should send rejected packets to the drop log.
+    #[cfg(target_os = "linux")]
+    LogFirewallDrops(bool),
+    /// Set domains whose queries should be sent to resolvers of their own.
+    #[cfg(target_os = "linux")]
+    SplitDns(Vec<SplitDnsRule>),
+    /// Set encrypted resolvers to use instead of the regular DNS servers.
+    #[cfg(target_os = "linux")]
+    EncryptedDns(Vec<EncryptedDnsServer>),
+    /// Set whether plain DNS may be used while the encrypted resolvers can't be reached.
+    #[cfg(target_os = "linux")]
+    EncryptedDnsFallback(bool),
+    /// Set names that should be blocked or resolved to fixed addresses.
+    #[cfg(target_os = "linux")]
+    LocalDnsRules(LocalDnsRules),
+    /// Enable or disable the DNS query log.
+    #[cfg(target_os = "linux")]
+    DnsQueryLog(bool),
     /// Notify the state machine of the connectivity of the device.
     IsOffline(bool),
+    /// Notify the state machine that the default route changed while the device stayed online.
+    /// Contains the interface of the new default route, if it is known.
+    #[cfg(target_os = "linux")]
+    NetworkChanged(Option<String>),
+    /// Replace the private key of the WireGuard tunnel. A connected tunnel is updated in place
+    /// unless it uses an ephemeral key, in which case it is reconnected.
+    RotateWireguardKey(wireguard::PrivateKey),
+    /// Set the persistent keepalive interval of the WireGuard tunnel. A running tunnel is updated
+    /// in place.
+    SetWireguardPersistentKeepalive(Option<u16>),
     /// Open tunnel connection.
     Connect,
     /// Close tunnel connection.
@@ -288,11 +370,30 @@ impl TunnelStateMachine {
             route_manager
                 .handle()
                 .map_err(Error::InitRouteManagerError)?,
+            #[cfg(target_os = "linux")]
+            args.settings.dns_drift_listeners.clone(),
             #[cfg(target_os = "macos")]
             args.command_tx.clone(),
         )
         .map_err(Error::InitDnsMonitorError)?;
 
+        #[cfg(target_os = "linux")]
+        let (route_change_tx, mut route_change_rx) = mpsc::unbounded();
+        #[cfg(target_os = "linux")]
+        {
+            let command_tx = args.command_tx.clone();
+            tokio::spawn(async move {
+                while let Some(interface) = route_change_rx.next().await {
+                    match command_tx.upgrade() {
+                        Some(tx) => {
+                            let _ = tx.unbounded_send(TunnelCommand::NetworkChanged(interface));
+                        }
+                        None => break,
+                    }
+                }
+            });
+        }
+
         let (offline_tx, mut offline_rx) = mpsc::unbounded();
         let initial_offline_state_tx = args.offline_state_tx.clone();
         tokio::spawn(async move {
@@ -308,6 +409,8 @@ impl TunnelStateMachine {
         let offline_monitor = offline::spawn_monitor(
             offline_tx,
             #[cfg(target_os = "linux")]
+            route_change_tx,
+            #[cfg(target_os = "linux")]
             route_manager
                 .handle()
                 .map_err(Error::InitRouteManagerError)?,
@@ -333,12 +436,36 @@ impl TunnelStateMachine {
             firewall,
             dns_monitor,
             route_manager,
-            _offline_monitor: offline_monitor,
+            offline_monitor,
             allow_lan: args.settings.allow_lan,
             block_when_disconnected: args.settings.block_when_disconnected,
             is_offline,
             dns_servers: args.settings.dns_servers,
             allowed_endpoint: args.settings.allowed_endpoint,
+            #[cfg(target_os = "linux")]
+            blocked_state_dns_servers: args.settings.blocked_state_dns_servers,
+            #[cfg(target_os = "linux")]
+            forwarded_interfaces: args.settings.forwarded_interfaces,
+            #[cfg(target_os = "linux")]
+            ipv6_firewall_mode: args.settings.ipv6_firewall_mode,
+            #[cfg(target_os = "linux")]
+            log_firewall_drops: args.settings.log_firewall_drops,
+            #[cfg(target_os = "linux")]
+            split_dns_rules: args.settings.split_dns_rules,
+            #[cfg(target_os = "linux")]
+            encrypted_dns_servers: args.settings.encrypted_dns_servers,
+            #[cfg(target_os = "linux")]
+            encrypted_dns_fallback: args.settings.encrypted_dns_fallback,
+            #[cfg(target_os = "linux")]
+            local_dns_rules: args.settings.local_dns_rules,
+            #[cfg(target_os = "linux")]
+            dns_query_log: args.settings.dns_query_log,
+            #[cfg(target_os = "linux")]
+            dns_fallback_listeners: args.settings.dns_fallback_listeners,
+            #[cfg(target_os = "linux")]
+            network_change_listeners: args.settings.network_change_listeners,
+            #[cfg(target_os = "linux")]
+            dns_stub: None,
             tunnel_parameters_generator: Box::new(args.tunnel_parameters_generator),
             tun_provider: Arc::new(Mutex::new(args.tun_provider)),
             log_dir: args.log_dir,
@@ -398,11 +525,13 @@ impl TunnelStateMachine {
 /// Trait for any type that can provide a stream of `TunnelParameters` to the `TunnelStateMachine`.
 pub trait TunnelParametersGenerator: Send + 'static {
     /// Given the number of consecutive failed retry attempts, it should yield a `TunnelParameters`
-    /// to establish a tunnel with.
+    /// to establish a tunnel with. `network` describes the network that relays are reached
+    /// through, if it is known.
     /// If this returns `None` then the state machine goes into the `Error` state.
     fn generate(
         &mut self,
         retry_attempt: u32,
+        network: Option<NetworkState>,
     ) -> Pin<Box<dyn Future<Output = Result<TunnelParameters, ParameterGenerationError>>>>;
 }
 
@@ -417,7 +546,7 @@ struct SharedTunnelStateValues {
     firewall: Firewall,
     dns_monitor: DnsMonitor,
     route_manager: RouteManager,
-    _offline_monitor: offline::MonitorHandle,
+    offline_monitor: offline::MonitorHandle,
     /// Should LAN access be allowed outside the tunnel.
     allow_lan: bool,
     /// Should network access be allowed when in the disconnected state.
@@ -428,6 +557,43 @@ struct SharedTunnelStateValues {
     dns_servers: Option<Vec<IpAddr>>,
     /// Endpoint that should not be blocked by the firewall.
     allowed_endpoint: AllowedEndpoint,
+    /// Resolvers on the local network that may be reached in the blocking states.
+    #[cfg(target_os = "linux")]
+    blocked_state_dns_servers: Vec<IpAddr>,
+    /// Bridge interfaces whose forwarded traffic should be allowed by the firewall.
+    #[cfg(target_os = "linux")]
+    forwarded_interfaces: Vec<ForwardedInterface>,
+    /// IPv6 traffic that the firewall should allow outside the tunnel.
+    #[cfg(target_os = "linux")]
+    ipv6_firewall_mode: Ipv6FirewallMode,
+    /// Send packets rejected by the firewall to the drop log.
+    #[cfg(target_os = "linux")]
+    log_firewall_drops: bool,
+    /// Domains whose queries are sent to resolvers of their own.
+    #[cfg(target_os = "linux")]
+    split_dns_rules: Vec<SplitDnsRule>,
+    /// Encrypted resolvers to use instead of `dns_servers`.
+    #[cfg(target_os = "linux")]
+    encrypted_dns_servers: Vec<EncryptedDnsServer>,
+    /// Use `dns_servers` over plain DNS while the encrypted resolvers can't be reached.
+    #[cfg(target_os = "linux")]
+    encrypted_dns_fallback: bool,
+    /// Names that are blocked or resolved to fixed addresses.
+    #[cfg(target_os = "linux")]
+    local_dns_rules: LocalDnsRules,
+    /// Log of the queries that pass through the stub resolver.
+    #[cfg(target_os = "linux")]
+    dns_query_log: crate::resolver::QueryLog,
+    /// Notified when the stub resolver falls back to plain DNS.
+    #[cfg(target_os = "linux")]
+    dns_fallback_listeners: crate::resolver::FallbackListeners,
+    /// Notified when the connected state keeps the tunnel up across a change of network.
+    #[cfg(target_os = "linux")]
+    network_change_listeners: NetworkChangeListeners,
+    /// Local stub resolver that the system is pointed at while connected, if split DNS,
+    /// encrypted DNS, local DNS rules or the query log are used.
+    #[cfg(target_os = "linux")]
+    dns_stub: Option<crate::resolver::ResolverHandle>,
     /// The generator of new `TunnelParameter`s
     tunnel_parameters_generator: Box<dyn TunnelParametersGenerator>,
     /// The provider of tunnel devices.
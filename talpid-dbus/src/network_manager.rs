@@ -563,6 +563,44 @@ impl NetworkManager {
         Ok(settings_backup)
     }
 
+    /// Returns the DNS servers in the connection that is currently applied to a device.
+    pub fn get_dns(&self, interface_name: &str) -> Result<Vec<IpAddr>> {
+        let device_path = self.fetch_device(interface_name)?;
+        let (settings, _version_id): (NetworkSettings<'_>, u64) = self
+            .as_path(&device_path)
+            .method_call(NM_DEVICE, "GetAppliedConnection", (0u32,))?;
+
+        let mut servers = vec![];
+        if let Some(addresses) = settings
+            .get("ipv4")
+            .and_then(|ipv4| ipv4.get("dns"))
+            .and_then(|dns| dns.0.as_iter())
+        {
+            servers.extend(addresses.filter_map(|address| {
+                // Network-byte order
+                let address = address.as_u64()? as u32;
+                Some(IpAddr::from(std::net::Ipv4Addr::from(u32::from_be(
+                    address,
+                ))))
+            }));
+        }
+        if let Some(addresses) = settings
+            .get("ipv6")
+            .and_then(|ipv6| ipv6.get("dns"))
+            .and_then(|dns| dns.0.as_iter())
+        {
+            servers.extend(addresses.filter_map(|address| {
+                let bytes: Vec<u8> = address
+                    .as_iter()?
+                    .filter_map(|byte| byte.as_u64().map(|byte| byte as u8))
+                    .collect();
+                let octets: [u8; 16] = bytes.try_into().ok()?;
+                Some(IpAddr::from(octets))
+            }));
+        }
+        Ok(servers)
+    }
+
     pub fn reapply_settings<Settings: arg::Append>(
         &self,
         device: &dbus::Path<'_>,
@@ -553,6 +553,13 @@ impl AsyncHandle {
             .map_err(Error::AsyncTaskError)?
     }
 
+    pub async fn get_domains(&self, interface_index: u32) -> Result<Vec<(String, bool)>> {
+        let interface = self.dbus_interface.clone();
+        tokio::task::spawn_blocking(move || interface.get_domains(interface_index))
+            .await
+            .map_err(Error::AsyncTaskError)?
+    }
+
     pub async fn disable_dot(&self, interface_index: u32) -> Result<()> {
         let interface = self.dbus_interface.clone();
         tokio::task::spawn_blocking(move || interface.link_disable_dns_over_tls(interface_index))
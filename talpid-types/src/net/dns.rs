@@ -0,0 +1,466 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    net::IpAddr,
+    time::{Duration, SystemTime},
+};
+
+/// Sends queries for a domain, and all of its subdomains, to a separate set of resolvers.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SplitDnsRule {
+    /// Domain that the rule applies to, such as `corp.example`.
+    pub domain: String,
+    /// Resolvers that queries for the domain are sent to.
+    pub servers: Vec<IpAddr>,
+}
+
+impl fmt::Display for SplitDnsRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ->", self.domain)?;
+        for server in &self.servers {
+            write!(f, " {}", server)?;
+        }
+        Ok(())
+    }
+}
+
+/// Names that the local stub resolver answers by itself instead of forwarding the query.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LocalDnsRules {
+    /// Domains that resolve to nothing. An entry such as `*.ads.example` blocks `ads.example`
+    /// and all of its subdomains, while `ads.example` only blocks that exact name.
+    pub blocked_domains: Vec<String>,
+    /// Names that resolve to fixed addresses, like entries in a hosts file.
+    pub overrides: Vec<DnsOverride>,
+}
+
+impl LocalDnsRules {
+    pub fn is_empty(&self) -> bool {
+        self.blocked_domains.is_empty() && self.overrides.is_empty()
+    }
+
+    /// Adds the entries of a file in hosts format. Names mapped to an unspecified or loopback
+    /// address are blocked, and other names are overridden. Returns the number of added entries.
+    pub fn import_hosts_file(&mut self, contents: &str) -> usize {
+        let mut added = 0;
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let address: IpAddr = match fields.next().and_then(|field| field.parse().ok()) {
+                Some(address) => address,
+                None => continue,
+            };
+            for name in fields {
+                if HOSTS_FILE_BUILTIN_NAMES.contains(&name) {
+                    continue;
+                }
+                if address.is_unspecified() || address.is_loopback() {
+                    if !self.blocked_domains.iter().any(|domain| domain == name) {
+                        self.blocked_domains.push(name.to_owned());
+                        added += 1;
+                    }
+                } else {
+                    let entry = DnsOverride {
+                        name: name.to_owned(),
+                        address,
+                    };
+                    if !self.overrides.contains(&entry) {
+                        self.overrides.push(entry);
+                        added += 1;
+                    }
+                }
+            }
+        }
+        added
+    }
+}
+
+/// Names that hosts files commonly define for the local machine. These are never imported.
+const HOSTS_FILE_BUILTIN_NAMES: &[&str] = &[
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+    "ip6-localnet",
+    "ip6-mcastprefix",
+    "ip6-allnodes",
+    "ip6-allrouters",
+    "ip6-allhosts",
+    "0.0.0.0",
+];
+
+/// A name that resolves to a fixed address.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DnsOverride {
+    pub name: String,
+    pub address: IpAddr,
+}
+
+impl fmt::Display for DnsOverride {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {}", self.name, self.address)
+    }
+}
+
+/// Transport used to reach an encrypted DNS resolver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EncryptedDnsProtocol {
+    /// DNS-over-TLS, as described in RFC 7858.
+    Tls,
+    /// DNS-over-HTTPS, as described in RFC 8484. Queries are sent to the `/dns-query` path.
+    Https,
+}
+
+impl EncryptedDnsProtocol {
+    /// Returns the port that the protocol uses unless another one is given.
+    pub fn default_port(self) -> u16 {
+        match self {
+            EncryptedDnsProtocol::Tls => 853,
+            EncryptedDnsProtocol::Https => 443,
+        }
+    }
+}
+
+/// A resolver that is reached over DNS-over-TLS or DNS-over-HTTPS.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct EncryptedDnsServer {
+    pub protocol: EncryptedDnsProtocol,
+    /// Name that the server certificate must be valid for.
+    pub hostname: String,
+    pub port: u16,
+    /// Addresses of the server. The hostname is never resolved, since there is no resolver to
+    /// ask before the server itself is reachable.
+    pub bootstrap_addresses: Vec<IpAddr>,
+    /// Base64 encoded SHA-256 digest of the server's public key, in DER encoded
+    /// SubjectPublicKeyInfo form. When set, the server certificate must also carry this key.
+    pub spki_pin: Option<String>,
+}
+
+impl EncryptedDnsServer {
+    /// Parses a resolver URL, such as `tls://dns.example` or `https://dns.example/dns-query`.
+    pub fn from_url(
+        url: &str,
+        bootstrap_addresses: Vec<IpAddr>,
+        spki_pin: Option<String>,
+    ) -> Result<Self, InvalidEncryptedDnsServer> {
+        let (protocol, rest) = if let Some(rest) = url.strip_prefix("tls://") {
+            (EncryptedDnsProtocol::Tls, rest)
+        } else if let Some(rest) = url.strip_prefix("https://") {
+            (EncryptedDnsProtocol::Https, rest)
+        } else {
+            return Err(InvalidEncryptedDnsServer::UnsupportedScheme);
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, ""),
+        };
+        match (protocol, path) {
+            (_, "") | (_, "/") | (EncryptedDnsProtocol::Https, "/dns-query") => (),
+            _ => return Err(InvalidEncryptedDnsServer::UnsupportedPath),
+        }
+
+        let (hostname, port) = match authority.rsplit_once(':') {
+            Some((hostname, port)) => (
+                hostname,
+                port.parse()
+                    .map_err(|_| InvalidEncryptedDnsServer::InvalidPort)?,
+            ),
+            None => (authority, protocol.default_port()),
+        };
+
+        let server = EncryptedDnsServer {
+            protocol,
+            hostname: hostname.to_owned(),
+            port,
+            bootstrap_addresses,
+            spki_pin,
+        };
+        server.validate()?;
+        Ok(server)
+    }
+
+    /// Checks that the server can be connected to and that the pin is well-formed.
+    pub fn validate(&self) -> Result<(), InvalidEncryptedDnsServer> {
+        if self.hostname.is_empty() {
+            return Err(InvalidEncryptedDnsServer::MissingHostname);
+        }
+        if self.bootstrap_addresses.is_empty() {
+            return Err(InvalidEncryptedDnsServer::MissingBootstrapAddress);
+        }
+        if let Some(pin) = &self.spki_pin {
+            decode_spki_pin(pin)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for EncryptedDnsServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.protocol {
+            EncryptedDnsProtocol::Tls => write!(f, "tls://{}:{}", self.hostname, self.port)?,
+            EncryptedDnsProtocol::Https => {
+                write!(f, "https://{}:{}/dns-query", self.hostname, self.port)?
+            }
+        }
+        write!(f, " via")?;
+        for address in &self.bootstrap_addresses {
+            write!(f, " {}", address)?;
+        }
+        if let Some(pin) = &self.spki_pin {
+            write!(f, ", pinned to {}", pin)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes a base64 encoded SHA-256 digest of a SubjectPublicKeyInfo.
+pub fn decode_spki_pin(pin: &str) -> Result<[u8; 32], InvalidEncryptedDnsServer> {
+    base64::decode(pin)
+        .ok()
+        .and_then(|digest| <[u8; 32]>::try_from(digest).ok())
+        .ok_or(InvalidEncryptedDnsServer::InvalidSpkiPin)
+}
+
+/// Reasons that an encrypted DNS resolver cannot be used.
+#[derive(err_derive::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidEncryptedDnsServer {
+    #[error(display = "Only tls:// and https:// resolver URLs are supported")]
+    UnsupportedScheme,
+
+    #[error(display = "DNS-over-HTTPS resolvers must use the /dns-query path")]
+    UnsupportedPath,
+
+    #[error(display = "Invalid resolver port")]
+    InvalidPort,
+
+    #[error(display = "Missing resolver hostname")]
+    MissingHostname,
+
+    #[error(display = "At least one bootstrap address is required")]
+    MissingBootstrapAddress,
+
+    #[error(display = "The SPKI pin must be a base64 encoded SHA-256 digest")]
+    InvalidSpkiPin,
+}
+
+/// A query that passed through the local stub resolver.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsQueryLogEntry {
+    /// When the query was received.
+    pub time: SystemTime,
+    pub name: String,
+    /// Record type of the query, such as `AAAA`.
+    pub query_type: String,
+    /// Resolvers that the query was forwarded to, such as `default` or the domain of a split DNS
+    /// rule. `None` if the query was answered locally.
+    pub upstream: Option<String>,
+    /// Local blocklist entry that blocked the query, if any.
+    pub blocked_by: Option<String>,
+    /// Response code of the answer, such as `NoError` or `NXDomain`.
+    pub response_code: String,
+    /// Time it took to answer the query.
+    pub latency: Duration,
+}
+
+impl fmt::Display for DnsQueryLogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.name, self.query_type, self.response_code
+        )?;
+        match (&self.upstream, &self.blocked_by) {
+            (_, Some(entry)) => write!(f, ", blocked by {}", entry)?,
+            (Some(upstream), None) => write!(f, ", via {}", upstream)?,
+            (None, None) => write!(f, ", answered locally")?,
+        }
+        write!(f, " in {} ms", self.latency.as_millis())
+    }
+}
+
+/// The system DNS configuration was changed by another program after it was set, and has been
+/// re-applied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsConfigDrift {
+    /// How DNS is managed, such as `systemd-resolved` or `/etc/resolv.conf`.
+    pub manager: String,
+    /// Interface that the DNS configuration was set for.
+    pub interface: String,
+    /// Number of times the configuration has been re-applied since it was set.
+    pub reapply_count: u32,
+}
+
+impl fmt::Display for DnsConfigDrift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "DNS config for {} was changed via {}, re-applied {} time(s)",
+            self.interface, self.manager, self.reapply_count
+        )
+    }
+}
+
+/// Encrypted resolvers that may fall back to plain DNS could not be reached, or can be reached
+/// again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EncryptedDnsFallback {
+    /// Queries are sent over plain DNS, since the encrypted resolvers failed with this error.
+    Started(String),
+    /// Queries are sent to the encrypted resolvers again.
+    Stopped,
+}
+
+impl fmt::Display for EncryptedDnsFallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncryptedDnsFallback::Started(error) => {
+                write!(f, "Encrypted DNS failed, using plain DNS: {}", error)
+            }
+            EncryptedDnsFallback::Stopped => "Encrypted DNS is working again".fmt(f),
+        }
+    }
+}
+
+/// Result of checking whether a custom DNS server can be used.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsServerTestResult {
+    pub address: IpAddr,
+    pub outcome: DnsServerTestOutcome,
+}
+
+impl fmt::Display for DnsServerTestResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.address, self.outcome)
+    }
+}
+
+/// What was found out about a custom DNS server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsServerTestOutcome {
+    /// The server answered a test query sent through the tunnel.
+    Responded(Duration),
+    /// Traffic to the server can be routed, but no query was sent since the tunnel is not up.
+    Routable,
+    /// Traffic to the server can't be routed with the current settings.
+    Unroutable(String),
+    /// The server did not answer a test query sent through the tunnel.
+    NoResponse(String),
+}
+
+impl DnsServerTestOutcome {
+    /// Returns whether the server is known to be unusable.
+    pub fn is_failure(&self) -> bool {
+        matches!(
+            self,
+            DnsServerTestOutcome::Unroutable(_) | DnsServerTestOutcome::NoResponse(_)
+        )
+    }
+}
+
+impl fmt::Display for DnsServerTestOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsServerTestOutcome::Responded(latency) => {
+                write!(f, "responded in {} ms", latency.as_millis())
+            }
+            DnsServerTestOutcome::Routable => "routable, not tested since not connected".fmt(f),
+            DnsServerTestOutcome::Unroutable(reason) => write!(f, "unroutable: {}", reason),
+            DnsServerTestOutcome::NoResponse(reason) => write!(f, "no response: {}", reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_url() {
+        let bootstrap = vec![IpAddr::from([192, 0, 2, 1])];
+
+        let server =
+            EncryptedDnsServer::from_url("tls://dns.example", bootstrap.clone(), None).unwrap();
+        assert_eq!(server.protocol, EncryptedDnsProtocol::Tls);
+        assert_eq!(server.hostname, "dns.example");
+        assert_eq!(server.port, 853);
+
+        let server = EncryptedDnsServer::from_url(
+            "https://dns.example:8443/dns-query",
+            bootstrap.clone(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(server.protocol, EncryptedDnsProtocol::Https);
+        assert_eq!(server.port, 8443);
+
+        assert_eq!(
+            EncryptedDnsServer::from_url("https://dns.example/resolve", bootstrap.clone(), None),
+            Err(InvalidEncryptedDnsServer::UnsupportedPath)
+        );
+        assert_eq!(
+            EncryptedDnsServer::from_url("udp://dns.example", bootstrap.clone(), None),
+            Err(InvalidEncryptedDnsServer::UnsupportedScheme)
+        );
+        assert_eq!(
+            EncryptedDnsServer::from_url("tls://dns.example", vec![], None),
+            Err(InvalidEncryptedDnsServer::MissingBootstrapAddress)
+        );
+    }
+
+    #[test]
+    fn test_import_hosts_file() {
+        let mut rules = LocalDnsRules {
+            blocked_domains: vec!["ads.example".to_owned()],
+            overrides: vec![],
+        };
+        let added = rules.import_hosts_file(
+            "# Blocklist\n\
+             127.0.0.1 localhost\n\
+             ::1 localhost ip6-localhost\n\
+             0.0.0.0 ads.example tracker.example # trackers\n\
+             \n\
+             192.168.1.10\tnas.lan printer.lan\n\
+             not-an-address foo.example\n",
+        );
+
+        assert_eq!(added, 3);
+        assert_eq!(
+            rules.blocked_domains,
+            vec!["ads.example", "tracker.example"]
+        );
+        assert_eq!(
+            rules.overrides,
+            vec![
+                DnsOverride {
+                    name: "nas.lan".to_owned(),
+                    address: IpAddr::from([192, 168, 1, 10]),
+                },
+                DnsOverride {
+                    name: "printer.lan".to_owned(),
+                    address: IpAddr::from([192, 168, 1, 10]),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spki_pin() {
+        let digest = [7u8; 32];
+        assert_eq!(decode_spki_pin(&base64::encode(digest)), Ok(digest));
+        assert_eq!(
+            decode_spki_pin(&base64::encode([7u8; 20])),
+            Err(InvalidEncryptedDnsServer::InvalidSpkiPin)
+        );
+        assert_eq!(
+            decode_spki_pin("not base64!"),
+            Err(InvalidEncryptedDnsServer::InvalidSpkiPin)
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, net::IpAddr};
+
+const IPPROTO_ICMP: u8 = 1;
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+const IPPROTO_ICMPV6: u8 = 58;
+
+/// Packets rejected by the firewall that share destination, protocol and owner.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DroppedPackets {
+    /// Destination address of the packets.
+    pub destination: IpAddr,
+    /// IP protocol number, such as 6 for TCP.
+    pub protocol: u8,
+    /// Destination port, for TCP and UDP packets.
+    pub port: Option<u16>,
+    /// User that sent the packets, if they were sent from a local socket.
+    pub uid: Option<u32>,
+    /// Number of packets dropped since the previous report.
+    pub count: u64,
+}
+
+impl DroppedPackets {
+    /// Returns a human readable name for the IP protocol of the packets.
+    pub fn protocol_name(&self) -> String {
+        match self.protocol {
+            IPPROTO_TCP => "TCP".to_owned(),
+            IPPROTO_UDP => "UDP".to_owned(),
+            IPPROTO_ICMP => "ICMP".to_owned(),
+            IPPROTO_ICMPV6 => "ICMPv6".to_owned(),
+            other => format!("protocol {}", other),
+        }
+    }
+}
+
+impl fmt::Display for DroppedPackets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} packet(s) to ", self.count, self.protocol_name())?;
+        match (self.port, self.destination) {
+            (Some(port), IpAddr::V4(address)) => write!(f, "{}:{}", address, port)?,
+            (Some(port), IpAddr::V6(address)) => write!(f, "[{}]:{}", address, port)?,
+            (None, address) => write!(f, "{}", address)?,
+        }
+        if let Some(uid) = self.uid {
+            write!(f, " from uid {}", uid)?;
+        }
+        Ok(())
+    }
+}
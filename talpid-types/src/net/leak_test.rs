@@ -0,0 +1,77 @@
+use crate::net::Endpoint;
+use serde::{Deserialize, Serialize};
+use std::{fmt, net::IpAddr};
+
+/// A single probe sent outside the tunnel by the leak self-test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LeakProbe {
+    /// UDP or TCP traffic to an arbitrary endpoint.
+    Transport(Endpoint),
+    /// ICMP/ICMPv6 echo request to a host.
+    Icmp(IpAddr),
+    /// A DNS query to a resolver that is not the configured one.
+    Dns(Endpoint),
+}
+
+impl LeakProbe {
+    /// Returns the destination address of the probe.
+    pub fn destination(&self) -> IpAddr {
+        match self {
+            LeakProbe::Transport(endpoint) | LeakProbe::Dns(endpoint) => endpoint.address.ip(),
+            LeakProbe::Icmp(address) => *address,
+        }
+    }
+}
+
+impl fmt::Display for LeakProbe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LeakProbe::Transport(endpoint) => write!(f, "{}", endpoint),
+            LeakProbe::Icmp(address) => write!(f, "{} over ICMP", address),
+            LeakProbe::Dns(endpoint) => write!(f, "DNS to {}", endpoint),
+        }
+    }
+}
+
+/// What happened to a probe.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LeakProbeOutcome {
+    /// The firewall rejected or dropped the traffic.
+    Blocked,
+    /// The traffic left the host.
+    Leaked,
+    /// There was no route to the destination, so nothing could leak.
+    Unreachable,
+    /// The probe could not be run.
+    Failed(String),
+}
+
+impl fmt::Display for LeakProbeOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LeakProbeOutcome::Blocked => "blocked".fmt(f),
+            LeakProbeOutcome::Leaked => "leaked".fmt(f),
+            LeakProbeOutcome::Unreachable => "unreachable".fmt(f),
+            LeakProbeOutcome::Failed(reason) => write!(f, "failed: {}", reason),
+        }
+    }
+}
+
+/// Result of a single leak probe.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeakProbeResult {
+    pub probe: LeakProbe,
+    pub outcome: LeakProbeOutcome,
+    /// Whether the probe targets an endpoint that the firewall is expected to let through,
+    /// such as the API endpoint.
+    pub expected_allowed: bool,
+}
+
+impl LeakProbeResult {
+    /// Returns whether traffic escaped the firewall when it should not have.
+    pub fn is_unexpected_leak(&self) -> bool {
+        self.outcome == LeakProbeOutcome::Leaked && !self.expected_allowed
+    }
+}
@@ -10,6 +10,10 @@ use std::{
     str::FromStr,
 };
 
+pub mod dns;
+pub mod drop_log;
+pub mod leak_test;
+pub mod nat64;
 pub mod obfuscation;
 pub mod openvpn;
 pub mod proxy;
@@ -34,6 +38,9 @@ impl TunnelParameters {
                 proxy: params.proxy.as_ref().map(|proxy| proxy.get_endpoint()),
                 obfuscation: None,
                 entry_endpoint: None,
+                mtu: None,
+                nat64: params.nat64,
+                wireguard_backend: None,
             },
             TunnelParameters::Wireguard(params) => TunnelEndpoint {
                 tunnel_type: TunnelType::Wireguard,
@@ -48,6 +55,9 @@ impl TunnelParameters {
                     .connection
                     .get_exit_endpoint()
                     .map(|_| params.connection.get_endpoint()),
+                mtu: None,
+                nat64: params.nat64,
+                wireguard_backend: None,
             },
         }
     }
@@ -74,6 +84,10 @@ impl TunnelParameters {
                 address: *endpoint,
                 protocol: TransportProtocol::Tcp,
             },
+            ObfuscatorConfig::Shadowsocks { endpoint, .. } => Endpoint {
+                address: *endpoint,
+                protocol: TransportProtocol::Udp,
+            },
         }
     }
 
@@ -85,6 +99,22 @@ impl TunnelParameters {
         }
     }
 
+    /// Returns the endpoints of any other peers that are connected to directly
+    pub fn get_additional_endpoints(&self) -> Vec<Endpoint> {
+        match self {
+            TunnelParameters::OpenVpn(_params) => vec![],
+            TunnelParameters::Wireguard(params) => params.connection.get_additional_endpoints(),
+        }
+    }
+
+    /// Returns the IP versions whose traffic is only partly routed through the tunnel
+    pub fn get_untunneled_ip_versions(&self) -> Vec<IpVersion> {
+        match self {
+            TunnelParameters::OpenVpn(_params) => vec![],
+            TunnelParameters::Wireguard(params) => params.connection.get_untunneled_ip_versions(),
+        }
+    }
+
     pub fn get_generic_options(&self) -> &GenericTunnelOptions {
         match &self {
             TunnelParameters::OpenVpn(params) => &params.generic_options,
@@ -142,6 +172,16 @@ pub struct TunnelEndpoint {
     pub obfuscation: Option<ObfuscationEndpoint>,
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub entry_endpoint: Option<Endpoint>,
+    /// MTU of the tunnel interface, if it was found by path MTU discovery.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub mtu: Option<u16>,
+    /// Whether the relay is reached through a NAT64 gateway, using an IPv6 address synthesized
+    /// from its IPv4 address.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub nat64: bool,
+    /// WireGuard implementation used by the tunnel, if it is known.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub wireguard_backend: Option<wireguard::BackendInfo>,
 }
 
 impl fmt::Display for TunnelEndpoint {
@@ -151,6 +191,9 @@ impl fmt::Display for TunnelEndpoint {
             write!(f, "(quantum resistant) ")?;
         }
         write!(f, "- {}", self.endpoint)?;
+        if self.nat64 {
+            write!(f, " (NAT64)")?;
+        }
         match self.tunnel_type {
             TunnelType::OpenVpn => {
                 if let Some(ref proxy) = self.proxy {
@@ -175,12 +218,15 @@ impl fmt::Display for TunnelEndpoint {
 pub enum ObfuscationType {
     #[serde(rename = "udp2tcp")]
     Udp2Tcp,
+    #[serde(rename = "shadowsocks")]
+    Shadowsocks,
 }
 
 impl fmt::Display for ObfuscationType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         let obfuscation = match self {
             ObfuscationType::Udp2Tcp => "Udp2Tcp",
+            ObfuscationType::Shadowsocks => "Shadowsocks",
         };
         write!(f, "{}", obfuscation)
     }
@@ -203,6 +249,13 @@ impl From<&ObfuscatorConfig> for ObfuscationEndpoint {
                 },
                 ObfuscationType::Udp2Tcp,
             ),
+            ObfuscatorConfig::Shadowsocks { endpoint, .. } => (
+                Endpoint {
+                    address: *endpoint,
+                    protocol: TransportProtocol::Udp,
+                },
+                ObfuscationType::Shadowsocks,
+            ),
         };
 
         ObfuscationEndpoint {
@@ -299,6 +352,100 @@ impl fmt::Display for AllowedTunnelTraffic {
     }
 }
 
+/// A local bridge interface, such as `docker0` or `virbr0`, whose forwarded traffic should be
+/// let through the firewall.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub struct ForwardedInterface {
+    /// Name of the bridge interface.
+    pub interface: String,
+    pub mode: ForwardingMode,
+}
+
+impl fmt::Display for ForwardedInterface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(f, "{} ({})", self.interface, self.mode)
+    }
+}
+
+/// Where traffic forwarded from a [`ForwardedInterface`] may go.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ForwardingMode {
+    /// Forward traffic through the tunnel, masquerading it behind the tunnel address.
+    Tunnel,
+    /// Only allow traffic between the bridge and the host or local network.
+    Local,
+}
+
+impl fmt::Display for ForwardingMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match *self {
+            ForwardingMode::Tunnel => "tunnel".fmt(f),
+            ForwardingMode::Local => "local".fmt(f),
+        }
+    }
+}
+
+/// Which IPv6 traffic the firewall lets through outside the tunnel.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ipv6FirewallMode {
+    /// Allow NDP and DHCPv6, and IPv6 LAN traffic if local network sharing is enabled.
+    #[default]
+    Default,
+    /// Block all IPv6 traffic outside the tunnel except NDP, including DHCPv6. NDP is needed for
+    /// IPv6 relay and API endpoints to remain reachable.
+    BlockOutsideTunnel,
+    /// Allow NDP and DHCPv6, but never IPv6 LAN traffic. IPv4 LAN traffic is still allowed if
+    /// local network sharing is enabled.
+    TunnelOnly,
+}
+
+impl Ipv6FirewallMode {
+    /// Returns whether DHCPv6 client traffic should be allowed. NDP is allowed in every mode.
+    pub fn allows_dhcpv6(&self) -> bool {
+        !matches!(self, Ipv6FirewallMode::BlockOutsideTunnel)
+    }
+
+    /// Returns whether IPv6 LAN traffic should be allowed when local network sharing is enabled.
+    pub fn allows_lan(&self) -> bool {
+        matches!(self, Ipv6FirewallMode::Default)
+    }
+}
+
+impl fmt::Display for Ipv6FirewallMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match *self {
+            Ipv6FirewallMode::Default => "default".fmt(f),
+            Ipv6FirewallMode::BlockOutsideTunnel => "block-outside-tunnel".fmt(f),
+            Ipv6FirewallMode::TunnelOnly => "tunnel-only".fmt(f),
+        }
+    }
+}
+
+/// The default route changed while connected, and the tunnel was kept up instead of being
+/// reconnected.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+pub struct NetworkChange {
+    /// Interface of the new default route, if it is known.
+    pub interface: Option<String>,
+    /// Whether the host was briefly offline during the change.
+    pub was_offline: bool,
+}
+
+impl fmt::Display for NetworkChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match &self.interface {
+            Some(interface) => write!(f, "Network changed to {}", interface)?,
+            None => write!(f, "Network changed")?,
+        }
+        if self.was_offline {
+            write!(f, " after being briefly offline")?;
+        }
+        write!(f, ", tunnel kept up")
+    }
+}
+
 /// IP protocol version.
 #[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
@@ -317,6 +464,47 @@ impl fmt::Display for IpVersion {
     }
 }
 
+/// IP versions that the host can use to reach the internet outside the tunnel.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IpAvailability {
+    Ipv4,
+    Ipv6,
+    Ipv4AndIpv6,
+}
+
+impl IpAvailability {
+    /// Returns the availability for the given IP versions, or `None` if neither is available.
+    pub fn new(ipv4: bool, ipv6: bool) -> Option<Self> {
+        match (ipv4, ipv6) {
+            (true, false) => Some(IpAvailability::Ipv4),
+            (false, true) => Some(IpAvailability::Ipv6),
+            (true, true) => Some(IpAvailability::Ipv4AndIpv6),
+            (false, false) => None,
+        }
+    }
+
+    pub fn has_ipv4(&self) -> bool {
+        matches!(self, IpAvailability::Ipv4 | IpAvailability::Ipv4AndIpv6)
+    }
+
+    pub fn has_ipv6(&self) -> bool {
+        matches!(self, IpAvailability::Ipv6 | IpAvailability::Ipv4AndIpv6)
+    }
+}
+
+/// The network that the host is connected to outside the tunnel.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct NetworkState {
+    /// IP versions that the host can use to reach the internet.
+    pub ip_availability: IpAvailability,
+    /// Changes whenever the default routes change, so that anything learned about a previous
+    /// network can be discarded.
+    pub generation: u64,
+    /// Prefix of the NAT64 gateway, if the network is IPv6-only and has one.
+    pub nat64_prefix: Option<nat64::Prefix>,
+}
+
 /// Representation of a transport protocol, either UDP or TCP.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
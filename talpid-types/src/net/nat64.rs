@@ -0,0 +1,129 @@
+//! NAT64 prefixes of IPv6-only networks, and synthesis of IPv6 addresses through which their
+//! gateways reach IPv4 hosts.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// Name that only has A records, so any AAAA record is synthesized by DNS64. See RFC 7050.
+pub const IPV4_ONLY_NAME: &str = "ipv4only.arpa";
+/// The addresses of [`IPV4_ONLY_NAME`].
+pub const IPV4_ONLY_ADDRESSES: [Ipv4Addr; 2] =
+    [Ipv4Addr::new(192, 0, 0, 170), Ipv4Addr::new(192, 0, 0, 171)];
+/// Prefix lengths allowed by RFC 6052, most common first.
+const PREFIX_LENGTHS: [u8; 6] = [96, 64, 56, 48, 40, 32];
+/// Index of the octet that must be zero in IPv4-embedded IPv6 addresses.
+const RESERVED_OCTET: usize = 8;
+
+/// IPv6 prefix that a NAT64 gateway translates to IPv4 addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Prefix {
+    prefix: Ipv6Addr,
+    len: u8,
+}
+
+impl Prefix {
+    /// Returns the prefix used to synthesize `addr` from one of the addresses of
+    /// `ipv4only.arpa`, or `None` if `addr` was not synthesized by DNS64.
+    pub fn from_synthesized(addr: Ipv6Addr) -> Option<Self> {
+        PREFIX_LENGTHS.iter().find_map(|&len| {
+            if !IPV4_ONLY_ADDRESSES.contains(&extract(addr, len)) {
+                return None;
+            }
+            let mut octets = addr.octets();
+            octets[usize::from(len / 8)..].fill(0);
+            Some(Prefix {
+                prefix: Ipv6Addr::from(octets),
+                len,
+            })
+        })
+    }
+
+    /// Returns the IPv6 address through which the NAT64 gateway reaches `addr`.
+    pub fn synthesize(&self, addr: Ipv4Addr) -> Ipv6Addr {
+        let mut octets = self.prefix.octets();
+        for (index, octet) in embedded_indices(self.len).zip(addr.octets()) {
+            octets[index] = octet;
+        }
+        Ipv6Addr::from(octets)
+    }
+
+    /// Returns `addr` mapped to this prefix if it is an IPv4 address.
+    pub fn synthesize_socket_addr(&self, addr: SocketAddr) -> SocketAddr {
+        match addr.ip() {
+            IpAddr::V4(ip) => SocketAddr::new(IpAddr::V6(self.synthesize(ip)), addr.port()),
+            IpAddr::V6(_) => addr,
+        }
+    }
+}
+
+impl std::fmt::Display for Prefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.prefix, self.len)
+    }
+}
+
+/// Returns the IPv4 address embedded in `addr` for a prefix of length `len`.
+fn extract(addr: Ipv6Addr, len: u8) -> Ipv4Addr {
+    let octets = addr.octets();
+    let mut ipv4 = [0u8; 4];
+    for (octet, index) in ipv4.iter_mut().zip(embedded_indices(len)) {
+        *octet = octets[index];
+    }
+    Ipv4Addr::from(ipv4)
+}
+
+/// Returns the indices of the octets that hold the IPv4 address for a prefix of length `len`.
+fn embedded_indices(len: u8) -> impl Iterator<Item = usize> {
+    (usize::from(len / 8)..16)
+        .filter(|index| *index != RESERVED_OCTET)
+        .take(4)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_well_known_prefix() {
+        let prefix = Prefix::from_synthesized("64:ff9b::c000:aa".parse().unwrap()).unwrap();
+        assert_eq!(prefix.to_string(), "64:ff9b::/96");
+        assert_eq!(
+            prefix.synthesize(Ipv4Addr::new(198, 51, 100, 1)),
+            "64:ff9b::c633:6401".parse::<Ipv6Addr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_network_specific_prefix() {
+        // Example from RFC 6052, section 2.4
+        let prefix =
+            Prefix::from_synthesized("2001:db8:122:344:c0:0:aa00:0".parse().unwrap()).unwrap();
+        assert_eq!(prefix.to_string(), "2001:db8:122:344::/64");
+        assert_eq!(
+            prefix.synthesize(Ipv4Addr::new(192, 0, 2, 33)),
+            "2001:db8:122:344:c0:2:2100:0".parse::<Ipv6Addr>().unwrap()
+        );
+
+        let prefix = Prefix::from_synthesized("2001:db8:1c0:0:aa::".parse().unwrap()).unwrap();
+        assert_eq!(prefix.to_string(), "2001:db8:100::/40");
+        assert_eq!(
+            prefix.synthesize(Ipv4Addr::new(192, 0, 2, 33)),
+            "2001:db8:1c0:2:21::".parse::<Ipv6Addr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_not_synthesized() {
+        assert!(Prefix::from_synthesized("2001:db8::1".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_synthesize_socket_addr() {
+        let prefix = Prefix::from_synthesized("64:ff9b::c000:aa".parse().unwrap()).unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:51820".parse().unwrap();
+        assert_eq!(prefix.synthesize_socket_addr(v6), v6);
+        assert_eq!(
+            prefix.synthesize_socket_addr("192.0.2.1:51820".parse().unwrap()),
+            "[64:ff9b::c000:201]:51820".parse::<SocketAddr>().unwrap()
+        );
+    }
+}
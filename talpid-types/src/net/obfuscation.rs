@@ -3,5 +3,15 @@ use std::net::SocketAddr;
 
 #[derive(Clone, Eq, PartialEq, Deserialize, Serialize, Debug)]
 pub enum ObfuscatorConfig {
-    Udp2Tcp { endpoint: SocketAddr },
+    Udp2Tcp {
+        endpoint: SocketAddr,
+    },
+    Shadowsocks {
+        /// Shadowsocks server to send the WireGuard traffic to.
+        endpoint: SocketAddr,
+        /// WireGuard endpoint that the Shadowsocks server forwards the traffic to.
+        wireguard_endpoint: SocketAddr,
+        cipher: String,
+        password: String,
+    },
 }
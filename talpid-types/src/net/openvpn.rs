@@ -13,6 +13,9 @@ pub struct TunnelParameters {
     pub options: TunnelOptions,
     pub generic_options: GenericTunnelOptions,
     pub proxy: Option<ProxySettings>,
+    /// Whether the relay was given an address synthesized for a NAT64 gateway.
+    #[serde(default)]
+    pub nat64: bool,
 }
 
 /// Connection configuration used by [`TunnelParameters`].
@@ -1,4 +1,4 @@
-use crate::net::{Endpoint, GenericTunnelOptions, TransportProtocol};
+use crate::net::{Endpoint, GenericTunnelOptions, IpVersion, TransportProtocol};
 use ipnetwork::IpNetwork;
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
@@ -8,6 +8,7 @@ use std::{
     cmp, fmt,
     hash::{Hash, Hasher},
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
 };
 
 /// Tunnel parameters required to start a `WireguardMonitor`.
@@ -18,6 +19,9 @@ pub struct TunnelParameters {
     pub options: TunnelOptions,
     pub generic_options: GenericTunnelOptions,
     pub obfuscation: Option<super::obfuscation::ObfuscatorConfig>,
+    /// Whether the next hop was given an address synthesized for a NAT64 gateway.
+    #[serde(default)]
+    pub nat64: bool,
 }
 
 /// Connection-specific configuration in [`TunnelParameters`].
@@ -26,6 +30,10 @@ pub struct ConnectionConfig {
     pub tunnel: TunnelConfig,
     pub peer: PeerConfig,
     pub exit_peer: Option<PeerConfig>,
+    /// Other peers on the same interface, which are reached directly. Only used by custom
+    /// tunnels.
+    #[serde(default)]
+    pub additional_peers: Vec<PeerConfig>,
     /// Gateway used by the tunnel (a private address).
     pub ipv4_gateway: Ipv4Addr,
     pub ipv6_gateway: Option<Ipv6Addr>,
@@ -45,6 +53,41 @@ impl ConnectionConfig {
             protocol: TransportProtocol::Udp,
         })
     }
+
+    /// Returns the endpoints of `additional_peers`.
+    pub fn get_additional_endpoints(&self) -> Vec<Endpoint> {
+        self.additional_peers
+            .iter()
+            .map(|peer| Endpoint {
+                address: peer.endpoint,
+                protocol: TransportProtocol::Udp,
+            })
+            .collect()
+    }
+
+    /// Returns all peers, starting with the entry peer.
+    pub fn peers(&self) -> impl Iterator<Item = &PeerConfig> {
+        std::iter::once(&self.peer)
+            .chain(self.exit_peer.iter())
+            .chain(self.additional_peers.iter())
+    }
+
+    /// Returns the IP versions that the tunnel only routes some destinations of. These have
+    /// allowed IPs, but none of them is the default route. Versions without any allowed IPs are
+    /// not routed at all, and are not returned.
+    pub fn get_untunneled_ip_versions(&self) -> Vec<IpVersion> {
+        [IpVersion::V4, IpVersion::V6]
+            .into_iter()
+            .filter(|version| {
+                let mut allowed_ips = self
+                    .peers()
+                    .flat_map(|peer| peer.allowed_ips.iter())
+                    .filter(|net| net.is_ipv4() == (*version == IpVersion::V4))
+                    .peekable();
+                allowed_ips.peek().is_some() && allowed_ips.all(|net| net.prefix() != 0)
+            })
+            .collect()
+    }
 }
 
 #[derive(Clone, Eq, PartialEq, Deserialize, Serialize, Debug, Hash)]
@@ -83,6 +126,24 @@ pub struct TunnelOptions {
     pub mtu: Option<u16>,
     /// Obtain a PSK using the relay config client.
     pub use_pq_safe_psk: bool,
+    /// Interval in seconds at which keepalive packets are sent to the peers when no other traffic
+    /// is sent. Disabled if `None`.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub persistent_keepalive: Option<u16>,
+    /// How quickly the tunnel is considered broken when no traffic is received.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub connectivity_profile: ConnectivityProfile,
+    /// Address inside the tunnel that is pinged to check connectivity. The gateway of the tunnel
+    /// is used if `None`.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub probe_address: Option<Ipv4Addr>,
+    /// Probe the largest packet size that passes through the tunnel after connecting, and lower
+    /// the MTU to it.
+    #[cfg(target_os = "linux")]
+    pub mtu_discovery: bool,
+    /// WireGuard implementation to use. One is picked automatically if `None`.
+    #[cfg(target_os = "linux")]
+    pub backend: Option<Backend>,
     /// Temporary switch for wireguard-nt
     #[cfg(windows)]
     #[serde(default = "default_wgnt_setting")]
@@ -101,12 +162,162 @@ impl Default for TunnelOptions {
         Self {
             mtu: None,
             use_pq_safe_psk: false,
+            persistent_keepalive: None,
+            connectivity_profile: ConnectivityProfile::default(),
+            probe_address: None,
+            #[cfg(target_os = "linux")]
+            mtu_discovery: false,
+            #[cfg(target_os = "linux")]
+            backend: None,
             #[cfg(windows)]
             use_wireguard_nt: default_wgnt_setting(),
         }
     }
 }
 
+/// How quickly a WireGuard tunnel is considered broken when no traffic is received.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectivityProfile {
+    /// Reconnect soon after traffic stops being received.
+    Aggressive,
+    #[default]
+    Default,
+    /// Wait longer before reconnecting. Suitable for links with high latency or long dropouts,
+    /// such as satellite links.
+    Tolerant,
+    /// Use the given timeouts.
+    Custom(ConnectivityTimeouts),
+}
+
+impl ConnectivityProfile {
+    /// Returns the timeouts used by this profile.
+    pub fn timeouts(&self) -> ConnectivityTimeouts {
+        match self {
+            ConnectivityProfile::Aggressive => ConnectivityTimeouts::AGGRESSIVE,
+            ConnectivityProfile::Default => ConnectivityTimeouts::DEFAULT,
+            ConnectivityProfile::Tolerant => ConnectivityTimeouts::TOLERANT,
+            ConnectivityProfile::Custom(timeouts) => *timeouts,
+        }
+    }
+}
+
+impl fmt::Display for ConnectivityProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectivityProfile::Aggressive => "aggressive".fmt(f),
+            ConnectivityProfile::Default => "default".fmt(f),
+            ConnectivityProfile::Tolerant => "tolerant".fmt(f),
+            ConnectivityProfile::Custom(timeouts) => write!(f, "custom ({})", timeouts),
+        }
+    }
+}
+
+/// Timeouts used to decide whether a WireGuard tunnel is working.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ConnectivityTimeouts {
+    /// How long to wait for incoming traffic after sending traffic, before pinging.
+    pub rx_timeout: Duration,
+    /// How long to keep pinging without receiving any traffic before the tunnel is considered
+    /// broken.
+    pub ping_timeout: Duration,
+    /// How long to wait for incoming traffic when connecting. This is doubled after each failed
+    /// attempt, up to `ping_timeout`.
+    pub establish_timeout: Duration,
+}
+
+impl ConnectivityTimeouts {
+    pub const AGGRESSIVE: Self = Self {
+        rx_timeout: Duration::from_secs(3),
+        ping_timeout: Duration::from_secs(8),
+        establish_timeout: Duration::from_secs(3),
+    };
+    pub const DEFAULT: Self = Self {
+        rx_timeout: Duration::from_secs(5),
+        ping_timeout: Duration::from_secs(15),
+        establish_timeout: Duration::from_secs(4),
+    };
+    pub const TOLERANT: Self = Self {
+        rx_timeout: Duration::from_secs(15),
+        ping_timeout: Duration::from_secs(60),
+        establish_timeout: Duration::from_secs(10),
+    };
+}
+
+impl fmt::Display for ConnectivityTimeouts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rx timeout: {}s, ping timeout: {}s, establish timeout: {}s",
+            self.rx_timeout.as_secs(),
+            self.ping_timeout.as_secs(),
+            self.establish_timeout.as_secs()
+        )
+    }
+}
+
+/// WireGuard implementation used by a tunnel on Linux.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    /// The kernel module, configured over netlink.
+    Kernel,
+    /// The kernel module, configured through NetworkManager.
+    NetworkManager,
+    /// wireguard-go.
+    Userspace,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Kernel => "kernel".fmt(f),
+            Backend::NetworkManager => "network-manager".fmt(f),
+            Backend::Userspace => "userspace".fmt(f),
+        }
+    }
+}
+
+/// Why a tunnel uses a particular WireGuard backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendReason {
+    /// The backend was selected in the settings.
+    Selected,
+    /// The userspace backend was forced by the `TALPID_FORCE_USERSPACE_WIREGUARD` environment
+    /// variable.
+    Forced,
+    /// The backend was picked automatically. NetworkManager is used if it manages DNS, and the
+    /// kernel module otherwise.
+    Automatic,
+    /// The contained backend was preferred, but failed to create the tunnel device.
+    Fallback(Backend),
+}
+
+/// The WireGuard backend used by a tunnel, and why it is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct BackendInfo {
+    pub backend: Backend,
+    pub reason: BackendReason,
+}
+
+impl fmt::Display for BackendInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.reason {
+            BackendReason::Selected => write!(f, "{} (selected in settings)", self.backend),
+            BackendReason::Forced => write!(f, "{} (forced by environment variable)", self.backend),
+            BackendReason::Automatic => write!(f, "{} (selected automatically)", self.backend),
+            BackendReason::Fallback(preferred) => {
+                write!(
+                    f,
+                    "{} (fallback because {} failed)",
+                    self.backend, preferred
+                )
+            }
+        }
+    }
+}
+
 /// Wireguard x25519 private key
 #[derive(Clone)]
 pub struct PrivateKey(x25519_dalek::StaticSecret);
@@ -129,6 +340,10 @@ impl PrivateKey {
     pub fn to_base64(&self) -> String {
         base64::encode(self.0.to_bytes())
     }
+
+    pub fn from_base64(key: &str) -> Result<Self, InvalidKeyError> {
+        decode_base64_key(key).map(Self::from)
+    }
 }
 
 impl From<[u8; 32]> for PrivateKey {
@@ -194,14 +409,18 @@ impl PublicKey {
     }
 
     pub fn from_base64(key: &str) -> Result<Self, InvalidKeyError> {
-        let bytes = base64::decode(key).map_err(|_| InvalidKeyError(()))?;
-        if bytes.len() != 32 {
-            return Err(InvalidKeyError(()));
-        }
-        let mut key = [0u8; 32];
-        key.copy_from_slice(&bytes);
-        Ok(From::from(key))
+        decode_base64_key(key).map(Self::from)
+    }
+}
+
+fn decode_base64_key(key: &str) -> Result<[u8; 32], InvalidKeyError> {
+    let bytes = base64::decode(key).map_err(|_| InvalidKeyError(()))?;
+    if bytes.len() != 32 {
+        return Err(InvalidKeyError(()));
     }
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&bytes);
+    Ok(key)
 }
 
 impl<'a> From<&'a x25519_dalek::StaticSecret> for PublicKey {
@@ -268,6 +487,14 @@ impl PresharedKey {
     pub fn as_bytes(&self) -> &[u8; 32] {
         &self.0
     }
+
+    pub fn to_base64(&self) -> String {
+        base64::encode(self.0)
+    }
+
+    pub fn from_base64(key: &str) -> Result<Self, InvalidKeyError> {
+        decode_base64_key(key).map(Self::from)
+    }
 }
 
 impl From<[u8; 32]> for PresharedKey {
@@ -1,7 +1,13 @@
 use async_trait::async_trait;
 use std::net::SocketAddr;
 
+mod quic;
+#[cfg(any(test, feature = "quic-server"))]
+pub mod quic_server;
+mod shadowsocks;
 mod udp2tcp;
+pub use self::shadowsocks::ShadowsocksSettings;
+pub use quic::QuicSettings;
 pub use udp2tcp::Udp2TcpSettings;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -14,6 +20,18 @@ pub enum Error {
 
     #[error(display = "Failed to run Udp2Tcp obfuscator")]
     RunUdp2TcpObfuscator(#[error(source)] udp2tcp::Error),
+
+    #[error(display = "Failed to create Shadowsocks obfuscator")]
+    CreateShadowsocksObfuscator(#[error(source)] shadowsocks::Error),
+
+    #[error(display = "Failed to run Shadowsocks obfuscator")]
+    RunShadowsocksObfuscator(#[error(source)] shadowsocks::Error),
+
+    #[error(display = "Failed to create QUIC obfuscator")]
+    CreateQuicObfuscator(#[error(source)] quic::Error),
+
+    #[error(display = "Failed to run QUIC obfuscator")]
+    RunQuicObfuscator(#[error(source)] quic::Error),
 }
 
 #[async_trait]
@@ -24,6 +42,8 @@ pub trait Obfuscator: Send {
 
 pub enum Settings {
     Udp2Tcp(Udp2TcpSettings),
+    Shadowsocks(ShadowsocksSettings),
+    Quic(QuicSettings),
 }
 
 pub async fn create_obfuscator(settings: &Settings) -> Result<Box<dyn Obfuscator>> {
@@ -31,5 +51,11 @@ pub async fn create_obfuscator(settings: &Settings) -> Result<Box<dyn Obfuscator
         Settings::Udp2Tcp(s) => udp2tcp::create_obfuscator(s)
             .await
             .map_err(Error::CreateUdp2TcpObfuscator),
+        Settings::Shadowsocks(s) => shadowsocks::create_obfuscator(s)
+            .await
+            .map_err(Error::CreateShadowsocksObfuscator),
+        Settings::Quic(s) => quic::create_obfuscator(s)
+            .await
+            .map_err(Error::CreateQuicObfuscator),
     }
 }
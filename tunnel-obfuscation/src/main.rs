@@ -1,5 +1,9 @@
 use std::{env::args, net::SocketAddr};
-use tunnel_obfuscation::{create_obfuscator, Obfuscator, Settings, Udp2TcpSettings};
+#[cfg(feature = "quic-server")]
+use tunnel_obfuscation::quic_server::QuicServer;
+use tunnel_obfuscation::{
+    create_obfuscator, Obfuscator, QuicSettings, Settings, ShadowsocksSettings, Udp2TcpSettings,
+};
 
 #[tokio::main]
 async fn main() {
@@ -7,7 +11,22 @@ async fn main() {
         println!("Missing arguments");
     }
 
-    let obfuscator = instantiate_requested(&args().last().unwrap()).await;
+    let obfuscator_type = args().last().unwrap();
+    #[cfg(feature = "quic-server")]
+    if obfuscator_type == "quic-server" {
+        let listen_addr = SocketAddr::new("127.0.0.1".parse().unwrap(), 3030);
+        let wireguard_endpoint = SocketAddr::new("127.0.0.1".parse().unwrap(), 51820);
+        let server = QuicServer::bind(listen_addr, wireguard_endpoint)
+            .expect("Creating QUIC endpoint failed");
+        println!(
+            "Forwarding QUIC datagrams on {} to {}",
+            listen_addr, wireguard_endpoint
+        );
+        server.run().await;
+        return;
+    }
+
+    let obfuscator = instantiate_requested(&obfuscator_type).await;
 
     println!("endpoint() returns {:?}", obfuscator.endpoint());
 
@@ -29,6 +48,33 @@ async fn instantiate_requested(obfuscator_type: &str) -> Box<dyn Obfuscator> {
                 .await
                 .expect("Creating obfuscator failed")
         }
+        "shadowsocks" => {
+            let settings = ShadowsocksSettings {
+                shadowsocks_endpoint: SocketAddr::new("127.0.0.1".parse().unwrap(), 3030),
+                wireguard_endpoint: SocketAddr::new("127.0.0.1".parse().unwrap(), 51820),
+                cipher: "aes-256-gcm".to_string(),
+                password: "mullvad".to_string(),
+                #[cfg(target_os = "linux")]
+                fwmark: Some(1337),
+            };
+
+            create_obfuscator(&Settings::Shadowsocks(settings))
+                .await
+                .expect("Creating obfuscator failed")
+        }
+        "quic" => {
+            let settings = QuicSettings {
+                peer: SocketAddr::new("127.0.0.1".parse().unwrap(), 3030),
+                server_name: "localhost".to_string(),
+                mtu: 1380,
+                #[cfg(target_os = "linux")]
+                fwmark: Some(1337),
+            };
+
+            create_obfuscator(&Settings::Quic(settings))
+                .await
+                .expect("Creating obfuscator failed")
+        }
         _ => {
             unimplemented!()
         }
@@ -0,0 +1,357 @@
+use crate::Obfuscator;
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::future::{self, Either};
+use quinn::{
+    ClientConfig, ConnectError, Connection, ConnectionError, Endpoint, EndpointConfig,
+    SendDatagramError, TokioRuntime, TransportConfig,
+};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tokio::net::UdpSocket;
+
+/// ALPN protocol identifier of HTTP/3. Used so that the connection looks like HTTP/3 traffic.
+pub const ALPN_PROTOCOL: &[u8] = b"h3";
+
+/// Interval at which keepalive packets are sent, so that the connection does not time out while
+/// WireGuard is idle.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Bytes that WireGuard adds to each packet sent through the tunnel.
+const WIREGUARD_OVERHEAD: usize = 32;
+
+pub struct QuicSettings {
+    /// QUIC server to connect to.
+    pub peer: SocketAddr,
+    /// Server name sent in the TLS handshake.
+    pub server_name: String,
+    /// MTU of the WireGuard tunnel. Creating the obfuscator fails if the QUIC connection cannot
+    /// carry WireGuard packets of this size, in which case the MTU must be lowered.
+    pub mtu: u16,
+    #[cfg(target_os = "linux")]
+    pub fwmark: Option<u32>,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Failed to bind the local UDP socket
+    #[error(display = "Failed to bind local UDP socket")]
+    BindUdp(#[error(source)] io::Error),
+
+    /// Failed to determine UDP socket details
+    #[error(display = "Failed to determine UDP socket details")]
+    GetUdpSocketDetails(#[error(source)] io::Error),
+
+    /// Failed to create QUIC endpoint
+    #[error(display = "Failed to create QUIC endpoint")]
+    CreateEndpoint(#[error(source)] io::Error),
+
+    /// Failed to start connecting to the QUIC server
+    #[error(display = "Failed to connect to QUIC server")]
+    Connect(#[error(source)] ConnectError),
+
+    /// The QUIC handshake failed
+    #[error(display = "QUIC handshake failed")]
+    Handshake(#[error(source)] ConnectionError),
+
+    /// The QUIC server does not accept datagrams
+    #[error(display = "QUIC server does not support datagrams")]
+    DatagramsUnsupported,
+
+    /// The QUIC datagrams cannot fit WireGuard packets of the requested MTU
+    #[error(
+        display = "WireGuard MTU {} is too large for QUIC datagrams of at most {} bytes",
+        _0,
+        _1
+    )]
+    MtuTooLarge(u16, usize),
+
+    /// Failed to receive packet from WireGuard
+    #[error(display = "Failed to receive packet from WireGuard")]
+    RecvFromWireguard(#[error(source)] io::Error),
+
+    /// Failed to send datagram to QUIC server
+    #[error(display = "Failed to send datagram to QUIC server")]
+    SendToQuic(#[error(source)] SendDatagramError),
+
+    /// Failed to receive datagram from QUIC server
+    #[error(display = "Failed to receive datagram from QUIC server")]
+    RecvFromQuic(#[error(source)] ConnectionError),
+
+    /// Failed to send packet to WireGuard
+    #[error(display = "Failed to send packet to WireGuard")]
+    SendToWireguard(#[error(source)] io::Error),
+}
+
+/// Forwards WireGuard packets as QUIC datagrams (RFC 9221).
+struct Quic {
+    local_addr: SocketAddr,
+    local_socket: UdpSocket,
+    // Closing the endpoint closes the connection.
+    _endpoint: Endpoint,
+    connection: Connection,
+}
+
+impl Quic {
+    pub async fn new(settings: &QuicSettings) -> Result<Self> {
+        let (listen_addr, bind_addr) = if settings.peer.is_ipv4() {
+            (
+                SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0),
+                SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+            )
+        } else {
+            (
+                SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 0),
+                SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+            )
+        };
+
+        let local_socket = UdpSocket::bind(listen_addr).await.map_err(Error::BindUdp)?;
+        let local_addr = local_socket
+            .local_addr()
+            .map_err(Error::GetUdpSocketDetails)?;
+
+        let endpoint = create_endpoint(
+            bind_addr,
+            #[cfg(target_os = "linux")]
+            settings.fwmark,
+        )
+        .map_err(Error::CreateEndpoint)?;
+        let connection = endpoint
+            .connect_with(
+                client_config(settings.peer),
+                settings.peer,
+                &settings.server_name,
+            )
+            .map_err(Error::Connect)?
+            .await
+            .map_err(Error::Handshake)?;
+
+        let max_datagram_size = connection
+            .max_datagram_size()
+            .ok_or(Error::DatagramsUnsupported)?;
+        if usize::from(settings.mtu) + WIREGUARD_OVERHEAD > max_datagram_size {
+            return Err(Error::MtuTooLarge(settings.mtu, max_datagram_size));
+        }
+
+        Ok(Self {
+            local_addr,
+            local_socket,
+            _endpoint: endpoint,
+            connection,
+        })
+    }
+}
+
+fn create_endpoint(
+    bind_addr: SocketAddr,
+    #[cfg(target_os = "linux")] fwmark: Option<u32>,
+) -> io::Result<Endpoint> {
+    let socket = Socket::new(
+        Domain::for_address(bind_addr),
+        Type::DGRAM,
+        Some(Protocol::UDP),
+    )?;
+    #[cfg(target_os = "linux")]
+    if let Some(fwmark) = fwmark {
+        socket.set_mark(fwmark)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&bind_addr.into())?;
+    Endpoint::new(EndpointConfig::default(), None, socket.into(), TokioRuntime)
+}
+
+fn client_config(peer: SocketAddr) -> ClientConfig {
+    let mut crypto = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(SkipServerVerification))
+        .with_no_client_auth();
+    crypto.alpn_protocols = vec![ALPN_PROTOCOL.to_vec()];
+
+    let mut transport = transport_config(peer);
+    transport.keep_alive_interval(Some(KEEPALIVE_INTERVAL));
+
+    let mut config = ClientConfig::new(Arc::new(crypto));
+    config.transport_config(Arc::new(transport));
+    config
+}
+
+/// Returns a transport config that starts out using the largest UDP payload that fits a 1500 byte
+/// link MTU, rather than the 1200 bytes that QUIC guarantees. Otherwise, packets from a WireGuard
+/// tunnel with a typical MTU would not fit in a datagram.
+pub(crate) fn transport_config(addr: SocketAddr) -> TransportConfig {
+    let ip_header_size = if addr.is_ipv4() { 20 } else { 40 };
+    let mut transport = TransportConfig::default();
+    transport.initial_max_udp_payload_size(1500 - ip_header_size - 8);
+    transport
+}
+
+/// Accepts any server certificate. QUIC is only used to disguise the traffic, and the peer is
+/// authenticated by WireGuard.
+struct SkipServerVerification;
+
+impl rustls::client::ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> std::result::Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
+
+#[async_trait]
+impl Obfuscator for Quic {
+    fn endpoint(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    async fn run(self: Box<Self>) -> crate::Result<()> {
+        // The address of the WireGuard socket is learned from the first packet it sends.
+        let wireguard_addr = Mutex::new(None);
+
+        let outgoing = Box::pin(forward_outgoing(
+            &self.local_socket,
+            &self.connection,
+            &wireguard_addr,
+        ));
+        let incoming = Box::pin(forward_incoming(
+            &self.local_socket,
+            &self.connection,
+            &wireguard_addr,
+        ));
+
+        let result = match future::select(outgoing, incoming).await {
+            Either::Left((result, _)) | Either::Right((result, _)) => result,
+        };
+        result.map_err(crate::Error::RunQuicObfuscator)
+    }
+}
+
+/// Send packets from WireGuard as QUIC datagrams.
+async fn forward_outgoing(
+    local_socket: &UdpSocket,
+    connection: &Connection,
+    wireguard_addr: &Mutex<Option<SocketAddr>>,
+) -> Result<()> {
+    let mut buffer = vec![0u8; usize::from(u16::MAX)];
+    loop {
+        let (read_n, from_addr) = local_socket
+            .recv_from(&mut buffer)
+            .await
+            .map_err(Error::RecvFromWireguard)?;
+        *wireguard_addr.lock().unwrap() = Some(from_addr);
+        // The MTU is checked when connecting, so a packet that does not fit in a datagram means
+        // that the WireGuard MTU is wrong. Fail instead of losing all large packets.
+        connection
+            .send_datagram(Bytes::copy_from_slice(&buffer[..read_n]))
+            .map_err(Error::SendToQuic)?;
+    }
+}
+
+/// Pass datagrams from the QUIC server on to WireGuard.
+async fn forward_incoming(
+    local_socket: &UdpSocket,
+    connection: &Connection,
+    wireguard_addr: &Mutex<Option<SocketAddr>>,
+) -> Result<()> {
+    loop {
+        let datagram = connection
+            .read_datagram()
+            .await
+            .map_err(Error::RecvFromQuic)?;
+        let wireguard_addr = *wireguard_addr.lock().unwrap();
+        if let Some(wireguard_addr) = wireguard_addr {
+            local_socket
+                .send_to(&datagram, wireguard_addr)
+                .await
+                .map_err(Error::SendToWireguard)?;
+        }
+    }
+}
+
+pub async fn create_obfuscator(settings: &QuicSettings) -> Result<Box<dyn Obfuscator>> {
+    Ok(Box::new(Quic::new(settings).await?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::quic_server::QuicServer;
+
+    const MTU: u16 = 1380;
+
+    async fn start_server(wireguard_endpoint: SocketAddr) -> SocketAddr {
+        let server = QuicServer::bind("127.0.0.1:0".parse().unwrap(), wireguard_endpoint).unwrap();
+        let server_addr = server.local_addr().unwrap();
+        tokio::spawn(server.run());
+        server_addr
+    }
+
+    fn settings(peer: SocketAddr, mtu: u16) -> QuicSettings {
+        QuicSettings {
+            peer,
+            server_name: "localhost".to_string(),
+            mtu,
+            #[cfg(target_os = "linux")]
+            fwmark: None,
+        }
+    }
+
+    /// Sends a packet of the largest size WireGuard sends with the tunnel MTU through the
+    /// obfuscator and the stand-in server, and back again.
+    #[tokio::test]
+    async fn test_round_trip() {
+        let wireguard_server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = start_server(wireguard_server.local_addr().unwrap()).await;
+
+        let obfuscator = create_obfuscator(&settings(server_addr, MTU))
+            .await
+            .unwrap();
+        let obfuscator_endpoint = obfuscator.endpoint();
+        tokio::spawn(obfuscator.run());
+
+        let wireguard_client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let request = vec![1u8; usize::from(MTU) + WIREGUARD_OVERHEAD];
+        wireguard_client
+            .send_to(&request, obfuscator_endpoint)
+            .await
+            .unwrap();
+
+        let mut buffer = vec![0u8; usize::from(u16::MAX)];
+        let (read_n, relay_addr) = wireguard_server.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..read_n], &request[..]);
+
+        let response = vec![2u8; usize::from(MTU) + WIREGUARD_OVERHEAD];
+        wireguard_server
+            .send_to(&response, relay_addr)
+            .await
+            .unwrap();
+        let (read_n, from_addr) = wireguard_client.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(from_addr, obfuscator_endpoint);
+        assert_eq!(&buffer[..read_n], &response[..]);
+    }
+
+    #[tokio::test]
+    async fn test_mtu_too_large() {
+        let wireguard_server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = start_server(wireguard_server.local_addr().unwrap()).await;
+
+        assert!(matches!(
+            Quic::new(&settings(server_addr, 1500)).await,
+            Err(Error::MtuTooLarge(1500, _))
+        ));
+    }
+}
@@ -0,0 +1,104 @@
+//! Stand-in for the server side of the QUIC obfuscator, so that it can be tested locally. Only
+//! built with the `quic-server` feature.
+
+use crate::quic::{transport_config, ALPN_PROTOCOL};
+use bytes::Bytes;
+use quinn::{Connection, Endpoint, ServerConfig};
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+use tokio::net::UdpSocket;
+
+/// Accepts QUIC connections and forwards their datagrams to a WireGuard endpoint.
+pub struct QuicServer {
+    endpoint: Endpoint,
+    wireguard_endpoint: SocketAddr,
+}
+
+impl QuicServer {
+    /// Listens for QUIC connections on `listen_addr`, using a self-signed certificate.
+    pub fn bind(listen_addr: SocketAddr, wireguard_endpoint: SocketAddr) -> io::Result<Self> {
+        let certificate = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+            .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?;
+        let cert_chain =
+            vec![rustls::Certificate(certificate.serialize_der().map_err(
+                |error| io::Error::new(io::ErrorKind::Other, error),
+            )?)];
+        let key = rustls::PrivateKey(certificate.serialize_private_key_der());
+
+        let mut crypto = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(cert_chain, key)
+            .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?;
+        crypto.alpn_protocols = vec![ALPN_PROTOCOL.to_vec()];
+
+        let mut config = ServerConfig::with_crypto(Arc::new(crypto));
+        config.transport_config(Arc::new(transport_config(listen_addr)));
+
+        Ok(Self {
+            endpoint: Endpoint::server(config, listen_addr)?,
+            wireguard_endpoint,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.endpoint.local_addr()
+    }
+
+    /// Serves connections until the endpoint is closed.
+    pub async fn run(self) {
+        while let Some(connecting) = self.endpoint.accept().await {
+            let wireguard_endpoint = self.wireguard_endpoint;
+            tokio::spawn(async move {
+                let connection = match connecting.await {
+                    Ok(connection) => connection,
+                    Err(err) => {
+                        log::warn!("QUIC handshake failed: {}", err);
+                        return;
+                    }
+                };
+                log::info!("Accepted connection from {}", connection.remote_address());
+                if let Err(err) = forward_connection(connection, wireguard_endpoint).await {
+                    log::error!("Forwarding failed: {}", err);
+                }
+            });
+        }
+    }
+}
+
+async fn forward_connection(
+    connection: Connection,
+    wireguard_endpoint: SocketAddr,
+) -> io::Result<()> {
+    let bind_addr = if wireguard_endpoint.is_ipv4() {
+        SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0)
+    } else {
+        SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0)
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    socket.connect(wireguard_endpoint).await?;
+
+    let mut buffer = vec![0u8; usize::from(u16::MAX)];
+    loop {
+        tokio::select! {
+            datagram = connection.read_datagram() => match datagram {
+                Ok(datagram) => {
+                    socket.send(&datagram).await?;
+                }
+                Err(err) => {
+                    log::info!("Connection closed: {}", err);
+                    return Ok(());
+                }
+            },
+            read_n = socket.recv(&mut buffer) => {
+                let read_n = read_n?;
+                if let Err(err) = connection.send_datagram(Bytes::copy_from_slice(&buffer[..read_n])) {
+                    log::warn!("Dropping datagram: {}", err);
+                }
+            }
+        }
+    }
+}
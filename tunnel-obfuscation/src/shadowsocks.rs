@@ -0,0 +1,288 @@
+use crate::Obfuscator;
+use async_trait::async_trait;
+use futures::future::{self, Either};
+use shadowsocks::{
+    config::{ServerConfig, ServerType},
+    context::Context,
+    crypto::CipherKind,
+    net::ConnectOpts,
+    relay::Address,
+    ProxySocket,
+};
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Mutex,
+};
+use tokio::net::UdpSocket;
+
+/// Settings for relaying WireGuard packets through a Shadowsocks server. Only UDP is supported,
+/// since WireGuard only sends UDP packets.
+pub struct ShadowsocksSettings {
+    /// Shadowsocks server to send packets to.
+    pub shadowsocks_endpoint: SocketAddr,
+    /// WireGuard endpoint that the Shadowsocks server forwards packets to.
+    pub wireguard_endpoint: SocketAddr,
+    /// Cipher used by the Shadowsocks server, e.g. `aes-256-gcm`.
+    pub cipher: String,
+    pub password: String,
+    #[cfg(target_os = "linux")]
+    pub fwmark: Option<u32>,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// The cipher is not supported
+    #[error(display = "Unsupported Shadowsocks cipher: {}", _0)]
+    InvalidCipher(String),
+
+    /// Failed to bind the local UDP socket
+    #[error(display = "Failed to bind local UDP socket")]
+    BindUdp(#[error(source)] std::io::Error),
+
+    /// Failed to determine UDP socket details
+    #[error(display = "Failed to determine UDP socket details")]
+    GetUdpSocketDetails(#[error(source)] std::io::Error),
+
+    /// Failed to create Shadowsocks socket
+    #[error(display = "Failed to create Shadowsocks socket")]
+    CreateShadowsocksSocket(#[error(source)] std::io::Error),
+
+    /// Failed to receive packet from WireGuard
+    #[error(display = "Failed to receive packet from WireGuard")]
+    RecvFromWireguard(#[error(source)] std::io::Error),
+
+    /// Failed to send packet to Shadowsocks server
+    #[error(display = "Failed to send packet to Shadowsocks server")]
+    SendToShadowsocks(#[error(source)] std::io::Error),
+
+    /// Failed to receive packet from Shadowsocks server
+    #[error(display = "Failed to receive packet from Shadowsocks server")]
+    RecvFromShadowsocks(#[error(source)] std::io::Error),
+
+    /// Failed to send packet to WireGuard
+    #[error(display = "Failed to send packet to WireGuard")]
+    SendToWireguard(#[error(source)] std::io::Error),
+}
+
+struct Shadowsocks {
+    local_addr: SocketAddr,
+    local_socket: UdpSocket,
+    shadowsocks_socket: ProxySocket,
+    wireguard_endpoint: Address,
+}
+
+impl Shadowsocks {
+    pub async fn new(settings: &ShadowsocksSettings) -> Result<Self> {
+        let cipher: CipherKind = settings
+            .cipher
+            .parse()
+            .map_err(|_| Error::InvalidCipher(settings.cipher.clone()))?;
+
+        let listen_addr = if settings.shadowsocks_endpoint.is_ipv4() {
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)
+        } else {
+            SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 0)
+        };
+        let local_socket = UdpSocket::bind(listen_addr).await.map_err(Error::BindUdp)?;
+        let local_addr = local_socket
+            .local_addr()
+            .map_err(Error::GetUdpSocketDetails)?;
+
+        let context = Context::new_shared(ServerType::Local);
+        let server_config = ServerConfig::new(
+            settings.shadowsocks_endpoint,
+            settings.password.clone(),
+            cipher,
+        );
+        #[allow(unused_mut)]
+        let mut connect_opts = ConnectOpts::default();
+        #[cfg(target_os = "linux")]
+        {
+            connect_opts.fwmark = settings.fwmark;
+        }
+        let shadowsocks_socket =
+            ProxySocket::connect_with_opts(context, &server_config, &connect_opts)
+                .await
+                .map_err(Error::CreateShadowsocksSocket)?;
+
+        Ok(Self {
+            local_addr,
+            local_socket,
+            shadowsocks_socket,
+            wireguard_endpoint: Address::SocketAddress(settings.wireguard_endpoint),
+        })
+    }
+}
+
+#[async_trait]
+impl Obfuscator for Shadowsocks {
+    fn endpoint(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    async fn run(self: Box<Self>) -> crate::Result<()> {
+        // The address of the WireGuard socket is learned from the first packet it sends.
+        let wireguard_addr = Mutex::new(None);
+
+        let outgoing = Box::pin(forward_outgoing(
+            &self.local_socket,
+            &self.shadowsocks_socket,
+            &self.wireguard_endpoint,
+            &wireguard_addr,
+        ));
+        let incoming = Box::pin(forward_incoming(
+            &self.local_socket,
+            &self.shadowsocks_socket,
+            &wireguard_addr,
+        ));
+
+        let result = match future::select(outgoing, incoming).await {
+            Either::Left((result, _)) | Either::Right((result, _)) => result,
+        };
+        result.map_err(crate::Error::RunShadowsocksObfuscator)
+    }
+}
+
+/// Encrypt packets from WireGuard and send them to the Shadowsocks server.
+async fn forward_outgoing(
+    local_socket: &UdpSocket,
+    shadowsocks_socket: &ProxySocket,
+    wireguard_endpoint: &Address,
+    wireguard_addr: &Mutex<Option<SocketAddr>>,
+) -> Result<()> {
+    let mut buffer = vec![0u8; usize::from(u16::MAX)];
+    loop {
+        let (read_n, from_addr) = local_socket
+            .recv_from(&mut buffer)
+            .await
+            .map_err(Error::RecvFromWireguard)?;
+        *wireguard_addr.lock().unwrap() = Some(from_addr);
+        shadowsocks_socket
+            .send(wireguard_endpoint, &buffer[..read_n])
+            .await
+            .map_err(Error::SendToShadowsocks)?;
+    }
+}
+
+/// Decrypt packets from the Shadowsocks server and pass them on to WireGuard.
+async fn forward_incoming(
+    local_socket: &UdpSocket,
+    shadowsocks_socket: &ProxySocket,
+    wireguard_addr: &Mutex<Option<SocketAddr>>,
+) -> Result<()> {
+    let mut buffer = vec![0u8; usize::from(u16::MAX)];
+    loop {
+        let (read_n, _, _) = shadowsocks_socket
+            .recv(&mut buffer)
+            .await
+            .map_err(Error::RecvFromShadowsocks)?;
+        // Nothing can be received before WireGuard has sent something, so the address is known.
+        let wireguard_addr = *wireguard_addr.lock().unwrap();
+        if let Some(wireguard_addr) = wireguard_addr {
+            local_socket
+                .send_to(&buffer[..read_n], wireguard_addr)
+                .await
+                .map_err(Error::SendToWireguard)?;
+        }
+    }
+}
+
+pub async fn create_obfuscator(settings: &ShadowsocksSettings) -> Result<Box<dyn Obfuscator>> {
+    Ok(Box::new(Shadowsocks::new(settings).await?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CIPHER: &str = "aes-256-gcm";
+    const PASSWORD: &str = "mullvad";
+
+    #[tokio::test]
+    async fn test_invalid_cipher() {
+        let settings = ShadowsocksSettings {
+            shadowsocks_endpoint: "127.0.0.1:1".parse().unwrap(),
+            wireguard_endpoint: "127.0.0.1:2".parse().unwrap(),
+            cipher: "rot13".to_string(),
+            password: PASSWORD.to_string(),
+            #[cfg(target_os = "linux")]
+            fwmark: None,
+        };
+        assert!(matches!(
+            Shadowsocks::new(&settings).await,
+            Err(Error::InvalidCipher(cipher)) if cipher == "rot13"
+        ));
+    }
+
+    /// Sends a packet from a stand-in WireGuard client through the obfuscator and a local
+    /// Shadowsocks server to a stand-in WireGuard server, and back again.
+    #[tokio::test]
+    async fn test_round_trip() {
+        let wireguard_server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let wireguard_endpoint = wireguard_server.local_addr().unwrap();
+
+        let server_config = ServerConfig::new(
+            "127.0.0.1:0".parse::<SocketAddr>().unwrap(),
+            PASSWORD,
+            CIPHER.parse().unwrap(),
+        );
+        let shadowsocks_server =
+            ProxySocket::bind(Context::new_shared(ServerType::Server), &server_config)
+                .await
+                .unwrap();
+        let shadowsocks_endpoint = shadowsocks_server.local_addr().unwrap();
+
+        let obfuscator = create_obfuscator(&ShadowsocksSettings {
+            shadowsocks_endpoint,
+            wireguard_endpoint,
+            cipher: CIPHER.to_string(),
+            password: PASSWORD.to_string(),
+            #[cfg(target_os = "linux")]
+            fwmark: None,
+        })
+        .await
+        .unwrap();
+        let obfuscator_endpoint = obfuscator.endpoint();
+        tokio::spawn(obfuscator.run());
+
+        let wireguard_client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let request = vec![1u8; 1400];
+        wireguard_client
+            .send_to(&request, obfuscator_endpoint)
+            .await
+            .unwrap();
+
+        // Forward the request like the Shadowsocks server on a relay would
+        let mut buffer = vec![0u8; usize::from(u16::MAX)];
+        let (read_n, obfuscator_addr, target_addr, _) =
+            shadowsocks_server.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..read_n], &request[..]);
+        assert_eq!(target_addr, Address::SocketAddress(wireguard_endpoint));
+        let relay_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        relay_socket
+            .send_to(&buffer[..read_n], wireguard_endpoint)
+            .await
+            .unwrap();
+
+        let (read_n, relay_addr) = wireguard_server.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..read_n], &request[..]);
+        let response = vec![2u8; 1400];
+        wireguard_server
+            .send_to(&response, relay_addr)
+            .await
+            .unwrap();
+
+        let read_n = relay_socket.recv(&mut buffer).await.unwrap();
+        shadowsocks_server
+            .send_to(obfuscator_addr, &target_addr, &buffer[..read_n])
+            .await
+            .unwrap();
+
+        let (read_n, from_addr) = wireguard_client.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(from_addr, obfuscator_endpoint);
+        assert_eq!(&buffer[..read_n], &response[..]);
+    }
+}